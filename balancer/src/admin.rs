@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use dotenv::dotenv;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::info;
+use serde_json::json;
+
+use crate::cache::SimpleCache;
+use crate::degraded::Staleness;
+use crate::locality::Locality;
+use crate::metrics::{self, Metrics};
+use crate::state::SharedState;
+
+// Port of the admin server, None if HOST_PORT_ADMIN_BALANCER is not set
+pub fn admin_port() -> Option<u16> {
+    dotenv().ok();
+    env::var("HOST_PORT_ADMIN_BALANCER")
+        .ok()
+        .filter(|port| !port.is_empty())
+        .map(|port| port.parse::<u16>().expect("HOST_PORT_ADMIN_BALANCER must be a valid u16"))
+}
+
+async fn status(shared_state: &SharedState, staleness: &Staleness, locality: &Locality) -> Response<Body> {
+    let state = shared_state.read().await;
+    let (local_requests, cross_zone_requests) = locality.requests();
+    let body = json!({
+        "queue_age_secs": state.age().map(|age| age.as_secs_f64()),
+        "stale": staleness.is_stale(&state),
+        "stale_after_secs": staleness.after.as_secs(),
+        "stale_policy": staleness.policy.to_string(),
+        "restored": state.is_restored(),
+        "backends": state.items().map_or(0, |items| items.len()),
+        "zone": locality.zone,
+        "local_backends": state.items().map_or(0, |items| items.iter().filter(|item| locality.is_local(item)).count()),
+        "local_requests": local_requests,
+        "cross_zone_requests": cross_zone_requests,
+    });
+
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn metrics(shared_state: &SharedState, staleness: &Staleness, metrics: &Metrics, cache: &SimpleCache) -> Response<Body> {
+    {
+        let state = shared_state.read().await;
+        metrics.set_queue(state.age(), staleness.is_stale(&state));
+    }
+    metrics.set_cache_size(cache.size_bytes().await);
+
+    Response::builder()
+        .header("Content-Type", metrics::CONTENT_TYPE)
+        .body(Body::from(metrics.encode()))
+        .unwrap()
+}
+
+// Everything the admin server reports on
+#[derive(Clone)]
+pub struct AdminContext {
+    pub shared_state: SharedState,
+    pub staleness: Staleness,
+    pub locality: Arc<Locality>,
+    pub metrics: Arc<Metrics>,
+    pub cache: Arc<SimpleCache>,
+}
+
+async fn handle_admin(req: Request<Body>, context: AdminContext) -> Result<Response<Body>, Infallible> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/status") => Ok(status(&context.shared_state, &context.staleness, &context.locality).await),
+        (&Method::GET, "/metrics") => Ok(metrics(&context.shared_state, &context.staleness, &context.metrics, &context.cache).await),
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .unwrap()),
+    }
+}
+
+// Serves the state of the balancer itself, separate from the balanced traffic
+pub async fn start_admin_server(port: u16, context: AdminContext) -> Result<(), hyper::Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_svc = make_service_fn(move |_| {
+        let context = context.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle_admin(req, context.clone())))
+        }
+    });
+
+    info!("Admin server listening on http://{}", addr);
+    Server::bind(&addr).serve(make_svc).await
+}
@@ -44,6 +44,11 @@ impl SimpleCache {
         None
     }
 
+    // Bytes of the cached values, including expired ones not yet collected
+    pub async fn size_bytes(&self) -> usize {
+        self.store.lock().await.iter().map(|entry| entry.value.len()).sum()
+    }
+
     pub async fn set(&self, key: String, value: Vec<u8>, ttl: Duration) {
         let mut store = self.store.lock().await;
         let new_entry = CacheEntry {
@@ -2,7 +2,7 @@ use std::sync::Arc;
 use std::fmt;
 use std::env;
 use dotenv::dotenv;
-use hyper::{Client, client::HttpConnector, Request, Response, Body};
+use hyper::{Client, Request, Response, Body};
 use hyper_tls::HttpsConnector;
 use tokio::sync::mpsc;
 use tokio::time::{timeout, Duration};
@@ -36,7 +36,6 @@ struct QueuedRequest {
 }
 
 pub struct UnboundedClient {
-    client: Client<HttpsConnector<HttpConnector>>,
     request_sender: mpsc::Sender<QueuedRequest>,
 }
 
@@ -50,10 +49,9 @@ impl UnboundedClient {
         let (request_sender, mut request_receiver) = mpsc::channel::<QueuedRequest>(100_000);
 
         // Background task to handle requests
-        let client_clone = client.clone();
         tokio::spawn(async move {
             while let Some(queued_request) = request_receiver.recv().await {
-                let client = client_clone.clone();
+                let client = client.clone();
                 tokio::spawn(async move {
                     // handling of request
                     let result = client.request(queued_request.request).await
@@ -66,7 +64,6 @@ impl UnboundedClient {
         });
 
         Arc::new(UnboundedClient {
-            client,
             request_sender,
         })
     }
@@ -108,21 +105,3 @@ impl UnboundedClient {
         }
     }
 }
-
-// Keep this function for compatibility (helper function)
-pub fn spawn_workers<F>(num_workers: usize, work: F)
-where
-    F: Fn() -> tokio::task::JoinHandle<()> + Send + Sync + 'static,
-{
-    let work = Arc::new(work);
-    for i in 0..num_workers {
-        let work = work.clone();
-        tokio::spawn(async move {
-            println!("Worker {} started", i);
-            loop {
-                let handle = work();
-                handle.await.unwrap();
-            }
-        });
-    }
-}
\ No newline at end of file
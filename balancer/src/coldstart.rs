@@ -0,0 +1,75 @@
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dotenv::dotenv;
+use log::{info, warn};
+use protocol::BalancerMessage;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::queue::QueueItem;
+use crate::socket::EventSender;
+
+// Wakes up a scaled-to-zero app and tracks how long the cold start takes
+pub struct ColdStart {
+    event_sender: EventSender,
+    wake_requested: Mutex<Option<Instant>>,
+    timeout: Duration,
+}
+
+impl ColdStart {
+    pub fn new(event_sender: EventSender) -> Arc<Self> {
+        dotenv().ok();
+        let cold_start_timeout = Duration::from_secs(
+            env::var("COLD_START_TIMEOUT")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse::<u64>()
+                .expect("COLD_START_TIMEOUT must be a valid u64")
+        );
+
+        Arc::new(ColdStart {
+            event_sender,
+            wake_requested: Mutex::new(None),
+            timeout: cold_start_timeout,
+        })
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // Sends a wake-up event unless one is already pending, returns the time of the wake-up request
+    pub async fn request_wake(&self) -> Instant {
+        let mut wake_requested = self.wake_requested.lock().await;
+        if let Some(requested_at) = *wake_requested {
+            if requested_at.elapsed() < self.timeout {
+                return requested_at;
+            }
+        }
+
+        info!("No backend available. Requesting wake-up from deployment agent");
+        if let Err(e) = self.event_sender.send(BalancerMessage::Wake) {
+            warn!("Failed to queue wake-up event: {}", e);
+        }
+
+        let requested_at = Instant::now();
+        *wake_requested = Some(requested_at);
+        requested_at
+    }
+
+    // Reports the cold start duration once the first buffered request can be forwarded
+    pub async fn complete(&self, requested_at: Instant) {
+        let mut wake_requested = self.wake_requested.lock().await;
+        if *wake_requested == Some(requested_at) {
+            info!("Cold start completed in {:?}", requested_at.elapsed());
+            *wake_requested = None;
+        }
+    }
+}
+
+// Readiness check: the backend accepts tcp-connections on the target port
+pub async fn is_ready(item: &QueueItem, port: &str) -> bool {
+    let addr = item.address(port);
+    matches!(timeout(Duration::from_secs(1), TcpStream::connect(&addr)).await, Ok(Ok(_)))
+}
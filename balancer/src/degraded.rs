@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+use dotenv::dotenv;
+use tokio::sync::Mutex;
+
+use crate::state::QueueState;
+
+// How the balancer routes while the queue is stale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StalePolicy {
+    // Keeps the weights of the last queue
+    Keep,
+    // Ignores the outdated scores, every backend gets the same weight
+    Equal,
+    // Equal weights, backends failing repeatedly are ejected for a while
+    Passive,
+}
+
+impl fmt::Display for StalePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StalePolicy::Keep => write!(f, "keep"),
+            StalePolicy::Equal => write!(f, "equal"),
+            StalePolicy::Passive => write!(f, "passive"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Staleness {
+    // Age of the queue after which it is considered stale
+    pub after: Duration,
+    pub policy: StalePolicy,
+}
+
+impl Staleness {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let after = Duration::from_secs(
+            env::var("QUEUE_STALE_AFTER")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse::<u64>()
+                .expect("QUEUE_STALE_AFTER must be a valid u64")
+        );
+
+        let policy = match env::var("STALE_POLICY").unwrap_or_else(|_| "passive".to_string()).as_str() {
+            "keep" => StalePolicy::Keep,
+            "equal" => StalePolicy::Equal,
+            "passive" => StalePolicy::Passive,
+            other => panic!("STALE_POLICY must be keep, equal or passive, got {}", other),
+        };
+
+        Staleness { after, policy }
+    }
+
+    // Restored queues stay stale until the discovery confirms them
+    pub fn is_stale(&self, state: &QueueState) -> bool {
+        state.is_restored() || state.age().is_some_and(|age| age > self.after)
+    }
+}
+
+#[derive(Default)]
+struct BackendHealth {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+// Health of the backends as observed from the outcome of forwarded requests
+pub struct PassiveHealth {
+    max_failures: u32,
+    ejection: Duration,
+    backends: Mutex<HashMap<String, BackendHealth>>,
+}
+
+impl PassiveHealth {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let max_failures = env::var("PASSIVE_HEALTH_FAILURES")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse::<u32>()
+            .expect("PASSIVE_HEALTH_FAILURES must be a valid u32")
+            .max(1);
+        let ejection = Duration::from_secs(
+            env::var("PASSIVE_HEALTH_EJECTION")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse::<u64>()
+                .expect("PASSIVE_HEALTH_EJECTION must be a valid u64")
+        );
+
+        PassiveHealth { max_failures, ejection, backends: Mutex::new(HashMap::new()) }
+    }
+
+    // Ejects a backend after max_failures failed requests in a row
+    pub async fn record(&self, dns_name: &str, success: bool) {
+        let mut backends = self.backends.lock().await;
+        let health = backends.entry(dns_name.to_string()).or_default();
+        if success {
+            health.consecutive_failures = 0;
+            return;
+        }
+
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.max_failures {
+            println!("Warning: Ejecting backend {} for {:?} after {} failed requests",
+                     dns_name, self.ejection, health.consecutive_failures);
+            health.consecutive_failures = 0;
+            health.ejected_until = Some(Instant::now() + self.ejection);
+        }
+    }
+
+    pub async fn ejected(&self) -> HashSet<String> {
+        let now = Instant::now();
+        self.backends.lock().await
+            .iter()
+            .filter(|(_, health)| health.ejected_until.is_some_and(|until| until > now))
+            .map(|(dns_name, _)| dns_name.clone())
+            .collect()
+    }
+
+    // Forgets backends that left the queue, their names are not reused
+    pub async fn retain(&self, dns_names: &HashSet<&str>) {
+        self.backends.lock().await.retain(|dns_name, _| dns_names.contains(dns_name.as_str()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passive_health() -> PassiveHealth {
+        PassiveHealth { max_failures: 2, ejection: Duration::from_secs(30), backends: Mutex::new(HashMap::new()) }
+    }
+
+    #[tokio::test]
+    async fn ejects_after_consecutive_failures() {
+        let health = passive_health();
+        health.record("a", false).await;
+        health.record("a", true).await;
+        health.record("a", false).await;
+        assert!(health.ejected().await.is_empty());
+
+        health.record("a", false).await;
+        assert_eq!(health.ejected().await, HashSet::from(["a".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn backends_that_left_the_queue_are_forgotten() {
+        let health = passive_health();
+        for dns_name in ["a", "b", "c"] {
+            health.record(dns_name, false).await;
+            health.record(dns_name, false).await;
+        }
+
+        health.retain(&HashSet::from(["b"])).await;
+        assert_eq!(health.ejected().await, HashSet::from(["b".to_string()]));
+        assert_eq!(health.backends.lock().await.len(), 1);
+
+        health.retain(&HashSet::new()).await;
+        assert!(health.backends.lock().await.is_empty());
+    }
+
+    #[test]
+    fn queues_are_stale_when_restored_or_too_old() {
+        let staleness = Staleness { after: Duration::from_millis(20), policy: StalePolicy::Passive };
+        let mut state = QueueState::default();
+        assert!(!staleness.is_stale(&state));
+
+        state.replace(Vec::new());
+        assert!(!staleness.is_stale(&state));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(staleness.is_stale(&state));
+        state.confirm();
+        assert!(!staleness.is_stale(&state));
+
+        // A restored queue is stale however recent it is, until the discovery confirms it
+        let path = env::temp_dir().join(format!("balancer-{}-snapshot-stale.json", std::process::id()));
+        let confirmed_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        std::fs::write(&path, format!("{{\"confirmed_at\": {}, \"items\": []}}", confirmed_at)).unwrap();
+        let staleness = Staleness { after: Duration::from_secs(3600), ..staleness };
+        let mut restored = crate::state::restore(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(restored.is_restored());
+        assert!(staleness.is_stale(&restored));
+        restored.confirm();
+        assert!(!staleness.is_stale(&restored));
+    }
+}
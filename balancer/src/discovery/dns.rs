@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use dotenv::dotenv;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use log::{error, info};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::queue::{QueueItem, UtilizationCategory};
+use crate::state::SharedState;
+
+#[derive(Debug, Clone, Copy)]
+enum RecordKind {
+    // A/AAAA records, every address served on the same port
+    Address,
+    // SRV records with port, priority and weight per target
+    Srv,
+}
+
+struct DnsConfig {
+    name: String,
+    kind: RecordKind,
+    // Port of the backends for A/AAAA records
+    port: u16,
+    // Bounds of the refresh interval, which otherwise follows the TTL of the records
+    min_interval: Duration,
+    max_interval: Duration,
+}
+
+impl DnsConfig {
+    fn from_env() -> Result<Self, String> {
+        dotenv().ok();
+        let name = env::var("DISCOVERY_DNS_NAME")
+            .ok()
+            .filter(|name| !name.is_empty())
+            .ok_or("DISCOVERY_DNS_NAME must be set for the DNS discovery")?;
+        let kind = match env::var("DISCOVERY_DNS_RECORD").unwrap_or_else(|_| "a".to_string()).as_str() {
+            "a" => RecordKind::Address,
+            "srv" => RecordKind::Srv,
+            other => return Err(format!("DISCOVERY_DNS_RECORD must be a or srv, got {}", other)),
+        };
+        let port = env::var("DISCOVERY_DNS_PORT")
+            .ok()
+            .filter(|port| !port.is_empty())
+            .or_else(|| env::var("TARGET_PORT").ok())
+            .ok_or("DISCOVERY_DNS_PORT or TARGET_PORT must be set")?
+            .parse::<u16>()
+            .map_err(|_| "DISCOVERY_DNS_PORT must be a valid u16")?;
+        let min_interval = Duration::from_secs(
+            env::var("DISCOVERY_DNS_MIN_INTERVAL")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse::<u64>()
+                .map_err(|_| "DISCOVERY_DNS_MIN_INTERVAL must be a valid u64")?
+                .max(1)
+        );
+        let max_interval = Duration::from_secs(
+            env::var("DISCOVERY_DNS_MAX_INTERVAL")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse::<u64>()
+                .map_err(|_| "DISCOVERY_DNS_MAX_INTERVAL must be a valid u64")?
+        ).max(min_interval);
+
+        Ok(DnsConfig { name, kind, port, min_interval, max_interval })
+    }
+
+    // Follows the TTL of the records within the configured bounds
+    fn refresh_interval(&self, valid_until: Instant) -> Duration {
+        valid_until.saturating_duration_since(Instant::now()).clamp(self.min_interval, self.max_interval)
+    }
+}
+
+// Uses DISCOVERY_DNS_SERVER (ip:port) if set, e.g. a local DNS server, otherwise the system configuration
+fn resolver() -> Result<TokioAsyncResolver, Box<dyn std::error::Error>> {
+    match env::var("DISCOVERY_DNS_SERVER") {
+        Ok(server) if !server.is_empty() => {
+            let server = server.parse::<SocketAddr>()
+                .map_err(|_| format!("DISCOVERY_DNS_SERVER must be ip:port, got {}", server))?;
+            let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+            Ok(TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], name_servers), ResolverOpts::default()))
+        }
+        _ => Ok(TokioAsyncResolver::tokio_from_system_conf()?),
+    }
+}
+
+// Resolves DISCOVERY_DNS_NAME into the backends of the balancer, again as soon as the records expire
+pub async fn resolve_backends(shared_state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let config = DnsConfig::from_env()?;
+    let resolver = resolver()?;
+    info!("Resolving backends from {:?} records of {}", config.kind, config.name);
+
+    loop {
+        let result = match config.kind {
+            RecordKind::Address => resolve_addresses(&resolver, &config).await,
+            RecordKind::Srv => resolve_srv(&resolver, &config).await,
+        };
+
+        let next = match result {
+            Ok((items, valid_until)) => {
+                let changed = shared_state.read().await.items() != Some(&items);
+                if changed {
+                    info!("Resolved {} backend(s) from {}", items.len(), config.name);
+                }
+                shared_state.write().await.replace(items);
+                config.refresh_interval(valid_until)
+            }
+            Err(e) => {
+                // The last backends stay in use and become stale
+                error!("Failed to resolve {}: {}", config.name, e);
+                config.min_interval
+            }
+        };
+        sleep(next).await;
+    }
+}
+
+async fn resolve_addresses(resolver: &TokioAsyncResolver, config: &DnsConfig) -> Result<(Vec<QueueItem>, Instant), String> {
+    let lookup = resolver.lookup_ip(config.name.as_str()).await.map_err(|e| e.to_string())?;
+    let mut items: Vec<QueueItem> = Vec::new();
+    for ip in lookup.iter() {
+        let item = queue_item(ip, config.port, 100.0, BTreeMap::new());
+        if !items.contains(&item) {
+            items.push(item);
+        }
+    }
+    items.sort_by(|a, b| a.dns_name.cmp(&b.dns_name));
+    Ok((items, lookup.valid_until().into()))
+}
+
+// Only the targets with the lowest priority are served, higher priorities are the fallback if none resolves.
+// Weights are relative within the served priority and scaled to scores up to 100.
+async fn resolve_srv(resolver: &TokioAsyncResolver, config: &DnsConfig) -> Result<(Vec<QueueItem>, Instant), String> {
+    let lookup = resolver.srv_lookup(config.name.as_str()).await.map_err(|e| e.to_string())?;
+    let mut valid_until: Instant = lookup.as_lookup().valid_until().into();
+
+    let mut records: Vec<_> = lookup.iter().collect();
+    records.sort_by_key(|srv| srv.priority());
+
+    let mut priorities: Vec<u16> = records.iter().map(|srv| srv.priority()).collect();
+    priorities.dedup();
+    for priority in priorities {
+        let group: Vec<_> = records.iter().filter(|srv| srv.priority() == priority).collect();
+        let max_weight = group.iter().map(|srv| srv.weight()).max().unwrap_or(0);
+
+        let mut items: Vec<QueueItem> = Vec::new();
+        for srv in group {
+            let target = srv.target().to_utf8();
+            let ips = match resolver.lookup_ip(srv.target().clone()).await {
+                Ok(ips) => ips,
+                Err(e) => {
+                    error!("Failed to resolve SRV target {}: {}", target, e);
+                    continue;
+                }
+            };
+            valid_until = valid_until.min(ips.valid_until().into());
+
+            // Weight 0 targets get a minimal share next to weighted ones, all equal if every weight is 0
+            let score = if max_weight == 0 { 100.0 } else { (srv.weight().max(1) as f64 / max_weight as f64) * 100.0 };
+            let mut labels = BTreeMap::new();
+            labels.insert("srv_target".to_string(), target.trim_end_matches('.').to_string());
+            labels.insert("srv_priority".to_string(), srv.priority().to_string());
+            labels.insert("srv_weight".to_string(), srv.weight().to_string());
+            for ip in ips.iter() {
+                let item = queue_item(ip, srv.port(), score, labels.clone());
+                if !items.iter().any(|existing| existing.dns_name == item.dns_name) {
+                    items.push(item);
+                }
+            }
+        }
+
+        if !items.is_empty() {
+            items.sort_by(|a, b| a.dns_name.cmp(&b.dns_name));
+            return Ok((items, valid_until));
+        }
+    }
+    Err("no SRV target could be resolved".to_string())
+}
+
+fn queue_item(ip: IpAddr, port: u16, score: f64, labels: BTreeMap<String, String>) -> QueueItem {
+    // IPv6 addresses in brackets
+    let mut item = QueueItem::new(SocketAddr::new(ip, port).to_string(), score, UtilizationCategory::Low);
+    item.host = Some(ip.to_string());
+    item.port = Some(port);
+    item.labels = labels;
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, UdpSocket};
+    use std::sync::{Mutex, OnceLock};
+    use std::thread;
+    use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use hickory_resolver::proto::rr::rdata::{A, AAAA, SRV};
+    use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+    use super::*;
+
+    // Records of the stand-in server, None for names that do not exist
+    fn zone(name: &str, record_type: RecordType) -> Option<Vec<(u32, RData)>> {
+        let a = |ip: [u8; 4]| RData::A(A(Ipv4Addr::from(ip)));
+        let srv = |priority, weight, port, target: &str| {
+            RData::SRV(SRV::new(priority, weight, port, Name::from_ascii(target).unwrap()))
+        };
+        let records = match (name, record_type) {
+            ("web.test.", RecordType::A) => vec![(60, a([10, 0, 0, 2])), (60, a([10, 0, 0, 1])), (60, a([10, 0, 0, 2]))],
+            ("v6.test.", RecordType::AAAA) => vec![(60, RData::AAAA(AAAA(Ipv6Addr::LOCALHOST)))],
+            ("t1.test." | "shared.test.", RecordType::A) => vec![(60, a([10, 0, 1, 1]))],
+            ("t2.test.", RecordType::A) => vec![(60, a([10, 0, 1, 2]))],
+            ("t3.test.", RecordType::A) => vec![(60, a([10, 0, 1, 3]))],
+            ("t4.test.", RecordType::A) => vec![(60, a([10, 0, 2, 1]))],
+            ("short.test.", RecordType::A) => vec![(2, a([10, 0, 3, 1]))],
+            ("srv.test.", RecordType::SRV) => vec![
+                (300, srv(10, 60, 8080, "t1.test.")),
+                (300, srv(10, 30, 8081, "t2.test.")),
+                (300, srv(10, 0, 8082, "t3.test.")),
+                (300, srv(20, 10, 9000, "t4.test.")),
+            ],
+            ("fallback.test.", RecordType::SRV) => vec![
+                (300, srv(10, 50, 8080, "missing.test.")),
+                (300, srv(20, 5, 9000, "t4.test.")),
+            ],
+            ("zero.test.", RecordType::SRV) => vec![
+                (300, srv(10, 0, 8080, "t1.test.")),
+                (300, srv(10, 0, 8081, "t2.test.")),
+            ],
+            ("dupes.test.", RecordType::SRV) => vec![
+                (300, srv(10, 20, 8080, "t1.test.")),
+                (300, srv(10, 10, 8080, "shared.test.")),
+            ],
+            ("broken.test.", RecordType::SRV) => vec![(300, srv(10, 1, 8080, "missing.test."))],
+            ("ttl.test.", RecordType::SRV) => vec![(300, srv(10, 1, 8080, "short.test."))],
+            ("missing.test.", _) => return None,
+            _ => vec![],
+        };
+        Some(records)
+    }
+
+    // Answers queries from the zone over UDP, shared by all tests
+    fn dns_server() -> SocketAddr {
+        static SERVER: OnceLock<SocketAddr> = OnceLock::new();
+        *SERVER.get_or_init(|| {
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind stand-in DNS server");
+            let address = socket.local_addr().unwrap();
+            thread::spawn(move || {
+                let mut buffer = [0u8; 512];
+                while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+                    let Ok(request) = Message::from_vec(&buffer[..len]) else { continue };
+                    let mut response = Message::new();
+                    response.set_id(request.id())
+                        .set_message_type(MessageType::Response)
+                        .set_op_code(request.op_code())
+                        .set_recursion_desired(request.recursion_desired())
+                        .set_recursion_available(true)
+                        .set_authoritative(true);
+                    for query in request.queries() {
+                        response.add_query(query.clone());
+                        match zone(&query.name().to_ascii(), query.query_type()) {
+                            Some(records) => {
+                                for (ttl, rdata) in records {
+                                    response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+                                }
+                            }
+                            None => {
+                                response.set_response_code(ResponseCode::NXDomain);
+                            }
+                        }
+                    }
+                    let _ = socket.send_to(&response.to_vec().unwrap(), peer);
+                }
+            });
+            address
+        })
+    }
+
+    fn stand_in_resolver() -> TokioAsyncResolver {
+        env::set_var("DISCOVERY_DNS_SERVER", dns_server().to_string());
+        resolver().expect("resolver for the stand-in server")
+    }
+
+    fn config(name: &str, kind: RecordKind) -> DnsConfig {
+        DnsConfig {
+            name: name.to_string(),
+            kind,
+            port: 8080,
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(30),
+        }
+    }
+
+    async fn srv_items(name: &str) -> Result<Vec<QueueItem>, String> {
+        resolve_srv(&stand_in_resolver(), &config(name, RecordKind::Srv)).await.map(|(items, _)| items)
+    }
+
+    fn backend(address: &str, score: f64) -> (String, f64) {
+        (address.to_string(), score)
+    }
+
+    fn backends(items: &[QueueItem]) -> Vec<(String, f64)> {
+        items.iter().map(|item| (item.dns_name.clone(), (item.score * 100.0).round() / 100.0)).collect()
+    }
+
+    #[tokio::test]
+    async fn address_records_become_deduplicated_backends() {
+        let (items, _) = resolve_addresses(&stand_in_resolver(), &config("web.test.", RecordKind::Address)).await.unwrap();
+        assert_eq!(backends(&items), vec![backend("10.0.0.1:8080", 100.0), backend("10.0.0.2:8080", 100.0)]);
+        assert_eq!(items[0].host.as_deref(), Some("10.0.0.1"));
+        assert_eq!(items[0].port, Some(8080));
+    }
+
+    #[tokio::test]
+    async fn ipv6_hosts_are_not_bracketed() {
+        let (items, _) = resolve_addresses(&stand_in_resolver(), &config("v6.test.", RecordKind::Address)).await.unwrap();
+        assert_eq!(items[0].dns_name, "[::1]:8080");
+        assert_eq!(items[0].host.as_deref(), Some("::1"));
+        assert_eq!(items[0].address("80"), "[::1]:8080");
+    }
+
+    #[tokio::test]
+    async fn only_the_lowest_srv_priority_is_served() {
+        let items = srv_items("srv.test.").await.unwrap();
+        // Weight 0 gets the share of weight 1
+        assert_eq!(backends(&items), vec![
+            backend("10.0.1.1:8080", 100.0),
+            backend("10.0.1.2:8081", 50.0),
+            backend("10.0.1.3:8082", 1.67),
+        ]);
+        let labels: Vec<(&str, &str)> = items[1].labels.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(labels, vec![("srv_priority", "10"), ("srv_target", "t2.test"), ("srv_weight", "30")]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_next_priority_if_no_target_resolves() {
+        let items = srv_items("fallback.test.").await.unwrap();
+        assert_eq!(backends(&items), vec![backend("10.0.2.1:9000", 100.0)]);
+        assert!(srv_items("broken.test.").await.is_err());
+        assert!(srv_items("missing.test.").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn all_zero_weights_are_equal() {
+        let items = srv_items("zero.test.").await.unwrap();
+        assert_eq!(backends(&items), vec![backend("10.0.1.1:8080", 100.0), backend("10.0.1.2:8081", 100.0)]);
+    }
+
+    #[tokio::test]
+    async fn targets_with_the_same_address_are_served_once() {
+        let items = srv_items("dupes.test.").await.unwrap();
+        assert_eq!(backends(&items), vec![backend("10.0.1.1:8080", 100.0)]);
+        assert_eq!(items[0].labels.get("srv_target").map(String::as_str), Some("t1.test"));
+    }
+
+    #[tokio::test]
+    async fn refresh_follows_the_shortest_ttl_within_bounds() {
+        let config = config("ttl.test.", RecordKind::Srv);
+        let (_, valid_until) = resolve_srv(&stand_in_resolver(), &config).await.unwrap();
+        // The target expires after 2 seconds, the SRV record after 300
+        assert!(valid_until <= Instant::now() + Duration::from_secs(2));
+        assert!(config.refresh_interval(valid_until) <= Duration::from_secs(2));
+
+        assert_eq!(config.refresh_interval(Instant::now() + Duration::from_secs(300)), Duration::from_secs(30));
+        assert_eq!(config.refresh_interval(Instant::now()), Duration::from_secs(1));
+    }
+
+    // Reads the config with only the given variables set
+    fn config_from(vars: &[(&str, &str)]) -> Result<DnsConfig, String> {
+        static ENV_LOCK: Mutex<()> = Mutex::new(());
+        let _guard = ENV_LOCK.lock().unwrap();
+        for name in ["DISCOVERY_DNS_NAME", "DISCOVERY_DNS_RECORD", "DISCOVERY_DNS_PORT", "TARGET_PORT",
+                     "DISCOVERY_DNS_MIN_INTERVAL", "DISCOVERY_DNS_MAX_INTERVAL"] {
+            env::remove_var(name);
+        }
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        DnsConfig::from_env()
+    }
+
+    #[test]
+    fn config_defaults_and_bounds() {
+        let config = config_from(&[("DISCOVERY_DNS_NAME", "web.test"), ("TARGET_PORT", "3000")]).unwrap();
+        assert!(matches!(config.kind, RecordKind::Address));
+        assert_eq!((config.port, config.min_interval, config.max_interval), (3000, Duration::from_secs(1), Duration::from_secs(30)));
+
+        let config = config_from(&[
+            ("DISCOVERY_DNS_NAME", "_http._tcp.web.test"),
+            ("DISCOVERY_DNS_RECORD", "srv"),
+            ("DISCOVERY_DNS_PORT", "8080"),
+            ("TARGET_PORT", "3000"),
+            ("DISCOVERY_DNS_MIN_INTERVAL", "0"),
+            ("DISCOVERY_DNS_MAX_INTERVAL", "0"),
+        ]).unwrap();
+        assert!(matches!(config.kind, RecordKind::Srv));
+        // The minimum is at least a second and the maximum never below it
+        assert_eq!((config.port, config.min_interval, config.max_interval), (8080, Duration::from_secs(1), Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert!(config_from(&[("TARGET_PORT", "3000")]).is_err());
+        assert!(config_from(&[("DISCOVERY_DNS_NAME", "web.test")]).is_err());
+        assert!(config_from(&[("DISCOVERY_DNS_NAME", "web.test"), ("TARGET_PORT", "3000"), ("DISCOVERY_DNS_RECORD", "mx")]).is_err());
+        assert!(config_from(&[("DISCOVERY_DNS_NAME", "web.test"), ("DISCOVERY_DNS_PORT", "70000")]).is_err());
+        assert!(config_from(&[("DISCOVERY_DNS_NAME", "web.test"), ("TARGET_PORT", "3000"), ("DISCOVERY_DNS_MAX_INTERVAL", "soon")]).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use dotenv::dotenv;
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::time::{interval, Duration};
+
+use crate::queue::{QueueItem, UtilizationCategory};
+use crate::state::SharedState;
+
+// Backend pools as read from DISCOVERY_FILE
+#[derive(Deserialize, Debug, Default)]
+struct PoolsFile {
+    #[serde(default)]
+    pools: BTreeMap<String, Pool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pool {
+    backends: Vec<Backend>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Backend {
+    // Host name or IP of the backend, IPv6 in brackets
+    address: String,
+    // Falls back to TARGET_PORT if not set
+    port: Option<u16>,
+    // http (default) or https
+    scheme: Option<String>,
+    zone: Option<String>,
+    // Static weight in the range of the agent scores (0-100)
+    #[serde(default = "default_weight")]
+    weight: f64,
+    // Draining backends are kept out of the rotation without removing them from the file
+    #[serde(default)]
+    drain: bool,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+fn default_weight() -> f64 {
+    100.0
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+struct FileConfig {
+    path: PathBuf,
+    format: Format,
+    // Only the backends of this pool, all pools if not set
+    pool: Option<String>,
+    interval: Duration,
+}
+
+impl FileConfig {
+    fn from_env() -> Result<Self, String> {
+        dotenv().ok();
+        let path = env::var("DISCOVERY_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .ok_or("DISCOVERY_FILE must be set for the file discovery")?;
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            _ => return Err(format!("DISCOVERY_FILE must end in .toml, .yaml or .yml, got {}", path.display())),
+        };
+        let pool = env::var("DISCOVERY_POOL").ok().filter(|pool| !pool.is_empty());
+        let interval = Duration::from_secs(
+            env::var("DISCOVERY_FILE_INTERVAL")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse::<u64>()
+                .map_err(|_| "DISCOVERY_FILE_INTERVAL must be a valid u64")?
+                .max(1)
+        );
+
+        Ok(FileConfig { path, format, pool, interval })
+    }
+}
+
+// Serves the backends of a static file, so the balancer runs without a deployment agent.
+// The file is checked every DISCOVERY_FILE_INTERVAL seconds and reloaded when it changed.
+pub async fn watch_file(shared_state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let config = FileConfig::from_env()?;
+    info!("Loading backends from {} every {:?}", config.path.display(), config.interval);
+    watch(&config, shared_state).await;
+    Ok(())
+}
+
+async fn watch(config: &FileConfig, shared_state: SharedState) {
+
+    let mut last_modified: Option<SystemTime> = None;
+    let mut loaded = false;
+    let mut interval = interval(config.interval);
+    loop {
+        interval.tick().await;
+
+        let modified = match tokio::fs::metadata(&config.path).await.and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                // The last pools stay in use and become stale
+                error!("Failed to read {}: {}", config.path.display(), e);
+                continue;
+            }
+        };
+        if last_modified == Some(modified) {
+            // The file still holds the served pools
+            if loaded {
+                shared_state.write().await.confirm();
+            }
+            continue;
+        }
+
+        // Invalid files are reported once per change, the last pools stay in use and become stale
+        last_modified = Some(modified);
+        loaded = match load_backends(config).await {
+            Ok(items) => {
+                info!("Loaded {} backend(s) from {}", items.len(), config.path.display());
+                shared_state.write().await.replace(items);
+                true
+            }
+            Err(e) => {
+                error!("Ignoring invalid backend file {}: {}", config.path.display(), e);
+                false
+            }
+        };
+    }
+}
+
+async fn load_backends(config: &FileConfig) -> Result<Vec<QueueItem>, String> {
+    let content = tokio::fs::read_to_string(&config.path).await.map_err(|e| e.to_string())?;
+    let file = parse(&content, config.format)?;
+
+    let pools: Vec<(&String, &Pool)> = match &config.pool {
+        Some(name) => match file.pools.get_key_value(name) {
+            Some(pool) => vec![pool],
+            None => return Err(format!("pool {} not found", name)),
+        },
+        None => file.pools.iter().collect(),
+    };
+
+    let mut items: Vec<QueueItem> = Vec::new();
+    for (name, pool) in pools {
+        for backend in &pool.backends {
+            let item = queue_item(name, backend)?;
+            if items.iter().any(|existing| existing.dns_name == item.dns_name) {
+                warn!("Backend {} is listed more than once, using the first entry", item.dns_name);
+                continue;
+            }
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
+
+fn parse(content: &str, format: Format) -> Result<PoolsFile, String> {
+    match format {
+        Format::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+    }
+}
+
+fn queue_item(pool: &str, backend: &Backend) -> Result<QueueItem, String> {
+    if backend.address.is_empty() {
+        return Err(format!("backend without address in pool {}", pool));
+    }
+    if !(0.0..=100.0).contains(&backend.weight) {
+        return Err(format!("weight of {} must be between 0 and 100, got {}", backend.address, backend.weight));
+    }
+
+    if let Some(scheme) = backend.scheme.as_deref().filter(|scheme| !matches!(*scheme, "http" | "https")) {
+        return Err(format!("scheme of {} must be http or https, got {}", backend.address, scheme));
+    }
+
+    let dns_name = match backend.port {
+        Some(port) => format!("{}:{}", backend.address, port),
+        None => backend.address.clone(),
+    };
+    let category = if backend.drain { UtilizationCategory::Sundown } else { UtilizationCategory::Low };
+    let mut item = QueueItem::new(dns_name, backend.weight, category);
+    item.scheme = backend.scheme.clone();
+    item.host = Some(backend.address.trim_start_matches('[').trim_end_matches(']').to_string());
+    item.port = backend.port;
+    item.zone = backend.zone.clone();
+    item.labels = backend.metadata.clone();
+    item.labels.insert("pool".to_string(), pool.to_string());
+    Ok(item)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use tokio::time::{sleep, timeout};
+    use super::*;
+    use crate::state::QueueState;
+
+    const TOML: &str = r#"
+[[pools.api.backends]]
+address = "10.0.0.1"
+port = 8080
+zone = "eu-1"
+weight = 50
+metadata = { version = "2" }
+
+[[pools.api.backends]]
+address = "[::1]"
+port = 8081
+scheme = "https"
+drain = true
+
+# Listed twice, the first entry is used
+[[pools.api.backends]]
+address = "10.0.0.1"
+port = 8080
+weight = 10
+
+[[pools.web.backends]]
+address = "web-1.internal"
+"#;
+
+    const YAML: &str = r#"
+pools:
+  api:
+    backends:
+      - address: 10.0.0.1
+        port: 8080
+        zone: eu-1
+        weight: 50
+        metadata:
+          version: "2"
+      - address: "[::1]"
+        port: 8081
+        scheme: https
+        drain: true
+      # Listed twice, the first entry is used
+      - address: 10.0.0.1
+        port: 8080
+        weight: 10
+  web:
+    backends:
+      - address: web-1.internal
+"#;
+
+    // Writes the content to a file of the test in the temp directory
+    fn pools_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("balancer-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).expect("Failed to write pools file");
+        path
+    }
+
+    fn config(path: PathBuf, pool: Option<&str>) -> FileConfig {
+        let format = if path.extension().is_some_and(|extension| extension == "toml") { Format::Toml } else { Format::Yaml };
+        FileConfig { path, format, pool: pool.map(str::to_string), interval: Duration::from_millis(10) }
+    }
+
+    async fn load(name: &str, content: &str, pool: Option<&str>) -> Result<Vec<QueueItem>, String> {
+        let path = pools_file(name, content);
+        let result = load_backends(&config(path.clone(), pool)).await;
+        let _ = std::fs::remove_file(path);
+        result
+    }
+
+    #[tokio::test]
+    async fn toml_and_yaml_describe_the_same_pools() {
+        let from_toml = load("same.toml", TOML, None).await.unwrap();
+        let from_yaml = load("same.yaml", YAML, None).await.unwrap();
+        assert_eq!(from_toml, from_yaml);
+
+        let names: Vec<&str> = from_toml.iter().map(|item| item.dns_name.as_str()).collect();
+        assert_eq!(names, vec!["10.0.0.1:8080", "[::1]:8081", "web-1.internal"]);
+    }
+
+    #[tokio::test]
+    async fn backends_keep_their_attributes() {
+        let items = load("attributes.toml", TOML, None).await.unwrap();
+
+        // The first entry of a duplicate wins
+        let first = &items[0];
+        assert_eq!((first.score, first.utilization_category), (50.0, UtilizationCategory::Low));
+        assert_eq!((first.host.as_deref(), first.port, first.zone.as_deref()), (Some("10.0.0.1"), Some(8080), Some("eu-1")));
+        assert_eq!(first.labels, BTreeMap::from([
+            ("pool".to_string(), "api".to_string()),
+            ("version".to_string(), "2".to_string()),
+        ]));
+
+        let draining = &items[1];
+        assert_eq!(draining.utilization_category, UtilizationCategory::Sundown);
+        assert_eq!((draining.scheme(), draining.host.as_deref()), ("https", Some("::1")));
+        assert_eq!(draining.address("80"), "[::1]:8081");
+
+        // Without a port the shared target port is used
+        let web = &items[2];
+        assert_eq!((web.score, web.port), (100.0, None));
+        assert_eq!(web.address("3000"), "web-1.internal:3000");
+    }
+
+    #[tokio::test]
+    async fn only_the_selected_pool_is_served() {
+        let items = load("pool.yaml", YAML, Some("web")).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].labels.get("pool").map(String::as_str), Some("web"));
+
+        assert_eq!(load("unknown-pool.yaml", YAML, Some("admin")).await, Err("pool admin not found".to_string()));
+    }
+
+    #[tokio::test]
+    async fn invalid_backends_are_rejected() {
+        let backend = |fields: &str| format!("[[pools.api.backends]]\n{}\n", fields);
+        for fields in [
+            "address = \"10.0.0.1\"\nweight = 101",
+            "address = \"10.0.0.1\"\nweight = -1",
+            "address = \"10.0.0.1\"\nscheme = \"ftp\"",
+            "address = \"\"",
+            "port = 8080",
+        ] {
+            assert!(load("invalid.toml", &backend(fields), None).await.is_err(), "accepted {}", fields);
+        }
+        assert!(load("invalid.yaml", "pools: [", None).await.is_err());
+
+        let edge = backend("address = \"10.0.0.1\"\nweight = 0\nscheme = \"http\"");
+        assert_eq!(load("edge.toml", &edge, None).await.unwrap()[0].score, 0.0);
+    }
+
+    async fn served(state: &SharedState) -> Option<Vec<String>> {
+        state.read().await.items().map(|items| items.iter().map(|item| item.dns_name.clone()).collect())
+    }
+
+    async fn wait_for(state: &SharedState, expected: Vec<&str>) {
+        let expected: Vec<String> = expected.into_iter().map(str::to_string).collect();
+        timeout(Duration::from_secs(5), async {
+            while served(state).await.as_ref() != Some(&expected) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("never served {:?}", expected));
+    }
+
+    #[tokio::test]
+    async fn invalid_rewrites_keep_the_last_pools() {
+        let path = pools_file("watch.toml", TOML);
+        let state: SharedState = Arc::new(RwLock::new(QueueState::default()));
+        let watcher = tokio::spawn({
+            let config = config(path.clone(), Some("api"));
+            let state = state.clone();
+            async move { watch(&config, state).await }
+        });
+        wait_for(&state, vec!["10.0.0.1:8080", "[::1]:8081"]).await;
+
+        std::fs::write(&path, "[[pools.api.backends]]\nweight = 5\n").unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(served(&state).await, Some(vec!["10.0.0.1:8080".to_string(), "[::1]:8081".to_string()]));
+
+        std::fs::write(&path, "[[pools.api.backends]]\naddress = \"10.0.0.9\"\n").unwrap();
+        wait_for(&state, vec!["10.0.0.9"]).await;
+        watcher.abort();
+        let _ = std::fs::remove_file(path);
+    }
+}
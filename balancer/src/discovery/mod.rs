@@ -0,0 +1,72 @@
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use dotenv::dotenv;
+use tokio::sync::mpsc;
+use protocol::BalancerMessage;
+
+use crate::metrics::Metrics;
+use crate::socket::connect_socket;
+use crate::state::SharedState;
+
+mod dns;
+mod file;
+mod redis;
+
+// Where the balancer learns about the queue of backends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Discovery {
+    // Connection to the deployment agent
+    WebSocket,
+    // Snapshots published by the deployment agent on a Redis channel
+    Redis,
+    // Static backend pools of a TOML or YAML file, without a deployment agent
+    File,
+    // A/AAAA or SRV records of a DNS name, without a deployment agent
+    Dns,
+}
+
+impl fmt::Display for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discovery::WebSocket => write!(f, "websocket"),
+            Discovery::Redis => write!(f, "redis"),
+            Discovery::File => write!(f, "file"),
+            Discovery::Dns => write!(f, "dns"),
+        }
+    }
+}
+
+impl Discovery {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        match env::var("DISCOVERY").unwrap_or_else(|_| "websocket".to_string()).as_str() {
+            "websocket" => Discovery::WebSocket,
+            "redis" => Discovery::Redis,
+            "file" => Discovery::File,
+            "dns" => Discovery::Dns,
+            other => panic!("DISCOVERY must be websocket, redis, file or dns, got {}", other),
+        }
+    }
+}
+
+// Keeps the shared state up to date and forwards the events for the deployment agent
+pub async fn run_discovery(discovery: Discovery, shared_state: SharedState, event_receiver: mpsc::UnboundedReceiver<BalancerMessage>, metrics: Arc<Metrics>) -> Result<(), Box<dyn std::error::Error>> {
+    match discovery {
+        Discovery::WebSocket => connect_socket(shared_state, event_receiver, metrics).await,
+        Discovery::Redis => redis::subscribe_queue(shared_state, event_receiver, metrics).await,
+        Discovery::File => {
+            // There is no deployment agent to receive the events
+            tokio::spawn(discard_events(event_receiver));
+            file::watch_file(shared_state).await
+        }
+        Discovery::Dns => {
+            tokio::spawn(discard_events(event_receiver));
+            dns::resolve_backends(shared_state).await
+        }
+    }
+}
+
+async fn discard_events(mut event_receiver: mpsc::UnboundedReceiver<BalancerMessage>) {
+    while event_receiver.recv().await.is_some() {}
+}
@@ -0,0 +1,283 @@
+use std::env;
+use std::sync::Arc;
+use dotenv::dotenv;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use protocol::{decode, encode, AgentMessage, BalancerMessage, PublishedEvent};
+use redis::AsyncCommands;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+
+use crate::metrics::Metrics;
+use crate::state::SharedState;
+
+struct RedisConfig {
+    url: String,
+    // Key and channel of the queue snapshots
+    channel: String,
+    // Channel of the events for the deployment agent
+    events_channel: String,
+    // Tells the deployment agent which balancer an event came from
+    balancer: String,
+}
+
+impl RedisConfig {
+    fn from_env() -> Self {
+        dotenv().ok();
+        let host = env::var("REDIS_HOST").expect("REDIS_HOST must be set");
+        let port = env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
+        let channel = env::var("QUEUE_CHANNEL").unwrap_or_else(|_| "queue-updates".to_string());
+        RedisConfig {
+            url: format!("redis://{}:{}/", host, port),
+            events_channel: format!("{}:events", channel),
+            channel,
+            balancer: env::var("HOSTNAME").unwrap_or_else(|_| "balancer".to_string()),
+        }
+    }
+}
+
+// Follows the queue snapshots the deployment agent publishes, without a connection to the agent itself
+pub async fn subscribe_queue(shared_state: SharedState, mut event_receiver: mpsc::UnboundedReceiver<BalancerMessage>, metrics: Arc<Metrics>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = RedisConfig::from_env();
+    let client = redis::Client::open(config.url.as_str())?;
+
+    let mut retry_delay = Duration::from_secs(1);
+    let max_retry_delay = Duration::from_secs(60);
+
+    loop {
+        info!("Subscribing to Redis channel {} at {}", config.channel, config.url);
+        let result = run_subscription(&client, &config, &shared_state, &mut event_receiver).await;
+        metrics.record_reconnect("redis");
+        match result {
+            Ok(()) => {
+                warn!("Redis subscription ended. Resubscribing...");
+                retry_delay = Duration::from_secs(1);
+            }
+            Err(e) => {
+                error!("Redis subscription failed: {}", e);
+                info!("Retrying in {} seconds...", retry_delay.as_secs());
+                sleep(retry_delay).await;
+                retry_delay = std::cmp::min(retry_delay * 2, max_retry_delay);
+            }
+        }
+    }
+}
+
+async fn run_subscription(client: &redis::Client, config: &RedisConfig, shared_state: &SharedState, event_receiver: &mut mpsc::UnboundedReceiver<BalancerMessage>) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(&config.channel).await?;
+    let mut conn = client.get_multiplexed_async_connection().await?;
+
+    // Subscribed first, so no snapshot is lost between reading the current one and the next message
+    let current: Option<String> = conn.get(&config.channel).await?;
+    if let Some(payload) = current {
+        apply_snapshot(&payload, shared_state).await;
+    }
+
+    let mut messages = pubsub.on_message();
+    loop {
+        tokio::select! {
+            msg = messages.next() => match msg {
+                Some(msg) => {
+                    let payload: String = msg.get_payload()?;
+                    apply_snapshot(&payload, shared_state).await;
+                }
+                None => return Ok(()),
+            },
+            // Outgoing events for the deployment agent
+            Some(message) = event_receiver.recv() => {
+                let event = PublishedEvent { balancer: config.balancer.clone(), message };
+                let _: () = conn.publish(&config.events_channel, encode(&event)).await?;
+            }
+        }
+    }
+}
+
+async fn apply_snapshot(payload: &str, shared_state: &SharedState) {
+    match decode::<AgentMessage>(payload) {
+        Ok(AgentMessage::Snapshot { seq, items }) => {
+            shared_state.write().await.replace(items);
+            info!("Updated queue state from published snapshot {}", seq);
+        }
+        Ok(other) => warn!("Ignoring unexpected message on the queue channel: {:?}", other),
+        Err(e) => error!("Failed to parse published queue: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Mutex;
+    use std::thread;
+    use protocol::{QueueItem, UtilizationCategory};
+    use redis::Commands;
+    use tokio::sync::RwLock;
+    use tokio::time::timeout;
+    use super::*;
+    use crate::state::QueueState;
+
+    #[derive(Default)]
+    struct Store {
+        values: HashMap<String, String>,
+        // Channel and connection of every subscription
+        subscribers: Vec<(String, TcpStream)>,
+    }
+
+    fn bulk(value: &str) -> String {
+        format!("${}\r\n{}\r\n", value.len(), value)
+    }
+
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|read| *read > 0)?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    // Redis with GET, SET, PUBLISH and SUBSCRIBE on a local port
+    fn fake_redis() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind fake Redis");
+        let port = listener.local_addr().unwrap().port();
+        let store = Arc::new(Mutex::new(Store::default()));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let mut writer = stream.try_clone().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    while let Some(command) = read_command(&mut reader) {
+                        let mut store = store.lock().unwrap();
+                        let reply = match (command[0].to_uppercase().as_str(), &command[1..]) {
+                            ("GET", [key]) => store.values.get(key).map_or("$-1\r\n".to_string(), |value| bulk(value)),
+                            ("SET", [key, value]) => {
+                                store.values.insert(key.clone(), value.clone());
+                                "+OK\r\n".to_string()
+                            }
+                            ("SUBSCRIBE", [channel]) => {
+                                store.subscribers.push((channel.clone(), writer.try_clone().unwrap()));
+                                format!("*3\r\n{}{}:1\r\n", bulk("subscribe"), bulk(channel))
+                            }
+                            ("PUBLISH", [channel, message]) => {
+                                let push = format!("*3\r\n{}{}{}", bulk("message"), bulk(channel), bulk(message));
+                                let mut received = 0;
+                                for (_, subscriber) in store.subscribers.iter_mut().filter(|(subscribed, _)| subscribed == channel) {
+                                    if subscriber.write_all(push.as_bytes()).is_ok() {
+                                        received += 1;
+                                    }
+                                }
+                                format!(":{}\r\n", received)
+                            }
+                            ("PING", _) => "+PONG\r\n".to_string(),
+                            _ => "+OK\r\n".to_string(),
+                        };
+                        if writer.write_all(reply.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    fn snapshot(seq: u64, dns_names: &[&str]) -> String {
+        encode(&AgentMessage::Snapshot {
+            seq,
+            items: dns_names.iter().map(|name| QueueItem::new(name.to_string(), 100.0, UtilizationCategory::Low)).collect(),
+        })
+    }
+
+    async fn served(state: &SharedState) -> Option<Vec<String>> {
+        state.read().await.items().map(|items| items.iter().map(|item| item.dns_name.clone()).collect())
+    }
+
+    async fn wait_for(state: &SharedState, expected: &[&str]) {
+        let expected: Vec<String> = expected.iter().map(|name| name.to_string()).collect();
+        timeout(Duration::from_secs(5), async {
+            while served(state).await.as_ref() != Some(&expected) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("never served {:?}", expected));
+    }
+
+    #[tokio::test]
+    async fn late_subscribers_start_with_the_stored_snapshot() {
+        let port = fake_redis();
+        let config = RedisConfig {
+            url: format!("redis://127.0.0.1:{}/", port),
+            channel: "queue-updates".to_string(),
+            events_channel: "queue-updates:events".to_string(),
+            balancer: "balancer-1".to_string(),
+        };
+        let client = redis::Client::open(config.url.as_str()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+        // Published before the balancer subscribed
+        let _: () = conn.set("queue-updates", snapshot(7, &["worker-a"])).unwrap();
+
+        let state: SharedState = Arc::new(RwLock::new(QueueState::default()));
+        let (events, mut event_receiver) = mpsc::unbounded_channel();
+        let subscription = tokio::spawn({
+            let state = state.clone();
+            async move { run_subscription(&client, &config, &state, &mut event_receiver).await }
+        });
+        wait_for(&state, &["worker-a"]).await;
+
+        let _: () = conn.publish("queue-updates", snapshot(8, &["worker-b", "worker-c"])).unwrap();
+        wait_for(&state, &["worker-b", "worker-c"]).await;
+
+        // Events are published for the deployment agent with the name of the balancer
+        let mut listener = redis::Client::open(format!("redis://127.0.0.1:{}/", port)).unwrap().get_connection().unwrap();
+        let received = tokio::task::spawn_blocking(move || {
+            let mut pubsub = listener.as_pubsub();
+            pubsub.subscribe("queue-updates:events").unwrap();
+            pubsub.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            loop {
+                let payload: String = pubsub.get_message().unwrap().get_payload().unwrap();
+                if payload != "ping" {
+                    return payload;
+                }
+            }
+        });
+        // Pings until the listener subscribed, so the event is not lost
+        while conn.publish::<_, _, usize>("queue-updates:events", "ping").unwrap() == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+        events.send(BalancerMessage::Wake).unwrap();
+        let payload = received.await.unwrap();
+        assert_eq!(decode::<PublishedEvent>(&payload).unwrap(), PublishedEvent {
+            balancer: "balancer-1".to_string(),
+            message: BalancerMessage::Wake,
+        });
+        subscription.abort();
+    }
+
+    #[tokio::test]
+    async fn only_snapshots_change_the_queue() {
+        let state: SharedState = Arc::new(RwLock::new(QueueState::default()));
+        for payload in [
+            encode(&AgentMessage::Heartbeat { seq: 3 }),
+            encode(&AgentMessage::Delta { seq: 4, delta: Default::default() }),
+            "not a message".to_string(),
+        ] {
+            apply_snapshot(&payload, &state).await;
+        }
+        // Neither served nor confirmed
+        assert!(state.read().await.items().is_none());
+        assert!(state.read().await.age().is_none());
+
+        apply_snapshot(&snapshot(5, &["worker-a"]), &state).await;
+        assert_eq!(served(&state).await, Some(vec!["worker-a".to_string()]));
+    }
+}
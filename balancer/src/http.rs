@@ -1,4 +1,6 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use hyper::service::{make_service_fn, service_fn};
@@ -6,34 +8,50 @@ use hyper::{Body, Request, Response, Server, Uri};
 use tokio::sync::{RwLock, Mutex};
 use rand::distributions::{WeightedIndex, Distribution};
 use rand::Rng;
-use tokio::time::{interval, sleep, timeout};
+use tokio::time::{interval, sleep};
 
-use crate::queue::QueueItem;
-use crate::socket::SharedState;
+use crate::queue::{QueueItem, UtilizationCategory};
+use crate::state::SharedState;
 use crate::client::UnboundedClient;
 use crate::cache::SimpleCache;
+use crate::coldstart::{self, ColdStart};
+use crate::degraded::{PassiveHealth, StalePolicy, Staleness};
+use crate::locality::Locality;
+use crate::metrics::{CacheResult, Metrics, Route};
+use crate::slowstart::SlowStart;
+use crate::telemetry::Telemetry;
 
 struct WeightedQueueItem {
     item: QueueItem,
     weight: f64,
+    added_at: Instant,
 }
 
 struct DynamicWeightedBalancer {
     items: Arc<RwLock<Vec<WeightedQueueItem>>>,
     last_update: Arc<Mutex<Instant>>,
     update_interval: Duration,
+    slow_start: SlowStart,
+    staleness: Staleness,
+    // Set while the queue is older than staleness.after
+    stale: AtomicBool,
+    passive_health: PassiveHealth,
+    locality: Arc<Locality>,
 }
 
 impl DynamicWeightedBalancer {
-    fn new(queue_items: Vec<QueueItem>) -> Self {
+    fn new(queue_items: Vec<QueueItem>, slow_start: SlowStart, staleness: Staleness, passive_health: PassiveHealth, locality: Arc<Locality>) -> Self {
         println!("Initializing DynamicWeightedBalancer");
+        // Initial backends are considered warm
+        let warm_since = Instant::now().checked_sub(slow_start.window).unwrap_or_else(Instant::now);
         let items = Arc::new(RwLock::new(
             queue_items
                 .into_iter()
-                .filter(|item| item.utilization_category != "SUNDOWN")
+                .filter(|item| item.utilization_category != UtilizationCategory::Sundown)
                 .map(|item| WeightedQueueItem {
-                    weight: Self::calculate_weight(item.score),
+                    weight: Self::calculate_weight(item.score) * slow_start.factor(warm_since.elapsed()),
                     item,
+                    added_at: warm_since,
                 })
                 .collect()
         ));
@@ -42,12 +60,41 @@ impl DynamicWeightedBalancer {
             items,
             last_update: Arc::new(Mutex::new(Instant::now())),
             update_interval: Duration::from_secs(10),
+            slow_start,
+            staleness,
+            stale: AtomicBool::new(false),
+            passive_health,
+            locality,
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    fn set_stale(&self, stale: bool) {
+        if self.stale.swap(stale, Ordering::Relaxed) != stale {
+            if stale {
+                println!("Warning: Queue is stale (restored or not confirmed within {:?}), switching to the {} policy",
+                         self.staleness.after, self.staleness.policy);
+            } else {
+                println!("Queue is current again, using the scores of the deployment agent");
+            }
+        }
+    }
+
+    // Scores of a stale queue are outdated, the equal and passive policies ignore them
+    fn base_weight(&self, score: f64) -> f64 {
+        if self.is_stale() && self.staleness.policy != StalePolicy::Keep {
+            1.0
+        } else {
+            Self::calculate_weight(score)
         }
     }
 
     // Weight based on the score
     fn calculate_weight(score: f64) -> f64 {
-        if score < 0.0 || score > 100.0 {
+        if !(0.0..=100.0).contains(&score) {
             println!("Warning: Invalid score: {}. Using default weight.", score);
             1.0 // default weight
         } else {
@@ -60,9 +107,9 @@ impl DynamicWeightedBalancer {
         if last_update.elapsed() >= self.update_interval {
             println!("Updating weights");
             let mut items = self.items.write().await;
-            items.retain(|item| item.item.utilization_category != "SUNDOWN");
+            items.retain(|item| item.item.utilization_category != UtilizationCategory::Sundown);
             for item in items.iter_mut() {
-                item.weight = Self::calculate_weight(item.item.score);
+                item.weight = self.base_weight(item.item.score) * self.slow_start.factor(item.added_at.elapsed());
                 if item.weight == 0.0 {
                     println!("Warning: Item {} has a weight of 0 (score: {})", item.item.dns_name, item.item.score);
                 }
@@ -72,6 +119,11 @@ impl DynamicWeightedBalancer {
         }
     }
 
+    async fn report_weights(&self, metrics: &Metrics) {
+        let items = self.items.read().await;
+        metrics.set_backend_weights(items.iter().map(|item| (item.item.dns_name.as_str(), item.weight)));
+    }
+
     // Chooses next QueueItem based on its weight
     async fn next(&self) -> Option<QueueItem> {
         let items = self.items.read().await;
@@ -80,48 +132,144 @@ impl DynamicWeightedBalancer {
             return None;
         }
 
-        let weights: Vec<f64> = items.iter().map(|item| item.weight.max(f64::EPSILON)).collect();
-        if weights.iter().all(|&w| w == 0.0) {
-            println!("Warning: All weights are zero. Selecting a random item.");
-            let index = rand::thread_rng().gen_range(0..items.len());
-            return Some(items[index].item.clone());
+        // With the passive policy, ejected backends are skipped unless all of them are ejected
+        let ejected = if self.is_stale() && self.staleness.policy == StalePolicy::Passive {
+            self.passive_health.ejected().await
+        } else {
+            HashSet::new()
+        };
+        let mut candidates: Vec<&WeightedQueueItem> = items.iter().filter(|item| !ejected.contains(&item.item.dns_name)).collect();
+        if candidates.is_empty() {
+            candidates = items.iter().collect();
+        }
+
+        let chosen = if self.locality.is_enabled() {
+            self.choose_by_zone(&candidates).await
+        } else {
+            Self::choose(&candidates)
+        };
+        self.locality.record(&chosen);
+        Some(chosen)
+    }
+
+    // Prefers the backends of the own zone as long as their capacity reaches the spillover threshold
+    async fn choose_by_zone(&self, candidates: &[&WeightedQueueItem]) -> QueueItem {
+        let (local, remote): (Vec<&WeightedQueueItem>, Vec<&WeightedQueueItem>) =
+            candidates.iter().copied().partition(|item| self.locality.is_local(&item.item));
+        if local.is_empty() || remote.is_empty() {
+            return Self::choose(candidates);
+        }
+
+        // Ejected backends and backends without weight do not count as capacity
+        let ejected = self.passive_health.ejected().await;
+        let capacity = self.locality.capacity_of(
+            local.iter()
+                .filter(|item| item.weight > 0.0 && !ejected.contains(&item.item.dns_name))
+                .map(|item| &item.item)
+        );
+        if rand::thread_rng().gen_bool(self.locality.local_share(capacity)) {
+            Self::choose(&local)
+        } else {
+            Self::choose(&remote)
         }
+    }
 
+    // Chooses one of the (non-empty) candidates based on its weight, backends without weight
+    // keep a minimal one so that a queue of zero weights is still balanced evenly
+    fn choose(candidates: &[&WeightedQueueItem]) -> QueueItem {
+        let weights: Vec<f64> = candidates.iter().map(|item| item.weight.max(f64::EPSILON)).collect();
         match WeightedIndex::new(&weights) {
             Ok(dist) => {
                 let mut rng = rand::thread_rng();
                 let chosen_index = dist.sample(&mut rng);
-                 Some(items[chosen_index].item.clone())
+                candidates[chosen_index].item.clone()
             },
             Err(e) => {
                 println!("Error: Failed to create WeightedIndex: {}. Selecting a random item.", e);
-                let index = rand::thread_rng().gen_range(0..items.len());
-                Some(items[index].item.clone())
+                let index = rand::thread_rng().gen_range(0..candidates.len());
+                candidates[index].item.clone()
             }
         }
     }
 
-    // Updates the queue with new QueueItems
+    // Updates the queue with new QueueItems, new backends start their slow-start window
     async fn set_queue_items(&self, queue_items: Vec<QueueItem>) {
         let mut items = self.items.write().await;
+        let now = Instant::now();
+        // Backends of the first queue (or after a scale-to-zero) have no traffic to share
+        let warm_since = if items.is_empty() {
+            now.checked_sub(self.slow_start.window).unwrap_or(now)
+        } else {
+            now
+        };
+        let added_at: HashMap<String, Instant> = items
+            .iter()
+            .map(|item| (item.item.dns_name.clone(), item.added_at))
+            .collect();
+
         *items = queue_items
             .into_iter()
-            .filter(|item| item.utilization_category != "SUNDOWN")
-            .map(|item| WeightedQueueItem {
-                weight: Self::calculate_weight(item.score),
-                item,
+            .filter(|item| item.utilization_category != UtilizationCategory::Sundown)
+            .map(|item| {
+                let added_at = added_at.get(&item.dns_name).copied().unwrap_or(warm_since);
+                WeightedQueueItem {
+                    weight: self.base_weight(item.score) * self.slow_start.factor(added_at.elapsed()),
+                    item,
+                    added_at,
+                }
             })
             .collect();
+
+        let dns_names: HashSet<&str> = items.iter().map(|item| item.item.dns_name.as_str()).collect();
+        self.passive_health.retain(&dns_names).await;
     }
 
     async fn print_queue(&self) {
         let items = self.items.read().await;
         println!("Current Queue in Balancer:");
+        if self.slow_start.is_enabled() {
+            println!("  Slow start: {:?} window, {} ramp from {:.0}% weight",
+                     self.slow_start.window, self.slow_start.mode, self.slow_start.floor * 100.0);
+        }
+        if self.is_stale() {
+            println!("  Stale queue, {} policy", self.staleness.policy);
+        }
+        if let Some(zone) = &self.locality.zone {
+            let (local, cross_zone) = self.locality.requests();
+            println!("  Zone {} (spillover below {} {}): {} local, {} cross-zone requests",
+                     zone, self.locality.threshold, self.locality.capacity, local, cross_zone);
+        }
         for (index, weighted_item) in items.iter().enumerate() {
             let item = &weighted_item.item;
-            println!("  {}. {} (Score: {:.2}, Category: {}, Weight: {:.2})",
-                     index + 1, item.dns_name, item.score, item.utilization_category, weighted_item.weight);
+            let progress = self.slow_start.progress(weighted_item.added_at.elapsed());
+            if progress < 1.0 {
+                println!("  {}. {} (Score: {:.2}, Category: {}, Weight: {:.2}, Slow start: {:.0}%)",
+                         index + 1, item.dns_name, item.score, item.utilization_category, weighted_item.weight, progress * 100.0);
+            } else {
+                println!("  {}. {} (Score: {:.2}, Category: {}, Weight: {:.2})",
+                         index + 1, item.dns_name, item.score, item.utilization_category, weighted_item.weight);
+            }
+        }
+    }
+}
+
+// Buffers a request until a woken-up backend passes its readiness check
+async fn wait_for_backend(balancer: &DynamicWeightedBalancer, cold_start: &ColdStart, port: &str) -> Option<QueueItem> {
+    let requested_at = cold_start.request_wake().await;
+    let deadline = Instant::now() + cold_start.timeout();
+
+    loop {
+        if let Some(item) = balancer.next().await {
+            if coldstart::is_ready(&item, port).await {
+                cold_start.complete(requested_at).await;
+                return Some(item);
+            }
+        }
+        if Instant::now() >= deadline {
+            println!("Error: Cold start timed out after {:?}", cold_start.timeout());
+            return None;
         }
+        sleep(Duration::from_millis(100)).await;
     }
 }
 
@@ -136,24 +284,34 @@ async fn handle_request(
     balancer: Arc<DynamicWeightedBalancer>,
     shared_client: Arc<UnboundedClient>,
     cache: Arc<SimpleCache>,
+    cold_start: Arc<ColdStart>,
+    telemetry: Arc<Telemetry>,
+    metrics: Arc<Metrics>,
 ) -> Result<Response<Body>, hyper::Error> {
-    let start_time = Instant::now();
     let path = req.uri().path().to_string();
     let method = req.method().clone();
     let uri = req.uri().clone();
     let is_static = is_static_resource(&path);
+    let route = if is_static { Route::Static } else { Route::Dynamic };
 
     if is_static && method == hyper::Method::GET {
         let cache_key = uri.to_string();
         if let Some(cached_response) = cache.get(&cache_key).await {
+            metrics.record_cache(CacheResult::Hit, cached_response.len());
+            metrics.record_request(200, route, "cache");
             return Ok(Response::new(Body::from(cached_response)));
         }
+        metrics.record_cache(CacheResult::Miss, 0);
     }
 
-    if let Some(item) = balancer.next().await {
-        let port = env::var("TARGET_PORT").expect("TARGET_PORT must be set");
+    let port = env::var("TARGET_PORT").expect("TARGET_PORT must be set");
+    let item = match balancer.next().await {
+        Some(item) => Some(item),
+        None => wait_for_backend(&balancer, &cold_start, &port).await,
+    };
 
-        let uri_string = format!("http://{}:{}{}", item.dns_name, port, uri.path());
+    if let Some(item) = item {
+        let uri_string = format!("{}://{}{}", item.scheme(), item.address(&port), uri.path());
         let new_uri: Uri = uri_string.parse().unwrap();
 
         let req = Request::builder()
@@ -162,7 +320,18 @@ async fn handle_request(
             .body(Body::empty())
             .unwrap();
 
-        match shared_client.request(req).await {
+        let start_time = Instant::now();
+        telemetry.begin(&item.dns_name).await;
+        metrics.begin_upstream(&item.dns_name);
+        let result = shared_client.request(req).await;
+        let is_error = result.as_ref().map_or(true, |response| response.status().is_server_error());
+        telemetry.finish(&item.dns_name, start_time.elapsed(), is_error).await;
+        // Counted before the request leaves the in-flight gauge, the series of a gone backend are removed after that
+        metrics.record_request(result.as_ref().map_or(503, |response| response.status().as_u16()), route, &item.dns_name);
+        metrics.finish_upstream(&item.dns_name, start_time.elapsed(), result.as_ref().err());
+        balancer.passive_health.record(&item.dns_name, !is_error).await;
+
+        match result {
             Ok(response) => {
                 let status = response.status();
 
@@ -188,6 +357,7 @@ async fn handle_request(
         }
     } else {
         println!("Error: No backend available");
+        metrics.record_request(503, route, "none");
         Ok(Response::builder()
             .status(503)
             .body(Body::from("No backend available"))
@@ -196,28 +366,40 @@ async fn handle_request(
 }
 
 // Starts http-Server and initializes the load balancer
+#[allow(clippy::too_many_arguments)]
 pub async fn start_http_server(
     shared_state: SharedState,
     shared_client: Arc<UnboundedClient>,
-    cache: Arc<SimpleCache>
+    cache: Arc<SimpleCache>,
+    cold_start: Arc<ColdStart>,
+    telemetry: Arc<Telemetry>,
+    staleness: Staleness,
+    locality: Arc<Locality>,
+    metrics: Arc<Metrics>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let addr = ([0, 0, 0, 0], env::var("HOST_PORT_HTTP_BALANCER").unwrap().parse().unwrap()).into();
 
     println!("Initializing balancer");
-    let balancer = Arc::new(DynamicWeightedBalancer::new(vec![]));
+    let balancer = Arc::new(DynamicWeightedBalancer::new(vec![], SlowStart::from_env(), staleness, PassiveHealth::from_env(), locality));
 
     // For every incoming request, the handle_request function is called (with a Service-Factory)
     let make_svc = make_service_fn({
         let balancer = balancer.clone();
         let client = shared_client.clone();
         let cache = cache.clone();
+        let cold_start = cold_start.clone();
+        let telemetry = telemetry.clone();
+        let metrics = metrics.clone();
         move |_| {
             let balancer = balancer.clone();
             let client = client.clone();
             let cache = cache.clone();
+            let cold_start = cold_start.clone();
+            let telemetry = telemetry.clone();
+            let metrics = metrics.clone();
             async move {
                 Ok::<_, hyper::Error>(service_fn(move |req| {
-                    handle_request(req, balancer.clone(), client.clone(), cache.clone())
+                    handle_request(req, balancer.clone(), client.clone(), cache.clone(), cold_start.clone(), telemetry.clone(), metrics.clone())
                 }))
             }
         }
@@ -234,10 +416,12 @@ pub async fn start_http_server(
         loop {
             interval.tick().await;
             let state = shared_state.read().await;
-            if let Some(queue_items) = &*state {
+            balancer_for_update.set_stale(staleness.is_stale(&state));
+            if let Some(queue_items) = state.items() {
                 balancer_for_update.set_queue_items(queue_items.clone()).await;
             }
             balancer_for_update.update_weights().await;
+            balancer_for_update.report_weights(&metrics).await;
         }
     });
 
@@ -254,4 +438,72 @@ pub async fn start_http_server(
     server.await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_client::metrics::family::Family;
+    use crate::degraded::StalePolicy;
+    use crate::locality::ZoneCapacity;
+    use crate::slowstart::RampMode;
+
+    fn balancer(items: Vec<QueueItem>, threshold: f64) -> DynamicWeightedBalancer {
+        let locality = Locality { zone: Some("eu-1".to_string()), capacity: ZoneCapacity::Count, threshold, requests: Family::default() };
+        DynamicWeightedBalancer::new(
+            items,
+            SlowStart { window: Duration::ZERO, floor: 0.1, mode: RampMode::Linear },
+            Staleness { after: Duration::from_secs(30), policy: StalePolicy::Keep },
+            PassiveHealth::from_env(),
+            Arc::new(locality),
+        )
+    }
+
+    fn item(dns_name: &str, zone: &str, score: f64) -> QueueItem {
+        let mut item = QueueItem::new(dns_name.to_string(), score, UtilizationCategory::Low);
+        item.zone = Some(zone.to_string());
+        item
+    }
+
+    // Share of the requests that stayed in the zone of the balancer
+    async fn local_share(balancer: &DynamicWeightedBalancer, requests: usize) -> f64 {
+        for _ in 0..requests {
+            balancer.next().await.expect("a backend");
+        }
+        let (local, cross_zone) = balancer.locality.requests();
+        local as f64 / (local + cross_zone) as f64
+    }
+
+    #[tokio::test]
+    async fn stays_in_the_zone_while_capacity_reaches_the_threshold() {
+        let balancer = balancer(vec![item("local", "eu-1", 50.0), item("remote", "eu-2", 50.0)], 1.0);
+        assert_eq!(local_share(&balancer, 500).await, 1.0);
+    }
+
+    #[tokio::test]
+    async fn spills_over_below_the_threshold() {
+        let balancer = balancer(vec![item("local", "eu-1", 50.0), item("remote", "eu-2", 50.0)], 2.0);
+        let share = local_share(&balancer, 4000).await;
+        assert!((0.45..=0.55).contains(&share), "local share {}", share);
+    }
+
+    #[tokio::test]
+    async fn backends_without_weight_are_no_capacity() {
+        let balancer = balancer(vec![item("local", "eu-1", 0.0), item("remote", "eu-2", 50.0)], 1.0);
+        assert_eq!(local_share(&balancer, 500).await, 0.0);
+    }
+
+    #[tokio::test]
+    async fn ejected_backends_are_no_capacity() {
+        let balancer = balancer(vec![item("local", "eu-1", 50.0), item("remote", "eu-2", 50.0)], 1.0);
+        for _ in 0..10 {
+            balancer.passive_health.record("local", false).await;
+        }
+        assert_eq!(local_share(&balancer, 500).await, 0.0);
+    }
+
+    #[tokio::test]
+    async fn without_remote_backends_the_local_ones_are_used() {
+        let balancer = balancer(vec![item("local", "eu-1", 0.0)], 1.0);
+        assert_eq!(local_share(&balancer, 100).await, 1.0);
+    }
+}
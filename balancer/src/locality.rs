@@ -0,0 +1,176 @@
+use std::env;
+use std::fmt;
+use dotenv::dotenv;
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+
+use crate::queue::QueueItem;
+
+// How the capacity of the local zone is measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneCapacity {
+    // Healthy backends
+    Count,
+    // Sum of the scores of the healthy backends
+    Score,
+}
+
+impl fmt::Display for ZoneCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoneCapacity::Count => write!(f, "count"),
+            ZoneCapacity::Score => write!(f, "score"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum RequestLocality {
+    Local,
+    CrossZone,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ZoneLabels {
+    pub locality: RequestLocality,
+}
+
+// Keeps traffic in the zone of the balancer. Below the threshold, the local zone only gets the
+// share of its capacity in the threshold and the rest spills over to the other zones.
+pub struct Locality {
+    pub zone: Option<String>,
+    pub capacity: ZoneCapacity,
+    pub threshold: f64,
+    // Forwarded requests by locality, also exported as metrics
+    pub requests: Family<ZoneLabels, Counter>,
+}
+
+impl Locality {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let zone = env::var("BALANCER_ZONE").ok().filter(|zone| !zone.is_empty());
+
+        let capacity = match env::var("ZONE_CAPACITY").unwrap_or_else(|_| "count".to_string()).as_str() {
+            "count" => ZoneCapacity::Count,
+            "score" => ZoneCapacity::Score,
+            other => panic!("ZONE_CAPACITY must be count or score, got {}", other),
+        };
+        let default_threshold = match capacity {
+            ZoneCapacity::Count => "1",
+            ZoneCapacity::Score => "100",
+        };
+        let threshold = env::var("ZONE_SPILLOVER_THRESHOLD")
+            .ok()
+            .filter(|threshold| !threshold.is_empty())
+            .unwrap_or_else(|| default_threshold.to_string())
+            .parse::<f64>()
+            .expect("ZONE_SPILLOVER_THRESHOLD must be a valid f64");
+        if threshold < 0.0 {
+            panic!("ZONE_SPILLOVER_THRESHOLD must not be negative");
+        }
+
+        Locality {
+            zone,
+            capacity,
+            threshold,
+            requests: Family::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.zone.is_some()
+    }
+
+    // Backends without a zone are never local
+    pub fn is_local(&self, item: &QueueItem) -> bool {
+        self.zone.is_some() && item.zone == self.zone
+    }
+
+    // Capacity of the given healthy local backends
+    pub fn capacity_of<'a>(&self, local: impl Iterator<Item = &'a QueueItem>) -> f64 {
+        match self.capacity {
+            ZoneCapacity::Count => local.count() as f64,
+            ZoneCapacity::Score => local.map(|item| item.score.max(0.0)).sum(),
+        }
+    }
+
+    // Share of the requests kept in the local zone
+    pub fn local_share(&self, capacity: f64) -> f64 {
+        if self.threshold == 0.0 {
+            return 1.0;
+        }
+        (capacity / self.threshold).min(1.0)
+    }
+
+    pub fn record(&self, item: &QueueItem) {
+        if !self.is_enabled() {
+            return;
+        }
+        let locality = if self.is_local(item) { RequestLocality::Local } else { RequestLocality::CrossZone };
+        self.requests.get_or_create(&ZoneLabels { locality }).inc();
+    }
+
+    // Requests forwarded within the zone and to other zones
+    pub fn requests(&self) -> (u64, u64) {
+        let count = |locality| self.requests.get_or_create(&ZoneLabels { locality }).get();
+        (count(RequestLocality::Local), count(RequestLocality::CrossZone))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::UtilizationCategory;
+
+    fn locality(capacity: ZoneCapacity, threshold: f64) -> Locality {
+        Locality { zone: Some("eu-1".to_string()), capacity, threshold, requests: Family::default() }
+    }
+
+    fn item(zone: Option<&str>, score: f64) -> QueueItem {
+        let mut item = QueueItem::new("worker".to_string(), score, UtilizationCategory::Low);
+        item.zone = zone.map(str::to_string);
+        item
+    }
+
+    #[test]
+    fn only_backends_of_the_own_zone_are_local() {
+        let locality = locality(ZoneCapacity::Count, 1.0);
+        assert!(locality.is_local(&item(Some("eu-1"), 50.0)));
+        assert!(!locality.is_local(&item(Some("eu-2"), 50.0)));
+        assert!(!locality.is_local(&item(None, 50.0)));
+
+        let disabled = Locality { zone: None, ..locality };
+        assert!(!disabled.is_local(&item(None, 50.0)));
+    }
+
+    #[test]
+    fn capacity_counts_backends_or_sums_scores() {
+        let items = [item(Some("eu-1"), 40.0), item(Some("eu-1"), 30.0)];
+        assert_eq!(locality(ZoneCapacity::Count, 1.0).capacity_of(items.iter()), 2.0);
+        assert_eq!(locality(ZoneCapacity::Score, 100.0).capacity_of(items.iter()), 70.0);
+    }
+
+    #[test]
+    fn local_share_reaches_one_at_the_threshold() {
+        let locality = locality(ZoneCapacity::Count, 2.0);
+        assert_eq!(locality.local_share(0.0), 0.0);
+        assert_eq!(locality.local_share(1.0), 0.5);
+        assert_eq!(locality.local_share(2.0), 1.0);
+        assert_eq!(locality.local_share(3.0), 1.0);
+    }
+
+    #[test]
+    fn zero_threshold_never_spills_over() {
+        assert_eq!(locality(ZoneCapacity::Count, 0.0).local_share(0.0), 1.0);
+    }
+
+    #[test]
+    fn requests_are_counted_by_locality() {
+        let locality = locality(ZoneCapacity::Count, 1.0);
+        locality.record(&item(Some("eu-1"), 50.0));
+        locality.record(&item(Some("eu-1"), 50.0));
+        locality.record(&item(Some("eu-2"), 50.0));
+        assert_eq!(locality.requests(), (2, 1));
+    }
+}
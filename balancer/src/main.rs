@@ -1,19 +1,35 @@
 use std::env;
 use std::sync::Arc;
 use dotenv::dotenv;
-use tokio::sync::RwLock;
-use log::info;
+use tokio::sync::{mpsc, RwLock};
+use log::{info, warn};
 
 mod socket;
 mod http;
 mod queue;
 mod client;
 mod cache;
+mod coldstart;
+mod slowstart;
+mod telemetry;
+mod state;
+mod degraded;
+mod admin;
+mod discovery;
+mod locality;
+mod metrics;
 
 use crate::http::start_http_server;
-use crate::socket::connect_socket;
 use crate::client::UnboundedClient;
 use crate::cache::SimpleCache;
+use crate::coldstart::ColdStart;
+use crate::telemetry::{report_telemetry, Telemetry};
+use crate::state::{persist_queue, restore, snapshot_path};
+use crate::degraded::Staleness;
+use crate::locality::Locality;
+use crate::admin::{admin_port, start_admin_server, AdminContext};
+use crate::metrics::Metrics;
+use crate::discovery::{run_discovery, Discovery};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 16)]
 async fn main() {
@@ -23,8 +39,20 @@ async fn main() {
 
     info!("Starting load balancer");
 
-    // Shared State for the communication between components
-    let shared_state = Arc::new(RwLock::new(None));
+    // Shared State for the communication between components, starting with the last-known-good queue
+    let snapshot_path = snapshot_path();
+    let shared_state = Arc::new(RwLock::new(restore(snapshot_path.as_deref())));
+    if let Some(path) = snapshot_path {
+        tokio::spawn(persist_queue(shared_state.clone(), path));
+    }
+    let staleness = Staleness::from_env();
+    let locality = Arc::new(Locality::from_env());
+    match &locality.zone {
+        Some(zone) => info!("Preferring backends in zone {}", zone),
+        None => info!("BALANCER_ZONE is not set, backends are chosen regardless of their zone"),
+    }
+
+    let metrics = Metrics::new(&locality);
 
     // Create UnboundedClient (for outgoing requests)
     let shared_client = UnboundedClient::new();
@@ -36,17 +64,46 @@ async fn main() {
         .expect("CACHE_CAPACITY must be a valid usize");
     let cache = Arc::new(SimpleCache::new(cache_size));
 
-    let ws_state = shared_state.clone();
+    match admin_port() {
+        Some(port) => {
+            let context = AdminContext {
+                shared_state: shared_state.clone(),
+                staleness,
+                locality: locality.clone(),
+                metrics: metrics.clone(),
+                cache: cache.clone(),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = start_admin_server(port, context).await {
+                    log::error!("Admin server error: {}", e);
+                }
+            });
+        }
+        None => warn!("HOST_PORT_ADMIN_BALANCER is not set, admin server and metrics disabled"),
+    }
+
+    // Events for the deployment agent (e.g. wake-up after scale-to-zero)
+    let (event_sender, event_receiver) = mpsc::unbounded_channel();
+    let cold_start = ColdStart::new(event_sender.clone());
+
+    // Request telemetry reported back to the deployment agent
+    let telemetry = Telemetry::new();
+    tokio::spawn(report_telemetry(telemetry.clone(), event_sender));
+
+    let discovery = Discovery::from_env();
+    info!("Discovering the queue via {}", discovery);
+    let discovery_state = shared_state.clone();
+    let discovery_metrics = metrics.clone();
     tokio::spawn(async move {
-        if let Err(e) = connect_socket(ws_state).await {
-            log::error!("WebSocket connection error: {}", e);
+        if let Err(e) = run_discovery(discovery, discovery_state, event_receiver, discovery_metrics).await {
+            log::error!("Discovery error ({}): {}", discovery, e);
         }
     });
 
     let http_state = shared_state.clone();
     let http_client = shared_client.clone();
     let http_cache = cache.clone();
-    if let Err(e) = start_http_server(http_state, http_client, http_cache).await {
+    if let Err(e) = start_http_server(http_state, http_client, http_cache, cold_start, telemetry, staleness, locality, metrics).await {
         log::error!("HTTP server error: {}", e);
     }
 }
\ No newline at end of file
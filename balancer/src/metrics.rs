@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+
+use crate::client::ClientError;
+use crate::locality::Locality;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Route {
+    // Cacheable resources like pictures and stylesheets
+    Static,
+    Dynamic,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    status: u16,
+    route: Route,
+    // dns_name of the backend, "cache" for cached responses and "none" without a backend
+    backend: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct BackendLabels {
+    backend: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum CacheResult {
+    Hit,
+    Miss,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CacheLabels {
+    result: CacheResult,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum UpstreamError {
+    Connect,
+    Timeout,
+    Other,
+}
+
+impl From<&ClientError> for UpstreamError {
+    fn from(error: &ClientError) -> Self {
+        match error {
+            ClientError::RequestTimeout => UpstreamError::Timeout,
+            ClientError::HyperError(e) if e.is_connect() => UpstreamError::Connect,
+            _ => UpstreamError::Other,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct UpstreamErrorLabels {
+    backend: String,
+    kind: UpstreamError,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DiscoveryLabels {
+    discovery: String,
+}
+
+fn request_duration_histogram() -> Histogram {
+    // 1 ms to about 16 s
+    Histogram::new(exponential_buckets(0.001, 2.0, 15))
+}
+
+// Metrics of the balancer, served in the OpenMetrics format by the admin server
+pub struct Metrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: Family<BackendLabels, Histogram, fn() -> Histogram>,
+    in_flight: Family<BackendLabels, Gauge>,
+    cache_requests: Family<CacheLabels, Counter>,
+    cache_hit_bytes: Counter,
+    cache_size_bytes: Gauge,
+    upstream_errors: Family<UpstreamErrorLabels, Counter>,
+    discovery_reconnects: Family<DiscoveryLabels, Counter>,
+    queue_age: Gauge<f64, AtomicU64>,
+    queue_stale: Gauge,
+    backend_weight: Family<BackendLabels, Gauge<f64, AtomicU64>>,
+    // Backends with a weight gauge
+    weighted_backends: Mutex<HashSet<String>>,
+    // Backends that left the queue while requests to them were in flight
+    gone_backends: Mutex<HashSet<String>>,
+    // Statuses and routes counted per backend, to remove them with the backend
+    request_series: Mutex<HashMap<String, HashSet<(u16, Route)>>>,
+}
+
+impl Metrics {
+    pub fn new(locality: &Locality) -> Arc<Self> {
+        let mut registry = Registry::with_prefix("balancer");
+        let requests = Family::default();
+        registry.register("requests", "Handled requests by status, route and backend", Family::clone(&requests));
+        let request_duration = Family::new_with_constructor(request_duration_histogram as fn() -> Histogram);
+        registry.register_with_unit("upstream_request_duration", "Duration of requests forwarded to a backend", Unit::Seconds, request_duration.clone());
+        let in_flight = Family::default();
+        registry.register("in_flight_requests", "Requests currently forwarded to a backend", Family::clone(&in_flight));
+        let cache_requests = Family::default();
+        registry.register("cache_requests", "Lookups of static resources in the cache", Family::clone(&cache_requests));
+        let cache_hit_bytes = Counter::default();
+        registry.register_with_unit("cache_hit", "Bytes served from the cache", Unit::Bytes, cache_hit_bytes.clone());
+        let cache_size_bytes = Gauge::default();
+        registry.register_with_unit("cache_size", "Bytes stored in the cache", Unit::Bytes, cache_size_bytes.clone());
+        let upstream_errors = Family::default();
+        registry.register("upstream_errors", "Failed requests to a backend by kind", Family::clone(&upstream_errors));
+        let discovery_reconnects = Family::default();
+        registry.register("discovery_reconnects", "Reconnects of the discovery after a lost or failed connection", Family::clone(&discovery_reconnects));
+        let queue_age = Gauge::default();
+        registry.register_with_unit("queue_age", "Time since the queue was last confirmed by the discovery", Unit::Seconds, Gauge::clone(&queue_age));
+        let queue_stale = Gauge::default();
+        registry.register("queue_stale", "1 while the queue is stale", Gauge::clone(&queue_stale));
+        let backend_weight = Family::default();
+        registry.register("backend_weight", "Current weight of a backend", Family::clone(&backend_weight));
+        registry.register("zone_requests", "Requests forwarded to backends in the own or other zones", locality.requests.clone());
+
+        Arc::new(Metrics {
+            registry,
+            requests,
+            request_duration,
+            in_flight,
+            cache_requests,
+            cache_hit_bytes,
+            cache_size_bytes,
+            upstream_errors,
+            discovery_reconnects,
+            queue_age,
+            queue_stale,
+            backend_weight,
+            weighted_backends: Mutex::new(HashSet::new()),
+            gone_backends: Mutex::new(HashSet::new()),
+            request_series: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn record_request(&self, status: u16, route: Route, backend: &str) {
+        self.requests.get_or_create(&RequestLabels { status, route, backend: backend.to_string() }).inc();
+        self.request_series.lock().unwrap().entry(backend.to_string()).or_default().insert((status, route));
+    }
+
+    pub fn begin_upstream(&self, backend: &str) {
+        self.in_flight.get_or_create(&BackendLabels { backend: backend.to_string() }).inc();
+    }
+
+    pub fn finish_upstream(&self, backend: &str, duration: Duration, error: Option<&ClientError>) {
+        let labels = BackendLabels { backend: backend.to_string() };
+        self.in_flight.get_or_create(&labels).dec();
+        self.request_duration.get_or_create(&labels).observe(duration.as_secs_f64());
+        if let Some(error) = error {
+            self.upstream_errors.get_or_create(&UpstreamErrorLabels { backend: labels.backend, kind: error.into() }).inc();
+        }
+    }
+
+    pub fn record_cache(&self, result: CacheResult, bytes: usize) {
+        if result == CacheResult::Hit {
+            self.cache_hit_bytes.inc_by(bytes as u64);
+        }
+        self.cache_requests.get_or_create(&CacheLabels { result }).inc();
+    }
+
+    pub fn record_reconnect(&self, discovery: &str) {
+        self.discovery_reconnects.get_or_create(&DiscoveryLabels { discovery: discovery.to_string() }).inc();
+    }
+
+    // Replaces the weights of all backends. The gauges are updated in place so that a
+    // concurrent scrape never sees an empty family, only gone backends are removed.
+    // Their request series follow once no request to them is in flight anymore.
+    pub fn set_backend_weights<'a>(&self, weights: impl Iterator<Item = (&'a str, f64)>) {
+        let mut weighted_backends = self.weighted_backends.lock().unwrap();
+        let mut current = HashSet::new();
+        for (backend, weight) in weights {
+            self.backend_weight.get_or_create(&BackendLabels { backend: backend.to_string() }).set(weight);
+            current.insert(backend.to_string());
+        }
+        let mut gone_backends = self.gone_backends.lock().unwrap();
+        for backend in weighted_backends.difference(&current) {
+            self.backend_weight.remove(&BackendLabels { backend: backend.clone() });
+            gone_backends.insert(backend.clone());
+        }
+        gone_backends.retain(|backend| !current.contains(backend) && !self.remove_request_series(backend));
+        *weighted_backends = current;
+    }
+
+    // Returns false while requests to the backend are in flight
+    fn remove_request_series(&self, backend: &str) -> bool {
+        let labels = BackendLabels { backend: backend.to_string() };
+        let in_flight = self.in_flight.get_or_create(&labels).get();
+        if in_flight > 0 {
+            return false;
+        }
+        self.in_flight.remove(&labels);
+        self.request_duration.remove(&labels);
+        for kind in [UpstreamError::Connect, UpstreamError::Timeout, UpstreamError::Other] {
+            self.upstream_errors.remove(&UpstreamErrorLabels { backend: backend.to_string(), kind });
+        }
+        if let Some(series) = self.request_series.lock().unwrap().remove(backend) {
+            for (status, route) in series {
+                self.requests.remove(&RequestLabels { status, route, backend: backend.to_string() });
+            }
+        }
+        true
+    }
+
+    // Values sampled at scrape time
+    pub fn set_queue(&self, age: Option<Duration>, stale: bool) {
+        self.queue_age.set(age.map_or(0.0, |age| age.as_secs_f64()));
+        self.queue_stale.set(stale as i64);
+    }
+
+    pub fn set_cache_size(&self, bytes: usize) {
+        self.cache_size_bytes.set(bytes as i64);
+    }
+
+    pub fn encode(&self) -> String {
+        let mut body = String::new();
+        encode(&mut body, &self.registry).expect("Failed to encode metrics");
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locality::ZoneCapacity;
+
+    fn metrics() -> Arc<Metrics> {
+        let locality = Locality { zone: None, capacity: ZoneCapacity::Count, threshold: 1.0, requests: Family::default() };
+        Metrics::new(&locality)
+    }
+
+    fn weights(metrics: &Metrics) -> Vec<String> {
+        let mut weights: Vec<String> = metrics.encode().lines()
+            .filter(|line| line.starts_with("balancer_backend_weight{"))
+            .map(str::to_string)
+            .collect();
+        weights.sort();
+        weights
+    }
+
+    #[test]
+    fn backend_weights_are_updated_in_place() {
+        let metrics = metrics();
+        metrics.set_backend_weights([("a", 1.0), ("b", 2.0)].into_iter());
+        assert_eq!(weights(&metrics), vec![
+            "balancer_backend_weight{backend=\"a\"} 1.0",
+            "balancer_backend_weight{backend=\"b\"} 2.0",
+        ]);
+
+        metrics.set_backend_weights([("b", 3.0), ("c", 0.5)].into_iter());
+        assert_eq!(weights(&metrics), vec![
+            "balancer_backend_weight{backend=\"b\"} 3.0",
+            "balancer_backend_weight{backend=\"c\"} 0.5",
+        ]);
+
+        metrics.set_backend_weights(std::iter::empty());
+        assert!(weights(&metrics).is_empty());
+    }
+
+    fn series_of(metrics: &Metrics, backend: &str) -> Vec<String> {
+        let label = format!("backend=\"{}\"", backend);
+        metrics.encode().lines()
+            .filter(|line| line.contains(&label))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn gone_backends_are_removed_after_their_last_request() {
+        let metrics = metrics();
+        metrics.set_backend_weights([("a", 1.0), ("b", 1.0)].into_iter());
+        for backend in ["a", "b"] {
+            metrics.begin_upstream(backend);
+            metrics.record_request(200, Route::Dynamic, backend);
+            metrics.finish_upstream(backend, Duration::from_millis(5), Some(&ClientError::RequestTimeout));
+        }
+        metrics.begin_upstream("b");
+        assert!(series_of(&metrics, "a").iter().any(|line| line.starts_with("balancer_requests_total")));
+
+        metrics.set_backend_weights([("c", 1.0)].into_iter());
+        assert!(series_of(&metrics, "a").is_empty(), "{:?}", series_of(&metrics, "a"));
+        // A request to b is still in flight
+        assert!(series_of(&metrics, "b").iter().any(|line| line == "balancer_in_flight_requests{backend=\"b\"} 1"));
+        assert!(!series_of(&metrics, "b").iter().any(|line| line.starts_with("balancer_backend_weight")));
+
+        metrics.record_request(200, Route::Dynamic, "b");
+        metrics.finish_upstream("b", Duration::from_millis(5), None);
+        metrics.set_backend_weights([("c", 1.0)].into_iter());
+        assert!(series_of(&metrics, "b").is_empty(), "{:?}", series_of(&metrics, "b"));
+        assert_eq!(weights(&metrics), vec!["balancer_backend_weight{backend=\"c\"} 1.0"]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use dotenv::dotenv;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RampMode {
+    Linear,
+    Exponential,
+}
+
+impl fmt::Display for RampMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RampMode::Linear => write!(f, "linear"),
+            RampMode::Exponential => write!(f, "exponential"),
+        }
+    }
+}
+
+// Ramps the weight of new backends from a floor to their full weight
+#[derive(Debug, Clone, Copy)]
+pub struct SlowStart {
+    pub window: Duration,
+    pub floor: f64,
+    pub mode: RampMode,
+}
+
+impl SlowStart {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let window = Duration::from_secs(
+            env::var("SLOW_START_WINDOW")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse::<u64>()
+                .expect("SLOW_START_WINDOW must be a valid u64")
+        );
+
+        let floor = env::var("SLOW_START_FLOOR")
+            .unwrap_or_else(|_| "0.1".to_string())
+            .parse::<f64>()
+            .expect("SLOW_START_FLOOR must be a valid f64");
+        if !(0.0..=1.0).contains(&floor) || floor == 0.0 {
+            panic!("SLOW_START_FLOOR must be within (0.0, 1.0]");
+        }
+
+        let mode = match env::var("SLOW_START_MODE").unwrap_or_else(|_| "linear".to_string()).as_str() {
+            "linear" => RampMode::Linear,
+            "exponential" => RampMode::Exponential,
+            other => panic!("SLOW_START_MODE must be linear or exponential, got {}", other),
+        };
+
+        SlowStart { window, floor, mode }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    // Progress of the ramp between 0.0 (just added) and 1.0 (fully warm)
+    pub fn progress(&self, age: Duration) -> f64 {
+        if !self.is_enabled() {
+            return 1.0;
+        }
+        (age.as_secs_f64() / self.window.as_secs_f64()).min(1.0)
+    }
+
+    // Factor applied to the score-derived weight of a backend with the given age
+    pub fn factor(&self, age: Duration) -> f64 {
+        let progress = self.progress(age);
+        match self.mode {
+            RampMode::Linear => self.floor + (1.0 - self.floor) * progress,
+            RampMode::Exponential => self.floor.powf(1.0 - progress),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(mode: RampMode) -> SlowStart {
+        SlowStart { window: Duration::from_secs(10), floor: 0.1, mode }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn linear_ramp_goes_from_floor_to_full_weight() {
+        let slow_start = ramp(RampMode::Linear);
+        assert_close(slow_start.factor(Duration::ZERO), 0.1);
+        assert_close(slow_start.factor(Duration::from_secs(5)), 0.55);
+        assert_close(slow_start.factor(Duration::from_secs(10)), 1.0);
+        assert_close(slow_start.factor(Duration::from_secs(60)), 1.0);
+    }
+
+    #[test]
+    fn exponential_ramp_stays_low_longer() {
+        let slow_start = ramp(RampMode::Exponential);
+        assert_close(slow_start.factor(Duration::ZERO), 0.1);
+        assert_close(slow_start.factor(Duration::from_secs(5)), 0.1_f64.sqrt());
+        assert_close(slow_start.factor(Duration::from_secs(10)), 1.0);
+        assert!(slow_start.factor(Duration::from_secs(5)) < ramp(RampMode::Linear).factor(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn disabled_window_gives_full_weight() {
+        let slow_start = SlowStart { window: Duration::ZERO, floor: 0.1, mode: RampMode::Linear };
+        assert!(!slow_start.is_enabled());
+        assert_close(slow_start.factor(Duration::ZERO), 1.0);
+    }
+}
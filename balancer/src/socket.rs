@@ -1,71 +1,307 @@
 use std::env;
-use futures_util::StreamExt;
+use std::path::Path;
 use std::sync::Arc;
+use futures_util::{SinkExt, StreamExt};
 use dotenv::dotenv;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use tokio::time::{sleep, Duration};
-use tokio::sync::RwLock;
+use tokio_tungstenite::{client_async, connect_async, tungstenite::protocol::Message, WebSocketStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+use tokio::sync::mpsc;
 use log::{info, error, warn};
+use protocol::tls::{self, client, ServerName, TlsConnector};
+use protocol::{decode, encode, AgentMessage, BalancerMessage, ErrorCode, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
-use crate::queue::{read_queue, QueueItem};
+use crate::metrics::Metrics;
+use crate::queue::QueueItem;
+use crate::state::SharedState;
 
-pub type SharedState = Arc<RwLock<Option<Vec<QueueItem>>>>;
+pub type EventSender = mpsc::UnboundedSender<BalancerMessage>;
 
-pub async fn connect_socket(shared_state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    let ws_env_port = env::var("HOST_PORT_WS_DEPLOYMENT_AGENT")
-        .expect("HOST_PORT_WS_DEPLOYMENT_AGENT must be set")
-        .parse::<u16>()
-        .expect("HOST_PORT_WS_DEPLOYMENT_AGENT must be a valid u16");
+// Host name of the deployment agent, also the name its certificate is issued for
+const AGENT_HOST: &str = "deployment-agent";
+// Reconnects if the agent is silent this long before announcing its heartbeat interval
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+// Missed heartbeats until the connection is considered dead
+const MISSED_HEARTBEATS: u32 = 3;
 
-    let url_string = format!("ws://deployment-agent:{}/ws", ws_env_port);
-    let url = url_string.as_str();
+// Queue as built from the messages of one connection
+#[derive(Default)]
+struct QueueSession {
+    seq: u64,
+    // None until the first snapshot and while waiting for a resync
+    items: Option<Vec<QueueItem>>,
+    resync_requested: bool,
+    idle_timeout: Option<Duration>,
+}
+
+impl QueueSession {
+    // Drops the queue of the connection and asks for a snapshot, once until it arrives
+    fn resync(&mut self) -> Option<BalancerMessage> {
+        self.items = None;
+        if self.resync_requested {
+            return None;
+        }
+        self.resync_requested = true;
+        Some(BalancerMessage::Resync)
+    }
+}
+
+// Connection to the deployment agent, with mutual TLS if WS_TLS_CERT_PATH is set
+enum Endpoint {
+    Plain { url: String },
+    Tls { url: String, address: String, connector: TlsConnector, server_name: ServerName<'static> },
+}
+
+impl Endpoint {
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        dotenv().ok();
+        let ws_env_port = env::var("HOST_PORT_WS_DEPLOYMENT_AGENT")
+            .expect("HOST_PORT_WS_DEPLOYMENT_AGENT must be set")
+            .parse::<u16>()
+            .expect("HOST_PORT_WS_DEPLOYMENT_AGENT must be a valid u16");
+
+        match env::var("WS_TLS_CERT_PATH") {
+            Ok(path) if !path.is_empty() => {
+                let config = tls::client_config(Path::new(&path))
+                    .map_err(|e| format!("Invalid WebSocket TLS configuration: {}", e))?;
+                Ok(Endpoint::Tls {
+                    url: format!("wss://{}:{}/ws", AGENT_HOST, ws_env_port),
+                    address: format!("{}:{}", AGENT_HOST, ws_env_port),
+                    connector: TlsConnector::from(config),
+                    server_name: tls::server_name(AGENT_HOST)?,
+                })
+            }
+            _ => {
+                warn!("WS_TLS_CERT_PATH is not set, the deployment agent is not authenticated");
+                Ok(Endpoint::Plain { url: format!("ws://{}:{}/ws", AGENT_HOST, ws_env_port) })
+            }
+        }
+    }
+
+    fn url(&self) -> &str {
+        match self {
+            Endpoint::Plain { url } | Endpoint::Tls { url, .. } => url,
+        }
+    }
+}
+
+pub async fn connect_socket(shared_state: SharedState, mut event_receiver: mpsc::UnboundedReceiver<BalancerMessage>, metrics: Arc<Metrics>) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint = Endpoint::from_env()?;
 
     let mut retry_delay = Duration::from_secs(1);
     let max_retry_delay = Duration::from_secs(60);
 
     // Main loop for websocket connection and messages
     loop {
-        info!("Attempting to connect to WebSocket at {}", url);
-        match connect_async(url).await {
-            Ok((mut ws_stream, _)) => {
-                info!("Connected to the WebSocket");
-                retry_delay = Duration::from_secs(1);  // Reset retry delay on successful connection
-
-                // Incoming websocket messages
-                while let Some(msg) = ws_stream.next().await {
-                    match msg {
-                        Ok(Message::Text(text)) => {
-                            tokio::spawn({
-                                let shared_state = shared_state.clone();
-                                let text = text.clone();
-                                async move {
-                                    match read_queue(&text) {
-                                        Ok(queue_items) => {
-                                            let mut state = shared_state.write().await;
-                                            *state = Some(queue_items);
-                                            info!("Updated queue state");
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to parse queue data: {}", e);
-                                        }
-                                    }
-                                }
-                            });
-                        }
-                        Ok(_) => warn!("Received non-text message from WebSocket"),
+        info!("Attempting to connect to WebSocket at {}", endpoint.url());
+        let handshake_completed = match &endpoint {
+            Endpoint::Plain { url } => match connect_async(url.as_str()).await {
+                Ok((ws_stream, _)) => Some(run_session(ws_stream, &shared_state, &mut event_receiver).await),
+                Err(e) => {
+                    error!("Failed to connect to WebSocket: {}", e);
+                    None
+                }
+            },
+            Endpoint::Tls { url, address, connector, server_name } => match connect_tls(url, address, connector, server_name).await {
+                Ok(ws_stream) => Some(run_session(ws_stream, &shared_state, &mut event_receiver).await),
+                Err(e) => {
+                    error!("Failed to connect to WebSocket: {}", e);
+                    None
+                }
+            },
+        };
+
+        metrics.record_reconnect("websocket");
+        // Backs off unless the connection got past the handshake
+        if handshake_completed == Some(true) {
+            retry_delay = Duration::from_secs(1);
+        } else {
+            info!("Retrying in {} seconds...", retry_delay.as_secs());
+            sleep(retry_delay).await;
+            retry_delay = std::cmp::min(retry_delay * 2, max_retry_delay);
+        }
+    }
+}
+
+async fn connect_tls(url: &str, address: &str, connector: &TlsConnector, server_name: &ServerName<'static>) -> Result<WebSocketStream<client::TlsStream<TcpStream>>, String> {
+    let stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+    let stream = connector.connect(server_name.clone(), stream).await
+        .map_err(|e| format!("TLS handshake with the deployment agent failed: {}", e))?;
+    let (ws_stream, _) = client_async(url, stream).await.map_err(|e| e.to_string())?;
+    Ok(ws_stream)
+}
+
+// Exchanges messages until the connection fails, returns whether the protocol handshake completed
+async fn run_session<S>(ws_stream: WebSocketStream<S>, shared_state: &SharedState, event_receiver: &mut mpsc::UnboundedReceiver<BalancerMessage>) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("Connected to the WebSocket");
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    let hello = BalancerMessage::Hello { min_version: MIN_PROTOCOL_VERSION, max_version: PROTOCOL_VERSION };
+    if let Err(e) = ws_sender.send(Message::Text(encode(&hello))).await {
+        error!("Failed to send hello: {}. Reconnecting...", e);
+        return false;
+    }
+
+    let mut session = QueueSession::default();
+    // Only messages of the agent move the deadline, the telemetry sent meanwhile does not
+    let idle = sleep_until(Instant::now() + HANDSHAKE_TIMEOUT);
+    tokio::pin!(idle);
+    loop {
+        let reply = tokio::select! {
+            // Incoming websocket messages
+            msg = ws_receiver.next() => {
+                let reply = match msg {
+                    Some(Ok(Message::Text(text))) => match decode::<AgentMessage>(&text) {
+                        Ok(message) => match handle_message(message, &mut session, shared_state).await {
+                            Ok(reply) => reply,
+                            Err(e) => {
+                                error!("{}. Reconnecting...", e);
+                                break; // retrying connection
+                            }
+                        },
                         Err(e) => {
-                            error!("Error receiving message: {}. Reconnecting...", e);
-                            break; // retrying connection
+                            error!("Failed to parse message from deployment agent: {}", e);
+                            None
                         }
+                    },
+                    Some(Ok(_)) => None,
+                    Some(Err(e)) => {
+                        error!("Error receiving message: {}. Reconnecting...", e);
+                        break; // retrying connection
                     }
-                }
+                    None => break,
+                };
+                idle.as_mut().reset(Instant::now() + session.idle_timeout.unwrap_or(HANDSHAKE_TIMEOUT));
+                reply
             }
-            Err(e) => {
-                error!("Failed to connect to WebSocket: {}. Retrying in {} seconds...", e, retry_delay.as_secs());
-                sleep(retry_delay).await;
-                retry_delay = std::cmp::min(retry_delay * 2, max_retry_delay);
+            () = &mut idle => {
+                warn!("No message from the deployment agent for {:?}. Reconnecting...", session.idle_timeout.unwrap_or(HANDSHAKE_TIMEOUT));
+                break; // retrying connection
             }
+            // Outgoing events for the deployment agent
+            Some(event) = event_receiver.recv() => Some(event),
+        };
+
+        if let Some(message) = reply {
+            if let Err(e) = ws_sender.send(Message::Text(encode(&message))).await {
+                error!("Failed to send message {:?}: {}. Reconnecting...", message, e);
+                break; // retrying connection
+            }
+        }
+    }
+
+    session.idle_timeout.is_some()
+}
+
+// Applies a message of the deployment agent and returns the answer, if any. Errors close the connection.
+async fn handle_message(message: AgentMessage, session: &mut QueueSession, shared_state: &SharedState) -> Result<Option<BalancerMessage>, String> {
+    match message {
+        AgentMessage::Hello { version, heartbeat_interval_secs } => {
+            info!("Deployment agent uses protocol version {}, heartbeat every {}s", version, heartbeat_interval_secs);
+            session.idle_timeout = Some(Duration::from_secs(heartbeat_interval_secs.max(1)) * MISSED_HEARTBEATS);
+            Ok(None)
+        }
+        AgentMessage::Snapshot { seq, items } => {
+            session.seq = seq;
+            session.resync_requested = false;
+            session.items = Some(items.clone());
+            shared_state.write().await.replace(items);
+            info!("Updated queue state from snapshot {}", seq);
+            Ok(None)
+        }
+        AgentMessage::Delta { seq, delta } => {
+            let Some(items) = session.items.as_mut() else {
+                return Ok(session.resync());
+            };
+            if seq != session.seq + 1 {
+                warn!("Missed queue updates (expected {}, got {}), requesting a snapshot", session.seq + 1, seq);
+                return Ok(session.resync());
+            }
+            delta.apply(items);
+            session.seq = seq;
+            shared_state.write().await.replace(items.clone());
+            info!("Updated queue state from delta {} (+{} ~{} -{})", seq, delta.added.len(), delta.updated.len(), delta.removed.len());
+            Ok(None)
+        }
+        AgentMessage::Heartbeat { seq } => {
+            if session.items.is_none() {
+                return Ok(None);
+            }
+            if seq != session.seq {
+                warn!("Heartbeat reports queue update {}, last applied {}, requesting a snapshot", seq, session.seq);
+                return Ok(session.resync());
+            }
+            // The queue is still current
+            shared_state.write().await.confirm();
+            Ok(None)
+        }
+        AgentMessage::Error { code: code @ (ErrorCode::UnsupportedVersion | ErrorCode::HandshakeRequired), message } => {
+            Err(format!("Deployment agent rejected the connection ({:?}): {}", code, message))
         }
+        AgentMessage::Error { code, message } => {
+            error!("Deployment agent reported an error ({:?}): {}", code, message);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tokio::time::timeout;
+    use tokio_tungstenite::accept_async;
+
+    // Connects a session to an agent that answers the hello with a heartbeat interval of one second
+    // (an idle timeout of three seconds), then sends the given number of heartbeats a second apart
+    // and falls silent. The balancer sends telemetry every half second throughout.
+    async fn session_duration(heartbeats: u64) -> Duration {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (agent, balancer) = tokio::join!(accept_async(server), client_async("ws://deployment-agent/", client));
+        let (mut agent, (balancer, _)): (WebSocketStream<DuplexStream>, _) = (agent.unwrap(), balancer.unwrap());
+
+        let agent = tokio::spawn(async move {
+            agent.next().await;
+            agent.send(Message::Text(encode(&AgentMessage::Hello { version: PROTOCOL_VERSION, heartbeat_interval_secs: 1 }))).await.unwrap();
+            for _ in 0..heartbeats {
+                sleep(Duration::from_secs(1)).await;
+                agent.send(Message::Text(encode(&AgentMessage::Heartbeat { seq: 0 }))).await.unwrap();
+            }
+            // Keeps reading the telemetry without answering
+            while let Some(Ok(_)) = agent.next().await {}
+        });
+        let (events, mut event_receiver) = mpsc::unbounded_channel();
+        let telemetry = tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_millis(500)).await;
+                if events.send(BalancerMessage::Telemetry { backends: Vec::new() }).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let start = Instant::now();
+        let handshake_completed = timeout(Duration::from_secs(60), run_session(balancer, &SharedState::default(), &mut event_receiver)).await
+            .expect("the idle timeout did not fire");
+        assert!(handshake_completed);
+        telemetry.abort();
+        agent.abort();
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn telemetry_does_not_keep_a_silent_agent_alive() {
+        let duration = session_duration(0).await;
+        assert!(duration >= Duration::from_secs(3) && duration < Duration::from_secs(4), "session lasted {:?}", duration);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn messages_of_the_agent_move_the_deadline() {
+        let duration = session_duration(5).await;
+        assert!(duration >= Duration::from_secs(8) && duration < Duration::from_secs(9), "session lasted {:?}", duration);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use dotenv::dotenv;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::time::interval;
+
+use crate::queue::QueueItem;
+
+pub type SharedState = Arc<RwLock<QueueState>>;
+
+// Latest queue of the discovery and when it was last confirmed to be current
+#[derive(Debug, Default)]
+pub struct QueueState {
+    items: Option<Vec<QueueItem>>,
+    // Last snapshot, delta or heartbeat of the discovery
+    confirmed_at: Option<SystemTime>,
+    // Loaded from the last-known-good file and not yet confirmed by the discovery
+    restored: bool,
+    // Increased on every change of the items, so unchanged queues are not persisted again
+    generation: u64,
+}
+
+impl QueueState {
+    pub fn items(&self) -> Option<&Vec<QueueItem>> {
+        self.items.as_ref()
+    }
+
+    pub fn replace(&mut self, items: Vec<QueueItem>) {
+        // A confirmed restore is persisted again, with its new confirmation time
+        if self.restored || self.items.as_ref() != Some(&items) {
+            self.items = Some(items);
+            self.generation += 1;
+        }
+        self.confirm();
+    }
+
+    // The discovery reports that the queue did not change
+    pub fn confirm(&mut self) {
+        self.confirmed_at = Some(SystemTime::now());
+        self.restored = false;
+    }
+
+    // Time since the queue was last confirmed, None before the first queue
+    pub fn age(&self) -> Option<Duration> {
+        self.confirmed_at.map(|confirmed_at| SystemTime::now().duration_since(confirmed_at).unwrap_or_default())
+    }
+
+    pub fn is_restored(&self) -> bool {
+        self.restored
+    }
+}
+
+// Last-known-good queue as written to QUEUE_SNAPSHOT_PATH
+#[derive(Serialize, Deserialize)]
+struct SavedQueue {
+    // Unix time the queue was last confirmed
+    confirmed_at: u64,
+    items: Vec<QueueItem>,
+}
+
+pub fn snapshot_path() -> Option<PathBuf> {
+    dotenv().ok();
+    env::var("QUEUE_SNAPSHOT_PATH").ok().filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+// Starts with the last-known-good queue, so a restarted balancer can serve before the discovery answers
+pub fn restore(path: Option<&Path>) -> QueueState {
+    let Some(path) = path else {
+        return QueueState::default();
+    };
+
+    let saved = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return QueueState::default(),
+        Err(e) => {
+            warn!("Failed to read last-known-good queue {}: {}", path.display(), e);
+            return QueueState::default();
+        }
+    };
+    match serde_json::from_str::<SavedQueue>(&saved) {
+        Ok(saved) => {
+            let confirmed_at = UNIX_EPOCH + Duration::from_secs(saved.confirmed_at);
+            info!("Restored {} backend(s) from {} (last confirmed {:?} ago)",
+                  saved.items.len(), path.display(), SystemTime::now().duration_since(confirmed_at).unwrap_or_default());
+            QueueState {
+                items: Some(saved.items),
+                confirmed_at: Some(confirmed_at),
+                restored: true,
+                generation: 0,
+            }
+        }
+        Err(e) => {
+            warn!("Ignoring invalid last-known-good queue {}: {}", path.display(), e);
+            QueueState::default()
+        }
+    }
+}
+
+// Writes the queue to the snapshot file whenever it changed
+pub async fn persist_queue(shared_state: SharedState, path: PathBuf) {
+    let mut interval = interval(Duration::from_secs(5));
+    let mut persisted_generation = 0;
+    loop {
+        interval.tick().await;
+        let saved = {
+            let state = shared_state.read().await;
+            match (&state.items, state.confirmed_at) {
+                (Some(items), Some(confirmed_at)) if state.generation != persisted_generation => {
+                    persisted_generation = state.generation;
+                    SavedQueue {
+                        confirmed_at: confirmed_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                        items: items.clone(),
+                    }
+                }
+                _ => continue,
+            }
+        };
+
+        // Written next to the target and renamed, so a crash never leaves a partial file
+        let tmp_path = path.with_extension("tmp");
+        let content = serde_json::to_string(&saved).expect("Failed to serialize queue");
+        let result = match tokio::fs::write(&tmp_path, content).await {
+            Ok(()) => tokio::fs::rename(&tmp_path, &path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to persist last-known-good queue to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::UtilizationCategory;
+
+    fn items(dns_names: &[&str]) -> Vec<QueueItem> {
+        dns_names.iter().map(|dns_name| QueueItem::new(dns_name.to_string(), 50.0, UtilizationCategory::Low)).collect()
+    }
+
+    fn snapshot_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("balancer-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn persisted_queues_are_restored() {
+        let path = snapshot_file("snapshot-round-trip.json");
+        let mut state = QueueState::default();
+        state.replace(items(&["a:80", "b:80"]));
+        let shared_state = Arc::new(RwLock::new(state));
+        let persist = tokio::spawn(persist_queue(shared_state, path.clone()));
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        persist.abort();
+
+        let restored = restore(Some(&path));
+        assert_eq!(restored.items(), Some(&items(&["a:80", "b:80"])));
+        assert!(restored.is_restored());
+        assert!(restored.age().is_some_and(|age| age < Duration::from_secs(5)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_and_corrupt_files_start_empty() {
+        let path = snapshot_file("snapshot-corrupt.json");
+        for state in [restore(None), restore(Some(&path))] {
+            assert!(state.items().is_none());
+            assert!(!state.is_restored());
+        }
+
+        std::fs::write(&path, "{\"confirmed_at\": 12, \"items\": [").unwrap();
+        let state = restore(Some(&path));
+        assert!(state.items().is_none());
+        assert!(!state.is_restored());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restored_queues_are_kept_until_confirmed() {
+        let path = snapshot_file("snapshot-restored.json");
+        let saved = SavedQueue { confirmed_at: 12, items: items(&["a:80"]) };
+        std::fs::write(&path, serde_json::to_string(&saved).unwrap()).unwrap();
+
+        let mut state = restore(Some(&path));
+        assert!(state.is_restored());
+        assert_eq!(state.generation, 0);
+        // The discovery confirms the same queue, which is persisted again with its new confirmation time
+        state.replace(items(&["a:80"]));
+        assert!(!state.is_restored());
+        assert_eq!(state.generation, 1);
+        assert!(state.age().is_some_and(|age| age < Duration::from_secs(5)));
+
+        let mut state = restore(Some(&path));
+        state.confirm();
+        assert!(!state.is_restored());
+        assert_eq!(state.generation, 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_changed_queues_increase_the_generation() {
+        let mut state = QueueState::default();
+        assert_eq!(state.age(), None);
+        state.replace(items(&["a:80"]));
+        assert_eq!(state.generation, 1);
+        state.replace(items(&["a:80"]));
+        state.confirm();
+        assert_eq!(state.generation, 1);
+        state.replace(items(&["a:80", "b:80"]));
+        assert_eq!(state.generation, 2);
+        state.replace(Vec::new());
+        assert_eq!(state.generation, 3);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dotenv::dotenv;
+use log::warn;
+use protocol::{BackendTelemetry, BalancerMessage};
+use tokio::sync::Mutex;
+use tokio::time::interval;
+
+use crate::socket::EventSender;
+
+#[derive(Default)]
+struct BackendStats {
+    requests: u64,
+    errors: u64,
+    in_flight: u64,
+    latencies_ms: Vec<f64>,
+}
+
+// Collects request outcomes per backend and reports them periodically
+pub struct Telemetry {
+    backends: Mutex<HashMap<String, BackendStats>>,
+    window_start: Mutex<Instant>,
+}
+
+impl Telemetry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Telemetry {
+            backends: Mutex::new(HashMap::new()),
+            window_start: Mutex::new(Instant::now()),
+        })
+    }
+
+    // Marks a request to the backend as started
+    pub async fn begin(&self, dns_name: &str) {
+        let mut backends = self.backends.lock().await;
+        backends.entry(dns_name.to_string()).or_default().in_flight += 1;
+    }
+
+    // Records the outcome of a request started with begin
+    pub async fn finish(&self, dns_name: &str, latency: Duration, is_error: bool) {
+        let mut backends = self.backends.lock().await;
+        let stats = backends.entry(dns_name.to_string()).or_default();
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+        stats.requests += 1;
+        if is_error {
+            stats.errors += 1;
+        }
+        stats.latencies_ms.push(latency.as_secs_f64() * 1000.0);
+    }
+
+    // Summarizes the current window and starts a new one
+    pub async fn snapshot(&self) -> Vec<BackendTelemetry> {
+        let mut backends = self.backends.lock().await;
+        let mut window_start = self.window_start.lock().await;
+        let window_secs = window_start.elapsed().as_secs_f64().max(f64::EPSILON);
+        *window_start = Instant::now();
+
+        let snapshot = backends
+            .iter_mut()
+            .map(|(dns_name, stats)| {
+                stats.latencies_ms.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let telemetry = BackendTelemetry {
+                    dns_name: dns_name.clone(),
+                    rps: stats.requests as f64 / window_secs,
+                    in_flight: stats.in_flight,
+                    p50_latency_ms: percentile(&stats.latencies_ms, 0.50),
+                    p95_latency_ms: percentile(&stats.latencies_ms, 0.95),
+                    p99_latency_ms: percentile(&stats.latencies_ms, 0.99),
+                    error_rate: if stats.requests > 0 { stats.errors as f64 / stats.requests as f64 } else { 0.0 },
+                };
+                stats.requests = 0;
+                stats.errors = 0;
+                stats.latencies_ms.clear();
+                telemetry
+            })
+            .collect();
+
+        // Forgets backends without traffic
+        backends.retain(|_, stats| stats.in_flight > 0);
+        snapshot
+    }
+}
+
+// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Sends the request telemetry to the deployment agent every TELEMETRY_INTERVAL seconds
+pub async fn report_telemetry(telemetry: Arc<Telemetry>, event_sender: EventSender) {
+    dotenv().ok();
+    let report_interval = Duration::from_secs(
+        env::var("TELEMETRY_INTERVAL")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse::<u64>()
+            .expect("TELEMETRY_INTERVAL must be a valid u64")
+    );
+
+    let mut interval = interval(report_interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let backends = telemetry.snapshot().await;
+        if let Err(e) = event_sender.send(BalancerMessage::Telemetry { backends }) {
+            warn!("Failed to queue telemetry event: {}", e);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::env;
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use crate::metrics::metrics;
+use crate::scaling::{MetricsSnapshot, ScalingDecision, ScalingPolicy};
+
+// Structured record of a single scaling evaluation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScalingEvaluation {
+    // Redis stream id, set when the evaluation is read back
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    // What triggered the evaluation: policy, schedule or scale_to_zero
+    pub source: String,
+    pub policy: String,
+    pub parameters: serde_json::Value,
+    pub inputs: serde_json::Value,
+    pub cooldown: String,
+    pub active_schedules: Vec<String>,
+    pub action: String,
+    pub count: usize,
+    pub reason: String,
+    pub containers: Vec<String>,
+    // applied, dry_run, held, skipped_cooldown, skipped_max_containers or failed
+    pub outcome: String,
+    pub dry_run: bool,
+}
+
+impl ScalingEvaluation {
+    pub fn new(source: &str, policy: &str, parameters: serde_json::Value, inputs: serde_json::Value, decision: &ScalingDecision) -> Self {
+        let (count, containers) = match decision {
+            ScalingDecision::ScaleOut { count, .. } => (*count, Vec::new()),
+            ScalingDecision::ScaleIn { containers, .. } => (containers.len(), containers.clone()),
+            ScalingDecision::Hold { .. } => (0, Vec::new()),
+        };
+
+        ScalingEvaluation {
+            id: String::new(),
+            timestamp: Utc::now(),
+            source: source.to_string(),
+            policy: policy.to_string(),
+            parameters,
+            inputs,
+            cooldown: String::new(),
+            active_schedules: Vec::new(),
+            action: decision.action().to_string(),
+            count,
+            reason: decision.reason().to_string(),
+            containers,
+            outcome: if matches!(decision, ScalingDecision::Hold { .. }) { "held" } else { "applied" }.to_string(),
+            dry_run: is_dry_run(),
+        }
+    }
+
+    // Evaluation of the configured scaling policy on a metrics snapshot
+    pub fn from_policy(policy: &dyn ScalingPolicy, snapshot: &MetricsSnapshot, decision: &ScalingDecision) -> Self {
+        let inputs = serde_json::to_value(snapshot).unwrap_or_default();
+        Self::new("policy", &policy.name(), policy.parameters(), inputs, decision)
+    }
+}
+
+// In dry-run mode decisions are recorded, but no containers are created or marked SUNDOWN
+pub fn is_dry_run() -> bool {
+    dotenv().ok();
+    env::var("SCALING_DRY_RUN")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+fn events_key(app_identifier: &str) -> String {
+    format!("scaling_events:{}", app_identifier)
+}
+
+// Appends the evaluation to the capped stream of scaling events
+pub fn record_evaluation(conn: &mut redis::Connection, app_identifier: &str, evaluation: &ScalingEvaluation) {
+    metrics().record_scaling(evaluation);
+    let max_len = env::var("SCALING_EVENTS_MAX_LEN")
+        .unwrap_or_else(|_| "1000".to_string())
+        .parse::<usize>()
+        .expect("SCALING_EVENTS_MAX_LEN must be a valid usize");
+    let json = serde_json::to_string(evaluation).expect("Failed to serialize scaling evaluation");
+
+    let mut command = redis::cmd("XADD");
+    command
+        .arg(events_key(app_identifier))
+        .arg("MAXLEN")
+        .arg("~")
+        .arg(max_len)
+        .arg("*")
+        .arg("event")
+        .arg(json);
+    let result: redis::RedisResult<String> = metrics().time_redis("xadd", || command.query(conn));
+    if let Err(e) = result {
+        eprintln!("Failed to record scaling evaluation in Redis: {:?}", e);
+    }
+}
+
+// Loads the latest evaluations, newest first
+pub fn load_evaluations(conn: &mut redis::Connection, app_identifier: &str, limit: usize) -> redis::RedisResult<Vec<ScalingEvaluation>> {
+    let entries: Vec<(String, Vec<String>)> = redis::cmd("XREVRANGE")
+        .arg(events_key(app_identifier))
+        .arg("+")
+        .arg("-")
+        .arg("COUNT")
+        .arg(limit)
+        .query(conn)?;
+
+    Ok(entries.into_iter()
+        .filter_map(|(id, fields)| {
+            let json = fields.chunks(2).find(|pair| pair[0] == "event").and_then(|pair| pair.get(1))?;
+            let mut evaluation: ScalingEvaluation = serde_json::from_str(json).ok()?;
+            evaluation.id = id;
+            Some(evaluation)
+        })
+        .collect())
+}
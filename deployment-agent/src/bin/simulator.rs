@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+use deployment_agent::scaling::{parse_policy, policy_from_env};
+use deployment_agent::schedule::ScheduleRule;
+use deployment_agent::simulation::{Demand, RecordedTick, SimulationConfig, Simulator, SyntheticLoad};
+
+const USAGE: &str = "Usage: simulator (--input <recording.jsonl> | --synthetic) [options]
+
+Replays recorded or synthetic load through a scaling policy. Scaling settings are
+read from the same environment variables (and .env file) as the deployment agent.
+
+Input:
+  --input <file>          Recording, one {\"timestamp\": <unix s>, \"statuses\": <GET /stats>} per line
+  --synthetic             Daily load curve instead of a recording
+  --days <n>              Simulated days of the synthetic load (default 1)
+  --interval <s>          Step of the synthetic load (default 10)
+  --start <unix s>        Start of the synthetic load (default 1704067200)
+  --base-rps <rps>        Lowest request rate of the synthetic load (default 10)
+  --peak-rps <rps>        Highest request rate of the synthetic load (default 200)
+  --peak-hour <h>         Hour (UTC) of the synthetic peak (default 14)
+  --capacity-rps <rps>    Request rate that fully utilizes one container (default 50)
+  --latency-ms <ms>       p95 latency of a container that is not overloaded (default 50)
+
+Simulation:
+  --policy <spec>         Scaling policy, overrides SCALING_POLICY
+  --schedules <file>      JSON array of scheduled scaling rules
+  --startup <s>           Time until a new container serves requests (default 30)
+  --slo-latency-ms <ms>   p95 latency counted as SLO violation (default LATENCY_TARGET_MS)
+
+Output:
+  --format <csv|json>     Output format (default json)
+  --output <file>         Output file for the report or the CSV ticks (default stdout)
+  --events <file>         Output file for the CSV scaling events";
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => fail(&e),
+    };
+    let option = |name: &str| options.get(name).cloned();
+    let number = |name: &str, default: f64| -> f64 {
+        option(name).map_or(default, |v| v.parse().unwrap_or_else(|_| fail(&format!("--{} must be a number", name))))
+    };
+
+    let mut config = SimulationConfig::from_env();
+    config.startup_secs = number("startup", config.startup_secs as f64) as i64;
+    config.slo_latency_ms = number("slo-latency-ms", config.slo_latency_ms);
+    if let Some(path) = option("schedules") {
+        let json = fs::read_to_string(&path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", path, e)));
+        config.schedules = serde_json::from_str::<Vec<ScheduleRule>>(&json)
+            .unwrap_or_else(|e| fail(&format!("Invalid schedules in {}: {}", path, e)));
+        for rule in &config.schedules {
+            rule.validate().unwrap_or_else(|e| fail(&format!("Invalid schedule {}: {}", rule.id, e)));
+        }
+    }
+
+    let policy = match option("policy") {
+        Some(spec) => parse_policy(&spec).unwrap_or_else(|e| fail(&e)),
+        None => policy_from_env(),
+    };
+
+    let demands: Vec<Demand> = if let Some(path) = option("input") {
+        let recording = fs::read_to_string(&path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", path, e)));
+        recording.lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                let tick: RecordedTick = serde_json::from_str(line)
+                    .unwrap_or_else(|e| fail(&format!("Invalid recording at line {}: {}", i + 1, e)));
+                Demand::from_recorded(&tick)
+            })
+            .collect()
+    } else if options.contains_key("synthetic") {
+        let load = SyntheticLoad {
+            base_rps: number("base-rps", 10.0),
+            peak_rps: number("peak-rps", 200.0),
+            capacity_rps: number("capacity-rps", 50.0),
+            latency_ms: number("latency-ms", 50.0),
+            season_secs: 86400,
+            peak_offset_secs: (number("peak-hour", 14.0) * 3600.0) as i64,
+        };
+        let start = number("start", 1704067200.0) as i64;
+        let interval = (number("interval", 10.0) as i64).max(1);
+        let end = start + (number("days", 1.0) * 86400.0) as i64;
+        (start..end).step_by(interval as usize).map(|t| load.demand(t)).collect()
+    } else {
+        fail("Either --input or --synthetic is required");
+    };
+
+    let mut simulator = Simulator::new(config, policy);
+    for demand in &demands {
+        simulator.step(demand);
+    }
+    let report = simulator.finish();
+
+    let output = match option("format").as_deref().unwrap_or("json") {
+        "json" => serde_json::to_string_pretty(&report).expect("Failed to serialize simulation report"),
+        "csv" => report.ticks_csv(),
+        other => fail(&format!("Unknown format '{}'", other)),
+    };
+    match option("output") {
+        Some(path) => fs::write(&path, output).unwrap_or_else(|e| fail(&format!("Failed to write {}: {}", path, e))),
+        None => print!("{}", output),
+    }
+    if let Some(path) = option("events") {
+        fs::write(&path, report.events_csv()).unwrap_or_else(|e| fail(&format!("Failed to write {}: {}", path, e)));
+    }
+
+    let summary = &report.summary;
+    eprintln!("Policy {}: {} ticks, {} SLO violations, {} scale-outs, {} scale-ins, {:.2} containers on average (peak {}), {} container-seconds",
+              summary.policy, summary.ticks, summary.slo_violations, summary.scale_out_events, summary.scale_in_events,
+              summary.average_containers, summary.peak_containers, summary.container_seconds);
+}
+
+// Collects --name value pairs, --synthetic is the only flag without value
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<HashMap<String, String>, String> {
+    let mut options = HashMap::new();
+    while let Some(arg) = args.next() {
+        let name = arg.strip_prefix("--").ok_or_else(|| format!("Unexpected argument '{}'", arg))?;
+        match name {
+            "help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "synthetic" => {
+                options.insert(name.to_string(), String::new());
+            }
+            _ => {
+                let value = args.next().ok_or_else(|| format!("Missing value for --{}", name))?;
+                options.insert(name.to_string(), value);
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
+}
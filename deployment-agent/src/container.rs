@@ -1,20 +1,17 @@
-use bollard::Docker;
-use bollard::container::{Config, CreateContainerOptions, ListContainersOptions, StartContainerOptions, StopContainerOptions, RemoveContainerOptions};
-use bollard::models::{HostConfig, PortBinding, ContainerSummary as APIContainers};
-use bollard::errors::Error;
-use std::collections::HashMap;
-use std::env;
+use std::collections::{BTreeMap, HashMap};
 use rand::Rng;
-use bollard::image::{CreateImageOptions, ListImagesOptions};
-use futures_util::StreamExt;
-use md5;
 use redis::Commands;
 use uuid::Uuid;
 use crate::db;
-use crate::queue::QueueItem;
+use crate::metrics::metrics;
+use crate::queue::{QueueItem, UtilizationCategory};
+use crate::runtime::{ContainerRuntime, ContainerSpec, RuntimeError};
+use std::env;
 use std::time::Duration;
+use dotenv::dotenv;
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
 use tokio::time::sleep;
-use std::time::Instant;
 
 pub fn generate_hash_based_key(app_identifier: &str, container_name: &str) -> String {
     let data = format!("{}:{}", app_identifier, container_name);
@@ -29,13 +26,13 @@ fn store_container_info_init(
     image: &str,
 ) -> redis::RedisResult<()> {
     println!("Storing container info for key: {}", key);
-    let _: () = conn.hset(key, "category", "INIT")?;
-    let _: () = conn.hset(key, "score", "100")?;
-    let _: () = conn.hset(key, "port", port.to_string())?;
-    let _: () = conn.hset(key, "image", image)?;
+    metrics().time_redis("hset", || conn.hset::<_, _, _, ()>(key, "category", UtilizationCategory::Init.as_str()))?;
+    metrics().time_redis("hset", || conn.hset::<_, _, _, ()>(key, "score", "100"))?;
+    metrics().time_redis("hset", || conn.hset::<_, _, _, ()>(key, "port", port.to_string()))?;
+    metrics().time_redis("hset", || conn.hset::<_, _, _, ()>(key, "image", image))?;
 
     // Verify that all fields were set correctly
-    let fields: HashMap<String, String> = conn.hgetall(key)?;
+    let fields: HashMap<String, String> = metrics().time_redis("hgetall", || conn.hgetall(key))?;
     if fields.len() != 4 || !fields.contains_key("category") || !fields.contains_key("score")
         || !fields.contains_key("port") || !fields.contains_key("image") {
         println!("Not all fields were set correctly for key: {}. Fields: {:?}", key, fields);
@@ -49,145 +46,36 @@ fn store_container_info_init(
 pub fn update_container_category(
     conn: &mut redis::Connection,
     key: &str,
-    category: &str,
+    category: UtilizationCategory,
 ) -> redis::RedisResult<()> {
     println!("Updating container category for key: {} to {}", key, category);
-    conn.hset(key, "category", category)
-}
-
-async fn pull_image(docker: &Docker, image_name: &str) -> Result<(), Error> {
-    println!("Checking if image exists locally: {}", image_name);
-
-    // Check if the image already exists locally
-    let list_options = ListImagesOptions::<String> {
-        all: true,
-        filters: {
-            let mut filters = HashMap::new();
-            filters.insert("reference".to_string(), vec![image_name.to_string()]);
-            filters
-        },
-        ..Default::default()
-    };
-
-    let images = docker.list_images(Some(list_options)).await?;
-
-    if !images.is_empty() {
-        println!("Image {} already exists locally. Skipping pull.", image_name);
-        return Ok(());
-    }
-
-    println!("Image not found locally. Starting to pull image: {}", image_name);
-    let start_time = Instant::now();
-    let create_image_options = CreateImageOptions {
-        from_image: image_name,
-        ..Default::default()
-    };
-    let mut retry_count = 0;
-    let max_retries = 3;
-    let mut delay = Duration::from_secs(5);
-
-    while retry_count < max_retries {
-        let mut stream = docker.create_image(Some(create_image_options.clone()), None, None);
-        let mut success = true;
-
-        while let Some(pull_result) = stream.next().await {
-            match pull_result {
-                Ok(output) => {
-                    if let Some(status) = output.status {
-                        let elapsed = start_time.elapsed().as_secs();
-                        match status.as_str() {
-                            "Downloading" => {
-                                if let (Some(id), Some(progress_detail)) = (output.id, output.progress_detail) {
-                                    if let (Some(current), Some(total)) = (progress_detail.current, progress_detail.total) {
-                                        println!("[{}s] Downloading layer {}: {:.1}% ({}/{} bytes)",
-                                                 elapsed, &id[..12], (current as f64 / total as f64) * 100.0, current, total);
-                                    }
-                                }
-                            }
-                            "Extracting" => {
-                                if let Some(id) = output.id {
-                                    println!("[{}s] Extracting layer {}", elapsed, &id[..12]);
-                                }
-                            }
-                            "Download complete" | "Pull complete" => {
-                                if let Some(id) = output.id {
-                                    println!("[{}s] Layer {} completed", elapsed, &id[..12]);
-                                }
-                            }
-                            _ => println!("[{}s] Status: {}", elapsed, status),
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Error while pulling image: {:?}", e);
-                    success = false;
-                    break;
-                }
-            }
-        }
-
-        if success {
-            println!("Image pulled successfully: {} (took {}s)", image_name, start_time.elapsed().as_secs());
-            return Ok(());
-        }
-
-        retry_count += 1;
-        if retry_count < max_retries {
-            println!("Retrying image pull in {:?}...", delay);
-            sleep(delay).await;
-            delay *= 2; // Exponential backoff
-        }
-    }
-
-    println!("Max retries reached while pulling image: {}", image_name);
-    Err(Error::IOError { err: std::io::Error::new(std::io::ErrorKind::Other, "Max retries reached while pulling image") })
+    metrics().time_redis("hset", || conn.hset(key, "category", category.as_str()))
 }
 
 pub async fn create_container(
+    runtime: &dyn ContainerRuntime,
     container_name: &str,
     image_name: &str,
     target_port: u16,
     app_identifier: &str,
     conn: &mut redis::Connection,
-) -> Result<String, Error> {
+) -> Result<String, RuntimeError> {
     println!("Creating container: {}", container_name);
-    let docker = Docker::connect_with_unix_defaults().expect("Failed to connect to Docker");
-
-    pull_image(&docker, image_name).await?;
-
-    let host_port: u16 = rand::thread_rng().gen_range(30000..40000);
-
-    let port_bindings = {
-        let mut map = HashMap::new();
-        map.insert(
-            format!("{}/tcp", target_port),
-            Some(vec![PortBinding {
-                host_ip: Some("0.0.0.0".to_string()),
-                host_port: Some(host_port.to_string()),
-            }]),
-        );
-        map
-    };
-
-    let host_config = HostConfig {
-        port_bindings: Some(port_bindings),
-        network_mode: Some("rust-network".to_string()),
-        ..Default::default()
-    };
 
     let mut labels = HashMap::new();
     labels.insert("application".to_string(), app_identifier.to_string());
+    // Balancers prefer workers of their own zone; cluster nodes replace it with their zone
+    if let Some(zone) = worker_zone() {
+        labels.insert("zone".to_string(), zone);
+    }
 
-    let config = Config {
-        image: Some(image_name.to_string()),
-        host_config: Some(host_config),
-        labels: Some(labels),
-        ..Default::default()
-    };
-
-    let create_options = CreateContainerOptions {
-        name: container_name,
-        ..Default::default()
+    let mut spec = ContainerSpec {
+        name: container_name.to_string(),
+        image: image_name.to_string(),
+        target_port,
+        host_port: 0,
+        labels,
+        network: Some("rust-network".to_string()),
     };
 
     let key = generate_hash_based_key(app_identifier, container_name);
@@ -197,24 +85,28 @@ pub async fn create_container(
     let mut delay = Duration::from_secs(5);
 
     while retry_count < max_retries {
-        match docker.create_container(Some(create_options.clone()), config.clone()).await {
-            Ok(create_response) => {
+        // A new port on every attempt, the last one may have been taken in the meantime
+        spec.host_port = rand::thread_rng().gen_range(30000..40000);
+        match runtime.create(&spec).await {
+            Ok(container_id) => {
                 println!("Container created successfully: {}", container_name);
 
                 // Store container info in Redis
-                if let Err(e) = store_container_info_init(conn, &key, host_port, image_name) {
+                if let Err(e) = store_container_info_init(conn, &key, spec.host_port, image_name) {
                     println!("Failed to store container info in Redis: {:?}", e);
                     // Consider whether to proceed or return an error here
                 }
 
-                // Start the container
-                match docker.start_container(&create_response.id, None::<StartContainerOptions<String>>).await {
+                // Start the container, a worker only counts as created once it runs
+                match runtime.start(&container_id).await {
                     Ok(_) => {
                         println!("Container started successfully: {}", container_name);
+                        metrics().record_created();
                         return Ok(container_name.to_string());
                     },
                     Err(e) => {
                         println!("Failed to start container: {:?}", e);
+                        metrics().record_failed();
                         // Consider cleanup actions here
                         return Err(e);
                     }
@@ -233,201 +125,185 @@ pub async fn create_container(
     }
 
     println!("Max retries reached while creating container: {}", container_name);
-    Err(Error::IOError { err: std::io::Error::new(std::io::ErrorKind::Other, "Max retries reached while creating container") })
+    metrics().record_failed();
+    Err(RuntimeError::Other("Max retries reached while creating container".to_string()))
 }
 
 pub async fn create_single_container(
+    runtime: &dyn ContainerRuntime,
     image_name: &str,
     target_port: u16,
     app_identifier: &str,
     conn: &mut redis::Connection,
-) -> Result<QueueItem, Error> {
+) -> Result<QueueItem, RuntimeError> {
     let uuid = Uuid::new_v4();
     let container_name = format!("worker-{}", &uuid.to_string()[..8]);
 
-    match create_container(&container_name, image_name, target_port, app_identifier, conn).await {
+    match create_container(runtime, &container_name, image_name, target_port, app_identifier, conn).await {
         Ok(dns_name) => {
-            let item = QueueItem {
-                dns_name,
-                score: 100.0,
-                utilization_category: "LU".to_string(),
-            };
-            Ok(item)
+            Ok(QueueItem::new(dns_name, 100.0, UtilizationCategory::Low))
         }
         Err(e) => Err(e)
     }
 }
 
-pub async fn list_running_containers(app_identifier: &str) -> Result<Vec<APIContainers>, Error> {
-    println!("Listing running containers for app: {}", app_identifier);
-    let docker = Docker::connect_with_unix_defaults().expect("Failed to connect to Docker");
-
-    let filters = {
-        let mut map = HashMap::new();
-        map.insert("label".to_string(), vec![format!("application={}", app_identifier)]);
-        map.insert("status".to_string(), vec!["running".to_string()]);
-        map
-    };
-
-    let options = ListContainersOptions {
-        all: false,
-        filters,
-        ..Default::default()
-    };
+pub async fn remove_container(runtime: &dyn ContainerRuntime, app_identifier: &str, container_name: &str) -> Result<(), RuntimeError> {
+    println!("Removing container: {}", container_name);
 
-    let containers = docker.list_containers(Some(options)).await?;
-    println!("Found {} running containers for app: {}", containers.len(), app_identifier);
-    Ok(containers)
-}
+    // First, stop the container
+    match runtime.stop(container_name).await {
+        Ok(_) => println!("Container stopped: {}", container_name),
+        Err(e) => println!("Error stopping container {}: {:?}", container_name, e),
+    }
 
-pub async fn check_and_stop_container_if_not_in_db(
-    container: &APIContainers,
-    conn: &mut redis::Connection,
-    app_identifier: &str,
-) -> Result<Option<QueueItem>, Error> {
-    let docker = Docker::connect_with_unix_defaults().expect("Failed to connect to Docker");
-
-    let container_name = container.names.as_ref()
-        .and_then(|names| names.get(0).cloned())
-        .unwrap_or_default()
-        .trim_start_matches('/')
-        .to_string();
-
-    println!("Checking container: {}", container_name);
-
-    let key = generate_hash_based_key(app_identifier, &container_name);
-
-    if db::check_config_value_exists(conn, &key) {
-        let category: String = conn.hget(&key, "category").unwrap_or_else(|_| "Unknown".to_string());
-        println!("Container {} found in DB with category: {}", container_name, category);
-        Ok(Some(QueueItem {
-            dns_name: container_name,
-            score: 100.0,
-            utilization_category: category,
-        }))
-    } else {
-        println!("Container {} not found in DB, stopping and removing", container_name);
-        docker.stop_container(container.id.as_deref().unwrap_or(""), None::<StopContainerOptions>).await?;
-        docker.remove_container(container.id.as_deref().unwrap_or(""), None::<RemoveContainerOptions>).await?;
-        Ok(None)
+    // Then, remove the container
+    match runtime.remove(container_name).await {
+        Ok(_) => println!("Container removed: {}", container_name),
+        Err(e) => println!("Error removing container {}: {:?}", container_name, e),
     }
-}
 
-pub fn cleanup_orphaned_db_entries(
-    conn: &mut redis::Connection,
-    running_container_keys: &HashMap<String, String>,
-) -> Result<(), Error> {
-    println!("Cleaning up orphaned DB entries");
-    let keys: Vec<String> = conn.keys("container:*")
-        .map_err(|e| Error::from(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))?;
-
-    for key in keys {
-        if !running_container_keys.contains_key(&key) {
-            println!("Deleting orphaned database entry '{}'", key);
-            let _: () = conn.del(&key)
-                .map_err(|e| Error::from(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())))?;
-        }
+    // Remove container info from Redis
+    let mut conn = db::get_redis_connection();
+    let key = generate_hash_based_key(app_identifier, container_name);
+    match metrics().time_redis("del", || conn.del::<_, ()>(&key)) {
+        Ok(_) => println!("Container info removed from Redis: {}", container_name),
+        Err(e) => println!("Error removing container info from Redis: {:?}", e),
     }
 
     Ok(())
 }
 
-pub async fn manage_containers(app_identifier: &str, default_container: i16) -> Result<Vec<QueueItem>, Error> {
-    println!("Managing containers for app: {}", app_identifier);
-    let mut conn = db::get_redis_connection();
-    let image_name = env::var("DOCKER_IMAGE").expect("DOCKER_IMAGE must be set");
-    let target_port: u16 = env::var("TARGET_PORT")
-        .expect("TARGET_PORT must be set")
-        .parse()
-        .expect("TARGET_PORT must be a valid number");
-
-    let containers = list_running_containers(app_identifier).await?;
-
-    let mut running_containers = HashMap::new();
-    let mut queue_items = Vec::new();
-
-    // Step 1: Process existing containers
-    for container in containers {
-        let container_name = container.names.as_ref()
-            .and_then(|names| names.get(0).cloned())
-            .unwrap_or_default()
-            .trim_start_matches('/')
-            .to_string();
-
-        let key = generate_hash_based_key(app_identifier, &container_name);
-
-        // Check the database for SUNDOWN status
-        let db_category: String = conn.hget(&key, "category").unwrap_or_else(|_| "Unknown".to_string());
-
-        if db_category == "SUNDOWN" {
-            // If the container is marked as SUNDOWN in the database, maintain this state
-            queue_items.push(QueueItem {
-                dns_name: container_name.clone(),
-                score: 0.0, // Set a low score for SUNDOWN containers
-                utilization_category: "SUNDOWN".to_string(),
-            });
-        } else {
-            // For non-SUNDOWN containers, proceed with normal processing
-            if let Some(item) = check_and_stop_container_if_not_in_db(&container, &mut conn, app_identifier).await? {
-                if let Some(id) = &container.id {
-                    running_containers.insert(generate_hash_based_key(app_identifier, &item.dns_name), id.clone());
-                }
-                queue_items.push(item);
-            }
-        }
-    }
+fn worker_zone() -> Option<String> {
+    dotenv().ok();
+    env::var("WORKER_ZONE").ok().filter(|zone| !zone.is_empty())
+}
 
-    cleanup_orphaned_db_entries(&mut conn, &running_containers)?;
+// Where a worker accepts requests, as found by inspecting it
+#[derive(Debug, Clone, Default)]
+struct WorkerEndpoint {
+    host: Option<String>,
+    port: Option<u16>,
+    zone: Option<String>,
+    labels: BTreeMap<String, String>,
+}
 
-    let running_containers_count = running_containers.len() as i16;
-    println!("Current running containers: {}, Default containers: {}", running_containers_count, default_container);
+// Endpoints do not change while a worker exists, so every worker is only inspected once
+static WORKER_ENDPOINTS: Lazy<Mutex<HashMap<String, WorkerEndpoint>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-    // Step 2: Create new containers if needed
-    if running_containers_count < default_container {
-        for i in running_containers_count + 1..=default_container {
-            println!("Creating container {} of {}", i, default_container);
-            match create_single_container(&image_name, target_port, app_identifier, &mut conn).await {
-                Ok(item) => {
-                    println!("Container created successfully: {}", item.dns_name);
-                    queue_items.push(item);
-                }
-                Err(e) => println!("Failed to create container: {:?}", e),
-            }
-        }
+async fn worker_endpoint(runtime: &dyn ContainerRuntime, name: &str) -> WorkerEndpoint {
+    if let Some(endpoint) = WORKER_ENDPOINTS.lock().await.get(name) {
+        return endpoint.clone();
     }
 
-    Ok(queue_items)
-}
+    let details = match runtime.inspect(name).await {
+        Ok(details) => details,
+        Err(e) => {
+            // Balancers fall back to the published name and their target port
+            println!("Failed to inspect container {}: {:?}", name, e);
+            return WorkerEndpoint::default();
+        }
+    };
 
-pub async fn remove_container(app_identifier: &str, container_name: &str) -> Result<(), Error> {
-    println!("Removing container: {}", container_name);
-    let docker = Docker::connect_with_unix_defaults().expect("Failed to connect to Docker");
+    dotenv().ok();
+    // Balancers outside of the worker network use the published port on this host
+    let published_host = env::var("WORKER_PUBLISHED_HOST").ok().filter(|host| !host.is_empty());
+    let (host, port) = match (published_host, details.published_port) {
+        (Some(host), Some(port)) => (Some(host), Some(port)),
+        _ => match details.address.as_deref().and_then(|address| address.rsplit_once(':')) {
+            Some((host, port)) => (Some(host.to_string()), port.parse::<u16>().ok()),
+            None => (None, None),
+        },
+    };
+    let endpoint = WorkerEndpoint {
+        host,
+        port,
+        // Workers created before the zone label fall back to the zone of the runtime
+        zone: details.labels.get("zone").cloned().or(details.zone).or_else(worker_zone),
+        labels: details.labels.into_iter().collect(),
+    };
+    if endpoint.host.is_some() {
+        WORKER_ENDPOINTS.lock().await.insert(name.to_string(), endpoint.clone());
+    }
+    endpoint
+}
 
-    // First, stop the container
-    match docker.stop_container(container_name, None::<StopContainerOptions>).await {
-        Ok(_) => println!("Container stopped: {}", container_name),
-        Err(e) => println!("Error stopping container {}: {:?}", container_name, e),
+// Replaces the container names of the queue with the addresses the balancers should use and
+// adds the endpoints of the workers. Also returns the mapping back from address to container
+// name for their telemetry.
+pub async fn published_queue(
+    runtime: &dyn ContainerRuntime,
+    conn: &mut redis::Connection,
+    app_identifier: &str,
+    items: &[QueueItem],
+) -> (Vec<QueueItem>, HashMap<String, String>) {
+    dotenv().ok();
+    let scheme = env::var("WORKER_SCHEME").ok().filter(|scheme| !scheme.is_empty());
+
+    // Removed workers are forgotten
+    WORKER_ENDPOINTS.lock().await.retain(|name, _| items.iter().any(|item| item.dns_name == *name));
+
+    let mut names = HashMap::new();
+    let mut published = Vec::with_capacity(items.len());
+    for item in items {
+        let key = generate_hash_based_key(app_identifier, &item.dns_name);
+        let port: Option<u16> = metrics().time_redis("hget", || conn.hget(&key, "port")).ok();
+        let address = match port {
+            Some(port) => runtime.queue_address(&item.dns_name, port),
+            None => item.dns_name.clone(),
+        };
+        let endpoint = worker_endpoint(runtime, &item.dns_name).await;
+        names.insert(address.clone(), item.dns_name.clone());
+        published.push(QueueItem {
+            dns_name: address,
+            scheme: scheme.clone(),
+            host: endpoint.host,
+            port: endpoint.port,
+            zone: endpoint.zone,
+            labels: endpoint.labels,
+            ..item.clone()
+        });
     }
+    (published, names)
+}
 
-    // Then, remove the container
-    match docker.remove_container(
-        container_name,
-        Some(RemoveContainerOptions {
-            force: true,
-            ..Default::default()
-        }),
-    ).await {
-        Ok(_) => println!("Container removed: {}", container_name),
-        Err(e) => println!("Error removing container {}: {:?}", container_name, e),
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::runtime::{FakeRuntime, MeasuredRuntime};
+    use crate::testing;
+
+    // Value of a counter of the shared metrics, the tests holding the fake Redis run one at a time
+    fn counter(name: &str) -> u64 {
+        let prefix = format!("agent_{}_total ", name);
+        metrics().encode().lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .map_or(0, |value| value.parse().expect("counter value"))
     }
 
-    // Remove container info from Redis
-    let mut conn = db::get_redis_connection();
-    let key = generate_hash_based_key(app_identifier, container_name);
-    match conn.del::<_, ()>(&key) {
-        Ok(_) => println!("Container info removed from Redis: {}", container_name),
-        Err(e) => println!("Error removing container info from Redis: {:?}", e),
+    #[tokio::test(start_paused = true)]
+    async fn workers_are_counted_once_after_all_attempts() {
+        let mut redis = testing::redis().await;
+        let runtime = MeasuredRuntime::new(Arc::new(FakeRuntime::new()));
+        let (created, failed) = (counter("containers_created"), counter("containers_failed"));
+
+        create_container(&runtime, "worker-a", "worker:2", 8080, "test-app", &mut redis.conn).await.unwrap();
+        assert_eq!((counter("containers_created"), counter("containers_failed")), (created + 1, failed));
+
+        // The name is taken, so all three attempts fail
+        assert!(create_container(&runtime, "worker-a", "worker:2", 8080, "test-app", &mut redis.conn).await.is_err());
+        assert_eq!((counter("containers_created"), counter("containers_failed")), (created + 1, failed + 1));
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn worker_records_are_stored_on_creation() {
+        let mut redis = testing::redis().await;
+        let runtime = FakeRuntime::new();
+        create_container(&runtime, "worker-b", "worker:2", 8080, "test-app", &mut redis.conn).await.unwrap();
+
+        let fields: HashMap<String, String> = redis.conn.hgetall(generate_hash_based_key("test-app", "worker-b")).unwrap();
+        assert_eq!(fields.get("category").map(String::as_str), Some("INIT"));
+        assert_eq!(fields.get("image").map(String::as_str), Some("worker:2"));
+        assert_eq!(runtime.names(), vec!["worker-b".to_string()]);
+    }
+}
@@ -1,28 +1,31 @@
 use redis::{Commands, Connection};
 use std::env;
 use dotenv::dotenv;
+use crate::metrics::metrics;
 
-pub fn get_redis_connection() -> Connection {
+pub fn redis_url() -> String {
     dotenv().ok();
 
     let redis_host = env::var("REDIS_HOST").expect("REDIS_HOST must be set in .env");
     let redis_port = env::var("REDIS_PORT").unwrap_or("6379".to_string());
-    let redis_url = format!("redis://{}:{}", redis_host, redis_port);
+    format!("redis://{}:{}", redis_host, redis_port)
+}
 
-    let client = redis::Client::open(redis_url).expect("Invalid Redis URL");
+pub fn get_redis_connection() -> Connection {
+    let client = redis::Client::open(redis_url()).expect("Invalid Redis URL");
     client.get_connection().expect("Failed to connect to Redis")
 }
 
 pub fn get_config_value<T: redis::FromRedisValue>(conn: &mut Connection, key: &str) -> Option<T> {
-    conn.get(key).ok()
+    metrics().time_redis("get", || conn.get(key)).ok()
 }
 
 pub fn set_config_value<T: redis::ToRedisArgs>(conn: &mut Connection, key: &str, value: T) -> redis::RedisResult<()> {
-    conn.set(key, value)
+    metrics().time_redis("set", || conn.set(key, value))
 }
 
 pub fn check_config_value_exists(conn: &mut Connection, key: &str) -> bool {
-    conn.exists(key).unwrap_or(false)
+    metrics().time_redis("exists", || conn.exists(key)).unwrap_or(false)
 }
 
 pub fn init(conn: &mut Connection) {
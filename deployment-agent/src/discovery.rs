@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use dotenv::dotenv;
+use protocol::{decode, encode, AgentMessage, BalancerMessage, PublishedEvent};
+use redis::Commands;
+use tokio::sync::mpsc;
+use crate::db;
+use crate::queue::{latest_queue, request_reconcile, wake_from_zero, QueueItem};
+use crate::socket::next_connection_id;
+use crate::telemetry::record_report;
+
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+// Key and channel of the queue for balancers using the Redis discovery; events are published on <channel>:events
+pub fn queue_channel() -> String {
+    dotenv().ok();
+    env::var("QUEUE_CHANNEL").unwrap_or_else(|_| "queue-updates".to_string())
+}
+
+// Stores the queue as a snapshot and notifies subscribed balancers. Published after every
+// rebuild, even if unchanged, so balancers can tell a silent agent from a stable queue.
+pub fn publish_queue(conn: &mut redis::Connection, items: &[QueueItem]) -> redis::RedisResult<()> {
+    let channel = queue_channel();
+    let snapshot = encode(&AgentMessage::Snapshot {
+        seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+        items: items.to_vec(),
+    });
+    let _: () = conn.set(&channel, &snapshot)?;
+    let _: () = conn.publish(&channel, snapshot)?;
+    Ok(())
+}
+
+// Receives events of balancers using the Redis discovery
+pub async fn run_event_subscriber() {
+    let channel = format!("{}:events", queue_channel());
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    // The Redis client is synchronous, so the subscription blocks a thread of its own
+    let subscribed_channel = channel.clone();
+    tokio::task::spawn_blocking(move || loop {
+        if let Err(e) = forward_events(&subscribed_channel, &sender) {
+            eprintln!("Redis subscription to {} failed: {}. Retrying in 5 seconds...", subscribed_channel, e);
+        }
+        if sender.is_closed() {
+            return;
+        }
+        std::thread::sleep(Duration::from_secs(5));
+    });
+
+    println!("Listening for balancer events on Redis channel {}", channel);
+    // Telemetry reports are kept per balancer
+    let mut reporters: HashMap<String, usize> = HashMap::new();
+    while let Some(payload) = receiver.recv().await {
+        match decode::<PublishedEvent>(&payload) {
+            Ok(event) => {
+                let reporter = *reporters.entry(event.balancer.clone()).or_insert_with(next_connection_id);
+                handle_event(&event.balancer, reporter, event.message).await;
+            }
+            Err(e) => eprintln!("Failed to parse balancer event: {}", e),
+        }
+    }
+}
+
+fn forward_events(channel: &str, sender: &mpsc::UnboundedSender<String>) -> redis::RedisResult<()> {
+    let client = redis::Client::open(db::redis_url())?;
+    let mut conn = client.get_connection()?;
+    let mut pubsub = conn.as_pubsub();
+    pubsub.subscribe(channel)?;
+
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+        if sender.send(payload).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn handle_event(balancer: &str, reporter: usize, message: BalancerMessage) {
+    match message {
+        BalancerMessage::Wake => {
+            println!("Received wake-up event from balancer {}", balancer);
+            wake_from_zero().await;
+            request_reconcile();
+        }
+        BalancerMessage::Telemetry { mut backends } => {
+            println!("Received telemetry for {} backend(s) from balancer {}", backends.len(), balancer);
+            // Telemetry is keyed by the published address, scaling by container name
+            if let Some(queue) = latest_queue() {
+                for backend in &mut backends {
+                    if let Some(name) = queue.names.get(&backend.dns_name) {
+                        backend.dns_name = name.clone();
+                    }
+                }
+            }
+            record_report(reporter, backends).await;
+        }
+        // Snapshots are always complete, there is nothing to negotiate or resync
+        BalancerMessage::Hello { .. } | BalancerMessage::Resync => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::BackendTelemetry;
+    use tokio::time::timeout;
+    use super::*;
+    use crate::queue::{reconcile_requested, send_queue, PublishedQueue, UtilizationCategory};
+    use crate::telemetry::{get_backend_telemetry, remove_reporter};
+    use crate::testing;
+
+    fn telemetry(dns_name: &str, rps: f64) -> BackendTelemetry {
+        BackendTelemetry {
+            dns_name: dns_name.to_string(),
+            rps,
+            in_flight: 1,
+            p50_latency_ms: 10.0,
+            p95_latency_ms: 20.0,
+            p99_latency_ms: 30.0,
+            error_rate: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_stored_for_late_subscribers() {
+        let mut redis = testing::redis().await;
+        let items = vec![QueueItem::new("127.0.0.1:30001".to_string(), 80.0, UtilizationCategory::Low)];
+        publish_queue(&mut redis.conn, &[]).unwrap();
+        publish_queue(&mut redis.conn, &items).unwrap();
+
+        let stored: String = redis.conn.get(queue_channel()).unwrap();
+        match decode::<AgentMessage>(&stored).unwrap() {
+            AgentMessage::Snapshot { seq, items: stored } => {
+                assert!(seq > 1);
+                assert_eq!(stored, items);
+            }
+            other => panic!("stored {:?} instead of a snapshot", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wake_events_restore_the_default_containers() {
+        let mut redis = testing::redis().await;
+        let _: () = redis.conn.set("DEFAULT_CONTAINER", 0).unwrap();
+        // Drops a request left by another test
+        let _ = timeout(Duration::ZERO, reconcile_requested()).await;
+
+        handle_event("balancer-1", next_connection_id(), BalancerMessage::Wake).await;
+        assert_eq!(redis.conn.get::<_, i16>("DEFAULT_CONTAINER").unwrap(), 1);
+        assert!(timeout(Duration::from_secs(1), reconcile_requested()).await.is_ok(), "no rebuild requested");
+    }
+
+    #[tokio::test]
+    async fn telemetry_is_keyed_by_container_name() {
+        // Reports count for the scaling of the tests using Redis
+        let _redis = testing::redis().await;
+        send_queue(PublishedQueue {
+            names: HashMap::from([("10.1.0.7:30001".to_string(), "worker-discovery".to_string())]),
+            ..Default::default()
+        });
+        let reporter = next_connection_id();
+        handle_event("balancer-1", reporter, BalancerMessage::Telemetry {
+            backends: vec![telemetry("10.1.0.7:30001", 12.0), telemetry("10.1.0.8:30002", 3.0)],
+        }).await;
+
+        let combined = get_backend_telemetry().await;
+        remove_reporter(reporter).await;
+        assert_eq!(combined.get("worker-discovery").map(|backend| backend.rps), Some(12.0));
+        assert!(!combined.contains_key("10.1.0.7:30001"));
+        // Addresses without a container stay as they are
+        assert_eq!(combined.get("10.1.0.8:30002").map(|backend| backend.rps), Some(3.0));
+    }
+}
@@ -0,0 +1,197 @@
+use std::env;
+use dotenv::dotenv;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use crate::metrics::metrics;
+
+// Observed load of the app at one point in time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoadSample {
+    pub timestamp: i64,
+    pub rps: f64,
+    pub containers: usize,
+}
+
+// Settings of the seasonal moving average forecast
+#[derive(Debug, Clone, Copy)]
+pub struct ForecastConfig {
+    pub horizon_secs: i64,
+    pub season_secs: i64,
+    pub seasons: i64,
+    pub bucket_secs: i64,
+}
+
+impl ForecastConfig {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let read = |name: &str, default: &str| -> i64 {
+            env::var(name)
+                .unwrap_or_else(|_| default.to_string())
+                .parse::<i64>()
+                .unwrap_or_else(|_| panic!("{} must be a valid i64", name))
+        };
+
+        ForecastConfig {
+            horizon_secs: read("PREDICTIVE_HORIZON", "600"),
+            season_secs: read("PREDICTIVE_SEASON_LENGTH", "86400"),
+            seasons: read("PREDICTIVE_SEASONS", "7"),
+            bucket_secs: read("PREDICTIVE_BUCKET", "300"),
+        }
+    }
+
+    // How long samples are needed for forecasts
+    pub fn retention_secs(&self) -> i64 {
+        self.season_secs * self.seasons + self.horizon_secs + self.bucket_secs
+    }
+}
+
+fn history_key(app_identifier: &str) -> String {
+    format!("load_history:{}", app_identifier)
+}
+
+// Stores a load sample and drops samples that are too old for any forecast
+pub fn record_load_sample(conn: &mut redis::Connection, app_identifier: &str, sample: &LoadSample, config: &ForecastConfig) -> redis::RedisResult<()> {
+    let key = history_key(app_identifier);
+    let member = serde_json::to_string(sample).expect("Failed to serialize load sample");
+    metrics().time_redis("zadd", || conn.zadd::<_, _, _, ()>(&key, member, sample.timestamp))?;
+    metrics().time_redis("zremrangebyscore", || conn.zrembyscore(&key, "-inf", sample.timestamp - config.retention_secs()))
+}
+
+// Loads all samples between from and to (unix seconds), oldest first
+pub fn load_history(conn: &mut redis::Connection, app_identifier: &str, from: i64, to: i64) -> redis::RedisResult<Vec<LoadSample>> {
+    let members: Vec<String> = metrics().time_redis("zrangebyscore", || conn.zrangebyscore(history_key(app_identifier), from, to))?;
+    Ok(members.iter()
+        .filter_map(|member| serde_json::from_str(member).ok())
+        .collect())
+}
+
+// Seasonal moving average: mean load around the same time of the previous seasons
+pub fn seasonal_forecast(history: &[LoadSample], at: i64, config: &ForecastConfig) -> Option<f64> {
+    let half_bucket = config.bucket_secs / 2;
+    let season_means: Vec<f64> = (1..=config.seasons)
+        .filter_map(|season| {
+            let center = at - season * config.season_secs;
+            let samples: Vec<f64> = history.iter()
+                .filter(|s| (s.timestamp - center).abs() <= half_bucket)
+                .map(|s| s.rps)
+                .collect();
+            if samples.is_empty() {
+                None
+            } else {
+                Some(samples.iter().sum::<f64>() / samples.len() as f64)
+            }
+        })
+        .collect();
+
+    if season_means.is_empty() {
+        return None;
+    }
+    Some(season_means.iter().sum::<f64>() / season_means.len() as f64)
+}
+
+// Forecast of a single point in time next to the load that actually occurred
+#[derive(Serialize, Debug, Clone)]
+pub struct ForecastPoint {
+    pub timestamp: i64,
+    pub forecast_rps: Option<f64>,
+    pub actual_rps: Option<f64>,
+    pub containers: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ForecastReport {
+    pub horizon_secs: i64,
+    pub next: ForecastPoint,
+    pub recommended_containers: Option<usize>,
+    pub series: Vec<ForecastPoint>,
+}
+
+// Compares the forecasts of the last `hours` with the recorded load and forecasts the next horizon
+pub fn forecast_report(history: &[LoadSample], now: i64, hours: i64, config: &ForecastConfig, rps_per_container: f64) -> ForecastReport {
+    let from = now - hours * 3600;
+    let series = history.iter()
+        .filter(|s| s.timestamp >= from)
+        .map(|s| ForecastPoint {
+            timestamp: s.timestamp,
+            forecast_rps: seasonal_forecast(history, s.timestamp, config),
+            actual_rps: Some(s.rps),
+            containers: Some(s.containers),
+        })
+        .collect();
+
+    let at = now + config.horizon_secs;
+    let forecast_rps = seasonal_forecast(history, at, config);
+    ForecastReport {
+        horizon_secs: config.horizon_secs,
+        next: ForecastPoint {
+            timestamp: at,
+            forecast_rps,
+            actual_rps: None,
+            containers: None,
+        },
+        recommended_containers: forecast_rps.map(|rps| (rps / rps_per_container).ceil() as usize),
+        series,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Seasons of 1000s, forecasts from the 3 previous seasons in buckets of ±50s
+    const CONFIG: ForecastConfig = ForecastConfig { horizon_secs: 100, season_secs: 1000, seasons: 3, bucket_secs: 100 };
+
+    fn sample(timestamp: i64, rps: f64) -> LoadSample {
+        LoadSample { timestamp, rps, containers: 1 }
+    }
+
+    #[test]
+    fn empty_history_has_no_forecast() {
+        assert_eq!(seasonal_forecast(&[], 10_000, &CONFIG), None);
+        assert_eq!(forecast_report(&[], 10_000, 1, &CONFIG, 50.0).recommended_containers, None);
+    }
+
+    #[test]
+    fn averages_the_buckets_of_all_seasons() {
+        let history = [
+            sample(7_000, 30.0),
+            sample(8_000, 60.0),
+            sample(9_000, 90.0),
+        ];
+        assert_eq!(seasonal_forecast(&history, 10_000, &CONFIG), Some(60.0));
+    }
+
+    #[test]
+    fn partial_seasons_average_only_the_seasons_with_samples() {
+        // The second season has no sample, the first one two within its bucket
+        let history = [
+            sample(7_000, 20.0),
+            sample(8_960, 40.0),
+            sample(9_040, 80.0),
+        ];
+        assert_eq!(seasonal_forecast(&history, 10_000, &CONFIG), Some(40.0));
+    }
+
+    #[test]
+    fn ignores_samples_outside_the_buckets_and_seasons() {
+        let history = [
+            // Just outside the bucket of the first season
+            sample(8_949, 500.0),
+            sample(9_051, 500.0),
+            // Fourth season, older than the configured ones
+            sample(6_000, 500.0),
+            sample(9_050, 10.0),
+        ];
+        assert_eq!(seasonal_forecast(&history, 10_000, &CONFIG), Some(10.0));
+        assert_eq!(seasonal_forecast(&history[..3], 10_000, &CONFIG), None);
+    }
+
+    #[test]
+    fn recommends_containers_for_the_forecast_horizon() {
+        // Forecast for now + horizon
+        let at = |rps| [sample(9_100, rps)];
+        assert_eq!(forecast_report(&at(100.0), 10_000, 1, &CONFIG, 50.0).recommended_containers, Some(2));
+        assert_eq!(forecast_report(&at(100.5), 10_000, 1, &CONFIG, 50.0).recommended_containers, Some(3));
+        assert_eq!(forecast_report(&at(0.0), 10_000, 1, &CONFIG, 50.0).recommended_containers, Some(0));
+    }
+}
@@ -1,18 +1,35 @@
 use axum::{
+    extract::{Path, Query},
+    http::{header, StatusCode},
     response::Json,
-    routing::get,
+    routing::{delete, get},
     Router,
 };
 use std::env;
 use std::net::SocketAddr;
 use dotenv::dotenv;
+use serde::Deserialize;
 use tower_http::cors::{Any, CorsLayer};
-use crate::stats::{get_container_statuses, ContainerStatus};
+use crate::audit::{load_evaluations, ScalingEvaluation};
+use crate::db;
+use crate::metrics::{metrics, CONTENT_TYPE};
+use crate::forecast::{forecast_report, load_history, ForecastConfig, ForecastReport};
+use crate::scaling::predictive_rps_per_container;
+use crate::schedule::{delete_rule, load_rules, rule_statuses, save_rule, ScheduleRule, ScheduleStatus};
+use crate::queue::latest_queue;
+use crate::runtime::{runtime_health, RuntimeHealth};
+use crate::stats::ContainerStatus;
 
 pub async fn start_http_server() {
     // Creates a new axum-router with CORS configuration
     let app = Router::new()
         .route("/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
+        .route("/health", get(get_health))
+        .route("/forecast", get(get_forecast))
+        .route("/scaling/events", get(get_scaling_events))
+        .route("/schedules", get(get_schedules).post(create_schedule))
+        .route("/schedules/:id", delete(delete_schedule).put(update_schedule))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -35,9 +52,107 @@ pub async fn start_http_server() {
         .unwrap();
 }
 
+// Container statuses of the latest queue rebuild
 async fn get_stats() -> Result<Json<Vec<ContainerStatus>>, axum::http::StatusCode> {
-    match get_container_statuses().await {
-        Ok(stats) => Ok(Json(stats)),
+    match latest_queue() {
+        Some(queue) => Ok(Json(queue.statuses.clone())),
+        None => Err(axum::http::StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+// Metrics in the OpenMetrics format, scores and categories are the ones of the latest queue rebuild
+async fn get_metrics() -> ([(header::HeaderName, &'static str); 1], String) {
+    if let Some(queue) = latest_queue() {
+        metrics().set_queue(&queue);
+    }
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], metrics().encode())
+}
+
+// Health of the container runtime, 503 while it is unreachable
+async fn get_health() -> (StatusCode, Json<RuntimeHealth>) {
+    let health = runtime_health().await;
+    let status = if health.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(health))
+}
+
+#[derive(Deserialize)]
+struct ScalingEventsQuery {
+    limit: Option<usize>,
+}
+
+// Latest scaling evaluations, newest first
+async fn get_scaling_events(Query(query): Query<ScalingEventsQuery>) -> Result<Json<Vec<ScalingEvaluation>>, StatusCode> {
+    dotenv().ok();
+    let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+    let mut conn = db::get_redis_connection();
+    match load_evaluations(&mut conn, &app_identifier, query.limit.unwrap_or(100)) {
+        Ok(evaluations) => Ok(Json(evaluations)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Deserialize)]
+struct ForecastQuery {
+    hours: Option<i64>,
+}
+
+async fn get_forecast(Query(query): Query<ForecastQuery>) -> Result<Json<ForecastReport>, axum::http::StatusCode> {
+    dotenv().ok();
+    let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+    let config = ForecastConfig::from_env();
+    let hours = query.hours.unwrap_or(24).max(0);
+    let now = chrono::Utc::now().timestamp();
+
+    // Forecasts of the compared window need the seasons before it
+    let mut conn = db::get_redis_connection();
+    match load_history(&mut conn, &app_identifier, now - hours * 3600 - config.retention_secs(), now) {
+        Ok(history) => Ok(Json(forecast_report(&history, now, hours, &config, predictive_rps_per_container()))),
         Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
     }
-}
\ No newline at end of file
+}
+
+async fn get_schedules() -> Result<Json<Vec<ScheduleStatus>>, StatusCode> {
+    let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+    let mut conn = db::get_redis_connection();
+    match load_rules(&mut conn, &app_identifier) {
+        Ok(rules) => Ok(Json(rule_statuses(rules, chrono::Utc::now()))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn create_schedule(Json(mut rule): Json<ScheduleRule>) -> Result<(StatusCode, Json<ScheduleRule>), (StatusCode, String)> {
+    if rule.id.is_empty() {
+        rule.id = uuid::Uuid::new_v4().to_string();
+    }
+    store_schedule(rule).map(|rule| (StatusCode::CREATED, rule))
+}
+
+async fn update_schedule(Path(id): Path<String>, Json(mut rule): Json<ScheduleRule>) -> Result<Json<ScheduleRule>, (StatusCode, String)> {
+    rule.id = id;
+    store_schedule(rule)
+}
+
+// Validates and persists a rule, it is applied with the next scaling check
+fn store_schedule(rule: ScheduleRule) -> Result<Json<ScheduleRule>, (StatusCode, String)> {
+    rule.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+    let mut conn = db::get_redis_connection();
+    save_rule(&mut conn, &app_identifier, &rule)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    println!("Stored scaling schedule {}: {:?}", rule.id, rule);
+    Ok(Json(rule))
+}
+
+async fn delete_schedule(Path(id): Path<String>) -> StatusCode {
+    let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+    let mut conn = db::get_redis_connection();
+    match delete_rule(&mut conn, &app_identifier, &id) {
+        Ok(true) => {
+            println!("Deleted scaling schedule {}", id);
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
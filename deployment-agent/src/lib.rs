@@ -0,0 +1,18 @@
+pub mod audit;
+pub mod container;
+pub mod stats;
+pub mod http;
+pub mod queue;
+pub mod reconcile;
+pub mod runtime;
+pub mod socket;
+pub mod db;
+pub mod discovery;
+pub mod forecast;
+pub mod metrics;
+pub mod scaling;
+pub mod schedule;
+pub mod simulation;
+pub mod telemetry;
+#[cfg(test)]
+mod testing;
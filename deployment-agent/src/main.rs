@@ -1,23 +1,37 @@
-use bollard::errors::Error;
 use dotenv::dotenv;
-use http::start_http_server;
-use crate::socket::socket;
-
-mod container;
-mod stats;
-mod http;
-mod queue;
-mod socket;
-mod db;
+use deployment_agent::db;
+use deployment_agent::discovery::run_event_subscriber;
+use deployment_agent::queue::{run_reconcile_loop, watch_runtime_events};
+use deployment_agent::runtime::{init_shared_runtime, monitor_runtime_health};
+use deployment_agent::http::start_http_server;
+use deployment_agent::socket::socket;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 3)]
-async fn main() -> Result<(), Error> {
+async fn main() {
     dotenv().ok();
 
+    // Connect to the container engine before anything depends on it
+    let runtime = match init_shared_runtime().await {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to initialize container runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(monitor_runtime_health(runtime.clone()));
+
     // Initialize db connection
     let mut conn = db::get_redis_connection();
     db::init(&mut conn);
 
+    println!("Starting reconciliation loop...");
+    tokio::spawn(watch_runtime_events(runtime.clone()));
+    tokio::spawn(run_reconcile_loop(runtime.clone()));
+
+    // Events of balancers discovering the queue over Redis instead of the socket
+    tokio::spawn(run_event_subscriber());
+
     println!("Starting HTTP server...");
     let http_server = tokio::spawn(async {
         start_http_server().await;
@@ -28,16 +42,28 @@ async fn main() -> Result<(), Error> {
         socket().await;
     });
 
-    // Await both tasks
-    let (http_result, socket_result) = tokio::join!(http_server, socket_task);
+    // Await both tasks, or stop the workers that do not outlive the agent on SIGINT or SIGTERM
+    tokio::select! {
+        (http_result, socket_result) = async { tokio::join!(http_server, socket_task) } => {
+            if let Err(e) = http_result {
+                eprintln!("HTTP server task failed: {:?}", e);
+            }
 
-    if let Err(e) = http_result {
-        eprintln!("HTTP server task failed: {:?}", e);
+            if let Err(e) = socket_result {
+                eprintln!("Socket task failed: {:?}", e);
+            }
+        }
+        _ = shutdown_signal() => {
+            println!("Shutting down...");
+            runtime.shutdown().await;
+        }
     }
+}
 
-    if let Err(e) = socket_result {
-        eprintln!("Socket task failed: {:?}", e);
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
-
-    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicU64;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+use crate::audit::ScalingEvaluation;
+use crate::queue::PublishedQueue;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+// Metrics of the agent, served in the OpenMetrics format on /metrics
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ContainerLabels {
+    container: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CategoryLabels {
+    category: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StageLabels {
+    stage: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ScalingLabels {
+    direction: String,
+    reason: String,
+    outcome: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CallLabels {
+    operation: String,
+}
+
+type FloatGauge = Gauge<f64, AtomicU64>;
+
+pub struct Metrics {
+    registry: Registry,
+    cpu_score: Family<ContainerLabels, FloatGauge>,
+    memory_score: Family<ContainerLabels, FloatGauge>,
+    network_score: Family<ContainerLabels, FloatGauge>,
+    availability_score: Family<ContainerLabels, FloatGauge>,
+    overall_score: Family<ContainerLabels, FloatGauge>,
+    containers: Family<CategoryLabels, Gauge>,
+    containers_created: Counter,
+    containers_removed: Counter,
+    containers_failed: Counter,
+    image_pull_duration: Histogram,
+    reconcile_duration: Histogram,
+    reconcile_errors: Family<StageLabels, Counter>,
+    scaling_decisions: Family<ScalingLabels, Counter>,
+    redis_duration: Family<CallLabels, Histogram, fn() -> Histogram>,
+    runtime_duration: Family<CallLabels, Histogram, fn() -> Histogram>,
+    // Label sets of the last published queue, to remove the ones that are gone
+    queue_labels: Mutex<(HashSet<String>, HashSet<String>)>,
+}
+
+// From 0.5ms up to about 16s
+fn call_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.0005, 2.0, 16))
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("agent");
+        let cpu_score = Family::default();
+        registry.register("container_cpu_score", "CPU score of a container", Family::clone(&cpu_score));
+        let memory_score = Family::default();
+        registry.register("container_memory_score", "Memory score of a container", Family::clone(&memory_score));
+        let network_score = Family::default();
+        registry.register("container_network_score", "Network score of a container", Family::clone(&network_score));
+        let availability_score = Family::default();
+        registry.register("container_availability_score", "Availability score of a container", Family::clone(&availability_score));
+        let overall_score = Family::default();
+        registry.register("container_overall_score", "Overall score of a container", Family::clone(&overall_score));
+        let containers = Family::default();
+        registry.register("containers", "Containers in the queue by utilization category", Family::clone(&containers));
+        let containers_created = Counter::default();
+        registry.register("containers_created", "Containers created by the agent", containers_created.clone());
+        let containers_removed = Counter::default();
+        registry.register("containers_removed", "Containers removed by the agent", containers_removed.clone());
+        let containers_failed = Counter::default();
+        registry.register("containers_failed", "Containers that could not be created", containers_failed.clone());
+        // From 1s up to about 8.5 minutes
+        let image_pull_duration = Histogram::new(exponential_buckets(1.0, 2.0, 10));
+        registry.register_with_unit("image_pull_duration", "Duration of image pulls", Unit::Seconds, image_pull_duration.clone());
+        // From 10ms up to about 80s
+        let reconcile_duration = Histogram::new(exponential_buckets(0.01, 2.0, 14));
+        registry.register_with_unit("reconcile_duration", "Duration of a pass of the reconciliation loop", Unit::Seconds, reconcile_duration.clone());
+        let reconcile_errors = Family::default();
+        registry.register("reconcile_errors", "Errors of the reconciliation loop by stage", Family::clone(&reconcile_errors));
+        let scaling_decisions = Family::default();
+        registry.register("scaling_decisions", "Scaling evaluations by direction, reason and outcome", Family::clone(&scaling_decisions));
+        let redis_duration = Family::new_with_constructor(call_histogram as fn() -> Histogram);
+        registry.register_with_unit("redis_call_duration", "Duration of Redis calls by operation", Unit::Seconds, redis_duration.clone());
+        let runtime_duration = Family::new_with_constructor(call_histogram as fn() -> Histogram);
+        registry.register_with_unit("runtime_call_duration", "Duration of container runtime calls by operation", Unit::Seconds, runtime_duration.clone());
+
+        Metrics {
+            registry,
+            cpu_score,
+            memory_score,
+            network_score,
+            availability_score,
+            overall_score,
+            containers,
+            containers_created,
+            containers_removed,
+            containers_failed,
+            image_pull_duration,
+            reconcile_duration,
+            reconcile_errors,
+            scaling_decisions,
+            redis_duration,
+            runtime_duration,
+            queue_labels: Mutex::new((HashSet::new(), HashSet::new())),
+        }
+    }
+
+    // Replaces scores and category counts with the ones of the published queue.
+    // Gauges are updated in place, so a concurrent scrape never sees them missing
+    pub fn set_queue(&self, queue: &PublishedQueue) {
+        let mut queue_labels = self.queue_labels.lock().unwrap();
+        let (known_containers, known_categories) = &mut *queue_labels;
+        let score_families = [&self.cpu_score, &self.memory_score, &self.network_score, &self.availability_score, &self.overall_score];
+
+        let mut containers = HashSet::new();
+        for status in &queue.statuses {
+            let labels = ContainerLabels { container: status.name.trim_start_matches('/').to_string() };
+            self.cpu_score.get_or_create(&labels).set(status.cpu_score);
+            self.memory_score.get_or_create(&labels).set(status.memory_score);
+            self.network_score.get_or_create(&labels).set(status.network_score);
+            self.availability_score.get_or_create(&labels).set(status.availability_score);
+            self.overall_score.get_or_create(&labels).set(status.overall_score);
+            containers.insert(labels.container);
+        }
+        for container in known_containers.difference(&containers) {
+            let labels = ContainerLabels { container: container.clone() };
+            for family in score_families {
+                family.remove(&labels);
+            }
+        }
+        *known_containers = containers;
+
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for item in &queue.items {
+            *counts.entry(item.utilization_category.to_string()).or_default() += 1;
+        }
+        for (category, count) in &counts {
+            self.containers.get_or_create(&CategoryLabels { category: category.clone() }).set(*count);
+        }
+        let categories: HashSet<String> = counts.into_keys().collect();
+        for category in known_categories.difference(&categories) {
+            self.containers.remove(&CategoryLabels { category: category.clone() });
+        }
+        *known_categories = categories;
+    }
+
+    pub fn record_created(&self) {
+        self.containers_created.inc();
+    }
+
+    pub fn record_removed(&self) {
+        self.containers_removed.inc();
+    }
+
+    pub fn record_failed(&self) {
+        self.containers_failed.inc();
+    }
+
+    pub fn record_image_pull(&self, duration: Duration) {
+        self.image_pull_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn record_reconcile(&self, duration: Duration) {
+        self.reconcile_duration.observe(duration.as_secs_f64());
+    }
+
+    // Stage is build, action or publish
+    pub fn record_reconcile_error(&self, stage: &str) {
+        self.reconcile_errors.get_or_create(&StageLabels { stage: stage.to_string() }).inc();
+    }
+
+    // The reason is what triggered the evaluation, failures are counted without their message
+    pub fn record_scaling(&self, evaluation: &ScalingEvaluation) {
+        let outcome = evaluation.outcome.split(':').next().unwrap_or_default();
+        self.scaling_decisions.get_or_create(&ScalingLabels {
+            direction: evaluation.action.clone(),
+            reason: evaluation.source.clone(),
+            outcome: outcome.to_string(),
+        }).inc();
+    }
+
+    // Times a blocking Redis call
+    pub fn time_redis<T>(&self, operation: &str, call: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = call();
+        self.redis_duration.get_or_create(&CallLabels { operation: operation.to_string() }).observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    pub fn record_runtime_call(&self, operation: &str, duration: Duration) {
+        self.runtime_duration.get_or_create(&CallLabels { operation: operation.to_string() }).observe(duration.as_secs_f64());
+    }
+
+    pub fn encode(&self) -> String {
+        let mut body = String::new();
+        encode(&mut body, &self.registry).expect("Failed to encode metrics");
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{QueueItem, UtilizationCategory};
+    use crate::stats::ContainerStatus;
+
+    fn status(name: &str, overall_score: f64) -> ContainerStatus {
+        ContainerStatus {
+            id: name.to_string(),
+            name: format!("/{}", name),
+            cpu_score: overall_score,
+            memory_score: overall_score,
+            network_score: overall_score,
+            network_throughput: 0.0,
+            availability_score: 100.0,
+            latency_score: 0.0,
+            error_score: 0.0,
+            overall_score,
+            utilization_category: UtilizationCategory::Low,
+            telemetry: None,
+        }
+    }
+
+    fn queue(containers: &[(&str, f64, UtilizationCategory)]) -> PublishedQueue {
+        PublishedQueue {
+            items: containers.iter().map(|(name, score, category)| QueueItem::new(name.to_string(), *score, *category)).collect(),
+            statuses: containers.iter().map(|(name, score, _)| status(name, *score)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn lines(metrics: &Metrics, prefix: &str) -> Vec<String> {
+        let mut lines: Vec<String> = metrics.encode().lines()
+            .filter(|line| line.starts_with(prefix))
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn queue_gauges_are_updated_in_place() {
+        let metrics = Metrics::new();
+        metrics.set_queue(&queue(&[("a", 10.0, UtilizationCategory::Low), ("b", 20.0, UtilizationCategory::Low)]));
+        assert_eq!(lines(&metrics, "agent_container_overall_score{"), vec![
+            "agent_container_overall_score{container=\"a\"} 10.0",
+            "agent_container_overall_score{container=\"b\"} 20.0",
+        ]);
+        assert_eq!(lines(&metrics, "agent_containers{"), vec!["agent_containers{category=\"LU\"} 2"]);
+
+        metrics.set_queue(&queue(&[("b", 30.0, UtilizationCategory::High), ("c", 5.0, UtilizationCategory::Low)]));
+        assert_eq!(lines(&metrics, "agent_container_overall_score{"), vec![
+            "agent_container_overall_score{container=\"b\"} 30.0",
+            "agent_container_overall_score{container=\"c\"} 5.0",
+        ]);
+        assert!(lines(&metrics, "agent_container_cpu_score{").iter().all(|line| !line.contains("\"a\"")));
+        assert_eq!(lines(&metrics, "agent_containers{"), vec![
+            "agent_containers{category=\"HU\"} 1",
+            "agent_containers{category=\"LU\"} 1",
+        ]);
+
+        metrics.set_queue(&PublishedQueue::default());
+        assert!(lines(&metrics, "agent_container_").iter().all(|line| !line.contains("container=")));
+        assert!(lines(&metrics, "agent_containers{").is_empty());
+    }
+}
@@ -1,30 +1,34 @@
-use std::collections::{VecDeque, HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use crate::container::{manage_containers, generate_hash_based_key, update_container_category, create_single_container, remove_container, list_running_containers};
+use tokio::sync::{watch, Mutex, Notify};
+use futures::StreamExt;
+use tokio::time::sleep;
+use crate::container::{published_queue, generate_hash_based_key, update_container_category, create_single_container, remove_container};
+use crate::reconcile::{execute, plan, DesiredState, ObservedState};
 use crate::stats::{get_container_statuses, ContainerStatus};
+use crate::audit::{is_dry_run, record_evaluation, ScalingEvaluation};
+use crate::runtime::{ContainerEvent, ContainerRuntime, RuntimeError};
+use crate::forecast::{load_history, record_load_sample, ForecastConfig, LoadSample};
+use crate::schedule::{effective_bounds, load_rules, ScalingBounds};
+use crate::scaling::{policy_from_env, ContainerMetrics, MetricsSnapshot, ScalingDecision, ScalingPolicy, ScalingTimers};
 use crate::db;
+use crate::discovery::publish_queue;
+use crate::metrics::metrics;
 use std::env;
-use serde::{Deserialize, Serialize};
-use std::pin::Pin;
-use std::future::Future;
-use bollard::errors::Error as BollardError;
+use serde_json::json;
 use redis::{Commands};
 use std::time::{Duration, Instant};
 use dotenv::dotenv;
-use futures_util::SinkExt;
 use once_cell::sync::Lazy;
-use tabled::{Style, Table, Tabled};
+use tabled::{Style, Table};
 
-static GLOBAL_COOLDOWN: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
-static LAST_SCALE_CHECK: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+pub use protocol::{QueueItem, UtilizationCategory};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Tabled)]
-pub struct QueueItem {
-     pub(crate) dns_name: String,
-     pub(crate) score: f64,
-     pub(crate) utilization_category: String,
-}
+static SCALING_TIMERS: Lazy<Mutex<ScalingTimers>> = Lazy::new(|| Mutex::new(ScalingTimers::new(Instant::now())));
+static LAST_ACTIVITY: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+static WAKE_REQUESTED: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+static SCALING_POLICY: Lazy<Mutex<Box<dyn ScalingPolicy>>> = Lazy::new(|| Mutex::new(policy_from_env()));
+static LAST_LOAD_SAMPLE: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
 
 const REQUIRED_FIELDS: [&str; 4] = ["category", "score", "port", "image"];
 
@@ -32,194 +36,235 @@ fn is_container_complete(fields: &HashMap<String, String>) -> bool {
      REQUIRED_FIELDS.iter().all(|&field| fields.contains_key(field))
 }
 
-pub type SharedQueue = Arc<Mutex<VecDeque<QueueItem>>>;
-
-pub fn build_queue() -> Pin<Box<dyn Future<Output = Result<SharedQueue, axum::http::StatusCode>> + Send>> {
-     Box::pin(async move {
-          println!("Starting build_queue function");
-          let queue = VecDeque::new();
-          let shared_queue = Arc::new(Mutex::new(queue));
-
-          let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
-          println!("App identifier: {}", app_identifier);
-
-          let mut conn = db::get_redis_connection();
-          let default_container: i16 = conn.get("DEFAULT_CONTAINER").unwrap_or_else(|_| {
-               env::var("DEFAULT_CONTAINER")
-                   .expect("DEFAULT_CONTAINER must be set")
-                   .parse()
-                   .expect("DEFAULT_CONTAINER must be a valid number")
-          });
-          println!("Default container count: {}", default_container);
-
-          println!("Fetching running containers");
-          let running_containers = match list_running_containers(&app_identifier).await {
-               Ok(containers) => containers,
-               Err(e) => {
-                    eprintln!("Failed to list running containers: {:?}", e);
-                    return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
-               }
-          };
-          println!("Found {} running containers", running_containers.len());
-
-          println!("Cleaning up database entries");
-          let db_containers: Vec<String> = conn.keys("container:*")
-              .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
-          println!("Found {} container entries in database", db_containers.len());
-
-          // Creates HashSet for running containers' keys
-          let running_container_keys: HashSet<String> = running_containers.iter()
-              .filter_map(|c| c.names.as_ref().and_then(|names| names.first()))
-              .map(|name| generate_hash_based_key(&app_identifier, name.trim_start_matches('/')))
-              .collect();
-
-          // Removing containers that are not running anymore
-          for db_container in db_containers {
-               if !running_container_keys.contains(&db_container) {
-                    println!("Container with key {} is in the database but not running. Removing from database.", db_container);
-                    let _: () = conn.del(&db_container)
-                        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
-               } else {
-                    println!("Container with key {} is running and in the database.", db_container);
-               }
+// Result of one queue rebuild
+#[derive(Debug, Clone, Default)]
+pub struct QueueSnapshot {
+     pub items: Vec<QueueItem>,
+     pub statuses: Vec<ContainerStatus>,
+}
+
+pub async fn build_queue(runtime: Arc<dyn ContainerRuntime>) -> Result<QueueSnapshot, axum::http::StatusCode> {
+     println!("Starting build_queue function");
+     let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+     println!("App identifier: {}", app_identifier);
+
+     let mut conn = db::get_redis_connection();
+     let desired = DesiredState::load(&mut conn);
+     println!("Desired state: {} replica(s) of {}", desired.replicas, desired.image);
+
+     let observed = match ObservedState::load(runtime.as_ref(), &mut conn, &app_identifier).await {
+          Ok(observed) => observed,
+          Err(e) => {
+               eprintln!("Failed to observe containers: {:?}", e);
+               return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
           }
+     };
+     println!("Observed {} running container(s), {} orphaned database entries", observed.containers.len(), observed.orphaned_records.len());
 
-          // Starting new containers if required
-          let running_count = running_containers.len() as i16;
-          println!("Current running containers: {}, Default containers: {}", running_count, default_container);
-          if running_count < default_container {
-               println!("Running containers ({}) less than DEFAULT_CONTAINER ({}). Starting new containers.", running_count, default_container);
-               let containers_to_start = default_container - running_count;
-               for i in 0..containers_to_start {
-                    println!("Starting container {} of {}", i+1, containers_to_start);
-                    let image_name = env::var("DOCKER_IMAGE").expect("DOCKER_IMAGE must be set");
-                    let target_port: u16 = env::var("TARGET_PORT")
-                        .expect("TARGET_PORT must be set")
-                        .parse()
-                        .expect("TARGET_PORT must be a valid number");
-
-                    match create_single_container(&image_name, target_port, &app_identifier, &mut conn).await {
-                         Ok(container) => println!("New container created successfully: {}", container.dns_name),
-                         Err(e) => {
-                              eprintln!("Failed to create new container: {:?}", e);
-                              return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+     // Bringing the observed state to the desired state
+     let plan = plan(&desired, &observed);
+     if !plan.is_empty() {
+          println!("Reconciliation plan: {:?}", plan.actions);
+     }
+     let mut managed_containers = execute(runtime.as_ref(), &desired, &app_identifier, plan).await;
+     println!("Successfully managed containers. Count: {}", managed_containers.len());
+
+     match get_container_statuses(runtime.clone()).await {
+          Ok(container_statuses) => {
+               println!("Retrieved container statuses. Count: {}", container_statuses.len());
+               for managed_container in &mut managed_containers {
+                    // SUNDOWN containers keep their state until they are removed
+                    if managed_container.utilization_category == UtilizationCategory::Sundown {
+                         continue;
+                    }
+                    let key = generate_hash_based_key(&app_identifier, &managed_container.dns_name);
+                    println!("Processing container: {} with key: {}", managed_container.dns_name, key);
+
+                    if let Ok(fields) = metrics().time_redis("hgetall", || conn.hgetall::<_, HashMap<String, String>>(&key)) {
+                         println!("Retrieved fields for container {}: {:?}", managed_container.dns_name, fields);
+                         if is_container_complete(&fields) {
+                              if let Some(status) = container_statuses.iter().find(|s| s.name.trim_start_matches('/') == managed_container.dns_name.trim_start_matches('/')) {
+                                   managed_container.score = status.overall_score;
+                                   managed_container.utilization_category = status.utilization_category;
+
+                                   // Updating database with new scores and categories for the containers
+                                   if let Err(e) = metrics().time_redis("hset", || conn.hset::<_, _, _, ()>(&key, "score", managed_container.score.to_string())) {
+                                        eprintln!("Failed to update score in database for {}: {:?}", managed_container.dns_name, e);
+                                   }
+                                   if let Err(e) = update_container_category(&mut conn, &key, managed_container.utilization_category) {
+                                        eprintln!("Failed to update category in database for {}: {:?}", managed_container.dns_name, e);
+                                   }
+
+                                   println!("Updated container {}: score = {}, category = {}",
+                                            managed_container.dns_name, managed_container.score, managed_container.utilization_category);
+                              } else {
+                                   println!("No status found for container: {}", managed_container.dns_name);
+                              }
+                         } else {
+                              println!("Container {} is incomplete. Fields: {:?}", managed_container.dns_name, fields);
                          }
+                    } else {
+                         println!("Failed to retrieve container data for {}.", managed_container.dns_name);
                     }
                }
+
+               report_cold_start(&mut conn, &container_statuses, &managed_containers).await;
+
+               // Removing inactive SUNDOWN containers
+               println!("Removing inactive SUNDOWN containers");
+               managed_containers = remove_inactive_sundown_containers(runtime.as_ref(), &app_identifier, managed_containers, &container_statuses).await;
+
+               println!("Checking and scaling containers");
+               if let Err(e) = check_and_scale_containers(runtime.as_ref(), &mut conn, &app_identifier, &container_statuses, &mut managed_containers).await {
+                    eprintln!("Failed to check and scale containers: {:?}", e);
+               }
+
+               println!("Sorting managed containers");
+               managed_containers.sort_by(|a, b| {
+                    if a.utilization_category == UtilizationCategory::Sundown && b.utilization_category != UtilizationCategory::Sundown {
+                         std::cmp::Ordering::Greater
+                    } else if a.utilization_category != UtilizationCategory::Sundown && b.utilization_category == UtilizationCategory::Sundown {
+                         std::cmp::Ordering::Less
+                    } else {
+                         b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal)
+                    }
+               });
+
+               print_final_queue(&managed_containers);
+               println!("build_queue function completed successfully");
+               Ok(QueueSnapshot { items: managed_containers, statuses: container_statuses })
+          }
+          Err(e) => {
+               eprintln!("Failed to get container statuses: {:?}", e);
+               Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
           }
+     }
+}
 
-          // Handling containers and updating container status
-          println!("Managing containers");
-          match manage_containers(&app_identifier, default_container).await {
-               Ok(mut managed_containers) => {
-                    println!("Successfully managed containers. Count: {}", managed_containers.len());
-                    match get_container_statuses().await {
-                         Ok(container_statuses) => {
-                              println!("Retrieved container statuses. Count: {}", container_statuses.len());
-                              for managed_container in &mut managed_containers {
-                                   let key = generate_hash_based_key(&app_identifier, &managed_container.dns_name);
-                                   println!("Processing container: {} with key: {}", managed_container.dns_name, key);
-
-                                   if let Ok(fields) = conn.hgetall::<_, HashMap<String, String>>(&key) {
-                                        println!("Retrieved fields for container {}: {:?}", managed_container.dns_name, fields);
-                                        if is_container_complete(&fields) {
-                                             if let Some(status) = container_statuses.iter().find(|s| s.name.trim_start_matches('/') == managed_container.dns_name.trim_start_matches('/')) {
-                                                  managed_container.score = status.overall_score;
-                                                  managed_container.utilization_category = status.utilization_category.clone();
-
-                                                  // Updating database with new scores and categories for the containers
-                                                  if let Err(e) = conn.hset::<_, _, _, ()>(&key, "score", managed_container.score.to_string()) {
-                                                       eprintln!("Failed to update score in database for {}: {:?}", managed_container.dns_name, e);
-                                                  }
-                                                  if let Err(e) = update_container_category(&mut conn, &key, &managed_container.utilization_category) {
-                                                       eprintln!("Failed to update category in database for {}: {:?}", managed_container.dns_name, e);
-                                                  }
-
-                                                  println!("Updated container {}: score = {}, category = {}",
-                                                           managed_container.dns_name, managed_container.score, managed_container.utilization_category);
-                                             } else {
-                                                  println!("No status found for container: {}", managed_container.dns_name);
-                                             }
-                                        } else {
-                                             println!("Container {} is incomplete. Fields: {:?}", managed_container.dns_name, fields);
-                                        }
-                                   } else {
-                                        println!("Failed to retrieve container data for {}.", managed_container.dns_name);
-                                   }
-                              }
+// Latest queue, as published by the reconciliation loop
+#[derive(Debug, Clone, Default)]
+pub struct PublishedQueue {
+     // Queue as sent to the balancers, with the addresses of the runtime
+     pub items: Vec<QueueItem>,
+     // Container names of the published addresses
+     pub names: HashMap<String, String>,
+     pub statuses: Vec<ContainerStatus>,
+}
 
-                              // Removing inactive SUNDOWN containers
-                              println!("Removing inactive SUNDOWN containers");
-                              managed_containers = remove_inactive_sundown_containers(&app_identifier, managed_containers, &container_statuses).await;
+static QUEUE_UPDATES: Lazy<watch::Sender<Option<Arc<PublishedQueue>>>> = Lazy::new(|| watch::channel(None).0);
+static RECONCILE_REQUESTED: Lazy<Notify> = Lazy::new(Notify::new);
 
-                              println!("Checking and scaling containers");
-                              if let Err(e) = check_and_scale_containers(&mut conn, &app_identifier, &container_statuses, &mut managed_containers).await {
-                                   eprintln!("Failed to check and scale containers: {:?}", e);
-                              }
+// Receives every queue published from now on; None until the first rebuild finished
+pub fn subscribe_queue() -> watch::Receiver<Option<Arc<PublishedQueue>>> {
+     QUEUE_UPDATES.subscribe()
+}
 
-                              println!("Sorting managed containers");
-                              managed_containers.sort_by(|a, b| {
-                                   if a.utilization_category == "SUNDOWN" && b.utilization_category != "SUNDOWN" {
-                                        std::cmp::Ordering::Greater
-                                   } else if a.utilization_category != "SUNDOWN" && b.utilization_category == "SUNDOWN" {
-                                        std::cmp::Ordering::Less
-                                   } else {
-                                        b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal)
-                                   }
-                              });
+pub fn latest_queue() -> Option<Arc<PublishedQueue>> {
+     QUEUE_UPDATES.borrow().clone()
+}
 
-                              println!("Updating shared queue");
-                              let mut locked_queue = shared_queue.lock().await;
-                              for container in managed_containers {
-                                   locked_queue.push_back(container);
-                              }
+// Makes the reconciliation loop rebuild the queue right away
+pub fn request_reconcile() {
+     RECONCILE_REQUESTED.notify_one();
+}
 
-                              print_final_queue(&locked_queue);
+// Resolves once a rebuild was requested
+pub async fn reconcile_requested() {
+     RECONCILE_REQUESTED.notified().await
+}
 
-                              // If Queue is empty, starting rebuild
-                              if locked_queue.is_empty() {
-                                   println!("Queue is empty after rebuild. Triggering immediate rebuild...");
-                                   drop(locked_queue);
-                                   return build_queue().await;
-                              }
-                         }
-                         Err(e) => {
-                              eprintln!("Failed to get container statuses: {:?}", e);
-                              return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
-                         }
+// Hands a rebuilt queue to all subscribers
+pub fn send_queue(queue: PublishedQueue) {
+     QUEUE_UPDATES.send_replace(Some(Arc::new(queue)));
+}
+
+// The only place the queue is rebuilt: reconciles every RECONCILE_INTERVAL seconds (or when
+// requested) and publishes the result to all subscribers, however many balancers are connected
+pub async fn run_reconcile_loop(runtime: Arc<dyn ContainerRuntime>) {
+     dotenv().ok();
+     let interval = env::var("RECONCILE_INTERVAL")
+         .unwrap_or_else(|_| "2".to_string())
+         .parse::<u64>()
+         .expect("RECONCILE_INTERVAL must be a valid u64");
+     let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+     let mut conn = db::get_redis_connection();
+
+     loop {
+          let start = Instant::now();
+          match build_queue(runtime.clone()).await {
+               Ok(snapshot) => {
+                    let (items, names) = published_queue(runtime.as_ref(), &mut conn, &app_identifier, &snapshot.items).await;
+                    if let Err(e) = metrics().time_redis("publish", || publish_queue(&mut conn, &items)) {
+                         eprintln!("Failed to publish queue to Redis: {:?}", e);
+                         metrics().record_reconcile_error("publish");
                     }
+                    send_queue(PublishedQueue {
+                         items,
+                         names,
+                         statuses: snapshot.statuses,
+                    });
                }
                Err(e) => {
-                    eprintln!("Failed to manage containers: {:?}", e);
-                    return Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+                    eprintln!("Failed to build queue: {:?}", e);
+                    metrics().record_reconcile_error("build");
                }
           }
+          metrics().record_reconcile(start.elapsed());
+
+          tokio::select! {
+               _ = sleep(Duration::from_secs(interval)) => {}
+               _ = reconcile_requested() => println!("Immediate queue rebuild requested"),
+          }
+     }
+}
 
-          println!("build_queue function completed successfully");
-          Ok(shared_queue)
-     })
+// Rebuilds the queue right away when a worker dies, e.g. on a lost cluster node or after its
+// process exited, instead of at the next interval
+pub async fn watch_runtime_events(runtime: Arc<dyn ContainerRuntime>) {
+     dotenv().ok();
+     let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
+
+     loop {
+          let mut events = runtime.events(("application", &app_identifier));
+          while let Some(event) = events.next().await {
+               match event {
+                    Ok(ContainerEvent::Died(name)) => {
+                         println!("Worker {} died, rebuilding the queue", name);
+                         request_reconcile();
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                         eprintln!("Failed to receive container events: {}", e);
+                         break;
+                    }
+               }
+          }
+          // The stream ends with the connection to the engine
+          sleep(Duration::from_secs(5)).await;
+     }
 }
 
 // Prints queue as table
-fn print_final_queue(queue: &VecDeque<QueueItem>) {
+fn print_final_queue(queue: &[QueueItem]) {
      let mut table = Table::new(queue);
      table.with(Style::modern());
      println!("{}", table);
 }
 
-async fn remove_inactive_sundown_containers(app_identifier: &str, containers: Vec<QueueItem>, container_statuses: &[ContainerStatus]) -> Vec<QueueItem> {
+async fn remove_inactive_sundown_containers(runtime: &dyn ContainerRuntime, app_identifier: &str, containers: Vec<QueueItem>, container_statuses: &[ContainerStatus]) -> Vec<QueueItem> {
      let mut active_containers = Vec::new();
      let status_map: HashMap<String, &ContainerStatus> = container_statuses.iter()
          .map(|status| (status.name.trim_start_matches('/').to_string(), status))
          .collect();
 
      for container in containers {
-          if container.utilization_category == "SUNDOWN" {
+          if container.utilization_category == UtilizationCategory::Sundown {
                let container_name = container.dns_name.trim_start_matches('/');
                if let Some(status) = status_map.get(container_name) {
-                    if status.network_score >= 99.9 {
+                    let in_flight = status.telemetry.as_ref().map_or(0, |t| t.in_flight);
+                    if status.network_score >= 99.9 && in_flight == 0 {
                          println!("Attempting to remove inactive SUNDOWN container: {}", container_name);
-                         match remove_container(app_identifier, container_name).await {
+                         match remove_container(runtime, app_identifier, container_name).await {
                               Ok(_) => {
                                    println!("Successfully removed SUNDOWN container: {}", container_name);
                                    continue; // Skip adding this container to active_containers
@@ -230,12 +275,12 @@ async fn remove_inactive_sundown_containers(app_identifier: &str, containers: Ve
                               }
                          }
                     } else {
-                         println!("SUNDOWN container {} not removed. Network score: {}", container_name, status.network_score);
+                         println!("SUNDOWN container {} not removed. Network score: {}, In-flight requests: {}", container_name, status.network_score, in_flight);
                          active_containers.push(container);
                     }
                } else {
                     println!("Status not found for SUNDOWN container {}. Attempting to remove it anyway.", container_name);
-                    match remove_container(app_identifier, container_name).await {
+                    match remove_container(runtime, app_identifier, container_name).await {
                          Ok(_) => println!("Successfully removed SUNDOWN container without status: {}", container_name),
                          Err(e) => {
                               eprintln!("Failed to remove container without status {}: {:?}", container_name, e);
@@ -250,39 +295,41 @@ async fn remove_inactive_sundown_containers(app_identifier: &str, containers: Ve
      active_containers
 }
 
-// Scaling containers based on load
+// Scaling containers based on the decision of the configured scaling policy
 pub async fn check_and_scale_containers(
+     runtime: &dyn ContainerRuntime,
      conn: &mut redis::Connection,
      app_identifier: &str,
      container_statuses: &[ContainerStatus],
      managed_containers: &mut [QueueItem],
-) -> Result<(), BollardError> {
+) -> Result<(), RuntimeError> {
      println!("DEBUG: Entering check_and_scale_containers");
 
-     // Filters out containers not in SUNDOWN state
-     let active_containers: Vec<_> = managed_containers.iter()
-         .filter(|c| c.utilization_category != "SUNDOWN")
-         .collect();
-
-     let active_container_count = active_containers.len();
-     // Calculates average container load
-     let average_load = active_containers.iter()
-         .map(|c| c.score)
-         .sum::<f64>() / active_container_count as f64;
-
      dotenv().ok();
-     println!("DEBUG: CRITICAL_LOAD_THRESHOLD = {:?}", env::var("CRITICAL_LOAD_THRESHOLD"));
+     let env_default_container: i16 = env::var("DEFAULT_CONTAINER")
+         .unwrap_or_else(|_| "1".to_string())
+         .parse()
+         .expect("DEFAULT_CONTAINER must be a valid number");
 
-     let critical_load_threshold = env::var("CRITICAL_LOAD_THRESHOLD")
-         .expect("CRITICAL_LOAD_THRESHOLD must be set")
-         .parse::<f64>()
-         .expect("CRITICAL_LOAD_THRESHOLD must be a valid f64");
+     let bounds = scheduled_bounds(conn, app_identifier, env_default_container);
+     let scheduled_floor = !bounds.active_rules.is_empty() && bounds.min_containers > 0;
+     if !bounds.active_rules.is_empty() {
+          println!("Scheduled rules {:?} active. Container bounds: {} - {}", bounds.active_rules, bounds.min_containers, bounds.max_containers);
+     }
 
-     let has_critically_loaded_container = active_containers.iter()
-         .any(|c| c.score < critical_load_threshold);
+     if managed_containers.iter().all(|c| c.utilization_category == UtilizationCategory::Sundown) {
+          if scheduled_floor {
+               println!("Scheduled rule requires {} container(s). Waking up from zero.", bounds.min_containers);
+               wake_from_zero().await;
+          } else {
+               println!("No active containers (scaled to zero). Waiting for wake-up.");
+          }
+          return Ok(());
+     }
 
-     println!("DEBUG: Active containers: {}, Average load: {:.2}, Has critically loaded container: {}",
-              active_container_count, average_load, has_critically_loaded_container);
+     if !scheduled_floor && scale_to_zero_if_idle(conn, app_identifier, container_statuses, managed_containers).await {
+          return Ok(());
+     }
 
      let cooldown_period = Duration::from_secs(
           env::var("COOLDOWN_PERIOD")
@@ -291,16 +338,9 @@ pub async fn check_and_scale_containers(
               .expect("COOLDOWN_PERIOD must be a valid u64")
      );
 
-     let cooldown_status = get_cooldown_status(cooldown_period).await;
-     let can_scale = can_scale(cooldown_period).await;
-
-     let env_default_container: i16 = env::var("DEFAULT_CONTAINER")
-         .unwrap_or_else(|_| "1".to_string())
-         .parse()
-         .expect("DEFAULT_CONTAINER must be a valid number");
-
-     println!("Current conditions: Average load: {}, Active container count: {}, Has critically loaded container: {}, Cooldown: {}",
-              average_load, active_container_count, has_critically_loaded_container, cooldown_status);
+     let mut timers = SCALING_TIMERS.lock().await;
+     let cooldown_status = timers.cooldown_status(Instant::now(), cooldown_period);
+     let can_scale = timers.can_scale_out(Instant::now(), cooldown_period);
 
      let scale_check_period = Duration::from_secs(
           env::var("SCALE_CHECK_PERIOD")
@@ -309,141 +349,571 @@ pub async fn check_and_scale_containers(
               .expect("SCALE_CHECK_PERIOD must be a valid u64")
      );
 
+     let forecast_config = ForecastConfig::from_env();
+     let mut snapshot = build_metrics_snapshot(conn, container_statuses, managed_containers, env_default_container, &bounds);
+     println!("Current conditions: Average load: {:.2}, Active container count: {}, Cooldown: {}",
+              snapshot.average_score(), snapshot.active_count(), cooldown_status);
+
+     record_load_history(conn, app_identifier, &snapshot, &forecast_config).await;
+
+     let dry_run = is_dry_run();
+     let check_due = timers.check_due(Instant::now(), scale_check_period);
+
+     // Scheduled bounds are enforced right away, independent of cooldown and check period
+     if !bounds.active_rules.is_empty() {
+          let active_count = snapshot.active_count();
+          let decision = if active_count < bounds.min_containers {
+               Some(ScalingDecision::ScaleOut {
+                    count: bounds.min_containers - active_count,
+                    reason: format!("scheduled minimum of {} container(s)", bounds.min_containers),
+               })
+          } else if active_count > bounds.max_containers {
+               Some(ScalingDecision::ScaleIn {
+                    containers: snapshot.scale_in_candidates(active_count - bounds.max_containers),
+                    reason: format!("scheduled maximum of {} container(s)", bounds.max_containers),
+               })
+          } else {
+               None
+          };
 
-     // Gets time of last scaling check and resets that time
-     let mut last_check = LAST_SCALE_CHECK.lock().await;
-     if last_check.elapsed() >= scale_check_period {
-          *last_check = Instant::now();
-
-          // Scale check (see if containers have to be scaled up/down)
-          let high_load_threshold = env::var("HIGH_LOAD_THRESHOLD")
-              .expect("HIGH_LOAD_THRESHOLD must be set")
-              .parse::<f64>()
-              .expect("HIGH_LOAD_THRESHOLD must be a valid f64");
-
-          let low_load_threshold = env::var("LOW_LOAD_THRESHOLD")
-              .expect("LOW_LOAD_THRESHOLD must be set")
-              .parse::<f64>()
-              .expect("LOW_LOAD_THRESHOLD must be a valid f64");
-
-          let max_containers = env::var("MAX_CONTAINERS")
-              .expect("MAX_CONTAINERS must be set")
-              .parse::<usize>()
-              .expect("MAX_CONTAINERS must be a valid usize");
+          if let Some(decision) = decision {
+               println!("Enforcing scheduled bounds: {}", decision);
+               let mut evaluation = ScalingEvaluation::new("schedule", "schedule", serde_json::to_value(&bounds).unwrap_or_default(),
+                                                           serde_json::to_value(&snapshot).unwrap_or_default(), &decision);
+               evaluation.cooldown = cooldown_status.clone();
+               evaluation.active_schedules = bounds.active_rules.clone();
+
+               if dry_run {
+                    // Without applying the decision it would be recorded on every queue rebuild
+                    if check_due {
+                         evaluation.outcome = "dry_run".to_string();
+                         record_evaluation(conn, app_identifier, &evaluation);
+                    }
+                    return Ok(());
+               }
 
-          let scale_step = env::var("SCALE_STEP")
-              .expect("SCALE_STEP must be set")
-              .parse::<usize>()
-              .expect("SCALE_STEP must be a valid usize");
+               let result = apply_decision(runtime, conn, app_identifier, managed_containers, &decision, env_default_container, bounds.min_containers as i16).await;
+               match &result {
+                    Ok(created) if !created.is_empty() => evaluation.containers = created.clone(),
+                    Ok(_) => {}
+                    Err(e) => evaluation.outcome = format!("failed: {}", e),
+               }
+               record_evaluation(conn, app_identifier, &evaluation);
+               return result.map(|_| ());
+          }
+     }
 
+     if check_due {
           println!("Performing scale check...");
-          if active_container_count >= max_containers {
-               println!("Max container limit ({}) reached. Cannot scale up further.", max_containers);
-          } else if can_scale && (average_load < high_load_threshold || has_critically_loaded_container) {
-               // Calculates amount of containers to add
-               let containers_to_add = std::cmp::min(scale_step, max_containers - active_container_count);
-
-               // Loop to add containers
-               for _ in 0..containers_to_add {
-                    let current_default: i16 = conn.get("DEFAULT_CONTAINER").unwrap_or(env_default_container);
-                    let new_default = current_default + 1;
-
-                    if let Err(e) = conn.set::<_, _, ()>("DEFAULT_CONTAINER", new_default) {
-                         eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
-                         return Err(BollardError::IOError {
-                              err: std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                         });
+          snapshot.load_history = load_history(conn, app_identifier, snapshot.timestamp - forecast_config.retention_secs(), snapshot.timestamp)
+              .unwrap_or_else(|e| {
+                   eprintln!("Failed to read load history from Redis: {:?}", e);
+                   Vec::new()
+              });
+          let mut policy = SCALING_POLICY.lock().await;
+          let decision = policy.evaluate(&snapshot);
+          println!("Scaling policy {} decided to {}", policy.name(), decision);
+
+          let mut evaluation = ScalingEvaluation::from_policy(policy.as_ref(), &snapshot, &decision);
+          evaluation.cooldown = cooldown_status.clone();
+          evaluation.active_schedules = bounds.active_rules.clone();
+
+          let decision = match decision {
+               ScalingDecision::ScaleOut { count, reason } => {
+                    let containers_to_add = std::cmp::min(count, snapshot.max_containers.saturating_sub(snapshot.active_count()));
+                    evaluation.count = containers_to_add;
+                    if containers_to_add == 0 {
+                         println!("Max container limit ({}) reached. Cannot scale up further.", snapshot.max_containers);
+                         evaluation.outcome = "skipped_max_containers".to_string();
+                         None
+                    } else if !can_scale {
+                         println!("Scale-out by {} container(s) skipped. Cooldown: {}", containers_to_add, cooldown_status);
+                         evaluation.outcome = "skipped_cooldown".to_string();
+                         None
+                    } else {
+                         Some(ScalingDecision::ScaleOut { count: containers_to_add, reason })
                     }
+               }
+               ScalingDecision::Hold { .. } => {
+                    println!("Current conditions do not require scaling.");
+                    None
+               }
+               scale_in => Some(scale_in),
+          };
 
-                    let image_name = env::var("DOCKER_IMAGE").expect("DOCKER_IMAGE must be set");
-                    let target_port: u16 = env::var("TARGET_PORT")
-                        .expect("TARGET_PORT must be set")
-                        .parse()
-                        .expect("TARGET_PORT must be a valid number");
-
-                    match create_single_container(&image_name, target_port, app_identifier, conn).await {
-                         Ok(_) => {
-                              println!("Created new container due to high load. New DEFAULT_CONTAINER value: {}", new_default);
-                         },
+          if let Some(decision) = decision {
+               if dry_run {
+                    println!("Dry run, not applying: {}", decision);
+                    evaluation.outcome = "dry_run".to_string();
+               } else {
+                    match apply_decision(runtime, conn, app_identifier, managed_containers, &decision, env_default_container, bounds.min_containers as i16).await {
+                         Ok(created) => {
+                              if let ScalingDecision::ScaleOut { count, .. } = decision {
+                                   timers.record_scale_out(Instant::now());
+                                   evaluation.containers = created;
+                                   println!("Cooldown period activated. Next scaling possible after {:?}", cooldown_period);
+                                   println!("Added {} new container(s). New active container count: {}", count, snapshot.active_count() + count);
+                              }
+                         }
                          Err(e) => {
-                              eprintln!("Failed to create new container: {:?}", e);
+                              evaluation.outcome = format!("failed: {}", e);
+                              record_evaluation(conn, app_identifier, &evaluation);
                               return Err(e);
                          }
                     }
                }
+          }
+          record_evaluation(conn, app_identifier, &evaluation);
+     } else {
+          // If not enough time has passed since the last check, skip the scale check
+          println!("Skipping scale check. Next check in {:?}", timers.next_check_in(Instant::now(), scale_check_period));
+     }
 
-               update_cooldown().await;
-               println!("Cooldown period activated. Next scaling possible after {:?}", cooldown_period);
-               println!("Added {} new container(s). New active container count: {}", containers_to_add, active_container_count + containers_to_add);
-          } else if average_load > low_load_threshold && active_container_count > env_default_container as usize {
-               let current_default: i16 = conn.get("DEFAULT_CONTAINER").unwrap_or(env_default_container);
-
-               // Calculates amount of containers to remove
-               let containers_to_remove = std::cmp::min(
-                    scale_step,
-                    std::cmp::min(
-                         active_container_count - env_default_container as usize,
-                         (current_default - env_default_container) as usize
-                    )
-               );
-
-               if containers_to_remove > 0 {
-                    let mut containers_to_sundown: Vec<_> = managed_containers.iter_mut()
-                        .filter(|c| c.utilization_category != "SUNDOWN" && c.utilization_category != "INIT")
-                        .collect();
-
-                    // Sort containers by their score
-                    containers_to_sundown.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
-                    //Mark containers to be put into SUNDOWN state
-                    for container in containers_to_sundown.iter_mut().take(containers_to_remove) {
-                         let key = generate_hash_based_key(app_identifier, &container.dns_name);
-                         if let Err(e) = update_container_category(conn, &key, "SUNDOWN") {
-                              eprintln!("Failed to mark container {} for SUNDOWN: {:?}", container.dns_name, e);
-                         } else {
-                              println!("Marked container {} for graceful shutdown", container.dns_name);
-                              container.utilization_category = "SUNDOWN".to_string();
-                         }
-                    }
+     println!("DEBUG: Exiting check_and_scale_containers");
+     Ok(())
+}
 
-                    let new_default = std::cmp::max(current_default - containers_to_remove as i16, env_default_container);
-                    if let Err(e) = conn.set::<_, _, ()>("DEFAULT_CONTAINER", new_default) {
-                         eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
-                    } else {
-                         println!("Updated DEFAULT_CONTAINER to {} due to scale-down", new_default);
-                    }
+// Collects the inputs of the scaling policy
+fn build_metrics_snapshot(
+     conn: &mut redis::Connection,
+     container_statuses: &[ContainerStatus],
+     managed_containers: &[QueueItem],
+     env_default_container: i16,
+     bounds: &ScalingBounds,
+) -> MetricsSnapshot {
+     let current_default: i16 = metrics().time_redis("get", || conn.get("DEFAULT_CONTAINER")).unwrap_or(env_default_container);
+
+     let containers = managed_containers.iter()
+         .filter(|c| c.utilization_category != UtilizationCategory::Sundown)
+         .map(|c| ContainerMetrics {
+              dns_name: c.dns_name.trim_start_matches('/').to_string(),
+              score: c.score,
+              utilization_category: c.utilization_category,
+              telemetry: container_statuses.iter()
+                  .find(|s| s.name.trim_start_matches('/') == c.dns_name.trim_start_matches('/'))
+                  .and_then(|s| s.telemetry.clone()),
+         })
+         .collect();
 
-                    println!("Marked {} container(s) for SUNDOWN. New active container count: {}", containers_to_remove, active_container_count - containers_to_remove);
-               } else {
-                    println!("Cannot scale down further. Minimum number of active containers reached.");
+     MetricsSnapshot {
+          now: Instant::now(),
+          timestamp: chrono::Utc::now().timestamp(),
+          containers,
+          current_default: std::cmp::max(current_default, 0) as usize,
+          min_containers: bounds.min_containers,
+          max_containers: bounds.max_containers,
+          load_history: Vec::new(),
+     }
+}
+
+// Container bounds from DEFAULT_CONTAINER and MAX_CONTAINERS, overridden by active scheduled rules
+fn scheduled_bounds(conn: &mut redis::Connection, app_identifier: &str, env_default_container: i16) -> ScalingBounds {
+     let max_containers = env::var("MAX_CONTAINERS")
+         .expect("MAX_CONTAINERS must be set")
+         .parse::<usize>()
+         .expect("MAX_CONTAINERS must be a valid usize");
+     let rules = load_rules(conn, app_identifier).unwrap_or_else(|e| {
+          eprintln!("Failed to load scaling schedules from Redis: {:?}", e);
+          Vec::new()
+     });
+     effective_bounds(&rules, std::cmp::max(env_default_container, 0) as usize, max_containers, chrono::Utc::now())
+}
+
+// Persists the current load every LOAD_HISTORY_INTERVAL seconds for predictive scaling
+async fn record_load_history(conn: &mut redis::Connection, app_identifier: &str, snapshot: &MetricsSnapshot, forecast_config: &ForecastConfig) {
+     let history_interval = Duration::from_secs(
+          env::var("LOAD_HISTORY_INTERVAL")
+              .unwrap_or_else(|_| "60".to_string())
+              .parse::<u64>()
+              .expect("LOAD_HISTORY_INTERVAL must be a valid u64")
+     );
+
+     let mut last_sample = LAST_LOAD_SAMPLE.lock().await;
+     if last_sample.is_some_and(|at| at.elapsed() < history_interval) {
+          return;
+     }
+
+     let sample = LoadSample {
+          timestamp: snapshot.timestamp,
+          rps: snapshot.total_rps(),
+          containers: snapshot.active_count(),
+     };
+     match record_load_sample(conn, app_identifier, &sample, forecast_config) {
+          Ok(_) => *last_sample = Some(Instant::now()),
+          Err(e) => eprintln!("Failed to record load sample in Redis: {:?}", e),
+     }
+}
+
+// Executes a scale-out or scale-in decision, returns the names of created containers
+async fn apply_decision(
+     runtime: &dyn ContainerRuntime,
+     conn: &mut redis::Connection,
+     app_identifier: &str,
+     managed_containers: &mut [QueueItem],
+     decision: &ScalingDecision,
+     env_default_container: i16,
+     min_containers: i16,
+) -> Result<Vec<String>, RuntimeError> {
+     match decision {
+          ScalingDecision::ScaleOut { count, .. } => scale_out(runtime, conn, app_identifier, *count, env_default_container).await,
+          ScalingDecision::ScaleIn { containers, .. } => {
+               let removed = scale_in(conn, app_identifier, managed_containers, containers, min_containers);
+               println!("Marked {} container(s) for SUNDOWN. New active container count: {}",
+                        removed, managed_containers.iter().filter(|c| c.utilization_category != UtilizationCategory::Sundown).count());
+               Ok(Vec::new())
+          }
+          ScalingDecision::Hold { .. } => Ok(Vec::new()),
+     }
+}
+
+// Creates new containers and raises DEFAULT_CONTAINER accordingly, returns their names
+async fn scale_out(
+     runtime: &dyn ContainerRuntime,
+     conn: &mut redis::Connection,
+     app_identifier: &str,
+     containers_to_add: usize,
+     env_default_container: i16,
+) -> Result<Vec<String>, RuntimeError> {
+     let mut created = Vec::new();
+     for _ in 0..containers_to_add {
+          let current_default: i16 = metrics().time_redis("get", || conn.get("DEFAULT_CONTAINER")).unwrap_or(env_default_container);
+          let new_default = current_default + 1;
+
+          if let Err(e) = metrics().time_redis("set", || conn.set::<_, _, ()>("DEFAULT_CONTAINER", new_default)) {
+               eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
+               return Err(RuntimeError::from(e));
+          }
+
+          let image_name = env::var("DOCKER_IMAGE").expect("DOCKER_IMAGE must be set");
+          let target_port: u16 = env::var("TARGET_PORT")
+              .expect("TARGET_PORT must be set")
+              .parse()
+              .expect("TARGET_PORT must be a valid number");
+
+          match create_single_container(runtime, &image_name, target_port, app_identifier, conn).await {
+               Ok(item) => {
+                    println!("Created new container due to high load. New DEFAULT_CONTAINER value: {}", new_default);
+                    created.push(item.dns_name);
+               },
+               Err(e) => {
+                    eprintln!("Failed to create new container: {:?}", e);
+                    return Err(e);
                }
+          }
+     }
+     Ok(created)
+}
+
+// Marks the given containers for SUNDOWN and lowers DEFAULT_CONTAINER, returns the number of marked containers.
+// DEFAULT_CONTAINER never drops below the minimum of the current bounds.
+fn scale_in(
+     conn: &mut redis::Connection,
+     app_identifier: &str,
+     managed_containers: &mut [QueueItem],
+     containers_to_remove: &[String],
+     min_containers: i16,
+) -> usize {
+     let current_default: i16 = metrics().time_redis("get", || conn.get("DEFAULT_CONTAINER")).unwrap_or(min_containers);
+
+     //Mark containers to be put into SUNDOWN state
+     let mut removed = 0;
+     for container in managed_containers.iter_mut()
+         .filter(|c| c.utilization_category != UtilizationCategory::Sundown && containers_to_remove.iter().any(|name| name == c.dns_name.trim_start_matches('/'))) {
+          let key = generate_hash_based_key(app_identifier, &container.dns_name);
+          if let Err(e) = update_container_category(conn, &key, UtilizationCategory::Sundown) {
+               eprintln!("Failed to mark container {} for SUNDOWN: {:?}", container.dns_name, e);
           } else {
-               println!("Current conditions do not require scaling.");
+               println!("Marked container {} for graceful shutdown", container.dns_name);
+               container.utilization_category = UtilizationCategory::Sundown;
+               removed += 1;
           }
+     }
+
+     let new_default = std::cmp::max(current_default - removed as i16, min_containers);
+     if let Err(e) = metrics().time_redis("set", || conn.set::<_, _, ()>("DEFAULT_CONTAINER", new_default)) {
+          eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
      } else {
-          // If not enough time has passed since the last check, skip the scale check
-          println!("Skipping scale check. Next check in {:?}", scale_check_period.checked_sub(last_check.elapsed()).unwrap_or(Duration::from_secs(0)));
+          println!("Updated DEFAULT_CONTAINER to {} due to scale-down", new_default);
      }
 
-     println!("DEBUG: Exiting check_and_scale_containers");
-     Ok(())
+     removed
 }
 
-async fn can_scale(cooldown_period: Duration) -> bool {
-     let cooldown = GLOBAL_COOLDOWN.lock().await;
-     cooldown.elapsed() >= cooldown_period
+// Scales the app to zero containers once none of them had traffic for the idle timeout
+async fn scale_to_zero_if_idle(
+     conn: &mut redis::Connection,
+     app_identifier: &str,
+     container_statuses: &[ContainerStatus],
+     managed_containers: &mut [QueueItem],
+) -> bool {
+     let idle_timeout = Duration::from_secs(
+          env::var("SCALE_TO_ZERO_IDLE_TIMEOUT")
+              .unwrap_or_else(|_| "0".to_string())
+              .parse::<u64>()
+              .expect("SCALE_TO_ZERO_IDLE_TIMEOUT must be a valid u64")
+     );
+     if idle_timeout.is_zero() {
+          return false;
+     }
+
+     let idle_threshold = env::var("SCALE_TO_ZERO_IDLE_THRESHOLD")
+         .unwrap_or_else(|_| "0".to_string())
+         .parse::<f64>()
+         .expect("SCALE_TO_ZERO_IDLE_THRESHOLD must be a valid f64");
+
+     let status_map: HashMap<String, &ContainerStatus> = container_statuses.iter()
+         .map(|status| (status.name.trim_start_matches('/').to_string(), status))
+         .collect();
+
+     // Traffic is what the balancers report, the network of a container also carries the agent's health probes.
+     // Containers without status or still initializing count as active.
+     let has_traffic = managed_containers.iter()
+         .filter(|c| c.utilization_category != UtilizationCategory::Sundown)
+         .any(|c| c.utilization_category == UtilizationCategory::Init || status_map.get(c.dns_name.trim_start_matches('/'))
+             .is_none_or(|status| status.telemetry.as_ref().is_some_and(|t| t.rps > idle_threshold || t.in_flight > 0)));
+
+     let mut last_activity = LAST_ACTIVITY.lock().await;
+     if has_traffic {
+          *last_activity = Instant::now();
+          return false;
+     }
+
+     let idle_for = last_activity.elapsed();
+     if idle_for < idle_timeout {
+          println!("No traffic for {:?}. Scaling to zero after {:?}.", idle_for, idle_timeout);
+          return false;
+     }
+
+     let decision = ScalingDecision::ScaleIn {
+          containers: managed_containers.iter()
+              .filter(|c| c.utilization_category != UtilizationCategory::Sundown)
+              .map(|c| c.dns_name.trim_start_matches('/').to_string())
+              .collect(),
+          reason: format!("no traffic for {:?}", idle_for),
+     };
+     let mut evaluation = ScalingEvaluation::new(
+          "scale_to_zero",
+          "scale_to_zero",
+          json!({ "idle_timeout_secs": idle_timeout.as_secs(), "idle_threshold": idle_threshold }),
+          json!({ "idle_for_secs": idle_for.as_secs() }),
+          &decision,
+     );
+     if evaluation.dry_run {
+          println!("Dry run, not scaling to zero after {:?} without traffic", idle_for);
+          evaluation.outcome = "dry_run".to_string();
+          record_evaluation(conn, app_identifier, &evaluation);
+          // Restarts the idle period so the decision is recorded once per idle timeout
+          *last_activity = Instant::now();
+          return false;
+     }
+     record_evaluation(conn, app_identifier, &evaluation);
+
+     println!("No traffic for {:?}. Scaling to zero.", idle_for);
+     for container in managed_containers.iter_mut().filter(|c| c.utilization_category != UtilizationCategory::Sundown) {
+          let key = generate_hash_based_key(app_identifier, &container.dns_name);
+          if let Err(e) = update_container_category(conn, &key, UtilizationCategory::Sundown) {
+               eprintln!("Failed to mark container {} for SUNDOWN: {:?}", container.dns_name, e);
+          } else {
+               println!("Marked idle container {} for graceful shutdown", container.dns_name);
+               container.utilization_category = UtilizationCategory::Sundown;
+          }
+     }
+
+     if let Err(e) = db::set_config_value(conn, "DEFAULT_CONTAINER", 0) {
+          eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
+     }
+
+     true
 }
 
-async fn update_cooldown() {
-     let mut cooldown = GLOBAL_COOLDOWN.lock().await;
-     *cooldown = Instant::now();
+// Restores the default container count after the app was scaled to zero
+pub async fn wake_from_zero() {
+     let mut conn = db::get_redis_connection();
+     let current_default: i16 = db::get_config_value(&mut conn, "DEFAULT_CONTAINER").unwrap_or(0);
+     if current_default > 0 {
+          println!("Wake-up requested, but app is already running with DEFAULT_CONTAINER = {}", current_default);
+          return;
+     }
+
+     let env_default_container: i16 = env::var("DEFAULT_CONTAINER")
+         .unwrap_or_else(|_| "1".to_string())
+         .parse()
+         .expect("DEFAULT_CONTAINER must be a valid number");
+     let wake_container = std::cmp::max(env_default_container, 1);
+
+     if let Err(e) = db::set_config_value(&mut conn, "DEFAULT_CONTAINER", wake_container) {
+          eprintln!("Failed to update DEFAULT_CONTAINER in Redis: {:?}", e);
+          return;
+     }
+
+     *LAST_ACTIVITY.lock().await = Instant::now();
+     let mut wake_requested = WAKE_REQUESTED.lock().await;
+     if wake_requested.is_none() {
+          *wake_requested = Some(Instant::now());
+     }
+     println!("Wake-up requested. Starting {} container(s).", wake_container);
 }
 
-async fn get_cooldown_status(cooldown_period: Duration) -> String {
-     let cooldown = GLOBAL_COOLDOWN.lock().await;
-     let elapsed = cooldown.elapsed();
-     if elapsed >= cooldown_period {
-          "inactive".to_string()
-     } else {
-          format!("active, next scaling possible in {:?}", cooldown_period.checked_sub(elapsed).unwrap_or(Duration::from_secs(0)))
+// Measures the time from a wake-up request until the first container passes its readiness check
+async fn report_cold_start(conn: &mut redis::Connection, container_statuses: &[ContainerStatus], managed_containers: &[QueueItem]) {
+     let mut wake_requested = WAKE_REQUESTED.lock().await;
+     let Some(requested_at) = *wake_requested else {
+          return;
+     };
+
+     let is_ready = managed_containers.iter()
+         .filter(|c| c.utilization_category != UtilizationCategory::Sundown)
+         .any(|c| container_statuses.iter()
+             .any(|s| s.name.trim_start_matches('/') == c.dns_name.trim_start_matches('/') && s.availability_score > 0.0));
+
+     if is_ready {
+          let cold_start = requested_at.elapsed();
+          println!("Cold start completed in {:?}", cold_start);
+          if let Err(e) = db::set_config_value(conn, "COLD_START_DURATION_MS", cold_start.as_millis() as u64) {
+               eprintln!("Failed to store cold start duration in Redis: {:?}", e);
+          }
+          *wake_requested = None;
      }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+     use super::*;
+     use crate::audit::load_evaluations;
+     use crate::runtime::{ContainerSpec, FakeRuntime};
+     use crate::schedule::{save_rule, ScheduleRule};
+     use crate::testing;
+
+     const APP: &str = "test-app";
+
+     // Running workers with complete records, their queue items and statuses with the given score
+     async fn workers(runtime: &FakeRuntime, conn: &mut redis::Connection, count: usize, score: f64) -> (Vec<QueueItem>, Vec<ContainerStatus>) {
+          let mut items = Vec::new();
+          let mut statuses = Vec::new();
+          for i in 0..count {
+               let name = format!("worker-{}", i);
+               let spec = ContainerSpec {
+                    name: name.clone(),
+                    image: "worker:2".to_string(),
+                    target_port: 8080,
+                    host_port: 30000,
+                    labels: HashMap::from([("application".to_string(), APP.to_string())]),
+                    network: None,
+               };
+               let id = runtime.create(&spec).await.unwrap();
+               runtime.start(&id).await.unwrap();
+               let _: () = conn.hset_multiple(generate_hash_based_key(APP, &name), &[
+                    ("category", "MEDIUM"), ("score", "50"), ("port", "30000"), ("image", "worker:2"),
+               ]).unwrap();
+
+               items.push(QueueItem::new(name.clone(), score, UtilizationCategory::Medium));
+               statuses.push(ContainerStatus {
+                    id,
+                    name,
+                    cpu_score: score,
+                    memory_score: score,
+                    network_score: score,
+                    network_throughput: 0.0,
+                    availability_score: score,
+                    latency_score: 100.0,
+                    error_score: 100.0,
+                    overall_score: score,
+                    utilization_category: UtilizationCategory::Medium,
+                    telemetry: None,
+               });
+          }
+          (items, statuses)
+     }
+
+     #[tokio::test]
+     async fn build_queue_starts_the_default_containers() {
+          let mut redis = testing::redis().await;
+          let runtime = Arc::new(FakeRuntime::new());
+          let _: () = redis.conn.set("DEFAULT_CONTAINER", 2).unwrap();
+
+          let snapshot = build_queue(runtime.clone()).await.unwrap();
+
+          assert_eq!(runtime.names().len(), 2);
+          assert_eq!(snapshot.statuses.len(), 2);
+          assert_eq!(snapshot.items.len(), 2);
+          for item in &snapshot.items {
+               let fields: HashMap<String, String> = redis.conn.hgetall(generate_hash_based_key(APP, &item.dns_name)).unwrap();
+               assert!(is_container_complete(&fields), "incomplete record {:?}", fields);
+               assert_ne!(item.utilization_category, UtilizationCategory::Sundown);
+          }
+     }
+
+     #[tokio::test]
+     async fn scales_out_below_the_high_load_threshold() {
+          let mut redis = testing::redis().await;
+          let runtime = FakeRuntime::new();
+          let _: () = redis.conn.set("DEFAULT_CONTAINER", 2).unwrap();
+          let (mut items, statuses) = workers(&runtime, &mut redis.conn, 2, 10.0).await;
+
+          check_and_scale_containers(&runtime, &mut redis.conn, APP, &statuses, &mut items).await.unwrap();
+
+          assert_eq!(runtime.names().len(), 3);
+          assert_eq!(redis.conn.get::<_, i16>("DEFAULT_CONTAINER").unwrap(), 3);
+          let evaluation = &load_evaluations(&mut redis.conn, APP, 1).unwrap()[0];
+          assert_eq!((evaluation.source.as_str(), evaluation.action.as_str(), evaluation.outcome.as_str()), ("policy", "scale_out", "applied"));
+     }
+
+     #[tokio::test]
+     async fn scales_in_above_the_low_load_threshold() {
+          let mut redis = testing::redis().await;
+          let runtime = FakeRuntime::new();
+          let _: () = redis.conn.set("DEFAULT_CONTAINER", 3).unwrap();
+          let (mut items, statuses) = workers(&runtime, &mut redis.conn, 3, 95.0).await;
+
+          check_and_scale_containers(&runtime, &mut redis.conn, APP, &statuses, &mut items).await.unwrap();
+
+          let drained: Vec<&QueueItem> = items.iter().filter(|item| item.utilization_category == UtilizationCategory::Sundown).collect();
+          assert_eq!(drained.len(), 1);
+          let category: String = redis.conn.hget(generate_hash_based_key(APP, &drained[0].dns_name), "category").unwrap();
+          assert_eq!(category, UtilizationCategory::Sundown.as_str());
+          assert_eq!(redis.conn.get::<_, i16>("DEFAULT_CONTAINER").unwrap(), 2);
+          // Workers are only removed once drained
+          assert_eq!(runtime.names().len(), 3);
+     }
+
+     #[tokio::test]
+     async fn scheduled_minimum_overrides_the_policy() {
+          let mut redis = testing::redis().await;
+          let runtime = FakeRuntime::new();
+          let _: () = redis.conn.set("DEFAULT_CONTAINER", 1).unwrap();
+          let (mut items, statuses) = workers(&runtime, &mut redis.conn, 1, 50.0).await;
+          let rule = ScheduleRule {
+               id: "always".to_string(),
+               cron: "* * * * *".to_string(),
+               timezone: "UTC".to_string(),
+               duration_secs: 3600,
+               min_containers: Some(3),
+               max_containers: None,
+          };
+          save_rule(&mut redis.conn, APP, &rule).unwrap();
+
+          check_and_scale_containers(&runtime, &mut redis.conn, APP, &statuses, &mut items).await.unwrap();
+
+          assert_eq!(runtime.names().len(), 3);
+          let evaluation = &load_evaluations(&mut redis.conn, APP, 1).unwrap()[0];
+          assert_eq!((evaluation.source.as_str(), evaluation.count), ("schedule", 2));
+          assert_eq!(evaluation.active_schedules, vec!["always".to_string()]);
+     }
+
+     #[tokio::test]
+     async fn dying_workers_request_a_rebuild() {
+          let mut redis = testing::redis().await;
+          let runtime = Arc::new(FakeRuntime::new());
+          // Drops a request left by another test
+          let _ = tokio::time::timeout(Duration::ZERO, reconcile_requested()).await;
+          let watcher = tokio::spawn(watch_runtime_events(runtime.clone()));
+          sleep(Duration::from_millis(50)).await;
+
+          workers(&runtime, &mut redis.conn, 1, 50.0).await;
+          assert!(tokio::time::timeout(Duration::from_millis(100), reconcile_requested()).await.is_err(), "rebuild requested on start");
+
+          runtime.kill("worker-0").unwrap();
+          assert!(tokio::time::timeout(Duration::from_secs(1), reconcile_requested()).await.is_ok(), "no rebuild requested");
+          watcher.abort();
+     }
+}
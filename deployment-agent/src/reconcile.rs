@@ -0,0 +1,460 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use futures::stream::{self, StreamExt};
+use redis::Commands;
+use crate::container::{create_single_container, generate_hash_based_key, update_container_category};
+use crate::db;
+use crate::metrics::metrics;
+use crate::queue::{QueueItem, UtilizationCategory};
+use crate::runtime::{ContainerRuntime, RuntimeError};
+
+// What the fleet of workers should look like
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesiredState {
+    // Minimum number of active (not SUNDOWN) workers
+    pub replicas: usize,
+    pub image: String,
+    pub target_port: u16,
+}
+
+impl DesiredState {
+    // DEFAULT_CONTAINER from Redis (changed by scaling) or the environment, image and port from the environment
+    pub fn load(conn: &mut redis::Connection) -> Self {
+        let replicas: i16 = metrics().time_redis("get", || conn.get("DEFAULT_CONTAINER")).unwrap_or_else(|_| {
+            env::var("DEFAULT_CONTAINER")
+                .expect("DEFAULT_CONTAINER must be set")
+                .parse()
+                .expect("DEFAULT_CONTAINER must be a valid number")
+        });
+
+        DesiredState {
+            replicas: replicas.max(0) as usize,
+            image: env::var("DOCKER_IMAGE").expect("DOCKER_IMAGE must be set"),
+            target_port: env::var("TARGET_PORT")
+                .expect("TARGET_PORT must be set")
+                .parse()
+                .expect("TARGET_PORT must be a valid number"),
+        }
+    }
+}
+
+// Redis entry of a worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerRecord {
+    pub category: UtilizationCategory,
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObservedContainer {
+    pub id: String,
+    pub name: String,
+    pub key: String,
+    pub record: Option<ContainerRecord>,
+}
+
+// Running workers as reported by the runtime, joined with their Redis entries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObservedState {
+    pub containers: Vec<ObservedContainer>,
+    // Redis entries without a running worker
+    pub orphaned_records: Vec<String>,
+}
+
+impl ObservedState {
+    pub async fn load(runtime: &dyn ContainerRuntime, conn: &mut redis::Connection, app_identifier: &str) -> Result<Self, RuntimeError> {
+        let running = runtime.list(("application", app_identifier), false).await?;
+        let mut keys: HashSet<String> = metrics().time_redis("keys", || conn.keys::<_, Vec<String>>("container:*"))?.into_iter().collect();
+
+        let mut containers = Vec::new();
+        for container in running {
+            let key = generate_hash_based_key(app_identifier, &container.name);
+            let record = if keys.remove(&key) {
+                let fields: HashMap<String, String> = metrics().time_redis("hgetall", || conn.hgetall(&key))?;
+                Some(ContainerRecord {
+                    category: fields.get("category").map_or(UtilizationCategory::Unknown, |category| category.as_str().into()),
+                    image: fields.get("image").cloned(),
+                })
+            } else {
+                None
+            };
+            containers.push(ObservedContainer { id: container.id, name: container.name, key, record });
+        }
+
+        let mut orphaned_records: Vec<String> = keys.into_iter().collect();
+        orphaned_records.sort();
+        Ok(ObservedState { containers, orphaned_records })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    // Starts one new worker with the desired image
+    Create,
+    // Stops and removes a worker the agent has no record of
+    Remove { id: String, name: String },
+    // Marks a worker with an outdated image for graceful shutdown
+    Drain { name: String, key: String },
+    // Deletes the Redis entry of a worker that is gone
+    DeleteRecord { key: String },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+    // Queue of the workers that are kept, in the order they were observed
+    pub queue: Vec<QueueItem>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+// Compares desired and observed state. Workers running an outdated image are replaced
+// by surging: replacements are created first, the outdated workers are drained once
+// enough up-to-date workers run.
+pub fn plan(desired: &DesiredState, observed: &ObservedState) -> Plan {
+    let mut actions = Vec::new();
+    let mut queue = Vec::new();
+    let mut up_to_date = 0;
+    let mut outdated = Vec::new();
+
+    for container in &observed.containers {
+        let Some(record) = &container.record else {
+            actions.push(Action::Remove { id: container.id.clone(), name: container.name.clone() });
+            continue;
+        };
+
+        if record.category == UtilizationCategory::Sundown {
+            // Set a low score for SUNDOWN containers
+            queue.push(QueueItem::new(container.name.clone(), 0.0, UtilizationCategory::Sundown));
+            continue;
+        }
+
+        // Entries without an image predate image tracking and are treated as current
+        if record.image.as_ref().is_some_and(|image| *image != desired.image) {
+            outdated.push(container);
+        } else {
+            up_to_date += 1;
+        }
+        queue.push(QueueItem::new(container.name.clone(), 100.0, record.category));
+    }
+
+    let target = desired.replicas.max(outdated.len());
+    if !outdated.is_empty() && up_to_date >= target {
+        for container in outdated {
+            actions.push(Action::Drain { name: container.name.clone(), key: container.key.clone() });
+            if let Some(item) = queue.iter_mut().find(|item| item.dns_name == container.name) {
+                item.score = 0.0;
+                item.utilization_category = UtilizationCategory::Sundown;
+            }
+        }
+    }
+
+    let missing = target.saturating_sub(up_to_date);
+    actions.extend(std::iter::repeat_n(Action::Create, missing));
+
+    actions.extend(observed.orphaned_records.iter().map(|key| Action::DeleteRecord { key: key.clone() }));
+
+    Plan { actions, queue }
+}
+
+fn concurrency() -> usize {
+    env::var("RECONCILE_CONCURRENCY")
+        .unwrap_or_else(|_| "4".to_string())
+        .parse::<usize>()
+        .expect("RECONCILE_CONCURRENCY must be a valid usize")
+        .max(1)
+}
+
+// Executes the plan with at most RECONCILE_CONCURRENCY actions at once and returns the queue
+// including created workers. Actions are safe to repeat: gone containers and entries count as done.
+pub async fn execute(runtime: &dyn ContainerRuntime, desired: &DesiredState, app_identifier: &str, plan: Plan) -> Vec<QueueItem> {
+    let mut queue = plan.queue;
+
+    let created: Vec<Option<QueueItem>> = stream::iter(plan.actions)
+        .map(|action| async move {
+            match execute_action(runtime, desired, app_identifier, &action).await {
+                Ok(item) => item,
+                Err(e) => {
+                    eprintln!("Failed to execute {:?}: {:?}", action, e);
+                    metrics().record_reconcile_error("action");
+                    None
+                }
+            }
+        })
+        .buffer_unordered(concurrency())
+        .collect()
+        .await;
+
+    queue.extend(created.into_iter().flatten());
+    queue
+}
+
+async fn execute_action(
+    runtime: &dyn ContainerRuntime,
+    desired: &DesiredState,
+    app_identifier: &str,
+    action: &Action,
+) -> Result<Option<QueueItem>, RuntimeError> {
+    match action {
+        Action::Create => {
+            let mut conn = db::get_redis_connection();
+            let item = create_single_container(runtime, &desired.image, desired.target_port, app_identifier, &mut conn).await?;
+            println!("Container created successfully: {}", item.dns_name);
+            Ok(Some(item))
+        }
+        Action::Remove { id, name } => {
+            println!("Container {} not found in DB, stopping and removing", name);
+            if let Err(e) = runtime.stop(id).await {
+                println!("Error stopping container {}: {:?}", name, e);
+            }
+            match runtime.remove(id).await {
+                Ok(_) | Err(RuntimeError::NotFound(_)) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        Action::Drain { name, key } => {
+            println!("Container {} runs an outdated image, marking it for graceful shutdown", name);
+            let mut conn = db::get_redis_connection();
+            update_container_category(&mut conn, key, UtilizationCategory::Sundown)?;
+            Ok(None)
+        }
+        Action::DeleteRecord { key } => {
+            println!("Deleting orphaned database entry '{}'", key);
+            let mut conn = db::get_redis_connection();
+            metrics().time_redis("del", || conn.del::<_, ()>(key))?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{ContainerSpec, FakeRuntime};
+    use crate::testing;
+
+    const APP: &str = "test-app";
+
+    async fn worker(runtime: &FakeRuntime, app_identifier: &str, name: &str, running: bool) {
+        let spec = ContainerSpec {
+            name: name.to_string(),
+            image: "worker:2".to_string(),
+            target_port: 8080,
+            host_port: 30000,
+            labels: HashMap::from([("application".to_string(), app_identifier.to_string())]),
+            network: None,
+        };
+        let id = runtime.create(&spec).await.unwrap();
+        if running {
+            runtime.start(&id).await.unwrap();
+        }
+    }
+
+    fn record(conn: &mut redis::Connection, name: &str, category: UtilizationCategory, image: Option<&str>) {
+        let key = generate_hash_based_key(APP, name);
+        let _: () = conn.hset(&key, "category", category.as_str()).unwrap();
+        if let Some(image) = image {
+            let _: () = conn.hset(&key, "image", image).unwrap();
+        }
+    }
+
+    fn category(conn: &mut redis::Connection, name: &str) -> Option<String> {
+        conn.hget(generate_hash_based_key(APP, name), "category").unwrap()
+    }
+
+    // One reconciliation pass, returns the plan it executed
+    async fn reconcile(runtime: &FakeRuntime, conn: &mut redis::Connection) -> (Vec<Action>, Vec<QueueItem>) {
+        let desired = DesiredState::load(conn);
+        let observed = ObservedState::load(runtime, conn, APP).await.unwrap();
+        let plan = plan(&desired, &observed);
+        let actions = plan.actions.clone();
+        (actions, execute(runtime, &desired, APP, plan).await)
+    }
+
+    // Category and image of a record
+    type Record = (UtilizationCategory, Option<&'static str>);
+
+    struct PlanCase {
+        name: &'static str,
+        replicas: usize,
+        // Running workers with their record, if they have one
+        containers: Vec<(&'static str, Option<Record>)>,
+        orphaned_records: Vec<&'static str>,
+        actions: Vec<Action>,
+        queue: Vec<(&'static str, f64, UtilizationCategory)>,
+    }
+
+    fn drain(name: &str) -> Action {
+        Action::Drain { name: name.to_string(), key: format!("key-{}", name) }
+    }
+
+    #[test]
+    fn plan_cases() {
+        use UtilizationCategory::{Low, Medium, Sundown};
+        let cases = vec![
+            PlanCase {
+                name: "workers without a record are removed",
+                replicas: 1,
+                containers: vec![("a", Some((Low, Some("worker:2")))), ("stray", None)],
+                orphaned_records: vec![],
+                actions: vec![Action::Remove { id: "id-stray".to_string(), name: "stray".to_string() }],
+                queue: vec![("a", 100.0, Low)],
+            },
+            PlanCase {
+                name: "SUNDOWN workers are kept with score 0 and do not count",
+                replicas: 1,
+                containers: vec![("a", Some((Sundown, Some("worker:2"))))],
+                orphaned_records: vec![],
+                actions: vec![Action::Create],
+                queue: vec![("a", 0.0, Sundown)],
+            },
+            PlanCase {
+                name: "records without an image count as current",
+                replicas: 2,
+                containers: vec![("a", Some((Low, None))), ("b", Some((Medium, Some("worker:2"))))],
+                orphaned_records: vec![],
+                actions: vec![],
+                queue: vec![("a", 100.0, Low), ("b", 100.0, Medium)],
+            },
+            PlanCase {
+                name: "outdated workers are replaced before they are drained",
+                replicas: 2,
+                containers: vec![("a", Some((Low, Some("worker:1")))), ("b", Some((Low, Some("worker:1"))))],
+                orphaned_records: vec![],
+                actions: vec![Action::Create, Action::Create],
+                queue: vec![("a", 100.0, Low), ("b", 100.0, Low)],
+            },
+            PlanCase {
+                name: "outdated workers are not drained while replacements are missing",
+                replicas: 2,
+                containers: vec![("a", Some((Low, Some("worker:1")))), ("b", Some((Low, Some("worker:1")))), ("c", Some((Low, Some("worker:2"))))],
+                orphaned_records: vec![],
+                actions: vec![Action::Create],
+                queue: vec![("a", 100.0, Low), ("b", 100.0, Low), ("c", 100.0, Low)],
+            },
+            PlanCase {
+                name: "outdated workers are drained once enough are up to date",
+                replicas: 2,
+                containers: vec![("a", Some((Low, Some("worker:1")))), ("b", Some((Low, Some("worker:2")))), ("c", Some((Low, Some("worker:2"))))],
+                orphaned_records: vec![],
+                actions: vec![drain("a")],
+                queue: vec![("a", 0.0, Sundown), ("b", 100.0, Low), ("c", 100.0, Low)],
+            },
+            PlanCase {
+                name: "more outdated workers than replicas are replaced one for one",
+                replicas: 1,
+                containers: vec![("a", Some((Low, Some("worker:1")))), ("b", Some((Low, Some("worker:1"))))],
+                orphaned_records: vec![],
+                actions: vec![Action::Create, Action::Create],
+                queue: vec![("a", 100.0, Low), ("b", 100.0, Low)],
+            },
+            PlanCase {
+                name: "orphaned records are deleted",
+                replicas: 0,
+                containers: vec![],
+                orphaned_records: vec!["key-gone"],
+                actions: vec![Action::DeleteRecord { key: "key-gone".to_string() }],
+                queue: vec![],
+            },
+        ];
+
+        let desired = DesiredState { replicas: 0, image: "worker:2".to_string(), target_port: 8080 };
+        for case in cases {
+            let observed = ObservedState {
+                containers: case.containers.iter()
+                    .map(|(name, record)| ObservedContainer {
+                        id: format!("id-{}", name),
+                        name: name.to_string(),
+                        key: format!("key-{}", name),
+                        record: record.map(|(category, image)| ContainerRecord { category, image: image.map(str::to_string) }),
+                    })
+                    .collect(),
+                orphaned_records: case.orphaned_records.iter().map(|key| key.to_string()).collect(),
+            };
+
+            let plan = plan(&DesiredState { replicas: case.replicas, ..desired.clone() }, &observed);
+            assert_eq!(plan.actions, case.actions, "{}", case.name);
+            let queue: Vec<(&str, f64, UtilizationCategory)> = plan.queue.iter()
+                .map(|item| (item.dns_name.as_str(), item.score, item.utilization_category))
+                .collect();
+            assert_eq!(queue, case.queue, "{}", case.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn load_joins_running_workers_with_their_records() {
+        let mut redis = testing::redis().await;
+        let runtime = FakeRuntime::new();
+        worker(&runtime, APP, "worker-a", true).await;
+        worker(&runtime, APP, "worker-b", true).await;
+        worker(&runtime, APP, "worker-c", false).await;
+        worker(&runtime, "other-app", "worker-d", true).await;
+        record(&mut redis.conn, "worker-a", UtilizationCategory::Low, Some("worker:2"));
+        record(&mut redis.conn, "worker-c", UtilizationCategory::Medium, Some("worker:2"));
+
+        let observed = ObservedState::load(&runtime, &mut redis.conn, APP).await.unwrap();
+
+        let records: Vec<(&str, Option<&ContainerRecord>)> = observed.containers.iter()
+            .map(|c| (c.name.as_str(), c.record.as_ref()))
+            .collect();
+        assert_eq!(records, vec![
+            ("worker-a", Some(&ContainerRecord { category: UtilizationCategory::Low, image: Some("worker:2".to_string()) })),
+            ("worker-b", None),
+        ]);
+        // Stopped workers are not observed, so their entries are orphaned
+        assert_eq!(observed.orphaned_records, vec![generate_hash_based_key(APP, "worker-c")]);
+    }
+
+    #[tokio::test]
+    async fn execute_brings_the_workers_to_the_desired_state() {
+        let mut redis = testing::redis().await;
+        let runtime = FakeRuntime::new();
+        let _: () = redis.conn.set("DEFAULT_CONTAINER", 2).unwrap();
+        worker(&runtime, APP, "worker-a", true).await;
+        worker(&runtime, APP, "stray", true).await;
+        record(&mut redis.conn, "worker-a", UtilizationCategory::Low, Some("worker:2"));
+        record(&mut redis.conn, "gone", UtilizationCategory::Low, Some("worker:2"));
+
+        let (actions, queue) = reconcile(&runtime, &mut redis.conn).await;
+        assert_eq!(actions.len(), 3);
+        assert_eq!(queue.len(), 2);
+        let names = runtime.names();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"worker-a".to_string()) && !names.contains(&"stray".to_string()));
+        assert!(category(&mut redis.conn, "gone").is_none());
+        let created = names.iter().find(|name| *name != "worker-a").unwrap();
+        assert_eq!(category(&mut redis.conn, created).as_deref(), Some(UtilizationCategory::Init.as_str()));
+
+        // A second pass has nothing left to do
+        let (actions, queue) = reconcile(&runtime, &mut redis.conn).await;
+        assert_eq!(actions, vec![]);
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn outdated_workers_are_drained_once_replaced() {
+        let mut redis = testing::redis().await;
+        let runtime = FakeRuntime::new();
+        let _: () = redis.conn.set("DEFAULT_CONTAINER", 1).unwrap();
+        worker(&runtime, APP, "worker-a", true).await;
+        record(&mut redis.conn, "worker-a", UtilizationCategory::Low, Some("worker:1"));
+
+        let (actions, _) = reconcile(&runtime, &mut redis.conn).await;
+        assert_eq!(actions, vec![Action::Create]);
+        assert_eq!(runtime.names().len(), 2);
+        assert_eq!(category(&mut redis.conn, "worker-a").as_deref(), Some(UtilizationCategory::Low.as_str()));
+
+        let (actions, queue) = reconcile(&runtime, &mut redis.conn).await;
+        assert_eq!(actions, vec![Action::Drain { name: "worker-a".to_string(), key: generate_hash_based_key(APP, "worker-a") }]);
+        assert_eq!(category(&mut redis.conn, "worker-a").as_deref(), Some(UtilizationCategory::Sundown.as_str()));
+        let drained = queue.iter().find(|item| item.dns_name == "worker-a").unwrap();
+        assert_eq!((drained.score, drained.utilization_category), (0.0, UtilizationCategory::Sundown));
+
+        let (actions, _) = reconcile(&runtime, &mut redis.conn).await;
+        assert_eq!(actions, vec![]);
+    }
+}
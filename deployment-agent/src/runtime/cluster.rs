@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use dotenv::dotenv;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use super::placement::{NodeLoad, PlacementStrategy};
+use super::{labelled_events, ContainerDetails, ContainerEvent, ContainerInfo, ContainerRuntime, ContainerSpec, ContainerStats, DockerEndpoint, DockerRuntime, LabelledEvents, RuntimeError};
+
+// Entry of the CLUSTER_NODES file
+#[derive(Deserialize, Debug, Clone)]
+pub struct NodeConfig {
+    pub name: String,
+    // Engine endpoint, like DOCKER_HOST
+    pub host: String,
+    // Directory with ca.pem, cert.pem and key.pem if the engine requires TLS
+    #[serde(default)]
+    pub cert_path: Option<PathBuf>,
+    // Host or ip under which the balancers reach the published ports of the node
+    pub address: String,
+    // Maximum number of workers on the node
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    // Network the workers join; without one the ports are published on the node
+    #[serde(default)]
+    pub network: Option<String>,
+}
+
+fn default_capacity() -> usize {
+    10
+}
+
+struct Node {
+    config: NodeConfig,
+    runtime: Arc<dyn ContainerRuntime>,
+    healthy: AtomicBool,
+}
+
+// Node name and labels of a container
+type Location = (String, HashMap<String, String>);
+
+// Places workers on several engines. Container ids are prefixed with the node name (`node/id`).
+pub struct ClusterRuntime {
+    nodes: Vec<Arc<Node>>,
+    strategy: PlacementStrategy,
+    // Node and labels of every known container by name
+    locations: Mutex<HashMap<String, Location>>,
+    // Died events of containers on lost nodes
+    events: LabelledEvents,
+}
+
+impl ClusterRuntime {
+    pub fn new(nodes: Vec<(NodeConfig, Arc<dyn ContainerRuntime>)>, strategy: PlacementStrategy) -> Self {
+        ClusterRuntime {
+            nodes: nodes.into_iter()
+                .map(|(config, runtime)| Arc::new(Node { config, runtime, healthy: AtomicBool::new(true) }))
+                .collect(),
+            strategy,
+            locations: Mutex::new(HashMap::new()),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    // Connects to every node of the JSON file in CLUSTER_NODES
+    pub fn from_env() -> Result<Self, RuntimeError> {
+        dotenv().ok();
+        let path = env::var("CLUSTER_NODES")
+            .map_err(|_| RuntimeError::Config("CLUSTER_NODES must be set for the cluster runtime".to_string()))?;
+        let content = fs::read_to_string(&path)
+            .map_err(|e| RuntimeError::Config(format!("failed to read CLUSTER_NODES file {}: {}", path, e)))?;
+        let configs: Vec<NodeConfig> = serde_json::from_str(&content)
+            .map_err(|e| RuntimeError::Config(format!("invalid CLUSTER_NODES file {}: {}", path, e)))?;
+        if configs.is_empty() {
+            return Err(RuntimeError::Config("CLUSTER_NODES must contain at least one node".to_string()));
+        }
+
+        let timeout_secs = DockerEndpoint::from_env(false)?.timeout_secs;
+        let mut nodes = Vec::new();
+        for config in configs {
+            let endpoint = DockerEndpoint {
+                host: config.host.clone(),
+                cert_path: config.cert_path.clone(),
+                timeout_secs,
+            };
+            let runtime = DockerRuntime::connect(endpoint)
+                .map_err(|e| RuntimeError::Config(format!("node {}: {}", config.name, e)))?;
+            nodes.push((config, Arc::new(runtime) as Arc<dyn ContainerRuntime>));
+        }
+
+        Ok(Self::new(nodes, PlacementStrategy::from_env()?))
+    }
+
+    fn node(&self, name: &str) -> Option<&Arc<Node>> {
+        self.nodes.iter().find(|node| node.config.name == name)
+    }
+
+    fn remember(&self, name: &str, node: &str, labels: &HashMap<String, String>) {
+        self.locations.lock().unwrap().insert(name.to_string(), (node.to_string(), labels.clone()));
+    }
+
+    // Node and node-local id of a container given by prefixed id or name
+    async fn locate(&self, id_or_name: &str) -> Result<(Arc<Node>, String), RuntimeError> {
+        if let Some((node, id)) = id_or_name.split_once('/') {
+            let node = self.node(node).ok_or_else(|| RuntimeError::NotFound(id_or_name.to_string()))?;
+            return Ok((node.clone(), id.to_string()));
+        }
+
+        let known = self.locations.lock().unwrap().get(id_or_name).map(|(node, _)| node.clone());
+        if let Some(node) = known.and_then(|node| self.node(&node).cloned()) {
+            return Ok((node, id_or_name.to_string()));
+        }
+
+        // Unknown after a restart of the agent: ask the nodes
+        for node in self.healthy_nodes() {
+            if node.runtime.inspect(id_or_name).await.is_ok() {
+                return Ok((node, id_or_name.to_string()));
+            }
+        }
+        Err(RuntimeError::NotFound(id_or_name.to_string()))
+    }
+
+    fn healthy_nodes(&self) -> Vec<Arc<Node>> {
+        self.nodes.iter().filter(|node| node.healthy.load(Ordering::Relaxed)).cloned().collect()
+    }
+
+    fn mark_healthy(&self, node: &Node) {
+        if !node.healthy.swap(true, Ordering::Relaxed) {
+            println!("Node {} is reachable again", node.config.name);
+        }
+    }
+
+    // Hides the containers of a lost node, so the agent recreates them on the remaining nodes
+    fn mark_lost(&self, node: &Node, reason: &RuntimeError) {
+        if !node.healthy.swap(false, Ordering::Relaxed) {
+            return;
+        }
+
+        let lost: Vec<(String, HashMap<String, String>)> = {
+            let mut locations = self.locations.lock().unwrap();
+            let names: Vec<String> = locations.iter()
+                .filter(|(_, (location, _))| *location == node.config.name)
+                .map(|(name, _)| name.clone())
+                .collect();
+            names.into_iter()
+                .filter_map(|name| locations.remove(&name).map(|(_, labels)| (name, labels)))
+                .collect()
+        };
+        eprintln!("Node {} lost ({}), rescheduling its {} container(s)", node.config.name, reason, lost.len());
+
+        for (name, labels) in lost {
+            // Sending only fails without subscribers
+            let _ = self.events.send((labels, ContainerEvent::Died(name)));
+        }
+    }
+
+    async fn node_loads(&self, application: &str) -> Vec<(Arc<Node>, NodeLoad)> {
+        let mut loads = Vec::new();
+        for node in self.healthy_nodes() {
+            // Created workers count before they are started, so concurrent creates respect the capacity
+            match node.runtime.list(("application", application), true).await {
+                Ok(containers) => {
+                    let load = NodeLoad {
+                        name: node.config.name.clone(),
+                        labels: node.config.labels.clone(),
+                        containers: containers.len(),
+                        capacity: node.config.capacity,
+                    };
+                    loads.push((node, load));
+                }
+                Err(e) => self.mark_lost(&node, &e),
+            }
+        }
+        loads
+    }
+}
+
+fn prefixed(node: &str, id: &str) -> String {
+    format!("{}/{}", node, id)
+}
+
+#[async_trait]
+impl ContainerRuntime for ClusterRuntime {
+    // Healthy while at least one node is reachable
+    async fn health(&self) -> Result<String, RuntimeError> {
+        let mut reachable = Vec::new();
+        for node in &self.nodes {
+            match node.runtime.health().await {
+                Ok(_) => {
+                    self.mark_healthy(node);
+                    reachable.push(node.config.name.clone());
+                }
+                Err(e) => self.mark_lost(node, &e),
+            }
+        }
+
+        if reachable.is_empty() {
+            return Err(RuntimeError::Unavailable("no node of the cluster is reachable".to_string()));
+        }
+        Ok(format!("cluster of {}/{} reachable nodes ({}), {:?} placement",
+            reachable.len(), self.nodes.len(), reachable.join(", "), self.strategy))
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError> {
+        let application = spec.labels.get("application").cloned().unwrap_or_default();
+        let loads = self.node_loads(&application).await;
+        let (node_loads, placement): (Vec<_>, Vec<_>) = loads.into_iter().unzip();
+        let index = self.strategy.choose(&placement)
+            .ok_or_else(|| RuntimeError::Unavailable(format!("no node can take container {} ({:?} placement)", spec.name, self.strategy)))?;
+        let node = &node_loads[index];
+
+        let mut labels = spec.labels.clone();
+        labels.insert("node".to_string(), node.config.name.clone());
+        if let Some(zone) = node.config.labels.get("zone") {
+            labels.insert("zone".to_string(), zone.clone());
+        }
+        let node_spec = ContainerSpec {
+            labels,
+            network: node.config.network.clone(),
+            ..spec.clone()
+        };
+
+        println!("Placing container {} on node {}", spec.name, node.config.name);
+        let id = node.runtime.create(&node_spec).await?;
+        self.remember(&spec.name, &node.config.name, &node_spec.labels);
+        Ok(prefixed(&node.config.name, &id))
+    }
+
+    async fn start(&self, id: &str) -> Result<(), RuntimeError> {
+        let (node, id) = self.locate(id).await?;
+        node.runtime.start(&id).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError> {
+        let (node, id) = self.locate(id).await?;
+        node.runtime.stop(&id).await
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError> {
+        let (node, local_id) = self.locate(id).await?;
+        // Locations are kept by name, which a prefixed id does not tell
+        let name = match node.runtime.inspect(&local_id).await {
+            Ok(details) => details.name,
+            Err(_) => local_id.clone(),
+        };
+        node.runtime.remove(&local_id).await?;
+        self.locations.lock().unwrap().remove(&name);
+        Ok(())
+    }
+
+    // Containers of the reachable nodes; those of lost nodes are left out
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError> {
+        let mut containers = Vec::new();
+        for node in &self.nodes {
+            if !node.healthy.load(Ordering::Relaxed) {
+                continue;
+            }
+            match node.runtime.list(label, all).await {
+                Ok(node_containers) => {
+                    for container in node_containers {
+                        self.remember(&container.name, &node.config.name, &container.labels);
+                        containers.push(ContainerInfo {
+                            id: prefixed(&node.config.name, &container.id),
+                            ..container
+                        });
+                    }
+                }
+                Err(e) => self.mark_lost(node, &e),
+            }
+        }
+        Ok(containers)
+    }
+
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError> {
+        let (node, id) = self.locate(id).await?;
+        node.runtime.stats(&id).await
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError> {
+        let (node, local_id) = self.locate(id).await?;
+        let details = node.runtime.inspect(&local_id).await?;
+
+        // Reachable over the published port of the node unless the workers share a network with the agent
+        let address = match (&node.config.network, details.published_port) {
+            (None, Some(port)) => Some(format!("{}:{}", node.config.address, port)),
+            _ => details.address.clone(),
+        };
+        let mut labels = details.labels.clone();
+        labels.insert("node".to_string(), node.config.name.clone());
+        Ok(ContainerDetails {
+            id: prefixed(&node.config.name, &details.id),
+            address,
+            labels,
+            // The zone label of the node, e.g. its availability zone
+            zone: node.config.labels.get("zone").cloned().or(details.zone.clone()),
+            ..details
+        })
+    }
+
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+        let lost = labelled_events(&self.events, label);
+
+        let mut streams: Vec<_> = self.nodes.iter().map(|node| node.runtime.events(label)).collect();
+        streams.push(lost);
+        stream::select_all(streams).boxed()
+    }
+
+    fn queue_address(&self, name: &str, host_port: u16) -> String {
+        let node = self.locations.lock().unwrap().get(name).map(|(node, _)| node.clone());
+        match node.and_then(|node| self.node(&node)) {
+            Some(node) if node.config.network.is_none() => format!("{}:{}", node.config.address, host_port),
+            _ => name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{timeout, Duration};
+    use super::*;
+    use crate::runtime::FakeRuntime;
+
+    fn node(name: &str, capacity: usize) -> NodeConfig {
+        NodeConfig {
+            name: name.to_string(),
+            host: format!("tcp://{}:2375", name),
+            cert_path: None,
+            address: format!("10.0.0.{}", name.len()),
+            capacity,
+            labels: HashMap::new(),
+            network: None,
+        }
+    }
+
+    fn spec(name: &str) -> ContainerSpec {
+        ContainerSpec {
+            name: name.to_string(),
+            image: "worker:2".to_string(),
+            target_port: 8080,
+            host_port: 30000,
+            labels: HashMap::from([("application".to_string(), "test-app".to_string())]),
+            network: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn removing_by_prefixed_id_forgets_the_location() {
+        let cluster = ClusterRuntime::new(vec![(node("n1", 5), Arc::new(FakeRuntime::new()))], PlacementStrategy::Spread);
+        let id = cluster.create(&spec("worker-a")).await.unwrap();
+        assert!(cluster.locations.lock().unwrap().contains_key("worker-a"));
+
+        // The reconciler removes workers by the id from list
+        cluster.remove(&id).await.unwrap();
+        assert!(cluster.locations.lock().unwrap().is_empty());
+    }
+
+    // Cluster of two nodes with room for two workers each
+    fn cluster() -> (ClusterRuntime, Arc<FakeRuntime>, Arc<FakeRuntime>) {
+        let (n1, n2) = (Arc::new(FakeRuntime::new()), Arc::new(FakeRuntime::new()));
+        let mut zoned = node("n2", 2);
+        zoned.labels.insert("zone".to_string(), "eu-2".to_string());
+        let cluster = ClusterRuntime::new(vec![
+            (node("n1", 2), n1.clone() as Arc<dyn ContainerRuntime>),
+            (zoned, n2.clone() as Arc<dyn ContainerRuntime>),
+        ], PlacementStrategy::Spread);
+        (cluster, n1, n2)
+    }
+
+    async fn names(cluster: &ClusterRuntime) -> Vec<String> {
+        let mut names: Vec<String> = cluster.list(("application", "test-app"), true).await.unwrap()
+            .into_iter()
+            .map(|container| format!("{}@{}", container.name, container.labels["node"]))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn workers_are_spread_until_the_nodes_are_full() {
+        let (cluster, n1, n2) = cluster();
+        let mut ids = Vec::new();
+        for name in ["worker-a", "worker-b", "worker-c", "worker-d"] {
+            ids.push(cluster.create(&spec(name)).await.unwrap());
+        }
+        assert_eq!(ids, vec!["n1/fake-000000000001", "n2/fake-000000000001", "n1/fake-000000000002", "n2/fake-000000000002"]);
+        assert_eq!((n1.names(), n2.names()), (
+            vec!["worker-a".to_string(), "worker-c".to_string()],
+            vec!["worker-b".to_string(), "worker-d".to_string()],
+        ));
+        assert!(matches!(cluster.create(&spec("worker-e")).await, Err(RuntimeError::Unavailable(_))));
+
+        // Workers take the zone of their node
+        let details = cluster.inspect("worker-b").await.unwrap();
+        assert_eq!(details.zone.as_deref(), Some("eu-2"));
+        assert_eq!(details.labels.get("zone").map(String::as_str), Some("eu-2"));
+    }
+
+    #[tokio::test]
+    async fn containers_are_found_by_prefixed_id_and_by_name() {
+        let (cluster, _, _) = cluster();
+        cluster.create(&spec("worker-a")).await.unwrap();
+        let id = cluster.create(&spec("worker-b")).await.unwrap();
+
+        cluster.start(&id).await.unwrap();
+        let by_id = cluster.inspect(&id).await.unwrap();
+        let by_name = cluster.inspect("worker-b").await.unwrap();
+        assert_eq!(by_id, by_name);
+        assert_eq!((by_id.id.as_str(), by_id.name.as_str(), by_id.running), ("n2/fake-000000000001", "worker-b", true));
+        // Published on the address of the node
+        assert_eq!(by_id.address.as_deref(), Some("10.0.0.2:30000"));
+        assert_eq!(cluster.queue_address("worker-b", 30000), "10.0.0.2:30000");
+
+        assert!(matches!(cluster.inspect("n3/fake-000000000001").await, Err(RuntimeError::NotFound(_))));
+        assert!(matches!(cluster.inspect("worker-x").await, Err(RuntimeError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn containers_of_lost_nodes_are_hidden_until_it_recovers() {
+        let (cluster, _, n2) = cluster();
+        for name in ["worker-a", "worker-b", "worker-c"] {
+            let id = cluster.create(&spec(name)).await.unwrap();
+            cluster.start(&id).await.unwrap();
+        }
+        let mut events = cluster.events(("application", "test-app"));
+
+        n2.set_available(false);
+        assert_eq!(names(&cluster).await, vec!["worker-a@n1", "worker-c@n1"]);
+        let event = timeout(Duration::from_secs(1), events.next()).await.expect("no event for the lost node");
+        assert_eq!(event.unwrap().unwrap(), ContainerEvent::Died("worker-b".to_string()));
+        assert!(cluster.health().await.unwrap().starts_with("cluster of 1/2 reachable nodes (n1)"));
+        // Replacements go to the remaining nodes only
+        assert!(matches!(cluster.create(&spec("worker-d")).await, Err(RuntimeError::Unavailable(_))));
+
+        n2.set_available(true);
+        assert!(cluster.health().await.unwrap().starts_with("cluster of 2/2 reachable nodes"));
+        assert_eq!(names(&cluster).await, vec!["worker-a@n1", "worker-b@n2", "worker-c@n1"]);
+        assert_eq!(cluster.create(&spec("worker-d")).await.unwrap(), "n2/fake-000000000002");
+    }
+
+    #[tokio::test]
+    async fn removed_containers_are_not_reported_lost() {
+        let (cluster, _, n2) = cluster();
+        cluster.create(&spec("worker-a")).await.unwrap();
+        let id = cluster.create(&spec("worker-b")).await.unwrap();
+        cluster.remove(&id).await.unwrap();
+        let mut events = cluster.events(("application", "test-app"));
+
+        n2.set_available(false);
+        cluster.health().await.unwrap();
+        // The event streams never end, so only a timeout shows that nothing was sent
+        assert!(timeout(Duration::from_millis(100), events.next()).await.is_err());
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use bollard::{Docker, API_DEFAULT_VERSION};
+use bollard::container::{Config, CreateContainerOptions, ListContainersOptions, RemoveContainerOptions, StartContainerOptions, StatsOptions, StopContainerOptions};
+use bollard::image::{CreateImageOptions, ListImagesOptions};
+use bollard::models::{HostConfig, PortBinding};
+use bollard::system::EventsOptions;
+use futures::stream::BoxStream;
+use futures_util::StreamExt;
+use tokio::time::sleep;
+use crate::metrics::metrics;
+use super::{ContainerDetails, ContainerEvent, ContainerInfo, ContainerRuntime, ContainerSpec, ContainerStats, RuntimeError};
+
+// Network the worker containers and the balancer share
+const WORKER_NETWORK: &str = "rust-network";
+
+const DEFAULT_DOCKER_SOCKET: &str = "unix:///var/run/docker.sock";
+const DEFAULT_PODMAN_SOCKET: &str = "unix:///run/podman/podman.sock";
+
+// Engine the runtime talks to: a Docker or Podman socket, or a TCP endpoint with optional TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct DockerEndpoint {
+    pub host: String,
+    // Directory with ca.pem, cert.pem and key.pem for client certificate authentication
+    pub cert_path: Option<PathBuf>,
+    pub timeout_secs: u64,
+}
+
+impl DockerEndpoint {
+    // Reads DOCKER_HOST, DOCKER_TLS_VERIFY, DOCKER_CERT_PATH and DOCKER_TIMEOUT. Without DOCKER_HOST
+    // the default socket of the engine is used; for Podman the rootless socket if it exists.
+    pub fn from_env(podman: bool) -> Result<Self, RuntimeError> {
+        let host = match env::var("DOCKER_HOST").ok().filter(|h| !h.is_empty()) {
+            Some(host) => host,
+            None if podman => default_podman_socket(),
+            None => DEFAULT_DOCKER_SOCKET.to_string(),
+        };
+
+        let tls_verify = env::var("DOCKER_TLS_VERIFY").is_ok_and(|v| !v.is_empty() && v != "0");
+        let cert_path = if tls_verify || host.starts_with("https://") {
+            let path = env::var("DOCKER_CERT_PATH")
+                .map_err(|_| RuntimeError::Config("DOCKER_CERT_PATH must be set when connecting with TLS".to_string()))?;
+            Some(PathBuf::from(path))
+        } else {
+            None
+        };
+
+        let timeout_secs = match env::var("DOCKER_TIMEOUT") {
+            Ok(v) => v.parse::<u64>()
+                .map_err(|_| RuntimeError::Config(format!("DOCKER_TIMEOUT must be a valid u64, got {}", v)))?,
+            Err(_) => 120,
+        };
+
+        Ok(DockerEndpoint { host, cert_path, timeout_secs })
+    }
+
+    fn connect(&self) -> Result<Docker, RuntimeError> {
+        let scheme = self.host.split("://").next().unwrap_or_default();
+        let docker = match (scheme, &self.cert_path) {
+            ("unix", _) => {
+                let path = self.host.trim_start_matches("unix://");
+                if !Path::new(path).exists() {
+                    return Err(RuntimeError::Config(format!("engine socket {} does not exist (is it mounted into the container?)", path)));
+                }
+                Docker::connect_with_unix(&self.host, self.timeout_secs, API_DEFAULT_VERSION)?
+            }
+            ("tcp" | "https", Some(cert_path)) => {
+                let file = |name: &str| -> Result<PathBuf, RuntimeError> {
+                    let file = cert_path.join(name);
+                    if file.is_file() {
+                        Ok(file)
+                    } else {
+                        Err(RuntimeError::Config(format!("TLS file {} not found in DOCKER_CERT_PATH", file.display())))
+                    }
+                };
+                Docker::connect_with_ssl(&self.host, &file("key.pem")?, &file("cert.pem")?, &file("ca.pem")?, self.timeout_secs, API_DEFAULT_VERSION)?
+            }
+            ("tcp" | "http", None) => Docker::connect_with_http(&self.host, self.timeout_secs, API_DEFAULT_VERSION)?,
+            _ => return Err(RuntimeError::Config(format!("unsupported DOCKER_HOST {}, expected unix://, tcp://, http:// or https://", self.host))),
+        };
+        Ok(docker)
+    }
+}
+
+fn default_podman_socket() -> String {
+    // Rootless Podman listens in the runtime directory of the user
+    env::var("XDG_RUNTIME_DIR").ok()
+        .map(|dir| format!("{}/podman/podman.sock", dir))
+        .filter(|path| Path::new(path).exists())
+        .map(|path| format!("unix://{}", path))
+        .unwrap_or_else(|| DEFAULT_PODMAN_SOCKET.to_string())
+}
+
+pub struct DockerRuntime {
+    docker: Docker,
+    endpoint: DockerEndpoint,
+}
+
+impl DockerRuntime {
+    pub fn connect(endpoint: DockerEndpoint) -> Result<Self, RuntimeError> {
+        let docker = endpoint.connect()?;
+        Ok(DockerRuntime { docker, endpoint })
+    }
+
+    pub fn endpoint(&self) -> &DockerEndpoint {
+        &self.endpoint
+    }
+
+    async fn pull_image(&self, image_name: &str) -> Result<(), RuntimeError> {
+        println!("Checking if image exists locally: {}", image_name);
+
+        // Check if the image already exists locally
+        let list_options = ListImagesOptions::<String> {
+            all: true,
+            filters: {
+                let mut filters = HashMap::new();
+                filters.insert("reference".to_string(), vec![image_name.to_string()]);
+                filters
+            },
+            ..Default::default()
+        };
+
+        let images = self.docker.list_images(Some(list_options)).await?;
+
+        if !images.is_empty() {
+            println!("Image {} already exists locally. Skipping pull.", image_name);
+            return Ok(());
+        }
+
+        println!("Image not found locally. Starting to pull image: {}", image_name);
+        let start_time = Instant::now();
+        let create_image_options = CreateImageOptions {
+            from_image: image_name,
+            ..Default::default()
+        };
+        let mut retry_count = 0;
+        let max_retries = 3;
+        let mut delay = Duration::from_secs(5);
+
+        while retry_count < max_retries {
+            let mut stream = self.docker.create_image(Some(create_image_options.clone()), None, None);
+            let mut success = true;
+
+            while let Some(pull_result) = stream.next().await {
+                match pull_result {
+                    Ok(output) => {
+                        if let Some(status) = output.status {
+                            let elapsed = start_time.elapsed().as_secs();
+                            match status.as_str() {
+                                "Downloading" => {
+                                    if let (Some(id), Some(progress_detail)) = (output.id, output.progress_detail) {
+                                        if let (Some(current), Some(total)) = (progress_detail.current, progress_detail.total) {
+                                            println!("[{}s] Downloading layer {}: {:.1}% ({}/{} bytes)",
+                                                     elapsed, &id[..12], (current as f64 / total as f64) * 100.0, current, total);
+                                        }
+                                    }
+                                }
+                                "Extracting" => {
+                                    if let Some(id) = output.id {
+                                        println!("[{}s] Extracting layer {}", elapsed, &id[..12]);
+                                    }
+                                }
+                                "Download complete" | "Pull complete" => {
+                                    if let Some(id) = output.id {
+                                        println!("[{}s] Layer {} completed", elapsed, &id[..12]);
+                                    }
+                                }
+                                _ => println!("[{}s] Status: {}", elapsed, status),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!("Error while pulling image: {:?}", e);
+                        success = false;
+                        break;
+                    }
+                }
+            }
+
+            if success {
+                println!("Image pulled successfully: {} (took {}s)", image_name, start_time.elapsed().as_secs());
+                metrics().record_image_pull(start_time.elapsed());
+                return Ok(());
+            }
+
+            retry_count += 1;
+            if retry_count < max_retries {
+                println!("Retrying image pull in {:?}...", delay);
+                sleep(delay).await;
+                delay *= 2; // Exponential backoff
+            }
+        }
+
+        println!("Max retries reached while pulling image: {}", image_name);
+        Err(RuntimeError::Other("Max retries reached while pulling image".to_string()))
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for DockerRuntime {
+    // Pings the engine, so a wrong endpoint or missing permissions show up at startup
+    async fn health(&self) -> Result<String, RuntimeError> {
+        self.docker.ping().await.map_err(|e| {
+            RuntimeError::Unavailable(format!("engine at {} is not reachable: {}", self.endpoint.host, e))
+        })?;
+        let version = self.docker.version().await?;
+        Ok(format!("{} {} (API {}) at {}",
+            version.platform.map(|p| p.name).unwrap_or_else(|| "Docker Engine".to_string()),
+            version.version.unwrap_or_default(),
+            version.api_version.unwrap_or_default(),
+            self.endpoint.host))
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError> {
+        self.pull_image(&spec.image).await?;
+
+        let port_bindings = {
+            let mut map = HashMap::new();
+            map.insert(
+                format!("{}/tcp", spec.target_port),
+                Some(vec![PortBinding {
+                    host_ip: Some("0.0.0.0".to_string()),
+                    host_port: Some(spec.host_port.to_string()),
+                }]),
+            );
+            map
+        };
+
+        let host_config = HostConfig {
+            port_bindings: Some(port_bindings),
+            network_mode: spec.network.clone(),
+            ..Default::default()
+        };
+
+        let config = Config {
+            image: Some(spec.image.clone()),
+            host_config: Some(host_config),
+            labels: Some(spec.labels.clone()),
+            ..Default::default()
+        };
+
+        let create_options = CreateContainerOptions {
+            name: spec.name.as_str(),
+            ..Default::default()
+        };
+
+        let response = self.docker.create_container(Some(create_options), config).await?;
+        Ok(response.id)
+    }
+
+    async fn start(&self, id: &str) -> Result<(), RuntimeError> {
+        Ok(self.docker.start_container(id, None::<StartContainerOptions<String>>).await?)
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError> {
+        Ok(self.docker.stop_container(id, None::<StopContainerOptions>).await?)
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError> {
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        Ok(self.docker.remove_container(id, Some(options)).await?)
+    }
+
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError> {
+        let filters = {
+            let mut map = HashMap::new();
+            map.insert("label".to_string(), vec![format!("{}={}", label.0, label.1)]);
+            if !all {
+                map.insert("status".to_string(), vec!["running".to_string()]);
+            }
+            map
+        };
+
+        let options = ListContainersOptions {
+            all,
+            filters,
+            ..Default::default()
+        };
+
+        let containers = self.docker.list_containers(Some(options)).await?;
+        Ok(containers.into_iter()
+            .filter_map(|container| Some(ContainerInfo {
+                id: container.id?,
+                name: container.names.unwrap_or_default().first().cloned().unwrap_or_default().trim_start_matches('/').to_string(),
+                running: container.state.as_deref() == Some("running"),
+                labels: container.labels.unwrap_or_default(),
+            }))
+            .collect())
+    }
+
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError> {
+        let mut stats_stream = self.docker.stats(id, Some(StatsOptions {
+            stream: false,
+            one_shot: true,
+        }));
+        let stats = stats_stream.next().await
+            .ok_or_else(|| RuntimeError::NotFound(id.to_string()))??;
+
+        let (network_rx_bytes, network_tx_bytes) = stats.networks.as_ref()
+            .map(|networks| networks.values().fold((0, 0), |(rx, tx), n| (rx + n.rx_bytes, tx + n.tx_bytes)))
+            .unwrap_or((0, 0));
+
+        Ok(ContainerStats {
+            cpu_total_usage: stats.cpu_stats.cpu_usage.total_usage,
+            system_cpu_usage: stats.cpu_stats.system_cpu_usage.unwrap_or(0),
+            online_cpus: stats.cpu_stats.online_cpus.unwrap_or(1),
+            memory_usage: stats.memory_stats.usage.unwrap_or(0),
+            memory_limit: stats.memory_stats.limit.unwrap_or(1),
+            network_rx_bytes,
+            network_tx_bytes,
+        })
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError> {
+        let info = self.docker.inspect_container(id, None).await?;
+
+        let address = info.network_settings.as_ref().and_then(|settings| {
+            let ip_address = settings.networks.as_ref()?.get(WORKER_NETWORK)?.ip_address.clone()?;
+            let port = settings.ports.as_ref()?.keys().next()?.split('/').next()?.to_string();
+            Some(format!("{}:{}", ip_address, port))
+        });
+        let published_port = info.network_settings.as_ref()
+            .and_then(|settings| settings.ports.as_ref())
+            .and_then(|ports| ports.values().flatten().flatten().find_map(|binding| binding.host_port.as_ref()?.parse::<u16>().ok()));
+
+        Ok(ContainerDetails {
+            id: info.id.unwrap_or_else(|| id.to_string()),
+            name: info.name.unwrap_or_default().trim_start_matches('/').to_string(),
+            running: info.state.and_then(|s| s.running).unwrap_or(false),
+            address,
+            published_port,
+            labels: info.config.and_then(|config| config.labels).unwrap_or_default(),
+            zone: None,
+        })
+    }
+
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+        let mut filters = HashMap::new();
+        filters.insert("type".to_string(), vec!["container".to_string()]);
+        filters.insert("label".to_string(), vec![format!("{}={}", label.0, label.1)]);
+
+        self.docker.events(Some(EventsOptions::<String> { filters, ..Default::default() }))
+            .filter_map(|event| async move {
+                match event {
+                    Ok(event) => {
+                        let name = event.actor
+                            .and_then(|actor| actor.attributes)
+                            .and_then(|attributes| attributes.get("name").cloned())
+                            .unwrap_or_default();
+                        match event.action.as_deref() {
+                            Some("start") => Some(Ok(ContainerEvent::Started(name))),
+                            Some("stop") => Some(Ok(ContainerEvent::Stopped(name))),
+                            Some("die") => Some(Ok(ContainerEvent::Died(name))),
+                            Some("destroy") => Some(Ok(ContainerEvent::Removed(name))),
+                            _ => None,
+                        }
+                    }
+                    Err(e) => Some(Err(RuntimeError::from(e))),
+                }
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Mutex;
+    use protocol::tls::testing::Authority;
+    use super::*;
+
+    // Tests reading the DOCKER_* variables run one at a time
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const VARIABLES: [&str; 5] = ["DOCKER_HOST", "DOCKER_TLS_VERIFY", "DOCKER_CERT_PATH", "DOCKER_TIMEOUT", "XDG_RUNTIME_DIR"];
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("agent-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Runtime flavour, variables set and the expected endpoint or error
+    type EnvCase<'a> = (bool, Vec<(&'a str, &'a str)>, Result<DockerEndpoint, &'a str>);
+
+    fn endpoint(host: &str, cert_path: Option<&Path>) -> DockerEndpoint {
+        DockerEndpoint { host: host.to_string(), cert_path: cert_path.map(Path::to_path_buf), timeout_secs: 120 }
+    }
+
+    #[test]
+    fn endpoints_are_read_from_the_environment() {
+        let _env = ENV_LOCK.lock().unwrap();
+        let runtime_dir = temp_dir("xdg");
+        fs::create_dir_all(runtime_dir.join("podman")).unwrap();
+        fs::write(runtime_dir.join("podman/podman.sock"), "").unwrap();
+        let rootless_socket = format!("unix://{}/podman/podman.sock", runtime_dir.display());
+        let runtime_dir = runtime_dir.to_str().unwrap();
+
+        let cases: Vec<EnvCase> = vec![
+            (false, vec![], Ok(endpoint(DEFAULT_DOCKER_SOCKET, None))),
+            (false, vec![("DOCKER_HOST", "")], Ok(endpoint(DEFAULT_DOCKER_SOCKET, None))),
+            (false, vec![("DOCKER_HOST", "tcp://engine:2375"), ("DOCKER_TIMEOUT", "30")],
+             Ok(DockerEndpoint { timeout_secs: 30, ..endpoint("tcp://engine:2375", None) })),
+            (false, vec![("DOCKER_HOST", "tcp://engine:2376"), ("DOCKER_TLS_VERIFY", "1"), ("DOCKER_CERT_PATH", "/certs")],
+             Ok(endpoint("tcp://engine:2376", Some(Path::new("/certs"))))),
+            (false, vec![("DOCKER_HOST", "tcp://engine:2375"), ("DOCKER_TLS_VERIFY", "0"), ("DOCKER_CERT_PATH", "/certs")],
+             Ok(endpoint("tcp://engine:2375", None))),
+            (false, vec![("DOCKER_HOST", "https://engine:2376"), ("DOCKER_CERT_PATH", "/certs")],
+             Ok(endpoint("https://engine:2376", Some(Path::new("/certs"))))),
+            (false, vec![("DOCKER_HOST", "https://engine:2376")], Err("DOCKER_CERT_PATH must be set")),
+            (false, vec![("DOCKER_TLS_VERIFY", "1")], Err("DOCKER_CERT_PATH must be set")),
+            (false, vec![("DOCKER_TIMEOUT", "soon")], Err("DOCKER_TIMEOUT must be a valid u64, got soon")),
+            (true, vec![], Ok(endpoint(DEFAULT_PODMAN_SOCKET, None))),
+            (true, vec![("XDG_RUNTIME_DIR", "/nonexistent")], Ok(endpoint(DEFAULT_PODMAN_SOCKET, None))),
+            (true, vec![("XDG_RUNTIME_DIR", runtime_dir)], Ok(endpoint(&rootless_socket, None))),
+            (true, vec![("XDG_RUNTIME_DIR", runtime_dir), ("DOCKER_HOST", "unix:///tmp/podman.sock")],
+             Ok(endpoint("unix:///tmp/podman.sock", None))),
+        ];
+
+        for (podman, variables, expected) in cases {
+            for name in VARIABLES {
+                env::remove_var(name);
+            }
+            for (name, value) in &variables {
+                env::set_var(name, value);
+            }
+            let result = DockerEndpoint::from_env(podman);
+            match (result, expected) {
+                (Ok(endpoint), Ok(expected)) => assert_eq!(endpoint, expected, "{:?}", variables),
+                (Err(e), Err(expected)) => assert!(e.to_string().contains(expected), "{:?}: {}", variables, e),
+                (result, expected) => panic!("{:?}: expected {:?}, got {:?}", variables, expected, result.map_err(|e| e.to_string())),
+            }
+        }
+        for name in VARIABLES {
+            env::remove_var(name);
+        }
+    }
+
+    #[tokio::test]
+    async fn endpoints_connect_by_scheme() {
+        let dir = temp_dir("engine");
+        let socket = dir.join("docker.sock");
+        fs::write(&socket, "").unwrap();
+        let certs = dir.join("certs");
+        let authority = Authority::new("engine-ca");
+        authority.trust(&certs);
+        authority.issue(&certs, "engine");
+        let without_key = dir.join("without-key");
+        authority.trust(&without_key);
+        authority.issue(&without_key, "engine");
+        fs::remove_file(without_key.join("key.pem")).unwrap();
+
+        let unix_host = format!("unix://{}", socket.display());
+        let cases: Vec<(DockerEndpoint, Result<(), String>)> = vec![
+            (endpoint(&unix_host, None), Ok(())),
+            (endpoint("unix:///nonexistent/docker.sock", None), Err("engine socket /nonexistent/docker.sock does not exist".to_string())),
+            (endpoint("tcp://127.0.0.1:2375", None), Ok(())),
+            (endpoint("http://127.0.0.1:2375", None), Ok(())),
+            (endpoint("tcp://127.0.0.1:2376", Some(&certs)), Ok(())),
+            (endpoint("https://127.0.0.1:2376", Some(&certs)), Ok(())),
+            (endpoint("tcp://127.0.0.1:2376", Some(&without_key)),
+             Err(format!("TLS file {} not found in DOCKER_CERT_PATH", without_key.join("key.pem").display()))),
+            (endpoint("tcp://127.0.0.1:2376", Some(&dir.join("missing"))), Err("not found in DOCKER_CERT_PATH".to_string())),
+            (endpoint("ssh://engine", None), Err("unsupported DOCKER_HOST ssh://engine".to_string())),
+            (endpoint("npipe:////./pipe/docker_engine", None), Err("unsupported DOCKER_HOST".to_string())),
+        ];
+
+        for (endpoint, expected) in cases {
+            let result = endpoint.connect().map(|_| ()).map_err(|e| e.to_string());
+            match (&result, &expected) {
+                (Ok(()), Ok(())) => {}
+                (Err(e), Err(expected)) => assert!(e.contains(expected.as_str()), "{}: {}", endpoint.host, e),
+                _ => panic!("{}: expected {:?}, got {:?}", endpoint.host, expected, result),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use tokio::sync::broadcast;
+use super::{labelled_events, ContainerDetails, ContainerEvent, ContainerInfo, ContainerRuntime, ContainerSpec, ContainerStats, LabelledEvents, RuntimeError};
+
+struct FakeContainer {
+    id: String,
+    spec: ContainerSpec,
+    running: bool,
+    stats: ContainerStats,
+}
+
+// Deterministic in-memory runtime: ids are sequential, containers start instantly
+// and report the stats set with `set_stats`
+pub struct FakeRuntime {
+    containers: Mutex<BTreeMap<String, FakeContainer>>,
+    next_id: Mutex<usize>,
+    // Events with the labels of their container
+    events: LabelledEvents,
+    // Cleared to simulate an engine that can not be reached
+    available: AtomicBool,
+}
+
+impl Default for FakeRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeRuntime {
+    pub fn new() -> Self {
+        FakeRuntime {
+            containers: Mutex::new(BTreeMap::new()),
+            next_id: Mutex::new(0),
+            events: broadcast::channel(64).0,
+            available: AtomicBool::new(true),
+        }
+    }
+
+    // While unavailable every call fails, the containers are kept
+    pub fn set_available(&self, available: bool) {
+        self.available.store(available, Ordering::Relaxed);
+    }
+
+    fn reachable(&self) -> Result<(), RuntimeError> {
+        if self.available.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(RuntimeError::Unavailable("fake runtime is unavailable".to_string()))
+        }
+    }
+
+    pub fn set_stats(&self, id_or_name: &str, stats: ContainerStats) -> Result<(), RuntimeError> {
+        self.with_container(id_or_name, |container| container.stats = stats)
+    }
+
+    // Stops the container as if its process had exited
+    pub fn kill(&self, id_or_name: &str) -> Result<(), RuntimeError> {
+        let (labels, name) = self.with_container(id_or_name, |container| {
+            container.running = false;
+            (container.spec.labels.clone(), container.spec.name.clone())
+        })?;
+        self.emit(labels, ContainerEvent::Died(name));
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.containers.lock().unwrap().values().map(|c| c.spec.name.clone()).collect()
+    }
+
+    fn with_container<T>(&self, id_or_name: &str, f: impl FnOnce(&mut FakeContainer) -> T) -> Result<T, RuntimeError> {
+        let mut containers = self.containers.lock().unwrap();
+        containers.values_mut()
+            .find(|c| c.id == id_or_name || c.spec.name == id_or_name)
+            .map(f)
+            .ok_or_else(|| RuntimeError::NotFound(id_or_name.to_string()))
+    }
+
+    fn emit(&self, labels: HashMap<String, String>, event: ContainerEvent) {
+        // Sending only fails without subscribers
+        let _ = self.events.send((labels, event));
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    async fn health(&self) -> Result<String, RuntimeError> {
+        self.reachable()?;
+        Ok("in-memory fake runtime".to_string())
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError> {
+        self.reachable()?;
+        let mut containers = self.containers.lock().unwrap();
+        if containers.values().any(|c| c.spec.name == spec.name) {
+            return Err(RuntimeError::Other(format!("container name {} is already in use", spec.name)));
+        }
+
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let id = format!("fake-{:012}", *next_id);
+        containers.insert(id.clone(), FakeContainer {
+            id: id.clone(),
+            spec: spec.clone(),
+            running: false,
+            stats: ContainerStats { online_cpus: 1, memory_limit: 1, ..Default::default() },
+        });
+        Ok(id)
+    }
+
+    async fn start(&self, id: &str) -> Result<(), RuntimeError> {
+        self.reachable()?;
+        let (labels, name) = self.with_container(id, |container| {
+            container.running = true;
+            (container.spec.labels.clone(), container.spec.name.clone())
+        })?;
+        self.emit(labels, ContainerEvent::Started(name));
+        Ok(())
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError> {
+        self.reachable()?;
+        let (labels, name) = self.with_container(id, |container| {
+            container.running = false;
+            (container.spec.labels.clone(), container.spec.name.clone())
+        })?;
+        self.emit(labels, ContainerEvent::Stopped(name));
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError> {
+        self.reachable()?;
+        let removed = {
+            let mut containers = self.containers.lock().unwrap();
+            let key = containers.values()
+                .find(|c| c.id == id || c.spec.name == id)
+                .map(|c| c.id.clone())
+                .ok_or_else(|| RuntimeError::NotFound(id.to_string()))?;
+            containers.remove(&key).expect("container exists")
+        };
+        self.emit(removed.spec.labels, ContainerEvent::Removed(removed.spec.name));
+        Ok(())
+    }
+
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError> {
+        self.reachable()?;
+        let containers = self.containers.lock().unwrap();
+        Ok(containers.values()
+            .filter(|c| c.spec.labels.get(label.0).is_some_and(|v| v == label.1))
+            .filter(|c| all || c.running)
+            .map(|c| ContainerInfo {
+                id: c.id.clone(),
+                name: c.spec.name.clone(),
+                running: c.running,
+                labels: c.spec.labels.clone(),
+            })
+            .collect())
+    }
+
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError> {
+        self.reachable()?;
+        self.with_container(id, |container| container.stats.clone())
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError> {
+        self.reachable()?;
+        self.with_container(id, |container| ContainerDetails {
+            id: container.id.clone(),
+            name: container.spec.name.clone(),
+            running: container.running,
+            address: Some(format!("127.0.0.1:{}", container.spec.host_port)),
+            published_port: Some(container.spec.host_port),
+            labels: container.spec.labels.clone(),
+            zone: None,
+        })
+    }
+
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+        labelled_events(&self.events, label)
+    }
+}
@@ -0,0 +1,77 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use crate::metrics::metrics;
+use super::{ContainerDetails, ContainerEvent, ContainerInfo, ContainerRuntime, ContainerSpec, ContainerStats, RuntimeError};
+
+// Wraps the runtime of the agent to time its calls and count removed containers. Created
+// and failed workers are counted by create_container, which retries failed creates.
+pub struct MeasuredRuntime {
+    inner: Arc<dyn ContainerRuntime>,
+}
+
+impl MeasuredRuntime {
+    pub fn new(inner: Arc<dyn ContainerRuntime>) -> Self {
+        MeasuredRuntime { inner }
+    }
+}
+
+async fn timed<T>(operation: &str, call: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = call.await;
+    metrics().record_runtime_call(operation, start.elapsed());
+    result
+}
+
+#[async_trait]
+impl ContainerRuntime for MeasuredRuntime {
+    async fn health(&self) -> Result<String, RuntimeError> {
+        timed("health", self.inner.health()).await
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError> {
+        timed("create", self.inner.create(spec)).await
+    }
+
+    async fn start(&self, id: &str) -> Result<(), RuntimeError> {
+        timed("start", self.inner.start(id)).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError> {
+        timed("stop", self.inner.stop(id)).await
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError> {
+        let result = timed("remove", self.inner.remove(id)).await;
+        if result.is_ok() {
+            metrics().record_removed();
+        }
+        result
+    }
+
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError> {
+        timed("list", self.inner.list(label, all)).await
+    }
+
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError> {
+        timed("stats", self.inner.stats(id)).await
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError> {
+        timed("inspect", self.inner.inspect(id)).await
+    }
+
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+        self.inner.events(label)
+    }
+
+    fn queue_address(&self, name: &str, host_port: u16) -> String {
+        self.inner.queue_address(name, host_port)
+    }
+
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+}
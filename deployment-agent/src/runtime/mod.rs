@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use dotenv::dotenv;
+use futures::stream::{self, BoxStream, StreamExt};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::sleep;
+
+pub mod cluster;
+pub mod docker;
+pub mod fake;
+pub mod measured;
+pub mod placement;
+pub mod process;
+
+pub use cluster::ClusterRuntime;
+pub use docker::{DockerEndpoint, DockerRuntime};
+pub use fake::FakeRuntime;
+pub use measured::MeasuredRuntime;
+pub use process::ProcessRuntime;
+
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    #[error("docker error: {0}")]
+    Docker(#[from] bollard::errors::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("invalid runtime configuration: {0}")]
+    Config(String),
+    #[error("runtime unavailable: {0}")]
+    Unavailable(String),
+    #[error("container {0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+// Everything needed to create a worker container
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerSpec {
+    pub name: String,
+    pub image: String,
+    pub target_port: u16,
+    pub host_port: u16,
+    pub labels: HashMap<String, String>,
+    pub network: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub name: String,
+    pub running: bool,
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerDetails {
+    pub id: String,
+    pub name: String,
+    pub running: bool,
+    // Address (ip:port) under which the worker accepts connections
+    pub address: Option<String>,
+    // Port published on the host of the runtime
+    pub published_port: Option<u16>,
+    pub labels: HashMap<String, String>,
+    // Zone of the host running the worker, if the runtime knows it
+    pub zone: Option<String>,
+}
+
+// Cumulative resource counters of a container at one point in time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContainerStats {
+    pub cpu_total_usage: u64,
+    pub system_cpu_usage: u64,
+    pub online_cpus: u64,
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+    pub network_rx_bytes: u64,
+    pub network_tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerEvent {
+    Started(String),
+    Stopped(String),
+    Died(String),
+    Removed(String),
+}
+
+// Events with the labels of their container, for runtimes that emit their own events
+pub type LabelledEvents = broadcast::Sender<(HashMap<String, String>, ContainerEvent)>;
+
+// Events of containers with the label. A lagging subscriber skips the events it missed.
+pub fn labelled_events(events: &LabelledEvents, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+    let (key, value) = (label.0.to_string(), label.1.to_string());
+    stream::unfold((events.subscribe(), key, value), |(mut receiver, key, value)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok((labels, event)) if labels.get(&key) == Some(&value) => return Some((Ok(event), (receiver, key, value))),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+// Operations the agent needs from a container engine. Containers can be addressed by id or name.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    // Checks that the runtime can be used and describes it
+    async fn health(&self) -> Result<String, RuntimeError>;
+    // Creates the container (pulling its image if needed) and returns its id
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError>;
+    async fn start(&self, id: &str) -> Result<(), RuntimeError>;
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError>;
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError>;
+    // Lists containers with the label, stopped ones only if `all` is set
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError>;
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError>;
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError>;
+    // Lifecycle events of containers with the label
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>>;
+
+    // Address the balancers use for the worker, by default its name on the shared network
+    fn queue_address(&self, name: &str, _host_port: u16) -> String {
+        name.to_string()
+    }
+
+    // Called before the agent exits. Containers of an engine outlive the agent, so by default nothing happens.
+    async fn shutdown(&self) {}
+}
+
+static SHARED_RUNTIME: OnceCell<Arc<dyn ContainerRuntime>> = OnceCell::new();
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RuntimeHealth {
+    pub healthy: bool,
+    pub description: String,
+    pub error: Option<String>,
+}
+
+static RUNTIME_HEALTHY: AtomicBool = AtomicBool::new(false);
+static RUNTIME_HEALTH: Lazy<Mutex<RuntimeHealth>> = Lazy::new(|| Mutex::new(RuntimeHealth::default()));
+
+// Creates the runtime selected by CONTAINER_RUNTIME and waits until it is reachable.
+// Has to succeed once at startup, before `shared_runtime` is used.
+pub async fn init_shared_runtime() -> Result<Arc<dyn ContainerRuntime>, RuntimeError> {
+    dotenv().ok();
+    let runtime: Arc<dyn ContainerRuntime> = Arc::new(MeasuredRuntime::new(runtime_from_env()?));
+
+    let max_retries = env::var("RUNTIME_CONNECT_RETRIES")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse::<u32>()
+        .map_err(|_| RuntimeError::Config("RUNTIME_CONNECT_RETRIES must be a valid u32".to_string()))?;
+    let mut delay = Duration::from_secs(2);
+    let mut attempt = 1;
+    loop {
+        match check_health(runtime.as_ref()).await {
+            Ok(description) => {
+                println!("Connected to container runtime: {}", description);
+                break;
+            }
+            Err(e) if attempt < max_retries => {
+                println!("Container runtime not ready (attempt {}): {}. Retrying in {:?}...", attempt, e, delay);
+                sleep(delay).await;
+                delay *= 2; // Exponential backoff
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    SHARED_RUNTIME.set(runtime.clone())
+        .map_err(|_| RuntimeError::Config("container runtime is already initialized".to_string()))?;
+    Ok(runtime)
+}
+
+// Runtime shared by all tasks of the agent
+pub fn shared_runtime() -> Arc<dyn ContainerRuntime> {
+    SHARED_RUNTIME.get()
+        .expect("init_shared_runtime must be called at startup")
+        .clone()
+}
+
+fn runtime_from_env() -> Result<Arc<dyn ContainerRuntime>, RuntimeError> {
+    match env::var("CONTAINER_RUNTIME").unwrap_or_else(|_| "docker".to_string()).as_str() {
+        "docker" => Ok(Arc::new(DockerRuntime::connect(DockerEndpoint::from_env(false)?)?)),
+        "podman" => Ok(Arc::new(DockerRuntime::connect(DockerEndpoint::from_env(true)?)?)),
+        "fake" => Ok(Arc::new(FakeRuntime::new())),
+        "process" => Ok(Arc::new(ProcessRuntime::from_env())),
+        "cluster" => Ok(Arc::new(ClusterRuntime::from_env()?)),
+        other => Err(RuntimeError::Config(format!("CONTAINER_RUNTIME must be docker, podman, fake, process or cluster, got {}", other))),
+    }
+}
+
+// Runs the health check and remembers the result for `runtime_health`
+async fn check_health(runtime: &dyn ContainerRuntime) -> Result<String, RuntimeError> {
+    let result = runtime.health().await;
+    let mut health = RUNTIME_HEALTH.lock().await;
+    match &result {
+        Ok(description) => {
+            health.description = description.clone();
+            health.error = None;
+        }
+        Err(e) => health.error = Some(e.to_string()),
+    }
+    health.healthy = result.is_ok();
+    RUNTIME_HEALTHY.store(health.healthy, Ordering::Relaxed);
+    result
+}
+
+// Checks the runtime every RUNTIME_HEALTH_INTERVAL seconds and logs when it becomes unreachable or recovers
+pub async fn monitor_runtime_health(runtime: Arc<dyn ContainerRuntime>) {
+    let interval = env::var("RUNTIME_HEALTH_INTERVAL")
+        .unwrap_or_else(|_| "30".to_string())
+        .parse::<u64>()
+        .expect("RUNTIME_HEALTH_INTERVAL must be a valid u64");
+
+    loop {
+        sleep(Duration::from_secs(interval)).await;
+        let was_healthy = RUNTIME_HEALTHY.load(Ordering::Relaxed);
+        match check_health(runtime.as_ref()).await {
+            Ok(description) if !was_healthy => println!("Container runtime recovered: {}", description),
+            Err(e) if was_healthy => eprintln!("Container runtime became unavailable: {}", e),
+            _ => {}
+        }
+    }
+}
+
+pub async fn runtime_health() -> RuntimeHealth {
+    RUNTIME_HEALTH.lock().await.clone()
+}
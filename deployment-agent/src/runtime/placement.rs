@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::env;
+use dotenv::dotenv;
+use super::RuntimeError;
+
+// What the placement knows about a node when a container is created
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeLoad {
+    pub name: String,
+    pub labels: HashMap<String, String>,
+    pub containers: usize,
+    pub capacity: usize,
+}
+
+impl NodeLoad {
+    fn has_room(&self) -> bool {
+        self.containers < self.capacity
+    }
+
+    fn utilization(&self) -> f64 {
+        self.containers as f64 / self.capacity.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlacementStrategy {
+    // Node with the lowest utilization, so containers are spread evenly
+    Spread,
+    // Fullest node that still has room, so few nodes are used
+    Binpack,
+    // Only nodes with all of the labels, spread among them
+    LabelAffinity(HashMap<String, String>),
+}
+
+impl PlacementStrategy {
+    // Reads PLACEMENT_STRATEGY (spread, binpack or affinity) and PLACEMENT_AFFINITY (key=value,...)
+    pub fn from_env() -> Result<Self, RuntimeError> {
+        dotenv().ok();
+        match env::var("PLACEMENT_STRATEGY").unwrap_or_else(|_| "spread".to_string()).as_str() {
+            "spread" => Ok(PlacementStrategy::Spread),
+            "binpack" => Ok(PlacementStrategy::Binpack),
+            "affinity" => {
+                let labels = env::var("PLACEMENT_AFFINITY").unwrap_or_default()
+                    .split(',')
+                    .filter(|pair| !pair.trim().is_empty())
+                    .map(|pair| pair.split_once('=')
+                        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                        .ok_or_else(|| RuntimeError::Config(format!("PLACEMENT_AFFINITY entries must be key=value, got {}", pair))))
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+                if labels.is_empty() {
+                    return Err(RuntimeError::Config("PLACEMENT_AFFINITY must be set for the affinity strategy".to_string()));
+                }
+                Ok(PlacementStrategy::LabelAffinity(labels))
+            }
+            other => Err(RuntimeError::Config(format!("PLACEMENT_STRATEGY must be spread, binpack or affinity, got {}", other))),
+        }
+    }
+
+    // Index of the node for the next container, None if no eligible node has room
+    pub fn choose(&self, nodes: &[NodeLoad]) -> Option<usize> {
+        let candidates = nodes.iter().enumerate().filter(|(_, node)| node.has_room());
+
+        // Ties go to the first node, so placement is deterministic
+        match self {
+            PlacementStrategy::Spread => candidates
+                .min_by(|(_, a), (_, b)| a.utilization().total_cmp(&b.utilization()))
+                .map(|(index, _)| index),
+            PlacementStrategy::Binpack => candidates
+                .fold(None, |best: Option<(usize, &NodeLoad)>, (index, node)| match best {
+                    Some((_, b)) if b.utilization() >= node.utilization() => best,
+                    _ => Some((index, node)),
+                })
+                .map(|(index, _)| index),
+            PlacementStrategy::LabelAffinity(labels) => candidates
+                .filter(|(_, node)| labels.iter().all(|(key, value)| node.labels.get(key) == Some(value)))
+                .min_by(|(_, a), (_, b)| a.utilization().total_cmp(&b.utilization()))
+                .map(|(index, _)| index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Case {
+        name: &'static str,
+        strategy: PlacementStrategy,
+        // Zone label, containers and capacity of every node
+        nodes: Vec<(&'static str, usize, usize)>,
+        expected: Option<usize>,
+    }
+
+    fn loads(nodes: &[(&str, usize, usize)]) -> Vec<NodeLoad> {
+        nodes.iter().enumerate()
+            .map(|(index, (zone, containers, capacity))| NodeLoad {
+                name: format!("node-{}", index),
+                labels: HashMap::from([("zone".to_string(), zone.to_string())]),
+                containers: *containers,
+                capacity: *capacity,
+            })
+            .collect()
+    }
+
+    fn affinity(zone: &str) -> PlacementStrategy {
+        PlacementStrategy::LabelAffinity(HashMap::from([("zone".to_string(), zone.to_string())]))
+    }
+
+    #[test]
+    fn choose_cases() {
+        let cases = [
+            Case { name: "spread picks the lowest utilization", strategy: PlacementStrategy::Spread,
+                   nodes: vec![("a", 2, 4), ("a", 1, 4), ("a", 3, 4)], expected: Some(1) },
+            Case { name: "spread compares utilization, not counts", strategy: PlacementStrategy::Spread,
+                   nodes: vec![("a", 1, 2), ("a", 2, 10)], expected: Some(1) },
+            Case { name: "spread ties go to the first node", strategy: PlacementStrategy::Spread,
+                   nodes: vec![("a", 1, 2), ("a", 2, 4)], expected: Some(0) },
+            Case { name: "binpack picks the fullest node with room", strategy: PlacementStrategy::Binpack,
+                   nodes: vec![("a", 1, 4), ("a", 3, 4), ("a", 4, 4)], expected: Some(1) },
+            Case { name: "binpack ties go to the first node", strategy: PlacementStrategy::Binpack,
+                   nodes: vec![("a", 2, 4), ("a", 1, 2)], expected: Some(0) },
+            Case { name: "binpack starts on empty nodes", strategy: PlacementStrategy::Binpack,
+                   nodes: vec![("a", 0, 4), ("a", 0, 4)], expected: Some(0) },
+            Case { name: "affinity spreads among matching nodes", strategy: affinity("eu-1"),
+                   nodes: vec![("eu-2", 0, 4), ("eu-1", 2, 4), ("eu-1", 1, 4)], expected: Some(2) },
+            Case { name: "affinity without a matching node", strategy: affinity("us-1"),
+                   nodes: vec![("eu-2", 0, 4), ("eu-1", 0, 4)], expected: None },
+            Case { name: "affinity never falls back to other nodes", strategy: affinity("eu-1"),
+                   nodes: vec![("eu-1", 4, 4), ("eu-2", 0, 4)], expected: None },
+            Case { name: "full nodes are skipped", strategy: PlacementStrategy::Spread,
+                   nodes: vec![("a", 2, 2), ("a", 3, 4)], expected: Some(1) },
+            Case { name: "nodes without capacity are skipped", strategy: PlacementStrategy::Binpack,
+                   nodes: vec![("a", 0, 0), ("a", 3, 4)], expected: Some(1) },
+            Case { name: "no node has room", strategy: PlacementStrategy::Spread,
+                   nodes: vec![("a", 2, 2), ("a", 4, 4)], expected: None },
+            Case { name: "no nodes", strategy: PlacementStrategy::Binpack,
+                   nodes: vec![], expected: None },
+        ];
+
+        for case in cases {
+            assert_eq!(case.strategy.choose(&loads(&case.nodes)), case.expected, "{}", case.name);
+        }
+    }
+}
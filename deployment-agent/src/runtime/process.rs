@@ -0,0 +1,512 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use dotenv::dotenv;
+use futures::future;
+use futures::stream::BoxStream;
+use tokio::process::Command;
+use tokio::sync::{broadcast, watch};
+use tokio::time::timeout;
+use super::{labelled_events, ContainerDetails, ContainerEvent, ContainerInfo, ContainerRuntime, ContainerSpec, ContainerStats, LabelledEvents, RuntimeError};
+
+// How a worker process is launched
+#[derive(Debug, Clone)]
+pub struct ProcessConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    // Time between SIGTERM and SIGKILL when stopping a worker
+    pub stop_timeout: Duration,
+}
+
+impl ProcessConfig {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let command_line = env::var("WORKER_COMMAND").expect("WORKER_COMMAND must be set for the process runtime");
+        let mut parts = command_line.split_whitespace().map(str::to_string);
+        let command = parts.next().expect("WORKER_COMMAND must not be empty");
+
+        let env = env::var("WORKER_ENV").unwrap_or_default()
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').expect("WORKER_ENV entries must be KEY=VALUE");
+                (key.trim().to_string(), value.trim().to_string())
+            })
+            .collect();
+
+        let stop_timeout = env::var("WORKER_STOP_TIMEOUT")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<u64>()
+            .expect("WORKER_STOP_TIMEOUT must be a valid u64");
+
+        ProcessConfig {
+            command,
+            args: parts.collect(),
+            env,
+            stop_timeout: Duration::from_secs(stop_timeout),
+        }
+    }
+}
+
+struct WorkerProcess {
+    id: String,
+    spec: ContainerSpec,
+    pid: Option<u32>,
+    running: bool,
+    // Flips to true once the process has exited
+    exited: Option<watch::Receiver<bool>>,
+}
+
+// Runs workers as child processes of the agent, each listening on 127.0.0.1 and its host port
+pub struct ProcessRuntime {
+    config: ProcessConfig,
+    processes: Arc<Mutex<BTreeMap<String, WorkerProcess>>>,
+    next_id: Mutex<usize>,
+    events: LabelledEvents,
+}
+
+impl ProcessRuntime {
+    pub fn new(config: ProcessConfig) -> Self {
+        ProcessRuntime {
+            config,
+            processes: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Mutex::new(0),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(ProcessConfig::from_env())
+    }
+
+    fn with_process<T>(&self, id_or_name: &str, f: impl FnOnce(&mut WorkerProcess) -> T) -> Result<T, RuntimeError> {
+        let mut processes = self.processes.lock().unwrap();
+        processes.values_mut()
+            .find(|p| p.id == id_or_name || p.spec.name == id_or_name)
+            .map(f)
+            .ok_or_else(|| RuntimeError::NotFound(id_or_name.to_string()))
+    }
+
+    fn emit(events: &LabelledEvents, labels: HashMap<String, String>, event: ContainerEvent) {
+        // Sending only fails without subscribers
+        let _ = events.send((labels, event));
+    }
+}
+
+// Signals the process group of the worker, so processes it started are stopped with it
+fn signal(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill has no memory effects, at worst the group no longer exists
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
+
+// `{port}` in the arguments is replaced with the port of the worker
+fn expand_port(arg: &str, port: u16) -> String {
+    arg.replace("{port}", &port.to_string())
+}
+
+#[async_trait]
+impl ContainerRuntime for ProcessRuntime {
+    async fn health(&self) -> Result<String, RuntimeError> {
+        Ok(format!("local processes running {}", self.config.command))
+    }
+
+    async fn create(&self, spec: &ContainerSpec) -> Result<String, RuntimeError> {
+        let mut processes = self.processes.lock().unwrap();
+        if processes.values().any(|p| p.spec.name == spec.name) {
+            return Err(RuntimeError::Other(format!("worker name {} is already in use", spec.name)));
+        }
+        if processes.values().any(|p| p.spec.host_port == spec.host_port) {
+            return Err(RuntimeError::Other(format!("port {} is already allocated", spec.host_port)));
+        }
+        // Fails if another program listens on the port. The port can still be taken before the worker
+        // binds it; the worker then exits, and its Died event makes the agent replace it on another port.
+        TcpListener::bind(("127.0.0.1", spec.host_port))?;
+
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let id = format!("proc-{:012}", *next_id);
+        processes.insert(id.clone(), WorkerProcess {
+            id: id.clone(),
+            spec: spec.clone(),
+            pid: None,
+            running: false,
+            exited: None,
+        });
+        Ok(id)
+    }
+
+    async fn start(&self, id: &str) -> Result<(), RuntimeError> {
+        let (exited_tx, exited_rx) = watch::channel(false);
+        // Checked and spawned under the lock, so concurrent starts launch a single process
+        let started = self.with_process(id, |p| -> Result<_, RuntimeError> {
+            if p.running {
+                return Ok(None);
+            }
+            let child = Command::new(&self.config.command)
+                .args(self.config.args.iter().map(|arg| expand_port(arg, p.spec.host_port)))
+                .envs(self.config.env.iter().cloned())
+                .env("PORT", p.spec.host_port.to_string())
+                .env("HOST", "127.0.0.1")
+                .env("WORKER_NAME", &p.spec.name)
+                .stdin(Stdio::null())
+                // Its own process group, so that stopping the worker also reaches its children
+                .process_group(0)
+                // Killed if the agent exits without stopping it
+                .kill_on_drop(true)
+                .spawn()?;
+            p.pid = child.id();
+            p.running = true;
+            p.exited = Some(exited_rx);
+            Ok(Some((p.id.clone(), p.spec.clone(), child)))
+        })??;
+        let Some((key, spec, mut child)) = started else {
+            return Ok(());
+        };
+        println!("Started worker process {} (pid {:?}) on port {}", spec.name, child.id(), spec.host_port);
+        Self::emit(&self.events, spec.labels.clone(), ContainerEvent::Started(spec.name.clone()));
+
+        // Reaps the process and reports its exit
+        let processes = self.processes.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let status = child.wait().await;
+            println!("Worker process {} exited: {:?}", spec.name, status);
+
+            // Workers stopped by the agent stay until they are removed. Workers that exited on their own
+            // are dropped, which frees their port, and the next reconciliation replaces them.
+            let stopped_by_agent = {
+                let mut processes = processes.lock().unwrap();
+                match processes.get_mut(&key) {
+                    Some(p) if !p.running => {
+                        p.pid = None;
+                        true
+                    }
+                    Some(_) => {
+                        processes.remove(&key);
+                        false
+                    }
+                    None => true,
+                }
+            };
+            let _ = exited_tx.send(true);
+
+            if stopped_by_agent {
+                Self::emit(&events, spec.labels, ContainerEvent::Stopped(spec.name));
+            } else {
+                Self::emit(&events, spec.labels.clone(), ContainerEvent::Died(spec.name.clone()));
+                Self::emit(&events, spec.labels, ContainerEvent::Removed(spec.name));
+            }
+        });
+
+        Ok(())
+    }
+
+    // SIGTERM first, SIGKILL if the worker has not exited within the stop timeout
+    async fn stop(&self, id: &str) -> Result<(), RuntimeError> {
+        let (pid, exited) = self.with_process(id, |p| {
+            p.running = false;
+            (p.pid, p.exited.clone())
+        })?;
+        let (Some(pid), Some(mut exited)) = (pid, exited) else {
+            return Ok(());
+        };
+
+        signal(pid, libc::SIGTERM);
+        let graceful = timeout(self.config.stop_timeout, exited.wait_for(|exited| *exited)).await.is_ok();
+        if !graceful {
+            println!("Worker process {} did not exit within {:?}, sending SIGKILL", pid, self.config.stop_timeout);
+            signal(pid, libc::SIGKILL);
+            let _ = exited.wait_for(|exited| *exited).await;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), RuntimeError> {
+        self.stop(id).await?;
+        let removed = {
+            let mut processes = self.processes.lock().unwrap();
+            let key = processes.values()
+                .find(|p| p.id == id || p.spec.name == id)
+                .map(|p| p.id.clone())
+                .ok_or_else(|| RuntimeError::NotFound(id.to_string()))?;
+            processes.remove(&key).expect("process exists")
+        };
+        Self::emit(&self.events, removed.spec.labels, ContainerEvent::Removed(removed.spec.name));
+        Ok(())
+    }
+
+    async fn list(&self, label: (&str, &str), all: bool) -> Result<Vec<ContainerInfo>, RuntimeError> {
+        let processes = self.processes.lock().unwrap();
+        Ok(processes.values()
+            .filter(|p| p.spec.labels.get(label.0).is_some_and(|v| v == label.1))
+            .filter(|p| all || p.running)
+            .map(|p| ContainerInfo {
+                id: p.id.clone(),
+                name: p.spec.name.clone(),
+                running: p.running,
+                labels: p.spec.labels.clone(),
+            })
+            .collect())
+    }
+
+    async fn stats(&self, id: &str) -> Result<ContainerStats, RuntimeError> {
+        let pid = self.with_process(id, |p| p.pid)?
+            .ok_or_else(|| RuntimeError::Other(format!("worker {} is not running", id)))?;
+        proc_stats(pid)
+    }
+
+    async fn inspect(&self, id: &str) -> Result<ContainerDetails, RuntimeError> {
+        self.with_process(id, |p| ContainerDetails {
+            id: p.id.clone(),
+            name: p.spec.name.clone(),
+            running: p.running,
+            address: Some(format!("127.0.0.1:{}", p.spec.host_port)),
+            published_port: Some(p.spec.host_port),
+            labels: p.spec.labels.clone(),
+            zone: None,
+        })
+    }
+
+    fn events(&self, label: (&str, &str)) -> BoxStream<'static, Result<ContainerEvent, RuntimeError>> {
+        labelled_events(&self.events, label)
+    }
+
+    // Workers listen on the loopback interface of the agent, not on a shared network
+    fn queue_address(&self, _name: &str, host_port: u16) -> String {
+        format!("127.0.0.1:{}", host_port)
+    }
+
+    // Workers are children of the agent and are stopped with it
+    async fn shutdown(&self) {
+        let running: Vec<String> = self.processes.lock().unwrap().values()
+            .filter(|p| p.running)
+            .map(|p| p.id.clone())
+            .collect();
+        println!("Stopping {} worker process(es)", running.len());
+        for result in future::join_all(running.iter().map(|id| self.stop(id))).await {
+            if let Err(e) = result {
+                eprintln!("Failed to stop worker process: {}", e);
+            }
+        }
+    }
+}
+
+// Reads CPU and memory of a process from /proc. CPU times are converted to nanoseconds
+// like the counters reported by Docker; per-process network counters do not exist.
+fn proc_stats(pid: u32) -> Result<ContainerStats, RuntimeError> {
+    let ticks_per_sec = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    };
+    let nanos_per_tick = 1_000_000_000 / ticks_per_sec;
+
+    // The command name may contain spaces, the fields after it are separated by single spaces
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    let fields: Vec<&str> = stat.rsplit_once(')')
+        .map(|(_, rest)| rest.split_whitespace().collect())
+        .unwrap_or_default();
+    // utime and stime are fields 14 and 15, i.e. 11 and 12 after the state
+    let process_ticks: u64 = fields.get(11..13)
+        .ok_or_else(|| RuntimeError::Other(format!("unexpected format of /proc/{}/stat", pid)))?
+        .iter()
+        .filter_map(|v| v.parse::<u64>().ok())
+        .sum();
+
+    let system_stat = fs::read_to_string("/proc/stat")?;
+    let system_ticks: u64 = system_stat.lines()
+        .find(|line| line.starts_with("cpu "))
+        .map(|line| line.split_whitespace().skip(1).filter_map(|v| v.parse::<u64>().ok()).sum())
+        .unwrap_or(0);
+    let online_cpus = system_stat.lines()
+        .filter(|line| line.starts_with("cpu") && !line.starts_with("cpu "))
+        .count() as u64;
+
+    let memory_usage = read_kb(&format!("/proc/{}/status", pid), "VmRSS:")? * 1024;
+    let memory_limit = read_kb("/proc/meminfo", "MemTotal:")? * 1024;
+
+    Ok(ContainerStats {
+        cpu_total_usage: process_ticks * nanos_per_tick,
+        system_cpu_usage: system_ticks * nanos_per_tick,
+        online_cpus: online_cpus.max(1),
+        memory_usage,
+        memory_limit: memory_limit.max(1),
+        network_rx_bytes: 0,
+        network_tx_bytes: 0,
+    })
+}
+
+fn read_kb(path: &str, field: &str) -> Result<u64, RuntimeError> {
+    let content = fs::read_to_string(path)?;
+    Ok(content.lines()
+        .find(|line| line.starts_with(field))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use super::*;
+
+    fn runtime(command_line: &str) -> Arc<ProcessRuntime> {
+        let mut parts = command_line.split_whitespace().map(str::to_string);
+        Arc::new(ProcessRuntime::new(ProcessConfig {
+            command: parts.next().unwrap(),
+            args: parts.collect(),
+            env: Vec::new(),
+            stop_timeout: Duration::from_secs(5),
+        }))
+    }
+
+    fn spec(name: &str) -> ContainerSpec {
+        // A port that is free right now
+        let host_port = TcpListener::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+        ContainerSpec {
+            name: name.to_string(),
+            image: String::new(),
+            target_port: host_port,
+            host_port,
+            labels: HashMap::from([("application".to_string(), "test-app".to_string())]),
+            network: None,
+        }
+    }
+
+    // Events of the test app until the worker was removed
+    async fn events_until_removed(events: BoxStream<'static, Result<ContainerEvent, RuntimeError>>) -> Vec<ContainerEvent> {
+        let mut events = events.map(Result::unwrap);
+        let mut received = Vec::new();
+        while let Ok(Some(event)) = timeout(Duration::from_secs(5), events.next()).await {
+            let removed = matches!(event, ContainerEvent::Removed(_));
+            received.push(event);
+            if removed {
+                break;
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn workers_exiting_on_their_own_are_dropped() {
+        let runtime = runtime("true");
+        let events = runtime.events(("application", "test-app"));
+        let spec = spec("worker-a");
+        let id = runtime.create(&spec).await.unwrap();
+        runtime.start(&id).await.unwrap();
+
+        let name = "worker-a".to_string();
+        assert_eq!(events_until_removed(events).await, vec![
+            ContainerEvent::Started(name.clone()),
+            ContainerEvent::Died(name.clone()),
+            ContainerEvent::Removed(name),
+        ]);
+        assert_eq!(runtime.list(("application", "test-app"), true).await.unwrap(), vec![]);
+        // The port is free for a replacement
+        runtime.create(&ContainerSpec { name: "worker-b".to_string(), ..spec }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn workers_stopped_by_the_agent_stay_until_removed() {
+        let runtime = runtime("sleep 30");
+        let events = runtime.events(("application", "test-app"));
+        let id = runtime.create(&spec("worker-a")).await.unwrap();
+        runtime.start(&id).await.unwrap();
+
+        runtime.stop(&id).await.unwrap();
+        let listed = runtime.list(("application", "test-app"), true).await.unwrap();
+        assert_eq!(listed.iter().map(|c| c.running).collect::<Vec<_>>(), vec![false]);
+
+        runtime.remove(&id).await.unwrap();
+        let name = "worker-a".to_string();
+        assert_eq!(events_until_removed(events).await, vec![
+            ContainerEvent::Started(name.clone()),
+            ContainerEvent::Stopped(name.clone()),
+            ContainerEvent::Removed(name),
+        ]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_starts_launch_one_process() {
+        let runtime = runtime("sleep 30");
+        let events = runtime.events(("application", "test-app"));
+        let id = runtime.create(&spec("worker-a")).await.unwrap();
+
+        let starts: Vec<_> = (0..8).map(|_| {
+            let (runtime, id) = (runtime.clone(), id.clone());
+            tokio::spawn(async move { runtime.start(&id).await })
+        }).collect();
+        for start in starts {
+            start.await.unwrap().unwrap();
+        }
+        runtime.remove(&id).await.unwrap();
+
+        let started = events_until_removed(events).await.into_iter()
+            .filter(|event| matches!(event, ContainerEvent::Started(_)))
+            .count();
+        assert_eq!(started, 1);
+    }
+
+    // Exited processes may stay zombies until their new parent reaps them
+    fn is_alive(pid: u32) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| stat.rsplit_once(')').is_some_and(|(_, rest)| !rest.trim_start().starts_with('Z')))
+    }
+
+    #[tokio::test]
+    async fn stopping_a_worker_stops_its_children() {
+        let pid_file = env::temp_dir().join(format!("agent-{}-child.pid", std::process::id()));
+        let _ = fs::remove_file(&pid_file);
+        let runtime = Arc::new(ProcessRuntime::new(ProcessConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), format!("sleep 30 & echo $! > {}; wait", pid_file.display())],
+            env: Vec::new(),
+            stop_timeout: Duration::from_secs(5),
+        }));
+        let id = runtime.create(&spec("worker-a")).await.unwrap();
+        runtime.start(&id).await.unwrap();
+
+        let mut child = None;
+        for _ in 0..100 {
+            child = fs::read_to_string(&pid_file).ok().and_then(|pid| pid.trim().parse::<u32>().ok());
+            if child.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let child = child.expect("the worker did not start its child");
+        assert!(is_alive(child));
+
+        runtime.stop(&id).await.unwrap();
+        fs::remove_file(&pid_file).unwrap();
+        for _ in 0..100 {
+            if !is_alive(child) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("child {} of the worker is still running", child);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_all_workers() {
+        let runtime = runtime("sleep 30");
+        let mut pids = Vec::new();
+        for name in ["worker-a", "worker-b"] {
+            let id = runtime.create(&spec(name)).await.unwrap();
+            runtime.start(&id).await.unwrap();
+            pids.push(runtime.with_process(&id, |p| p.pid).unwrap().unwrap());
+        }
+
+        runtime.shutdown().await;
+        assert!(runtime.list(("application", "test-app"), false).await.unwrap().is_empty());
+        assert!(pids.into_iter().all(|pid| !is_alive(pid)));
+    }
+}
@@ -0,0 +1,934 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+use dotenv::dotenv;
+use serde::Serialize;
+use serde_json::json;
+use crate::forecast::{seasonal_forecast, ForecastConfig, LoadSample};
+use crate::queue::UtilizationCategory;
+use crate::telemetry::BackendTelemetry;
+
+// Relative deviation from the target value that does not trigger scaling
+const TARGET_TRACKING_TOLERANCE: f64 = 0.1;
+
+// Metrics of a single active (non-SUNDOWN) container
+#[derive(Serialize, Debug, Clone)]
+pub struct ContainerMetrics {
+    pub dns_name: String,
+    pub score: f64,
+    pub utilization_category: UtilizationCategory,
+    pub telemetry: Option<BackendTelemetry>,
+}
+
+// Everything a policy can base its decision on
+#[derive(Serialize, Debug, Clone)]
+pub struct MetricsSnapshot {
+    #[serde(skip)]
+    pub now: Instant,
+    // Unix time of the snapshot
+    pub timestamp: i64,
+    pub containers: Vec<ContainerMetrics>,
+    pub current_default: usize,
+    pub min_containers: usize,
+    pub max_containers: usize,
+    // Recorded load of previous seasons, used for forecasts
+    #[serde(skip)]
+    pub load_history: Vec<LoadSample>,
+}
+
+impl MetricsSnapshot {
+    pub fn active_count(&self) -> usize {
+        self.containers.len()
+    }
+
+    pub fn average_score(&self) -> f64 {
+        if self.containers.is_empty() {
+            return 0.0;
+        }
+        self.containers.iter().map(|c| c.score).sum::<f64>() / self.containers.len() as f64
+    }
+
+    pub fn total_rps(&self) -> f64 {
+        self.containers.iter().filter_map(|c| c.telemetry.as_ref()).map(|t| t.rps).sum()
+    }
+
+    // Least loaded containers first, preferring containers with fewer in-flight requests
+    pub fn scale_in_candidates(&self, count: usize) -> Vec<String> {
+        let mut candidates: Vec<&ContainerMetrics> = self.containers.iter()
+            .filter(|c| c.utilization_category != UtilizationCategory::Init)
+            .collect();
+        candidates.sort_by(|a, b| {
+            let in_flight = |c: &ContainerMetrics| c.telemetry.as_ref().map_or(0, |t| t.in_flight);
+            b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| in_flight(a).cmp(&in_flight(b)))
+        });
+        candidates.into_iter().take(count).map(|c| c.dns_name.clone()).collect()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum ScalingDecision {
+    ScaleOut { count: usize, reason: String },
+    ScaleIn { containers: Vec<String>, reason: String },
+    Hold { reason: String },
+}
+
+impl ScalingDecision {
+    // Number of active containers after applying the decision
+    pub fn target_count(&self, current: usize) -> usize {
+        match self {
+            ScalingDecision::ScaleOut { count, .. } => current + count,
+            ScalingDecision::ScaleIn { containers, .. } => current.saturating_sub(containers.len()),
+            ScalingDecision::Hold { .. } => current,
+        }
+    }
+
+    pub fn action(&self) -> &'static str {
+        match self {
+            ScalingDecision::ScaleOut { .. } => "scale_out",
+            ScalingDecision::ScaleIn { .. } => "scale_in",
+            ScalingDecision::Hold { .. } => "hold",
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            ScalingDecision::ScaleOut { reason, .. }
+            | ScalingDecision::ScaleIn { reason, .. }
+            | ScalingDecision::Hold { reason } => reason,
+        }
+    }
+
+    fn with_reason(self, reason: String) -> Self {
+        match self {
+            ScalingDecision::ScaleOut { count, .. } => ScalingDecision::ScaleOut { count, reason },
+            ScalingDecision::ScaleIn { containers, .. } => ScalingDecision::ScaleIn { containers, reason },
+            ScalingDecision::Hold { .. } => ScalingDecision::Hold { reason },
+        }
+    }
+}
+
+impl fmt::Display for ScalingDecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScalingDecision::ScaleOut { count, reason } => write!(f, "scale out by {} ({})", count, reason),
+            ScalingDecision::ScaleIn { containers, reason } => write!(f, "scale in {:?} ({})", containers, reason),
+            ScalingDecision::Hold { reason } => write!(f, "hold ({})", reason),
+        }
+    }
+}
+
+// Scale check period and scale-out cooldown, evaluated against an explicit point in time
+// so the simulator can drive them with a virtual clock
+#[derive(Debug, Clone)]
+pub struct ScalingTimers {
+    last_check: Instant,
+    last_scale_out: Instant,
+}
+
+impl ScalingTimers {
+    pub fn new(now: Instant) -> Self {
+        ScalingTimers { last_check: now, last_scale_out: now }
+    }
+
+    // Returns whether a scale check is due and starts a new check period if so
+    pub fn check_due(&mut self, now: Instant, check_period: Duration) -> bool {
+        if now.saturating_duration_since(self.last_check) >= check_period {
+            self.last_check = now;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn next_check_in(&self, now: Instant, check_period: Duration) -> Duration {
+        check_period.saturating_sub(now.saturating_duration_since(self.last_check))
+    }
+
+    pub fn can_scale_out(&self, now: Instant, cooldown_period: Duration) -> bool {
+        now.saturating_duration_since(self.last_scale_out) >= cooldown_period
+    }
+
+    pub fn record_scale_out(&mut self, now: Instant) {
+        self.last_scale_out = now;
+    }
+
+    pub fn cooldown_status(&self, now: Instant, cooldown_period: Duration) -> String {
+        let elapsed = now.saturating_duration_since(self.last_scale_out);
+        if elapsed >= cooldown_period {
+            "inactive".to_string()
+        } else {
+            format!("active, next scaling possible in {:?}", cooldown_period - elapsed)
+        }
+    }
+}
+
+pub trait ScalingPolicy: Send {
+    fn name(&self) -> String;
+    fn evaluate(&mut self, snapshot: &MetricsSnapshot) -> ScalingDecision;
+
+    // Thresholds and targets of the policy, recorded with every evaluation
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+// Scales on the average container score compared to fixed thresholds
+pub struct ThresholdPolicy {
+    pub high_load_threshold: f64,
+    pub low_load_threshold: f64,
+    pub critical_load_threshold: f64,
+    pub critical_error_rate: f64,
+    pub scale_step: usize,
+}
+
+impl ThresholdPolicy {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        ThresholdPolicy {
+            high_load_threshold: env::var("HIGH_LOAD_THRESHOLD")
+                .expect("HIGH_LOAD_THRESHOLD must be set")
+                .parse::<f64>()
+                .expect("HIGH_LOAD_THRESHOLD must be a valid f64"),
+            low_load_threshold: env::var("LOW_LOAD_THRESHOLD")
+                .expect("LOW_LOAD_THRESHOLD must be set")
+                .parse::<f64>()
+                .expect("LOW_LOAD_THRESHOLD must be a valid f64"),
+            critical_load_threshold: env::var("CRITICAL_LOAD_THRESHOLD")
+                .expect("CRITICAL_LOAD_THRESHOLD must be set")
+                .parse::<f64>()
+                .expect("CRITICAL_LOAD_THRESHOLD must be a valid f64"),
+            critical_error_rate: env::var("CRITICAL_ERROR_RATE")
+                .unwrap_or_else(|_| "0.5".to_string())
+                .parse::<f64>()
+                .expect("CRITICAL_ERROR_RATE must be a valid f64"),
+            scale_step: env::var("SCALE_STEP")
+                .expect("SCALE_STEP must be set")
+                .parse::<usize>()
+                .expect("SCALE_STEP must be a valid usize"),
+        }
+    }
+}
+
+impl ScalingPolicy for ThresholdPolicy {
+    fn name(&self) -> String {
+        "threshold".to_string()
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "high_load_threshold": self.high_load_threshold,
+            "low_load_threshold": self.low_load_threshold,
+            "critical_load_threshold": self.critical_load_threshold,
+            "critical_error_rate": self.critical_error_rate,
+            "scale_step": self.scale_step,
+        })
+    }
+
+    fn evaluate(&mut self, snapshot: &MetricsSnapshot) -> ScalingDecision {
+        let active_count = snapshot.active_count();
+        let average_load = snapshot.average_score();
+
+        let critical_container = snapshot.containers.iter().find(|c| {
+            c.score < self.critical_load_threshold
+                || c.telemetry.as_ref().is_some_and(|t| t.error_rate > self.critical_error_rate)
+        });
+
+        if active_count >= snapshot.max_containers {
+            ScalingDecision::Hold { reason: format!("max container limit ({}) reached", snapshot.max_containers) }
+        } else if average_load < self.high_load_threshold || critical_container.is_some() {
+            let reason = match critical_container {
+                Some(c) => format!("container {} is critically loaded (score {:.2})", c.dns_name, c.score),
+                None => format!("average score {:.2} below high load threshold {:.2}", average_load, self.high_load_threshold),
+            };
+            ScalingDecision::ScaleOut {
+                count: std::cmp::min(self.scale_step, snapshot.max_containers - active_count),
+                reason,
+            }
+        } else if average_load > self.low_load_threshold && active_count > snapshot.min_containers {
+            let count = std::cmp::min(
+                self.scale_step,
+                std::cmp::min(
+                    active_count - snapshot.min_containers,
+                    snapshot.current_default.saturating_sub(snapshot.min_containers),
+                ),
+            );
+            if count == 0 {
+                return ScalingDecision::Hold { reason: "minimum number of active containers reached".to_string() };
+            }
+            ScalingDecision::ScaleIn {
+                containers: snapshot.scale_in_candidates(count),
+                reason: format!("average score {:.2} above low load threshold {:.2}", average_load, self.low_load_threshold),
+            }
+        } else {
+            ScalingDecision::Hold { reason: format!("average score {:.2} within thresholds", average_load) }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetMetric {
+    P95Latency,
+    RpsPerContainer,
+}
+
+impl fmt::Display for TargetMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetMetric::P95Latency => write!(f, "p95_latency"),
+            TargetMetric::RpsPerContainer => write!(f, "rps_per_container"),
+        }
+    }
+}
+
+// Keeps a metric observed by the balancers at a target value
+pub struct TargetTrackingPolicy {
+    pub metric: TargetMetric,
+    pub target_value: f64,
+    pub scale_out_window: Duration,
+    pub scale_in_window: Duration,
+    recommendations: VecDeque<(Instant, usize)>,
+}
+
+impl TargetTrackingPolicy {
+    pub fn new(metric: TargetMetric, target_value: f64, scale_out_window: Duration, scale_in_window: Duration) -> Self {
+        TargetTrackingPolicy {
+            metric,
+            target_value,
+            scale_out_window,
+            scale_in_window,
+            recommendations: VecDeque::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let metric = match env::var("TARGET_METRIC").unwrap_or_else(|_| "p95_latency".to_string()).as_str() {
+            "p95_latency" => TargetMetric::P95Latency,
+            "rps_per_container" => TargetMetric::RpsPerContainer,
+            other => panic!("TARGET_METRIC must be p95_latency or rps_per_container, got {}", other),
+        };
+        let target_value = env::var("TARGET_VALUE")
+            .expect("TARGET_VALUE must be set for the target_tracking policy")
+            .parse::<f64>()
+            .expect("TARGET_VALUE must be a valid f64");
+        let scale_out_window = Duration::from_secs(
+            env::var("SCALE_OUT_STABILIZATION")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse::<u64>()
+                .expect("SCALE_OUT_STABILIZATION must be a valid u64")
+        );
+        let scale_in_window = Duration::from_secs(
+            env::var("SCALE_IN_STABILIZATION")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse::<u64>()
+                .expect("SCALE_IN_STABILIZATION must be a valid u64")
+        );
+
+        Self::new(metric, target_value, scale_out_window, scale_in_window)
+    }
+
+    // Current value of the metric as observed by the balancers
+    fn metric_value(&self, snapshot: &MetricsSnapshot) -> Option<f64> {
+        let telemetry: Vec<&BackendTelemetry> = snapshot.containers.iter()
+            .filter_map(|c| c.telemetry.as_ref())
+            .collect();
+        if telemetry.is_empty() {
+            return None;
+        }
+
+        let total_rps = snapshot.total_rps();
+        match self.metric {
+            TargetMetric::RpsPerContainer => Some(total_rps / snapshot.active_count() as f64),
+            // Request-weighted p95 latency across all containers
+            TargetMetric::P95Latency if total_rps > 0.0 => {
+                Some(telemetry.iter().map(|t| t.p95_latency_ms * t.rps).sum::<f64>() / total_rps)
+            }
+            TargetMetric::P95Latency => None,
+        }
+    }
+
+    // Applies the stabilization windows, returns the container counts to scale out and scale in to
+    fn stabilize(&mut self, now: Instant, desired: usize) -> (usize, usize) {
+        self.recommendations.push_back((now, desired));
+        let longest_window = std::cmp::max(self.scale_out_window, self.scale_in_window);
+        while self.recommendations.front().is_some_and(|(at, _)| now.duration_since(*at) > longest_window) {
+            self.recommendations.pop_front();
+        }
+
+        // Scale out only as far as all recommendations of the window agree, scale in only as far as the highest one
+        let scale_out_to = self.recommendations.iter()
+            .filter(|(at, _)| now.duration_since(*at) <= self.scale_out_window)
+            .map(|(_, count)| *count)
+            .min()
+            .unwrap_or(desired);
+        let scale_in_to = self.recommendations.iter()
+            .filter(|(at, _)| now.duration_since(*at) <= self.scale_in_window)
+            .map(|(_, count)| *count)
+            .max()
+            .unwrap_or(desired);
+
+        (scale_out_to, scale_in_to)
+    }
+}
+
+impl ScalingPolicy for TargetTrackingPolicy {
+    fn name(&self) -> String {
+        "target_tracking".to_string()
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "metric": self.metric.to_string(),
+            "target_value": self.target_value,
+            "scale_out_stabilization_secs": self.scale_out_window.as_secs(),
+            "scale_in_stabilization_secs": self.scale_in_window.as_secs(),
+        })
+    }
+
+    fn evaluate(&mut self, snapshot: &MetricsSnapshot) -> ScalingDecision {
+        let Some(metric_value) = self.metric_value(snapshot) else {
+            return ScalingDecision::Hold { reason: format!("no request telemetry for {} available", self.metric) };
+        };
+
+        let current = snapshot.active_count();
+        let ratio = metric_value / self.target_value;
+        let desired = if (ratio - 1.0).abs() <= TARGET_TRACKING_TOLERANCE {
+            current
+        } else {
+            (current as f64 * ratio).ceil() as usize
+        }.clamp(std::cmp::max(snapshot.min_containers, 1), snapshot.max_containers);
+
+        let (scale_out_to, scale_in_to) = self.stabilize(snapshot.now, desired);
+        let observed = format!("{} = {:.2}, target {:.2}", self.metric, metric_value, self.target_value);
+
+        if scale_out_to > current {
+            ScalingDecision::ScaleOut { count: scale_out_to - current, reason: observed }
+        } else if scale_in_to < current {
+            ScalingDecision::ScaleIn { containers: snapshot.scale_in_candidates(current - scale_in_to), reason: observed }
+        } else {
+            ScalingDecision::Hold {
+                reason: format!("{}, recommended {} container(s), stabilized range {}..{}", observed, desired, scale_in_to, scale_out_to),
+            }
+        }
+    }
+}
+
+// Provisions containers ahead of the load forecast from previous seasons
+pub struct PredictivePolicy {
+    pub config: ForecastConfig,
+    pub rps_per_container: f64,
+}
+
+impl PredictivePolicy {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        PredictivePolicy {
+            config: ForecastConfig::from_env(),
+            rps_per_container: predictive_rps_per_container(),
+        }
+    }
+}
+
+pub fn predictive_rps_per_container() -> f64 {
+    env::var("PREDICTIVE_RPS_PER_CONTAINER")
+        .unwrap_or_else(|_| "50".to_string())
+        .parse::<f64>()
+        .expect("PREDICTIVE_RPS_PER_CONTAINER must be a valid f64")
+}
+
+impl ScalingPolicy for PredictivePolicy {
+    fn name(&self) -> String {
+        "predictive".to_string()
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "horizon_secs": self.config.horizon_secs,
+            "season_secs": self.config.season_secs,
+            "seasons": self.config.seasons,
+            "bucket_secs": self.config.bucket_secs,
+            "rps_per_container": self.rps_per_container,
+        })
+    }
+
+    fn evaluate(&mut self, snapshot: &MetricsSnapshot) -> ScalingDecision {
+        let at = snapshot.timestamp + self.config.horizon_secs;
+        let Some(forecast_rps) = seasonal_forecast(&snapshot.load_history, at, &self.config) else {
+            return ScalingDecision::Hold { reason: "not enough load history for a forecast".to_string() };
+        };
+
+        let current = snapshot.active_count();
+        let desired = ((forecast_rps / self.rps_per_container).ceil() as usize)
+            .clamp(std::cmp::max(snapshot.min_containers, 1), snapshot.max_containers);
+        let reason = format!("forecast of {:.2} rps in {}s needs {} container(s)", forecast_rps, self.config.horizon_secs, desired);
+
+        if desired > current {
+            ScalingDecision::ScaleOut { count: desired - current, reason }
+        } else if desired < current {
+            ScalingDecision::ScaleIn { containers: snapshot.scale_in_candidates(current - desired), reason }
+        } else {
+            ScalingDecision::Hold { reason }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+    MaxOf,
+    MinOf,
+}
+
+// Evaluates several policies and follows the one asking for the most (max-of) or fewest (min-of) containers
+pub struct CompositePolicy {
+    pub combinator: Combinator,
+    pub policies: Vec<Box<dyn ScalingPolicy>>,
+}
+
+impl ScalingPolicy for CompositePolicy {
+    fn name(&self) -> String {
+        let names: Vec<String> = self.policies.iter().map(|p| p.name()).collect();
+        match self.combinator {
+            Combinator::MaxOf => format!("max({})", names.join(",")),
+            Combinator::MinOf => format!("min({})", names.join(",")),
+        }
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        self.policies.iter()
+            .map(|p| (p.name(), p.parameters()))
+            .collect::<serde_json::Map<String, serde_json::Value>>()
+            .into()
+    }
+
+    fn evaluate(&mut self, snapshot: &MetricsSnapshot) -> ScalingDecision {
+        let current = snapshot.active_count();
+        // Every policy is evaluated so stateful policies keep their history
+        let decisions: Vec<(String, ScalingDecision)> = self.policies.iter_mut()
+            .map(|p| (p.name(), p.evaluate(snapshot)))
+            .collect();
+
+        let chosen = match self.combinator {
+            Combinator::MaxOf => decisions.into_iter().reduce(|a, b| if b.1.target_count(current) > a.1.target_count(current) { b } else { a }),
+            Combinator::MinOf => decisions.into_iter().reduce(|a, b| if b.1.target_count(current) < a.1.target_count(current) { b } else { a }),
+        };
+
+        match chosen {
+            Some((name, decision)) => {
+                let reason = format!("{}: {}", name, decision.reason());
+                decision.with_reason(reason)
+            }
+            None => ScalingDecision::Hold { reason: "no policies configured".to_string() },
+        }
+    }
+}
+
+// Builds a policy from a spec like "threshold", "target_tracking" or "max(threshold,min(...))"
+pub fn parse_policy(spec: &str) -> Result<Box<dyn ScalingPolicy>, String> {
+    let spec = spec.trim();
+    let (combinator, inner) = if let Some(inner) = spec.strip_prefix("max(") {
+        (Combinator::MaxOf, inner)
+    } else if let Some(inner) = spec.strip_prefix("min(") {
+        (Combinator::MinOf, inner)
+    } else {
+        return match spec {
+            "threshold" => Ok(Box::new(ThresholdPolicy::from_env())),
+            "target_tracking" => Ok(Box::new(TargetTrackingPolicy::from_env())),
+            "predictive" => Ok(Box::new(PredictivePolicy::from_env())),
+            other => Err(format!("unknown scaling policy '{}'", other)),
+        };
+    };
+
+    let inner = inner.strip_suffix(')').ok_or_else(|| format!("missing ')' in '{}'", spec))?;
+    let policies = split_top_level(inner)?
+        .into_iter()
+        .map(parse_policy)
+        .collect::<Result<Vec<_>, _>>()?;
+    if policies.is_empty() {
+        return Err(format!("no policies in '{}'", spec));
+    }
+
+    Ok(Box::new(CompositePolicy { combinator, policies }))
+}
+
+// Splits a comma-separated list, ignoring commas inside parentheses
+fn split_top_level(list: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced ')' in '{}'", list)),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced '(' in '{}'", list));
+    }
+    parts.push(&list[start..]);
+    Ok(parts.into_iter().filter(|p| !p.trim().is_empty()).collect())
+}
+
+pub fn policy_from_env() -> Box<dyn ScalingPolicy> {
+    dotenv().ok();
+    let spec = env::var("SCALING_POLICY").unwrap_or_else(|_| "threshold".to_string());
+    parse_policy(&spec).unwrap_or_else(|e| panic!("SCALING_POLICY is invalid: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(index: usize, rps: f64, p95_latency_ms: f64) -> ContainerMetrics {
+        let dns_name = format!("worker-{}", index);
+        ContainerMetrics {
+            dns_name: dns_name.clone(),
+            score: 50.0,
+            utilization_category: UtilizationCategory::Medium,
+            telemetry: Some(BackendTelemetry {
+                dns_name,
+                rps,
+                in_flight: 0,
+                p50_latency_ms: p95_latency_ms / 2.0,
+                p95_latency_ms,
+                p99_latency_ms: p95_latency_ms * 2.0,
+                error_rate: 0.0,
+            }),
+        }
+    }
+
+    // Containers sharing the total load evenly
+    fn snapshot(now: Instant, count: usize, total_rps: f64, p95_latency_ms: f64) -> MetricsSnapshot {
+        MetricsSnapshot {
+            now,
+            timestamp: 0,
+            containers: (0..count).map(|i| container(i, total_rps / count as f64, p95_latency_ms)).collect(),
+            current_default: count,
+            min_containers: 1,
+            max_containers: 10,
+            load_history: Vec::new(),
+        }
+    }
+
+    fn rps_policy(scale_out_secs: u64, scale_in_secs: u64) -> TargetTrackingPolicy {
+        TargetTrackingPolicy::new(TargetMetric::RpsPerContainer, 50.0, Duration::from_secs(scale_out_secs), Duration::from_secs(scale_in_secs))
+    }
+
+    fn assert_scale_out(decision: ScalingDecision, expected: usize) {
+        match decision {
+            ScalingDecision::ScaleOut { count, .. } => assert_eq!(count, expected),
+            other => panic!("expected scale out by {}, got {}", expected, other),
+        }
+    }
+
+    fn assert_scale_in(decision: ScalingDecision, expected: usize) {
+        match decision {
+            ScalingDecision::ScaleIn { containers, .. } => assert_eq!(containers.len(), expected),
+            other => panic!("expected scale in by {}, got {}", expected, other),
+        }
+    }
+
+    fn assert_hold(decision: ScalingDecision) {
+        assert!(matches!(decision, ScalingDecision::Hold { .. }), "expected hold, got {}", decision);
+    }
+
+    #[test]
+    fn rps_target_scales_to_the_needed_containers() {
+        let now = Instant::now();
+        // 300 rps at 50 rps per container need 6 containers
+        assert_scale_out(rps_policy(0, 0).evaluate(&snapshot(now, 2, 300.0, 10.0)), 4);
+        // 50 rps need 1 container
+        assert_scale_in(rps_policy(0, 0).evaluate(&snapshot(now, 4, 50.0, 10.0)), 3);
+    }
+
+    #[test]
+    fn rps_within_the_tolerance_holds() {
+        // 52.5 rps per container is within 10% of the target
+        assert_hold(rps_policy(0, 0).evaluate(&snapshot(Instant::now(), 2, 105.0, 10.0)));
+    }
+
+    #[test]
+    fn rps_target_stays_within_the_container_bounds() {
+        let mut snapshot = snapshot(Instant::now(), 2, 5000.0, 10.0);
+        snapshot.max_containers = 5;
+        assert_scale_out(rps_policy(0, 0).evaluate(&snapshot), 3);
+    }
+
+    #[test]
+    fn latency_target_uses_the_request_weighted_p95() {
+        let mut policy = TargetTrackingPolicy::new(TargetMetric::P95Latency, 100.0, Duration::ZERO, Duration::ZERO);
+        let mut snapshot = snapshot(Instant::now(), 2, 0.0, 0.0);
+        // (300 * 90ms + 100 * 330ms) / 400 rps = 150ms, 1.5 times the target
+        snapshot.containers = vec![container(0, 300.0, 90.0), container(1, 100.0, 330.0)];
+        assert_scale_out(policy.evaluate(&snapshot), 1);
+    }
+
+    #[test]
+    fn latency_target_without_requests_holds() {
+        let mut policy = TargetTrackingPolicy::new(TargetMetric::P95Latency, 100.0, Duration::ZERO, Duration::ZERO);
+        assert_hold(policy.evaluate(&snapshot(Instant::now(), 2, 0.0, 500.0)));
+
+        let mut without_telemetry = snapshot(Instant::now(), 2, 0.0, 0.0);
+        without_telemetry.containers.iter_mut().for_each(|c| c.telemetry = None);
+        assert_hold(policy.evaluate(&without_telemetry));
+    }
+
+    #[test]
+    fn scale_in_waits_for_the_stabilization_window() {
+        let mut policy = rps_policy(0, 300);
+        let start = Instant::now();
+        // 200 rps on 4 containers is on target
+        assert_hold(policy.evaluate(&snapshot(start, 4, 200.0, 10.0)));
+        // The load drops, but the recommendation of 4 containers is still within the window
+        assert_hold(policy.evaluate(&snapshot(start + Duration::from_secs(60), 4, 100.0, 10.0)));
+        assert_hold(policy.evaluate(&snapshot(start + Duration::from_secs(300), 4, 100.0, 10.0)));
+        // Once it has left the window, the highest remaining recommendation is 2
+        assert_scale_in(policy.evaluate(&snapshot(start + Duration::from_secs(301), 4, 100.0, 10.0)), 2);
+    }
+
+    #[test]
+    fn scale_in_follows_the_highest_recommendation_of_the_window() {
+        let mut policy = rps_policy(0, 300);
+        let start = Instant::now();
+        assert_hold(policy.evaluate(&snapshot(start, 6, 300.0, 10.0)));
+        assert_hold(policy.evaluate(&snapshot(start + Duration::from_secs(100), 6, 150.0, 10.0)));
+        assert_hold(policy.evaluate(&snapshot(start + Duration::from_secs(200), 6, 50.0, 10.0)));
+        // Recommendations of 3 and 1 container(s) remain
+        assert_scale_in(policy.evaluate(&snapshot(start + Duration::from_secs(301), 6, 50.0, 10.0)), 3);
+    }
+
+    #[test]
+    fn scale_out_needs_agreement_within_its_window() {
+        let mut policy = rps_policy(60, 300);
+        let start = Instant::now();
+        assert_hold(policy.evaluate(&snapshot(start, 2, 100.0, 10.0)));
+        // A spike alone does not scale out while the lower recommendation is in the window
+        assert_hold(policy.evaluate(&snapshot(start + Duration::from_secs(30), 2, 300.0, 10.0)));
+        assert_scale_out(policy.evaluate(&snapshot(start + Duration::from_secs(61), 2, 300.0, 10.0)), 4);
+    }
+
+    fn threshold_policy() -> ThresholdPolicy {
+        ThresholdPolicy {
+            high_load_threshold: 20.0,
+            low_load_threshold: 90.0,
+            critical_load_threshold: 5.0,
+            critical_error_rate: 0.5,
+            scale_step: 2,
+        }
+    }
+
+    // Lower scores mean more load
+    fn scored(scores: &[f64]) -> MetricsSnapshot {
+        let mut snapshot = snapshot(Instant::now(), scores.len(), 0.0, 10.0);
+        for (container, score) in snapshot.containers.iter_mut().zip(scores) {
+            container.score = *score;
+        }
+        snapshot
+    }
+
+    #[test]
+    fn threshold_holds_at_the_max_containers() {
+        let mut snapshot = scored(&[1.0, 1.0, 1.0]);
+        snapshot.max_containers = 3;
+        let decision = threshold_policy().evaluate(&snapshot);
+        assert_eq!(decision.reason(), "max container limit (3) reached");
+        assert_hold(decision);
+    }
+
+    #[test]
+    fn threshold_scales_out_on_a_low_average_score() {
+        assert_scale_out(threshold_policy().evaluate(&scored(&[10.0, 15.0])), 2);
+        // The step is limited by the max containers
+        let mut snapshot = scored(&[10.0, 15.0]);
+        snapshot.max_containers = 3;
+        assert_scale_out(threshold_policy().evaluate(&snapshot), 1);
+    }
+
+    #[test]
+    fn threshold_scales_out_on_a_critical_container() {
+        let decision = threshold_policy().evaluate(&scored(&[95.0, 95.0, 3.0]));
+        assert!(decision.reason().contains("worker-2 is critically loaded"), "{}", decision);
+        assert_scale_out(decision, 2);
+
+        let mut snapshot = scored(&[95.0, 95.0, 95.0]);
+        snapshot.containers[1].telemetry.as_mut().unwrap().error_rate = 0.6;
+        let decision = threshold_policy().evaluate(&snapshot);
+        assert!(decision.reason().contains("worker-1 is critically loaded"), "{}", decision);
+        assert_scale_out(decision, 2);
+    }
+
+    #[test]
+    fn threshold_scale_in_keeps_the_min_and_default_containers() {
+        assert_scale_in(threshold_policy().evaluate(&scored(&[95.0; 4])), 2);
+
+        let mut snapshot = scored(&[95.0; 4]);
+        snapshot.min_containers = 3;
+        assert_scale_in(threshold_policy().evaluate(&snapshot), 1);
+
+        // Only containers above the default can be removed
+        let mut snapshot = scored(&[95.0; 4]);
+        snapshot.current_default = 2;
+        assert_scale_in(threshold_policy().evaluate(&snapshot), 1);
+
+        snapshot.current_default = 1;
+        let decision = threshold_policy().evaluate(&snapshot);
+        assert_eq!(decision.reason(), "minimum number of active containers reached");
+        assert_hold(decision);
+
+        // Never below the min containers
+        assert_hold(threshold_policy().evaluate(&scored(&[95.0])));
+    }
+
+    #[test]
+    fn threshold_holds_within_the_thresholds() {
+        assert_hold(threshold_policy().evaluate(&scored(&[20.0, 90.0, 50.0])));
+    }
+
+    // Always returns the same decision
+    struct FixedPolicy(&'static str, ScalingDecision);
+
+    impl ScalingPolicy for FixedPolicy {
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn evaluate(&mut self, _: &MetricsSnapshot) -> ScalingDecision {
+            self.1.clone()
+        }
+    }
+
+    fn composite(combinator: Combinator) -> CompositePolicy {
+        let reason = String::new;
+        CompositePolicy {
+            combinator,
+            policies: vec![
+                Box::new(FixedPolicy("hold", ScalingDecision::Hold { reason: reason() })),
+                Box::new(FixedPolicy("out", ScalingDecision::ScaleOut { count: 2, reason: reason() })),
+                Box::new(FixedPolicy("in", ScalingDecision::ScaleIn { containers: vec!["worker-0".to_string()], reason: reason() })),
+                Box::new(FixedPolicy("out-1", ScalingDecision::ScaleOut { count: 1, reason: reason() })),
+            ],
+        }
+    }
+
+    #[test]
+    fn composite_max_follows_the_most_containers() {
+        let decision = composite(Combinator::MaxOf).evaluate(&scored(&[50.0; 3]));
+        assert_eq!(decision, ScalingDecision::ScaleOut { count: 2, reason: "out: ".to_string() });
+    }
+
+    #[test]
+    fn composite_min_follows_the_fewest_containers() {
+        let decision = composite(Combinator::MinOf).evaluate(&scored(&[50.0; 3]));
+        assert_eq!(decision, ScalingDecision::ScaleIn { containers: vec!["worker-0".to_string()], reason: "in: ".to_string() });
+    }
+
+    #[test]
+    fn composite_without_policies_holds() {
+        let mut policy = CompositePolicy { combinator: Combinator::MaxOf, policies: Vec::new() };
+        assert_hold(policy.evaluate(&scored(&[50.0])));
+    }
+
+    fn parse_error(spec: &str) -> String {
+        parse_policy(spec).err().unwrap_or_else(|| panic!("'{}' should not parse", spec))
+    }
+
+    // The predictive policy only reads optional settings, so it can be built without an environment
+    #[test]
+    fn parses_single_and_nested_policies() {
+        assert_eq!(parse_policy("predictive").unwrap().name(), "predictive");
+        assert_eq!(parse_policy(" max(predictive, predictive) ").unwrap().name(), "max(predictive,predictive)");
+        assert_eq!(parse_policy("max(predictive,min(predictive,predictive))").unwrap().name(),
+                   "max(predictive,min(predictive,predictive))");
+        assert_eq!(parse_policy("min(max(predictive),predictive)").unwrap().name(), "min(max(predictive),predictive)");
+    }
+
+    // The threshold and target tracking policies read the environment of the tests using Redis
+    #[tokio::test]
+    async fn parses_policies_from_the_environment() {
+        let _redis = crate::testing::redis().await;
+        env::set_var("TARGET_VALUE", "250");
+        let policy = parse_policy("max(threshold,target_tracking)");
+        env::remove_var("TARGET_VALUE");
+
+        let policy = policy.unwrap();
+        assert_eq!(policy.name(), "max(threshold,target_tracking)");
+        let parameters = policy.parameters();
+        assert_eq!(parameters["threshold"]["high_load_threshold"], 20.0);
+        assert_eq!(parameters["threshold"]["scale_step"], 1);
+        assert_eq!(parameters["target_tracking"]["target_value"], 250.0);
+        assert_eq!(parse_policy("threshold").unwrap().name(), "threshold");
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses() {
+        assert!(parse_error("max(predictive").contains("missing ')'"));
+        assert!(parse_error("max(predictive))").contains("unbalanced ')'"));
+        assert!(parse_error("max(min(predictive,predictive)").contains("unbalanced '('"));
+        assert!(parse_error("max(predictive),min(predictive)").contains("unbalanced ')'"));
+    }
+
+    #[test]
+    fn rejects_unknown_policies() {
+        assert_eq!(parse_error("thresholds"), "unknown scaling policy 'thresholds'");
+        assert_eq!(parse_error("max(predictive,average)"), "unknown scaling policy 'average'");
+        assert_eq!(parse_error("avg(predictive)"), "unknown scaling policy 'avg(predictive)'");
+    }
+
+    #[test]
+    fn rejects_empty_arguments() {
+        assert_eq!(parse_error(""), "unknown scaling policy ''");
+        assert_eq!(parse_error("max()"), "no policies in 'max()'");
+        assert_eq!(parse_error("min( , )"), "no policies in 'min( , )'");
+    }
+
+    #[test]
+    fn splits_only_at_top_level_commas() {
+        assert_eq!(split_top_level("a,max(b,c),d").unwrap(), vec!["a", "max(b,c)", "d"]);
+        assert_eq!(split_top_level("a,,b").unwrap(), vec!["a", "b"]);
+        assert!(split_top_level("a)(").is_err());
+    }
+
+    fn predictive(history_rps: f64) -> (PredictivePolicy, MetricsSnapshot) {
+        let config = ForecastConfig { horizon_secs: 100, season_secs: 1000, seasons: 1, bucket_secs: 100 };
+        let mut snapshot = snapshot(Instant::now(), 2, 0.0, 0.0);
+        snapshot.timestamp = 10_000;
+        snapshot.load_history = vec![LoadSample { timestamp: 9_100, rps: history_rps, containers: 2 }];
+        (PredictivePolicy { config, rps_per_container: 50.0 }, snapshot)
+    }
+
+    #[test]
+    fn predictive_rounds_the_forecast_up_to_whole_containers() {
+        let (mut policy, snapshot) = predictive(100.0);
+        assert_hold(policy.evaluate(&snapshot));
+        let (mut policy, snapshot) = predictive(100.5);
+        assert_scale_out(policy.evaluate(&snapshot), 1);
+        let (mut policy, snapshot) = predictive(40.0);
+        assert_scale_in(policy.evaluate(&snapshot), 1);
+    }
+
+    #[test]
+    fn predictive_stays_within_the_container_bounds() {
+        // No load still keeps one container
+        let (mut policy, mut snapshot) = predictive(0.0);
+        snapshot.min_containers = 0;
+        assert_scale_in(policy.evaluate(&snapshot), 1);
+
+        let (mut policy, mut snapshot) = predictive(10_000.0);
+        snapshot.max_containers = 4;
+        assert_scale_out(policy.evaluate(&snapshot), 2);
+    }
+
+    #[test]
+    fn predictive_without_history_holds() {
+        let (mut policy, mut snapshot) = predictive(100.0);
+        snapshot.load_history.clear();
+        assert_hold(policy.evaluate(&snapshot));
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use crate::metrics::metrics;
+
+// Time window in which the container bounds are overridden
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduleRule {
+    #[serde(default)]
+    pub id: String,
+    // Start of the window, e.g. "0 8 * * Mon-Fri" (seconds may be given as an additional first field)
+    pub cron: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    pub duration_secs: i64,
+    pub min_containers: Option<usize>,
+    pub max_containers: Option<usize>,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScheduleStatus {
+    #[serde(flatten)]
+    pub rule: ScheduleRule,
+    pub active: bool,
+    pub next_start: Option<DateTime<Utc>>,
+}
+
+// Container bounds after applying all active rules
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScalingBounds {
+    pub min_containers: usize,
+    pub max_containers: usize,
+    pub active_rules: Vec<String>,
+}
+
+impl ScheduleRule {
+    fn schedule(&self) -> Result<Schedule, String> {
+        // The cron crate expects a seconds field, plain five-field expressions start at second 0
+        let expression = if self.cron.split_whitespace().count() == 5 {
+            format!("0 {}", self.cron)
+        } else {
+            self.cron.clone()
+        };
+        Schedule::from_str(&expression).map_err(|e| format!("invalid cron expression '{}': {}", self.cron, e))
+    }
+
+    fn timezone(&self) -> Result<Tz, String> {
+        self.timezone.parse::<Tz>().map_err(|e| format!("invalid time zone '{}': {}", self.timezone, e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.schedule()?;
+        self.timezone()?;
+        if self.duration_secs <= 0 {
+            return Err("duration_secs must be positive".to_string());
+        }
+        if self.min_containers.is_none() && self.max_containers.is_none() {
+            return Err("either min_containers or max_containers must be set".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.min_containers, self.max_containers) {
+            if min > max {
+                return Err("min_containers must not exceed max_containers".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Starts of the window after the given time. The cron fields match the wall clock of the time zone:
+    // a time repeated when the clocks go back fires once, at its first occurrence, and a time skipped
+    // when they go forward does not fire that day.
+    fn starts_after(&self, after: DateTime<Utc>) -> Option<impl Iterator<Item = DateTime<Utc>>> {
+        let schedule = self.schedule().ok()?;
+        let tz = self.timezone().ok()?;
+        // Schedules of the cron crate skip ambiguous local times, so it iterates the naive wall clock
+        let wall_clock = Utc.from_utc_datetime(&after.with_timezone(&tz).naive_local());
+        Some(schedule.after_owned(wall_clock)
+            .filter_map(move |start| tz.from_local_datetime(&start.naive_utc()).earliest())
+            .map(|start| start.with_timezone(&Utc))
+            .filter(move |start| *start > after))
+    }
+
+    // A rule is active from each cron firing until duration_secs later
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.starts_after(now - Duration::seconds(self.duration_secs))
+            .and_then(|mut starts| starts.next())
+            .is_some_and(|start| start <= now)
+    }
+
+    pub fn next_start(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.starts_after(now)?.next()
+    }
+}
+
+fn schedules_key(app_identifier: &str) -> String {
+    format!("scaling_schedules:{}", app_identifier)
+}
+
+// Loads all rules of the app, rules that can not be parsed are skipped
+pub fn load_rules(conn: &mut redis::Connection, app_identifier: &str) -> redis::RedisResult<Vec<ScheduleRule>> {
+    let entries: HashMap<String, String> = metrics().time_redis("hgetall", || conn.hgetall(schedules_key(app_identifier)))?;
+    let mut rules: Vec<ScheduleRule> = entries.values()
+        .filter_map(|json| match serde_json::from_str(json) {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                eprintln!("Skipping invalid scaling schedule: {}", e);
+                None
+            }
+        })
+        .collect();
+    rules.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(rules)
+}
+
+pub fn save_rule(conn: &mut redis::Connection, app_identifier: &str, rule: &ScheduleRule) -> redis::RedisResult<()> {
+    let json = serde_json::to_string(rule).expect("Failed to serialize scaling schedule");
+    metrics().time_redis("hset", || conn.hset(schedules_key(app_identifier), &rule.id, json))
+}
+
+// Returns whether a rule with the id existed
+pub fn delete_rule(conn: &mut redis::Connection, app_identifier: &str, id: &str) -> redis::RedisResult<bool> {
+    let removed: usize = metrics().time_redis("hdel", || conn.hdel(schedules_key(app_identifier), id))?;
+    Ok(removed > 0)
+}
+
+pub fn rule_statuses(rules: Vec<ScheduleRule>, now: DateTime<Utc>) -> Vec<ScheduleStatus> {
+    rules.into_iter()
+        .map(|rule| ScheduleStatus {
+            active: rule.is_active(now),
+            next_start: rule.next_start(now),
+            rule,
+        })
+        .collect()
+}
+
+// Applies the active rules to the configured bounds. Overlapping rules resolve to the
+// highest floor and the highest ceiling, the floor always wins over the ceiling.
+pub fn effective_bounds(rules: &[ScheduleRule], default_min: usize, default_max: usize, now: DateTime<Utc>) -> ScalingBounds {
+    let active: Vec<&ScheduleRule> = rules.iter().filter(|r| r.is_active(now)).collect();
+
+    let min_containers = active.iter()
+        .filter_map(|r| r.min_containers)
+        .max()
+        .unwrap_or(default_min);
+    let max_containers = active.iter()
+        .filter_map(|r| r.max_containers)
+        .max()
+        .unwrap_or(default_max);
+
+    ScalingBounds {
+        min_containers,
+        max_containers: std::cmp::max(max_containers, min_containers),
+        active_rules: active.iter().map(|r| r.id.clone()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, cron: &str, timezone: &str, duration_secs: i64) -> ScheduleRule {
+        ScheduleRule {
+            id: id.to_string(),
+            cron: cron.to_string(),
+            timezone: timezone.to_string(),
+            duration_secs,
+            min_containers: Some(1),
+            max_containers: None,
+        }
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_follow_the_time_zone_of_the_rule() {
+        // 08:00 in Berlin is 07:00 UTC in winter and 06:00 UTC in summer
+        let rule = rule("office", "0 8 * * Mon-Fri", "Europe/Berlin", 3600);
+        assert!(!rule.is_active(utc(1, 15, 6, 59)));
+        assert!(rule.is_active(utc(1, 15, 7, 0)));
+        assert!(rule.is_active(utc(1, 15, 7, 59)));
+        assert!(!rule.is_active(utc(1, 15, 8, 0)));
+        assert!(rule.is_active(utc(7, 15, 6, 30)));
+        assert!(!rule.is_active(utc(7, 15, 7, 30)));
+        // Saturday
+        assert!(!rule.is_active(utc(1, 17, 7, 30)));
+        assert_eq!(rule.next_start(utc(1, 15, 12, 0)), Some(utc(1, 16, 7, 0)));
+    }
+
+    #[test]
+    fn windows_in_zones_with_half_hour_offsets() {
+        let rule = rule("india", "0 9 * * *", "Asia/Kolkata", 600);
+        assert_eq!(rule.next_start(utc(1, 15, 0, 0)), Some(utc(1, 15, 3, 30)));
+        assert!(rule.is_active(utc(1, 15, 3, 35)));
+    }
+
+    #[test]
+    fn start_moves_with_the_spring_clock_change() {
+        // Clocks go forward from 02:00 to 03:00 in Berlin on March 29
+        let rule = rule("daily", "0 8 * * *", "Europe/Berlin", 3600);
+        assert_eq!(rule.next_start(utc(3, 28, 0, 0)), Some(utc(3, 28, 7, 0)));
+        assert_eq!(rule.next_start(utc(3, 28, 12, 0)), Some(utc(3, 29, 6, 0)));
+    }
+
+    #[test]
+    fn duration_is_elapsed_time_across_a_clock_change() {
+        // Starts at 01:00 CET (00:00 UTC), three hours later it is 05:00 CEST
+        let rule = rule("night", "0 1 * * *", "Europe/Berlin", 3 * 3600);
+        assert!(rule.is_active(utc(3, 29, 2, 59)));
+        assert!(!rule.is_active(utc(3, 29, 3, 0)));
+    }
+
+    #[test]
+    fn time_skipped_by_the_clock_change_does_not_fire() {
+        let rule = rule("skipped", "30 2 * * *", "Europe/Berlin", 600);
+        assert_eq!(rule.next_start(utc(3, 28, 12, 0)), Some(utc(3, 30, 0, 30)));
+    }
+
+    #[test]
+    fn time_repeated_by_the_clock_change_fires_once() {
+        // Clocks go back from 03:00 to 02:00 in Berlin on October 25, 02:30 happens at 00:30 and 01:30 UTC
+        let rule = rule("repeated", "30 2 * * *", "Europe/Berlin", 1800);
+        assert_eq!(rule.next_start(utc(10, 24, 12, 0)), Some(utc(10, 25, 0, 30)));
+        assert_eq!(rule.next_start(utc(10, 25, 0, 30)), Some(utc(10, 26, 1, 30)));
+        assert!(rule.is_active(utc(10, 25, 0, 40)));
+        assert!(!rule.is_active(utc(10, 25, 1, 40)));
+    }
+
+    #[test]
+    fn overlapping_rules_use_the_highest_floor_and_ceiling() {
+        let now = utc(1, 15, 7, 30);
+        let mut morning = rule("morning", "0 8 * * *", "Europe/Berlin", 3600);
+        morning.min_containers = Some(3);
+        morning.max_containers = Some(5);
+        let mut peak = rule("peak", "15 7 * * *", "UTC", 3600);
+        peak.min_containers = Some(4);
+        let mut evening = rule("evening", "0 18 * * *", "UTC", 3600);
+        evening.min_containers = Some(10);
+
+        let bounds = effective_bounds(&[morning.clone(), peak.clone(), evening], 1, 8, now);
+        assert_eq!(bounds, ScalingBounds { min_containers: 4, max_containers: 5, active_rules: vec!["morning".to_string(), "peak".to_string()] });
+
+        // The floor wins over a lower ceiling
+        morning.max_containers = Some(2);
+        assert_eq!(effective_bounds(&[morning, peak], 1, 8, now).max_containers, 4);
+    }
+
+    #[test]
+    fn without_active_rules_the_defaults_apply() {
+        let bounds = effective_bounds(&[rule("evening", "0 18 * * *", "UTC", 3600)], 1, 8, utc(1, 15, 7, 30));
+        assert_eq!(bounds, ScalingBounds { min_containers: 1, max_containers: 8, active_rules: vec![] });
+    }
+}
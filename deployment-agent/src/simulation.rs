@@ -0,0 +1,530 @@
+use std::env;
+use std::time::{Duration, Instant};
+use chrono::{TimeZone, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use crate::forecast::{ForecastConfig, LoadSample};
+use crate::scaling::{ContainerMetrics, MetricsSnapshot, ScalingDecision, ScalingPolicy, ScalingTimers};
+use crate::schedule::{effective_bounds, ScheduleRule};
+use crate::stats::{calculate_error_score, calculate_latency_score, calculate_score, categorize_utilization, ContainerStatus};
+use crate::queue::UtilizationCategory;
+use crate::telemetry::BackendTelemetry;
+
+// One line of a recording: the output of GET /stats at a point in time (unix seconds)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedTick {
+    pub timestamp: i64,
+    pub statuses: Vec<ContainerStatus>,
+}
+
+// Load of the whole app at one point in time, independent of the container count.
+// Resource demands are summed usages in percent of a single container.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Demand {
+    pub timestamp: i64,
+    pub cpu: f64,
+    pub memory: f64,
+    pub network: f64,
+    pub availability_score: f64,
+    pub rps: f64,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    pub p99_latency_ms: f64,
+    pub error_rate: f64,
+    // Highest per-container utilization (0..1) at which the latencies were observed
+    pub reference_utilization: f64,
+}
+
+impl Demand {
+    // Sums up the load of all serving containers of a recording
+    pub fn from_recorded(tick: &RecordedTick) -> Demand {
+        let serving: Vec<&ContainerStatus> = tick.statuses.iter()
+            .filter(|s| s.utilization_category != UtilizationCategory::Init && s.utilization_category != UtilizationCategory::Sundown)
+            .collect();
+        let count = serving.len().max(1) as f64;
+        let usage = |score: f64| (100.0 - score).clamp(0.0, 100.0);
+
+        let cpu = serving.iter().map(|s| usage(s.cpu_score)).sum::<f64>();
+        let memory = serving.iter().map(|s| usage(s.memory_score)).sum::<f64>();
+        let network = serving.iter().map(|s| usage(s.network_score)).sum::<f64>();
+
+        let telemetry: Vec<&BackendTelemetry> = serving.iter().filter_map(|s| s.telemetry.as_ref()).collect();
+        let rps = telemetry.iter().map(|t| t.rps).sum::<f64>();
+        let worst = |latency: fn(&BackendTelemetry) -> f64| telemetry.iter().map(|t| latency(t)).fold(0.0, f64::max);
+        let error_rate = if rps > 0.0 {
+            telemetry.iter().map(|t| t.error_rate * t.rps).sum::<f64>() / rps
+        } else {
+            0.0
+        };
+
+        Demand {
+            timestamp: tick.timestamp,
+            cpu,
+            memory,
+            network,
+            availability_score: if serving.is_empty() { 100.0 } else { serving.iter().map(|s| s.availability_score).sum::<f64>() / count },
+            rps,
+            p50_latency_ms: worst(|t| t.p50_latency_ms),
+            p95_latency_ms: worst(|t| t.p95_latency_ms),
+            p99_latency_ms: worst(|t| t.p99_latency_ms),
+            error_rate,
+            reference_utilization: cpu.max(memory).max(network) / count / 100.0,
+        }
+    }
+}
+
+// Daily load curve with a single peak, all resources scale with the request rate
+#[derive(Debug, Clone)]
+pub struct SyntheticLoad {
+    pub base_rps: f64,
+    pub peak_rps: f64,
+    // Requests per second that fully utilize one container
+    pub capacity_rps: f64,
+    // Latency of a container that is not overloaded
+    pub latency_ms: f64,
+    pub season_secs: i64,
+    // Offset of the peak within the season
+    pub peak_offset_secs: i64,
+}
+
+impl SyntheticLoad {
+    pub fn demand(&self, timestamp: i64) -> Demand {
+        let phase = (timestamp - self.peak_offset_secs).rem_euclid(self.season_secs) as f64 / self.season_secs as f64;
+        let rps = self.base_rps + (self.peak_rps - self.base_rps) * (1.0 + (2.0 * std::f64::consts::PI * phase).cos()) / 2.0;
+        let usage = 100.0 * rps / self.capacity_rps;
+
+        Demand {
+            timestamp,
+            cpu: usage,
+            memory: usage,
+            network: usage,
+            availability_score: 100.0,
+            rps,
+            p50_latency_ms: self.latency_ms / 2.0,
+            p95_latency_ms: self.latency_ms,
+            p99_latency_ms: self.latency_ms * 1.5,
+            error_rate: 0.0,
+            reference_utilization: 0.0,
+        }
+    }
+}
+
+// Containers of the simulation, a container serves requests once its startup time passed
+struct SimulatedContainer {
+    name: String,
+    started_at: i64,
+}
+
+pub struct SimulatedFleet {
+    containers: Vec<SimulatedContainer>,
+    next_id: usize,
+    startup_secs: i64,
+}
+
+impl SimulatedFleet {
+    pub fn new(startup_secs: i64) -> Self {
+        SimulatedFleet { containers: Vec::new(), next_id: 0, startup_secs }
+    }
+
+    pub fn create_container(&mut self, timestamp: i64) -> String {
+        self.next_id += 1;
+        let name = format!("sim-{}", self.next_id);
+        self.containers.push(SimulatedContainer { name: name.clone(), started_at: timestamp });
+        name
+    }
+
+    pub fn remove_containers(&mut self, names: &[String]) -> usize {
+        let before = self.containers.len();
+        self.containers.retain(|c| !names.contains(&c.name));
+        before - self.containers.len()
+    }
+
+    pub fn count(&self) -> usize {
+        self.containers.len()
+    }
+
+    fn is_ready(&self, container: &SimulatedContainer, timestamp: i64) -> bool {
+        timestamp - container.started_at >= self.startup_secs
+    }
+
+    pub fn ready_count(&self, timestamp: i64) -> usize {
+        self.containers.iter().filter(|c| self.is_ready(c, timestamp)).count()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub startup_secs: i64,
+    pub slo_latency_ms: f64,
+    pub cooldown_period: Duration,
+    pub check_period: Duration,
+    pub default_containers: usize,
+    pub max_containers: usize,
+    pub history_interval_secs: i64,
+    pub forecast: ForecastConfig,
+    pub schedules: Vec<ScheduleRule>,
+}
+
+impl SimulationConfig {
+    // Reads the same variables as the agent, so a .env file can be evaluated unchanged
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let read = |name: &str, default: &str| -> String {
+            env::var(name).unwrap_or_else(|_| default.to_string())
+        };
+
+        SimulationConfig {
+            startup_secs: 30,
+            slo_latency_ms: read("LATENCY_TARGET_MS", "200").parse().expect("LATENCY_TARGET_MS must be a valid f64"),
+            cooldown_period: Duration::from_secs(read("COOLDOWN_PERIOD", "60").parse().expect("COOLDOWN_PERIOD must be a valid u64")),
+            check_period: Duration::from_secs(read("SCALE_CHECK_PERIOD", "30").parse().expect("SCALE_CHECK_PERIOD must be a valid u64")),
+            default_containers: read("DEFAULT_CONTAINER", "1").parse().expect("DEFAULT_CONTAINER must be a valid usize"),
+            max_containers: read("MAX_CONTAINERS", "10").parse().expect("MAX_CONTAINERS must be a valid usize"),
+            history_interval_secs: read("LOAD_HISTORY_INTERVAL", "60").parse().expect("LOAD_HISTORY_INTERVAL must be a valid i64"),
+            forecast: ForecastConfig::from_env(),
+            schedules: Vec::new(),
+        }
+    }
+}
+
+// State of the simulation at one step, the action is taken afterwards
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TickRecord {
+    pub timestamp: i64,
+    pub containers: usize,
+    pub ready_containers: usize,
+    pub rps: f64,
+    pub average_score: f64,
+    pub p95_latency_ms: f64,
+    pub slo_violation: bool,
+    pub action: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScalingEvent {
+    pub timestamp: i64,
+    pub action: String,
+    pub count: usize,
+    pub containers_after: usize,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimulationSummary {
+    pub policy: String,
+    pub ticks: usize,
+    pub slo_violations: usize,
+    pub scale_out_events: usize,
+    pub scale_in_events: usize,
+    pub container_seconds: i64,
+    pub average_containers: f64,
+    pub peak_containers: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    pub summary: SimulationSummary,
+    pub ticks: Vec<TickRecord>,
+    pub events: Vec<ScalingEvent>,
+}
+
+// Replays demands through a scaling policy with a virtual clock and a simulated fleet
+pub struct Simulator {
+    config: SimulationConfig,
+    policy: Box<dyn ScalingPolicy>,
+    fleet: SimulatedFleet,
+    timers: ScalingTimers,
+    origin: Instant,
+    start_timestamp: Option<i64>,
+    last_timestamp: i64,
+    container_seconds: i64,
+    history: Vec<LoadSample>,
+    last_sample: Option<i64>,
+    ticks: Vec<TickRecord>,
+    events: Vec<ScalingEvent>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulationConfig, policy: Box<dyn ScalingPolicy>) -> Self {
+        let origin = Instant::now();
+        Simulator {
+            fleet: SimulatedFleet::new(config.startup_secs),
+            config,
+            policy,
+            timers: ScalingTimers::new(origin),
+            origin,
+            start_timestamp: None,
+            last_timestamp: 0,
+            container_seconds: 0,
+            history: Vec::new(),
+            last_sample: None,
+            ticks: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    // Virtual point in time of a timestamp, relative to the first replayed timestamp
+    fn instant_at(&self, timestamp: i64) -> Instant {
+        let offset = timestamp - self.start_timestamp.unwrap_or(timestamp);
+        self.origin + Duration::from_secs(offset.max(0) as u64)
+    }
+
+    pub fn step(&mut self, demand: &Demand) {
+        let timestamp = demand.timestamp;
+        if self.start_timestamp.is_none() {
+            self.start_timestamp = Some(timestamp);
+            self.last_timestamp = timestamp;
+            // Containers of the initial deployment are already running
+            for _ in 0..self.config.default_containers {
+                self.fleet.create_container(timestamp - self.config.startup_secs);
+            }
+        }
+        self.container_seconds += self.fleet.count() as i64 * (timestamp - self.last_timestamp).max(0);
+        self.last_timestamp = timestamp;
+        let now = self.instant_at(timestamp);
+
+        let (metrics, p95_latency_ms) = self.container_metrics(demand);
+        let containers = self.fleet.count();
+        let ready_containers = self.fleet.ready_count(timestamp);
+        let mut snapshot = MetricsSnapshot {
+            now,
+            timestamp,
+            containers: metrics,
+            current_default: containers,
+            min_containers: self.config.default_containers,
+            max_containers: self.config.max_containers,
+            load_history: Vec::new(),
+        };
+        let bounds = effective_bounds(
+            &self.config.schedules,
+            self.config.default_containers,
+            self.config.max_containers,
+            Utc.timestamp_opt(timestamp, 0).single().unwrap_or_else(Utc::now),
+        );
+        snapshot.min_containers = bounds.min_containers;
+        snapshot.max_containers = bounds.max_containers;
+
+        if self.last_sample.is_none_or(|at| timestamp - at >= self.config.history_interval_secs) {
+            self.history.push(LoadSample { timestamp, rps: demand.rps, containers: snapshot.active_count() });
+            self.history.retain(|s| s.timestamp >= timestamp - self.config.forecast.retention_secs());
+            self.last_sample = Some(timestamp);
+        }
+
+        let average_score = snapshot.average_score();
+        let action = if !bounds.active_rules.is_empty() && snapshot.active_count() < bounds.min_containers {
+            let count = bounds.min_containers - snapshot.active_count();
+            self.scale_out(timestamp, count, format!("scheduled minimum of {} container(s)", bounds.min_containers));
+            Some("schedule_scale_out".to_string())
+        } else if !bounds.active_rules.is_empty() && snapshot.active_count() > bounds.max_containers {
+            let containers = snapshot.scale_in_candidates(snapshot.active_count() - bounds.max_containers);
+            self.scale_in(timestamp, &containers, format!("scheduled maximum of {} container(s)", bounds.max_containers));
+            Some("schedule_scale_in".to_string())
+        } else if self.timers.check_due(now, self.config.check_period) {
+            snapshot.load_history = self.history.clone();
+            match self.policy.evaluate(&snapshot) {
+                ScalingDecision::ScaleOut { count, reason } => {
+                    let count = count.min(snapshot.max_containers.saturating_sub(snapshot.active_count()));
+                    if count > 0 && self.timers.can_scale_out(now, self.config.cooldown_period) {
+                        self.scale_out(timestamp, count, reason);
+                        self.timers.record_scale_out(now);
+                        Some("scale_out".to_string())
+                    } else {
+                        None
+                    }
+                }
+                // Like in the agent the cooldown only holds back scale-outs
+                ScalingDecision::ScaleIn { containers, reason } => {
+                    self.scale_in(timestamp, &containers, reason);
+                    Some("scale_in".to_string())
+                }
+                ScalingDecision::Hold { .. } => None,
+            }
+        } else {
+            None
+        };
+
+        self.ticks.push(TickRecord {
+            timestamp,
+            containers,
+            ready_containers,
+            rps: demand.rps,
+            average_score,
+            p95_latency_ms,
+            slo_violation: (ready_containers == 0 && demand.rps > 0.0) || p95_latency_ms > self.config.slo_latency_ms,
+            action,
+        });
+    }
+
+    fn scale_out(&mut self, timestamp: i64, count: usize, reason: String) {
+        for _ in 0..count {
+            self.fleet.create_container(timestamp);
+        }
+        self.events.push(ScalingEvent {
+            timestamp,
+            action: "scale_out".to_string(),
+            count,
+            containers_after: self.fleet.count(),
+            reason,
+        });
+    }
+
+    fn scale_in(&mut self, timestamp: i64, containers: &[String], reason: String) {
+        let removed = self.fleet.remove_containers(containers);
+        self.events.push(ScalingEvent {
+            timestamp,
+            action: "scale_in".to_string(),
+            count: removed,
+            containers_after: self.fleet.count(),
+            reason,
+        });
+    }
+
+    // Spreads the demand over the ready containers and scores them like the agent does.
+    // Latencies grow with the utilization above 100% compared to the recording.
+    fn container_metrics(&self, demand: &Demand) -> (Vec<ContainerMetrics>, f64) {
+        let ready = self.fleet.ready_count(demand.timestamp).max(1) as f64;
+        let usage = |total: f64| total / ready;
+        let utilization = usage(demand.cpu).max(usage(demand.memory)).max(usage(demand.network)) / 100.0;
+        let latency_factor = utilization.max(1.0) / demand.reference_utilization.max(1.0);
+        let p95_latency_ms = demand.p95_latency_ms * latency_factor;
+
+        let metrics = self.fleet.containers.iter()
+            .map(|container| {
+                if !self.fleet.is_ready(container, demand.timestamp) {
+                    return ContainerMetrics {
+                        dns_name: container.name.clone(),
+                        score: 100.0,
+                        utilization_category: UtilizationCategory::Init,
+                        telemetry: None,
+                    };
+                }
+
+                let telemetry = BackendTelemetry {
+                    dns_name: container.name.clone(),
+                    rps: demand.rps / ready,
+                    in_flight: 0,
+                    p50_latency_ms: demand.p50_latency_ms * latency_factor,
+                    p95_latency_ms,
+                    p99_latency_ms: demand.p99_latency_ms * latency_factor,
+                    error_rate: demand.error_rate,
+                };
+                let score = calculate_score(
+                    100.0 - usage(demand.cpu).min(100.0),
+                    100.0 - usage(demand.memory).min(100.0),
+                    100.0 - usage(demand.network).min(100.0),
+                    demand.availability_score,
+                    calculate_latency_score(Some(&telemetry)),
+                    calculate_error_score(Some(&telemetry)),
+                );
+                ContainerMetrics {
+                    dns_name: container.name.clone(),
+                    score,
+                    utilization_category: categorize_utilization(score),
+                    telemetry: Some(telemetry),
+                }
+            })
+            .collect();
+        (metrics, p95_latency_ms)
+    }
+
+    pub fn finish(self) -> SimulationReport {
+        let ticks = self.ticks.len();
+        let summary = SimulationSummary {
+            policy: self.policy.name(),
+            ticks,
+            slo_violations: self.ticks.iter().filter(|t| t.slo_violation).count(),
+            scale_out_events: self.events.iter().filter(|e| e.action == "scale_out").count(),
+            scale_in_events: self.events.iter().filter(|e| e.action == "scale_in").count(),
+            container_seconds: self.container_seconds,
+            average_containers: if ticks > 0 { self.ticks.iter().map(|t| t.containers).sum::<usize>() as f64 / ticks as f64 } else { 0.0 },
+            peak_containers: self.ticks.iter().map(|t| t.containers).max().unwrap_or(0),
+        };
+        SimulationReport { summary, ticks: self.ticks, events: self.events }
+    }
+}
+
+impl SimulationReport {
+    pub fn ticks_csv(&self) -> String {
+        let mut csv = String::from("timestamp,containers,ready_containers,rps,average_score,p95_latency_ms,slo_violation,action\n");
+        for tick in &self.ticks {
+            csv.push_str(&format!("{},{},{},{:.3},{:.2},{:.2},{},{}\n",
+                tick.timestamp, tick.containers, tick.ready_containers, tick.rps, tick.average_score,
+                tick.p95_latency_ms, tick.slo_violation, tick.action.as_deref().unwrap_or("")));
+        }
+        csv
+    }
+
+    pub fn events_csv(&self) -> String {
+        let mut csv = String::from("timestamp,action,count,containers_after,reason\n");
+        for event in &self.events {
+            csv.push_str(&format!("{},{},{},{},\"{}\"\n",
+                event.timestamp, event.action, event.count, event.containers_after, event.reason.replace('"', "\"\"")));
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaling::{TargetMetric, TargetTrackingPolicy};
+
+    fn config(cooldown_secs: u64) -> SimulationConfig {
+        SimulationConfig {
+            startup_secs: 30,
+            slo_latency_ms: 200.0,
+            cooldown_period: Duration::from_secs(cooldown_secs),
+            check_period: Duration::from_secs(30),
+            default_containers: 1,
+            max_containers: 10,
+            history_interval_secs: 60,
+            forecast: ForecastConfig { horizon_secs: 600, season_secs: 86400, seasons: 7, bucket_secs: 300 },
+            schedules: Vec::new(),
+        }
+    }
+
+    fn policy(scale_out_secs: u64, scale_in_secs: u64) -> Box<dyn ScalingPolicy> {
+        Box::new(TargetTrackingPolicy::new(TargetMetric::RpsPerContainer, 50.0, Duration::from_secs(scale_out_secs), Duration::from_secs(scale_in_secs)))
+    }
+
+    fn load(base_rps: f64, peak_rps: f64) -> SyntheticLoad {
+        SyntheticLoad { base_rps, peak_rps, capacity_rps: 50.0, latency_ms: 50.0, season_secs: 86400, peak_offset_secs: 14 * 3600 }
+    }
+
+    fn run(config: SimulationConfig, policy: Box<dyn ScalingPolicy>, demands: &[Demand]) -> SimulationReport {
+        // Containers are scored like in the agent, every test uses the same weights
+        for weight in ["CPU_WEIGHT", "MEMORY_WEIGHT", "NETWORK_WEIGHT", "AVAILABILITY_WEIGHT"] {
+            env::set_var(weight, "0.25");
+        }
+        let mut simulator = Simulator::new(config, policy);
+        for demand in demands {
+            simulator.step(demand);
+        }
+        simulator.finish()
+    }
+
+    #[test]
+    fn synthetic_runs_are_reproducible() {
+        let load = load(10.0, 200.0);
+        let demands: Vec<Demand> = (0..86400).step_by(60).map(|t| load.demand(t)).collect();
+
+        let first = run(config(60), policy(0, 300), &demands);
+        let second = run(config(60), policy(0, 300), &demands);
+        assert!(first.summary.scale_out_events > 0 && first.summary.scale_in_events > 0);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn cooldown_holds_back_scale_out_only() {
+        let (high, low) = (load(200.0, 200.0), load(10.0, 10.0));
+        let demands: Vec<Demand> = (0..=1200).step_by(30)
+            .map(|t| if t <= 600 { high.demand(t) } else { low.demand(t) })
+            .collect();
+
+        let report = run(config(600), policy(0, 0), &demands);
+        let scale_out = report.events.iter().find(|e| e.action == "scale_out").expect("no scale out");
+        assert_eq!(scale_out.timestamp, 600);
+        let scale_in = report.events.iter().find(|e| e.action == "scale_in").expect("no scale in");
+        assert!(scale_in.timestamp < scale_out.timestamp + 600, "scale in at {}", scale_in.timestamp);
+    }
+}
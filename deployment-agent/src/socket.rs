@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
@@ -5,11 +6,24 @@ use axum::{
     routing::get,
     Router,
 };
+use hyper::server::conn::Http;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use dotenv::dotenv;
-use serde::{Deserialize, Serialize};
-use crate::queue::{build_queue};
+use protocol::tls::{self, TlsAcceptor};
+use protocol::{decode, encode, negotiate, AgentMessage, BalancerMessage, ErrorCode, QueueDelta, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use tokio::net::TcpListener;
+use tokio::time::{interval_at, timeout, Duration, Instant};
+use crate::queue::{latest_queue, request_reconcile, subscribe_queue, wake_from_zero, QueueItem};
+use crate::telemetry::{record_report, remove_reporter};
+
+static NEXT_CONNECTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Identifies a balancer in the telemetry reports, also for balancers not connected over the socket
+pub fn next_connection_id() -> usize {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 pub async fn socket() {
     dotenv().ok();
@@ -21,12 +35,66 @@ pub async fn socket() {
     let addr_ws = SocketAddr::from(([0, 0, 0, 0], ws_env_port));
     let app = Router::new().route("/ws", get(http_to_ws));
 
-    println!("Socket listening on {}", addr_ws);
+    // Mutual TLS if WS_TLS_CERT_PATH is set, plain WebSocket otherwise
+    let tls_config = match env::var("WS_TLS_CERT_PATH") {
+        Ok(path) if !path.is_empty() => match tls::server_config(Path::new(&path)) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("Invalid WebSocket TLS configuration: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
+    match tls_config {
+        Some(config) => {
+            println!("Socket listening on {} (wss, client certificates required)", addr_ws);
+            let listener = TcpListener::bind(addr_ws).await.expect("Failed to bind WebSocket port");
+            serve_tls(listener, app, TlsAcceptor::from(config)).await;
+        }
+        None => {
+            println!("Warning: WS_TLS_CERT_PATH is not set, the queue is served unencrypted and without authentication");
+            println!("Socket listening on {}", addr_ws);
+            axum::Server::bind(&addr_ws)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+    }
+}
 
-    axum::Server::bind(&addr_ws)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+// Accepts only balancers that present a certificate signed by the configured CA
+async fn serve_tls(listener: TcpListener, app: Router, acceptor: TlsAcceptor) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Failed to accept WebSocket connection: {}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    eprintln!("Rejected WebSocket connection from {}: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    eprintln!("Rejected WebSocket connection from {}: no TLS handshake within {} seconds", peer, TLS_HANDSHAKE_TIMEOUT.as_secs());
+                    return;
+                }
+            };
+
+            if let Err(e) = Http::new().serve_connection(stream, app).with_upgrades().await {
+                eprintln!("Error serving WebSocket connection from {}: {}", peer, e);
+            }
+        });
+    }
 }
 
 async fn http_to_ws(ws: WebSocketUpgrade) -> impl IntoResponse {
@@ -34,38 +102,237 @@ async fn http_to_ws(ws: WebSocketUpgrade) -> impl IntoResponse {
     ws.on_upgrade(handle_socket)
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum Event {
-    Echo { message: String },
-    // Other variants...
+// Time a balancer has to complete the TLS handshake after connecting
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Time a balancer has to send its hello after connecting
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn heartbeat_interval() -> Duration {
+    let secs = env::var("WS_HEARTBEAT_INTERVAL")
+        .unwrap_or_else(|_| "15".to_string())
+        .parse::<u64>()
+        .expect("WS_HEARTBEAT_INTERVAL must be a valid u64");
+    Duration::from_secs(secs.max(1))
+}
+
+async fn send(socket: &mut WebSocket, message: &AgentMessage) -> Result<(), axum::Error> {
+    socket.send(Message::Text(encode(message))).await
+}
+
+async fn reject(socket: &mut WebSocket, code: ErrorCode, message: String) {
+    eprintln!("Rejecting WebSocket connection: {}", message);
+    if let Err(e) = send(socket, &AgentMessage::Error { code, message }).await {
+        eprintln!("Error sending message: {}", e);
+    }
+}
+
+// Waits for the hello of the balancer and returns the protocol version of the connection
+async fn handshake(socket: &mut WebSocket) -> Option<u32> {
+    let text = match timeout(HELLO_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => text,
+        Ok(Some(Ok(_))) => {
+            reject(socket, ErrorCode::HandshakeRequired, "expected a hello message".to_string()).await;
+            return None;
+        }
+        Ok(Some(Err(_))) | Ok(None) => return None,
+        Err(_) => {
+            reject(socket, ErrorCode::HandshakeRequired, format!("no hello within {} seconds", HELLO_TIMEOUT.as_secs())).await;
+            return None;
+        }
+    };
+
+    match decode::<BalancerMessage>(&text) {
+        Ok(BalancerMessage::Hello { min_version, max_version }) => {
+            let version = negotiate(min_version, max_version);
+            if version.is_none() {
+                reject(socket, ErrorCode::UnsupportedVersion, format!(
+                    "balancer speaks protocol versions {}-{}, agent {}-{}",
+                    min_version, max_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)).await;
+            }
+            version
+        }
+        Ok(_) | Err(_) => {
+            reject(socket, ErrorCode::HandshakeRequired, "expected a hello message".to_string()).await;
+            None
+        }
+    }
+}
+
+// State of the queue as known by one balancer
+#[derive(Default)]
+struct QueueStream {
+    seq: u64,
+    // Last queue sent, None until the first snapshot
+    sent: Option<Vec<QueueItem>>,
+}
+
+impl QueueStream {
+    // Snapshot for a new (or resyncing) balancer, otherwise the changes since the last message
+    fn next_message(&mut self, items: &[QueueItem]) -> Option<AgentMessage> {
+        let message = match &self.sent {
+            None => AgentMessage::Snapshot { seq: self.seq + 1, items: items.to_vec() },
+            Some(sent) => {
+                let delta = QueueDelta::diff(sent, items);
+                if delta.is_empty() {
+                    return None;
+                }
+                AgentMessage::Delta { seq: self.seq + 1, delta }
+            }
+        };
+        self.seq += 1;
+        self.sent = Some(items.to_vec());
+        Some(message)
+    }
 }
 
 async fn handle_socket(mut socket: WebSocket) {
-    println!("WebSocket connection established");
+    let connection_id = next_connection_id();
+    println!("WebSocket connection {} established", connection_id);
+
+    let Some(version) = handshake(&mut socket).await else {
+        println!("WebSocket connection {} closed during handshake", connection_id);
+        return;
+    };
+    let heartbeat = heartbeat_interval();
+    let hello = AgentMessage::Hello { version, heartbeat_interval_secs: heartbeat.as_secs() };
+    if let Err(e) = send(&mut socket, &hello).await {
+        eprintln!("Error sending message: {}", e);
+        return;
+    }
+    println!("WebSocket connection {} uses protocol version {}", connection_id, version);
+
+    let mut updates = subscribe_queue();
+    let mut stream = QueueStream::default();
+    let mut heartbeats = interval_at(Instant::now() + heartbeat, heartbeat);
+    // Telemetry is keyed by the published address, scaling by container name
+    let mut names = HashMap::new();
+
+    // Sends the latest queue of the reconciliation loop right away
+    let mut pending = updates.borrow_and_update().clone().and_then(|queue| {
+        names = queue.names.clone();
+        stream.next_message(&queue.items)
+    });
 
     loop {
-        // Gets current queue
-        match build_queue().await {
-            Ok(shared_queue) => {
-                let locked_queue = shared_queue.lock().await;
-                let queue_string = serde_json::to_string(&*locked_queue).expect("Failed to serialize queue");
-
-                // Sends serialized queue with the websocket
-                if let Err(e) = socket.send(Message::Text(queue_string.clone())).await {
-                    eprintln!("Error sending message: {}", e);
-                    // Breaks loop, if an error occurred
+        if let Some(message) = pending.take() {
+            if let Err(e) = send(&mut socket, &message).await {
+                eprintln!("Error sending message: {}", e);
+                // Breaks loop, if an error occurred
+                break;
+            }
+        }
+
+        pending = tokio::select! {
+            changed = updates.changed() => {
+                if changed.is_err() {
                     break;
                 }
+                updates.borrow_and_update().clone().and_then(|queue| {
+                    names = queue.names.clone();
+                    stream.next_message(&queue.items)
+                })
             }
-            Err(e) => {
-                eprintln!("Failed to build queue: {:?}", e);
-                break;
+            _ = heartbeats.tick() => Some(AgentMessage::Heartbeat { seq: stream.seq }),
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => handle_message(connection_id, &text, &names, &mut stream).await,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => None,
+                Some(Err(e)) => {
+                    eprintln!("Error receiving message: {}", e);
+                    break;
+                }
             }
+        };
+    }
+
+    remove_reporter(connection_id).await;
+    println!("WebSocket connection {} closed", connection_id);
+}
+
+// Handles a message sent by the balancer and returns the answer, if any
+async fn handle_message(connection_id: usize, text: &str, names: &HashMap<String, String>, stream: &mut QueueStream) -> Option<AgentMessage> {
+    match decode::<BalancerMessage>(text) {
+        Ok(BalancerMessage::Wake) => {
+            println!("Received wake-up event");
+            wake_from_zero().await;
+            // Rebuilds the queue right away after a wake-up
+            request_reconcile();
+            None
         }
+        Ok(BalancerMessage::Telemetry { mut backends }) => {
+            println!("Received telemetry for {} backend(s) from connection {}", backends.len(), connection_id);
+            for backend in &mut backends {
+                if let Some(name) = names.get(&backend.dns_name) {
+                    backend.dns_name = name.clone();
+                }
+            }
+            record_report(connection_id, backends).await;
+            None
+        }
+        Ok(BalancerMessage::Resync) => {
+            println!("Connection {} requested a resync", connection_id);
+            stream.sent = None;
+            latest_queue().and_then(|queue| stream.next_message(&queue.items))
+        }
+        Ok(BalancerMessage::Hello { .. }) => {
+            eprintln!("Ignoring repeated hello from connection {}", connection_id);
+            None
+        }
+        Err(e) => {
+            eprintln!("Failed to parse message: {}", e);
+            Some(AgentMessage::Error { code: ErrorCode::InvalidMessage, message: e.to_string() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use protocol::tls::testing::{anonymous_client_config, Authority};
+    use protocol::tls::{client_config, server_config, server_name, TlsConnector};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use super::*;
 
-        // Waits 2 secs before sending update
-        tokio::time::sleep(Duration::from_secs(2)).await;
+    fn cert_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("agent-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
     }
 
-    println!("WebSocket connection closed");
-}
\ No newline at end of file
+    // Serves an empty router over mutual TLS on a loopback port
+    async fn serve(agent: &Path) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_tls(listener, Router::new(), TlsAcceptor::from(server_config(agent).unwrap())));
+        address
+    }
+
+    // Sends a request and returns the status line of the response, None if the connection was rejected
+    async fn request(address: SocketAddr, connector: TlsConnector) -> Option<String> {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect(server_name("localhost").unwrap(), stream).await.ok()?;
+        stream.write_all(b"GET /status HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n").await.ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+        response.lines().next().map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn only_balancers_of_the_ca_are_served() {
+        let authority = Authority::new("test-ca");
+        let (agent, balancer) = (cert_dir("tls-agent"), cert_dir("tls-balancer"));
+        for (dir, dns_name) in [(&agent, "localhost"), (&balancer, "balancer")] {
+            authority.trust(dir);
+            authority.issue(dir, dns_name);
+        }
+        let address = serve(&agent).await;
+
+        assert_eq!(request(address, TlsConnector::from(client_config(&balancer).unwrap())).await.as_deref(), Some("HTTP/1.1 404 Not Found"));
+        assert_eq!(request(address, TlsConnector::from(anonymous_client_config(&balancer).unwrap())).await, None);
+
+        // Trusts the agent, but presents a certificate of another authority
+        Authority::new("other-ca").issue(&balancer, "balancer");
+        assert_eq!(request(address, TlsConnector::from(client_config(&balancer).unwrap())).await, None);
+    }
+}
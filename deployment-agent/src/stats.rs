@@ -1,10 +1,6 @@
-use bollard::Docker;
-use bollard::container::{ListContainersOptions, StatsOptions};
-use bollard::errors::Error;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use std::env;
-use futures::stream::StreamExt;
 use std::time::{Duration, Instant};
 use tokio::time;
 use std::sync::Arc;
@@ -13,17 +9,24 @@ use tokio::sync::Mutex;
 use lazy_static::lazy_static;
 use tokio::net::TcpStream;
 use tokio::time::timeout;
+use crate::runtime::{ContainerRuntime, ContainerStats, RuntimeError};
+use crate::telemetry::{get_backend_telemetry, BackendTelemetry};
+use crate::queue::UtilizationCategory;
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ContainerStatus {
     pub id: String,
     pub name: String,
     pub cpu_score: f64,
     pub memory_score: f64,
     pub network_score: f64,
+    pub network_throughput: f64,
     pub availability_score: f64,
+    pub latency_score: f64,
+    pub error_score: f64,
     pub overall_score: f64,
-    pub utilization_category: String,
+    pub utilization_category: UtilizationCategory,
+    pub telemetry: Option<BackendTelemetry>,
 }
 
 #[derive(Clone, Debug)]
@@ -132,7 +135,7 @@ impl ResponseTimeMetrics {
 
         let trend_adjustment = trend * 10.0;
 
-        let raw_score = (base_score - penalty + trend_adjustment).max(0.0).min(100.0);
+        let raw_score = (base_score - penalty + trend_adjustment).clamp(0.0, 100.0);
 
         let ema_alpha = env::var("EMA_ALPHA")
             .expect("EMA_ALPHA must be set")
@@ -151,33 +154,18 @@ lazy_static! {
 }
 
 // Calculates values based on the states of the containers
-pub async fn get_container_statuses() -> Result<Vec<ContainerStatus>, Error> {
-    let docker = Docker::connect_with_unix_defaults().expect("Failed to connect to Docker");
-
+pub async fn get_container_statuses(runtime: Arc<dyn ContainerRuntime>) -> Result<Vec<ContainerStatus>, RuntimeError> {
     let app_identifier = env::var("APP_IDENTIFIER").expect("APP_IDENTIFIER must be set");
 
-    let filters = {
-        let mut map = HashMap::new();
-        map.insert("label".to_string(), vec![format!("application={}", app_identifier)]);
-        map
-    };
-
-    let options = ListContainersOptions {
-        all: true,
-        filters,
-        ..Default::default()
-    };
-
-    let containers = docker.list_containers(Some(options)).await?;
+    let containers = runtime.list(("application", &app_identifier), true).await?;
+    let backend_telemetry = get_backend_telemetry().await;
 
     // Asynchronous tasks for the containers
-    let futures: Vec<_> = containers.into_iter().filter_map(|container| {
-        container.id.map(|id| {
-            let docker = docker.clone();
-            let name = container.names.unwrap_or_default().get(0).cloned().unwrap_or_default();
-            tokio::spawn(async move {
-                get_single_container_status(&docker, &id, name).await
-            })
+    let futures: Vec<_> = containers.into_iter().map(|container| {
+        let runtime = runtime.clone();
+        let telemetry = backend_telemetry.get(&container.name).cloned();
+        tokio::spawn(async move {
+            get_single_container_status(runtime.as_ref(), &container.id, container.name, telemetry).await
         })
     }).collect();
 
@@ -189,29 +177,27 @@ pub async fn get_container_statuses() -> Result<Vec<ContainerStatus>, Error> {
     Ok(statuses)
 }
 
-async fn get_single_container_status(docker: &Docker, container_id: &str, container_name: String) -> Result<ContainerStatus, Error> {
-    let mut stats_stream = docker.stats(container_id, Some(StatsOptions{
-        stream: true,
-        one_shot: false,
-    }));
-
-    let stats1 = stats_stream.next().await.unwrap()?;
+async fn get_single_container_status(runtime: &dyn ContainerRuntime, container_id: &str, container_name: String, telemetry: Option<BackendTelemetry>) -> Result<ContainerStatus, RuntimeError> {
+    let stats1 = runtime.stats(container_id).await?;
     let start_time = Instant::now();
     time::sleep(Duration::from_secs(1)).await;
-    let stats2 = stats_stream.next().await.unwrap()?;
+    let stats2 = runtime.stats(container_id).await?;
     let duration = start_time.elapsed();
 
     let cpu_usage_percent = calculate_cpu_usage(&stats1, &stats2);
     let memory_usage_percent = calculate_memory_usage(&stats2);
-    let network_usage_percent = calculate_network_usage(container_id, &stats1, &stats2, duration).await;
+    let network_throughput = calculate_network_throughput(&stats1, &stats2, duration);
+    let network_usage_percent = calculate_network_usage(container_id, network_throughput).await;
 
     let cpu_score = 100.0 - cpu_usage_percent;
     let memory_score = 100.0 - memory_usage_percent;
     let network_score = 100.0 - network_usage_percent;
 
-    let availability_score = check_container_availability(docker, container_id).await;
+    let availability_score = check_container_availability(runtime, container_id).await;
+    let latency_score = calculate_latency_score(telemetry.as_ref());
+    let error_score = calculate_error_score(telemetry.as_ref());
 
-    let overall_score = calculate_score(cpu_score, memory_score, network_score, availability_score);
+    let overall_score = calculate_score(cpu_score, memory_score, network_score, availability_score, latency_score, error_score);
     let utilization_category = categorize_utilization(overall_score);
 
     // Sets container status
@@ -221,16 +207,20 @@ async fn get_single_container_status(docker: &Docker, container_id: &str, contai
         cpu_score,
         memory_score,
         network_score,
+        network_throughput,
         availability_score,
+        latency_score,
+        error_score,
         overall_score,
         utilization_category,
+        telemetry,
     })
 }
 
-fn calculate_cpu_usage(stats1: &bollard::container::Stats, stats2: &bollard::container::Stats) -> f64 {
-    let cpu_delta = stats2.cpu_stats.cpu_usage.total_usage - stats1.cpu_stats.cpu_usage.total_usage;
-    let system_delta = stats2.cpu_stats.system_cpu_usage.unwrap_or(0) - stats1.cpu_stats.system_cpu_usage.unwrap_or(0);
-    let num_cpus = stats2.cpu_stats.online_cpus.unwrap_or(1) as f64;
+fn calculate_cpu_usage(stats1: &ContainerStats, stats2: &ContainerStats) -> f64 {
+    let cpu_delta = stats2.cpu_total_usage.saturating_sub(stats1.cpu_total_usage);
+    let system_delta = stats2.system_cpu_usage.saturating_sub(stats1.system_cpu_usage);
+    let num_cpus = stats2.online_cpus.max(1) as f64;
 
     if system_delta > 0 && cpu_delta > 0 {
         (cpu_delta as f64 / system_delta as f64) * num_cpus * 100.0
@@ -239,26 +229,24 @@ fn calculate_cpu_usage(stats1: &bollard::container::Stats, stats2: &bollard::con
     }
 }
 
-fn calculate_memory_usage(stats: &bollard::container::Stats) -> f64 {
-    let usage = stats.memory_stats.usage.unwrap_or(0) as f64;
-    let limit = stats.memory_stats.limit.unwrap_or(1) as f64;
+fn calculate_memory_usage(stats: &ContainerStats) -> f64 {
+    let usage = stats.memory_usage as f64;
+    let limit = stats.memory_limit.max(1) as f64;
     (usage / limit) * 100.0
 }
 
-// Network usage within a specific timespan
-async fn calculate_network_usage(container_id: &str, stats1: &bollard::container::Stats, stats2: &bollard::container::Stats, duration: Duration) -> f64 {
+// Network throughput (MB/s) within a specific timespan
+fn calculate_network_throughput(stats1: &ContainerStats, stats2: &ContainerStats, duration: Duration) -> f64 {
     let duration_secs = duration.as_secs_f64();
 
-    // Received and transmitted bytes at 2 timestamps
-    let rx_bytes1: u64 = stats1.networks.as_ref().map(|n| n.values().map(|i| i.rx_bytes).sum()).unwrap_or(0);
-    let tx_bytes1: u64 = stats1.networks.as_ref().map(|n| n.values().map(|i| i.tx_bytes).sum()).unwrap_or(0);
-    let rx_bytes2: u64 = stats2.networks.as_ref().map(|n| n.values().map(|i| i.rx_bytes).sum()).unwrap_or(0);
-    let tx_bytes2: u64 = stats2.networks.as_ref().map(|n| n.values().map(|i| i.tx_bytes).sum()).unwrap_or(0);
-
-    // Amount of bytes within the timespan
-    let total_bytes = (rx_bytes2.saturating_sub(rx_bytes1) + tx_bytes2.saturating_sub(tx_bytes1)) as f64;
-    let mb_per_second = total_bytes / duration_secs / 1_000_000.0;
+    // Amount of received and transmitted bytes within the timespan
+    let total_bytes = (stats2.network_rx_bytes.saturating_sub(stats1.network_rx_bytes)
+        + stats2.network_tx_bytes.saturating_sub(stats1.network_tx_bytes)) as f64;
+    total_bytes / duration_secs / 1_000_000.0
+}
 
+// Network usage based on the change of throughput
+async fn calculate_network_usage(container_id: &str, mb_per_second: f64) -> f64 {
     // Adjusts network usage by adding new value to the old one
     let mut network_usage = NETWORK_USAGE.lock().await;
     let prev_usage = network_usage.entry(container_id.to_string()).or_insert(0.0);
@@ -272,7 +260,29 @@ async fn calculate_network_usage(container_id: &str, stats1: &bollard::container
     usage_percent
 }
 
-fn calculate_score(cpu_score: f64, memory_score: f64, network_score: f64, availability_score: f64) -> f64 {
+// Latency score based on the p99 latency reported by the balancers
+pub fn calculate_latency_score(telemetry: Option<&BackendTelemetry>) -> f64 {
+    dotenv().ok();
+    let latency_target = env::var("LATENCY_TARGET_MS")
+        .unwrap_or_else(|_| "200".to_string())
+        .parse::<f64>()
+        .expect("LATENCY_TARGET_MS must be a valid f64");
+
+    match telemetry {
+        Some(t) if t.rps > 0.0 => 100.0 * latency_target / t.p99_latency_ms.max(latency_target),
+        _ => 100.0, // No traffic, no penalty
+    }
+}
+
+// Error score based on the error rate reported by the balancers
+pub fn calculate_error_score(telemetry: Option<&BackendTelemetry>) -> f64 {
+    match telemetry {
+        Some(t) if t.rps > 0.0 => 100.0 * (1.0 - t.error_rate.clamp(0.0, 1.0)),
+        _ => 100.0,
+    }
+}
+
+pub fn calculate_score(cpu_score: f64, memory_score: f64, network_score: f64, availability_score: f64, latency_score: f64, error_score: f64) -> f64 {
     dotenv().ok();
 
     let wc = env::var("CPU_WEIGHT")
@@ -295,60 +305,48 @@ fn calculate_score(cpu_score: f64, memory_score: f64, network_score: f64, availa
         .parse::<f64>()
         .expect("AVAILABILITY_WEIGHT must be a valid f64");
 
+    let wl = env::var("LATENCY_WEIGHT")
+        .unwrap_or_else(|_| "0.0".to_string())
+        .parse::<f64>()
+        .expect("LATENCY_WEIGHT must be a valid f64");
+
+    let we = env::var("ERROR_WEIGHT")
+        .unwrap_or_else(|_| "0.0".to_string())
+        .parse::<f64>()
+        .expect("ERROR_WEIGHT must be a valid f64");
+
     let score = wc * cpu_score +
         wm * memory_score +
         wn * network_score +
-        wa * availability_score;
-    score.min(100.0).max(0.0)
+        wa * availability_score +
+        wl * latency_score +
+        we * error_score;
+    score.clamp(0.0, 100.0)
 }
 
 // Categorizes Usage with High, Medium and Low
-fn categorize_utilization(score: f64) -> String {
+pub fn categorize_utilization(score: f64) -> UtilizationCategory {
     match score {
-        s if s >= 70.0 => "LU",   // Low Utilization (Good performance)
-        s if s >= 40.0 => "MU",   // Medium Utilization
-        _ => "HU",                // High Utilization (Poor performance)
-    }.to_string()
+        s if s >= 70.0 => UtilizationCategory::Low,
+        s if s >= 40.0 => UtilizationCategory::Medium,
+        _ => UtilizationCategory::High,
+    }
 }
 
 // Checks Availability based on response time
-async fn check_container_availability(docker: &Docker, container_id: &str) -> f64 {
-    let response_time = get_container_response_time(docker, container_id).await;
+async fn check_container_availability(runtime: &dyn ContainerRuntime, container_id: &str) -> f64 {
+    let response_time = get_container_response_time(runtime, container_id).await;
     calculate_availability_score(container_id, response_time).await
 }
 
-async fn get_container_response_time(docker: &Docker, container_id: &str) -> Option<f64> {
-    match docker.inspect_container(container_id, None).await {
-        Ok(info) => {
-            let container_name = info.name.unwrap_or_default().trim_start_matches('/').to_string();
-
-            if let Some(network_settings) = &info.network_settings {
-                if let Some(networks) = &network_settings.networks {
-                    if let Some(network) = networks.get("rust-network") {
-                        if let Some(ip_address) = &network.ip_address {
-                            if let Some(ports) = &network_settings.ports {
-                                for (container_port, _) in ports {
-                                    let port = container_port.split('/').next().unwrap_or("5000");
-                                    let addr = format!("{}:{}", ip_address, port);
-
-                                    // Measures time needed to get tcp-connection to container
-                                    let start = Instant::now();
-                                    match timeout(Duration::from_secs(5), TcpStream::connect(&addr)).await {
-                                        Ok(Ok(_)) => {
-                                            let duration = start.elapsed().as_secs_f64();
-                                            return Some(duration);
-                                        },
-                                        Ok(Err(_)) | Err(_) => continue,
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            None // No response time could be calculated
-        },
-        Err(_) => None,
+async fn get_container_response_time(runtime: &dyn ContainerRuntime, container_id: &str) -> Option<f64> {
+    let addr = runtime.inspect(container_id).await.ok()?.address?;
+
+    // Measures time needed to get tcp-connection to container
+    let start = Instant::now();
+    match timeout(Duration::from_secs(5), TcpStream::connect(&addr)).await {
+        Ok(Ok(_)) => Some(start.elapsed().as_secs_f64()),
+        Ok(Err(_)) | Err(_) => None, // No response time could be calculated
     }
 }
 
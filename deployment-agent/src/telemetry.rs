@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use dotenv::dotenv;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+
+pub use protocol::BackendTelemetry;
+
+struct Report {
+    received_at: Instant,
+    backends: Vec<BackendTelemetry>,
+}
+
+lazy_static! {
+    // Latest report of every connected balancer
+    static ref REPORTS: Arc<Mutex<HashMap<usize, Report>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+// Stores the latest telemetry report of a balancer connection
+pub async fn record_report(reporter_id: usize, backends: Vec<BackendTelemetry>) {
+    let mut reports = REPORTS.lock().await;
+    reports.insert(reporter_id, Report {
+        received_at: Instant::now(),
+        backends,
+    });
+}
+
+pub async fn remove_reporter(reporter_id: usize) {
+    REPORTS.lock().await.remove(&reporter_id);
+}
+
+// Combines the recent reports of all balancers into one telemetry entry per backend
+pub async fn get_backend_telemetry() -> HashMap<String, BackendTelemetry> {
+    dotenv().ok();
+    let max_age = Duration::from_secs(
+        env::var("TELEMETRY_MAX_AGE")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .expect("TELEMETRY_MAX_AGE must be a valid u64")
+    );
+
+    let reports = REPORTS.lock().await;
+    let mut combined: HashMap<String, BackendTelemetry> = HashMap::new();
+    for report in reports.values().filter(|r| r.received_at.elapsed() <= max_age) {
+        for backend in &report.backends {
+            let name = backend.dns_name.trim_start_matches('/').to_string();
+            match combined.get_mut(&name) {
+                Some(entry) => {
+                    let total_rps = entry.rps + backend.rps;
+                    if total_rps > 0.0 {
+                        entry.error_rate = (entry.error_rate * entry.rps + backend.error_rate * backend.rps) / total_rps;
+                    }
+                    entry.rps = total_rps;
+                    entry.in_flight += backend.in_flight;
+                    // Percentiles can not be merged exactly, the worst balancer view is kept
+                    entry.p50_latency_ms = entry.p50_latency_ms.max(backend.p50_latency_ms);
+                    entry.p95_latency_ms = entry.p95_latency_ms.max(backend.p95_latency_ms);
+                    entry.p99_latency_ms = entry.p99_latency_ms.max(backend.p99_latency_ms);
+                }
+                None => {
+                    combined.insert(name.clone(), BackendTelemetry { dns_name: name, ..backend.clone() });
+                }
+            }
+        }
+    }
+    combined
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use once_cell::sync::Lazy;
+use crate::db;
+
+// Environment of a small deployment, shared by all tests using Redis
+const ENVIRONMENT: [(&str, &str); 20] = [
+    ("APP_IDENTIFIER", "test-app"),
+    ("DOCKER_IMAGE", "worker:2"),
+    ("TARGET_PORT", "8080"),
+    ("DEFAULT_CONTAINER", "1"),
+    ("MAX_CONTAINERS", "5"),
+    ("COOLDOWN_PERIOD", "0"),
+    ("SCALE_CHECK_PERIOD", "0"),
+    ("SCALING_POLICY", "threshold"),
+    ("HIGH_LOAD_THRESHOLD", "20"),
+    ("LOW_LOAD_THRESHOLD", "90"),
+    ("CRITICAL_LOAD_THRESHOLD", "5"),
+    ("SCALE_STEP", "1"),
+    ("CPU_WEIGHT", "0.25"),
+    ("MEMORY_WEIGHT", "0.25"),
+    ("NETWORK_WEIGHT", "0.25"),
+    ("AVAILABILITY_WEIGHT", "0.25"),
+    ("HISTORY_SIZE", "10"),
+    ("BEST_TIME_WINDOW", "5"),
+    ("EMA_ALPHA", "0.5"),
+    ("REDIS_HOST", "127.0.0.1"),
+];
+
+enum Entry {
+    Value(String),
+    Hash(BTreeMap<String, String>),
+    SortedSet(Vec<(f64, String)>),
+    Stream(Vec<(String, Vec<String>)>),
+}
+
+enum Reply {
+    Status(&'static str),
+    Integer(usize),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+    Error(String),
+}
+
+impl Reply {
+    fn strings(values: impl IntoIterator<Item = String>) -> Reply {
+        Reply::Array(values.into_iter().map(|value| Reply::Bulk(Some(value))).collect())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => out.extend_from_slice(format!("+{}\r\n", status).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(value)) => out.extend_from_slice(format!("${}\r\n{}\r\n", value.len(), value).as_bytes()),
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Reply::Error(message) => out.extend_from_slice(format!("-{}\r\n", message).as_bytes()),
+        }
+    }
+}
+
+type Store = Arc<Mutex<HashMap<String, Entry>>>;
+
+// In-memory Redis with the commands the agent uses, served over RESP on a local port
+struct FakeRedis {
+    port: u16,
+    store: Store,
+}
+
+impl FakeRedis {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind fake Redis");
+        let port = listener.local_addr().expect("Fake Redis has no address").port();
+        let store: Store = Arc::default();
+
+        let connections = store.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let store = connections.clone();
+                thread::spawn(move || serve(stream, store));
+            }
+        });
+        FakeRedis { port, store }
+    }
+}
+
+static FAKE_REDIS: Lazy<FakeRedis> = Lazy::new(FakeRedis::start);
+static REDIS_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+// Access to the fake Redis; tests holding it run one at a time against an empty store
+pub struct TestRedis {
+    pub conn: redis::Connection,
+    _guard: tokio::sync::MutexGuard<'static, ()>,
+}
+
+pub async fn redis() -> TestRedis {
+    let guard = REDIS_LOCK.lock().await;
+    FAKE_REDIS.store.lock().unwrap().clear();
+    for (name, value) in ENVIRONMENT {
+        env::set_var(name, value);
+    }
+    env::set_var("REDIS_PORT", FAKE_REDIS.port.to_string());
+
+    TestRedis { conn: db::get_redis_connection(), _guard: guard }
+}
+
+fn serve(stream: TcpStream, store: Store) {
+    let mut writer = stream.try_clone().expect("Failed to clone fake Redis connection");
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(command)) = read_command(&mut reader) {
+        let mut out = Vec::new();
+        execute(&mut store.lock().unwrap(), &command).encode(&mut out);
+        if writer.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Vec<String>>> {
+    let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected line {:?}", line));
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let count: usize = line.trim_end().strip_prefix('*').and_then(|n| n.parse().ok()).ok_or_else(|| invalid(&line))?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let len: usize = line.trim_end().strip_prefix('$').and_then(|n| n.parse().ok()).ok_or_else(|| invalid(&line))?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        arg.truncate(len);
+        args.push(String::from_utf8(arg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+    }
+    Ok(Some(args))
+}
+
+// Only '*' is supported, which is all the agent uses
+fn matches(pattern: &str, key: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == key,
+        Some((prefix, rest)) => key.strip_prefix(prefix)
+            .is_some_and(|key| (0..=key.len()).filter(|i| key.is_char_boundary(*i)).any(|i| matches(rest, &key[i..]))),
+    }
+}
+
+// Score bounds like "-inf", "+inf", "5" or the exclusive "(5", invalid bounds match nothing
+fn in_range(score: f64, min: &str, max: &str) -> bool {
+    let bound = |bound: &str| -> (f64, bool) {
+        let (value, exclusive) = bound.strip_prefix('(').map_or((bound, false), |value| (value, true));
+        let value = match value {
+            "-inf" => f64::NEG_INFINITY,
+            "+inf" | "inf" => f64::INFINITY,
+            value => value.parse().unwrap_or(f64::NAN),
+        };
+        (value, exclusive)
+    };
+    let ((min, min_exclusive), (max, max_exclusive)) = (bound(min), bound(max));
+    (if min_exclusive { score > min } else { score >= min }) && (if max_exclusive { score < max } else { score <= max })
+}
+
+fn execute(store: &mut HashMap<String, Entry>, command: &[String]) -> Reply {
+    let wrong_type = || Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string());
+    let Some((name, args)) = command.split_first() else {
+        return Reply::Error("ERR empty command".to_string());
+    };
+
+    match (name.to_uppercase().as_str(), args) {
+        ("PING", _) => Reply::Status("PONG"),
+        ("CLIENT" | "SELECT", _) => Reply::Status("OK"),
+        ("GET", [key]) => match store.get(key) {
+            Some(Entry::Value(value)) => Reply::Bulk(Some(value.clone())),
+            None => Reply::Bulk(None),
+            Some(_) => wrong_type(),
+        },
+        ("SET", [key, value]) => {
+            store.insert(key.clone(), Entry::Value(value.clone()));
+            Reply::Status("OK")
+        }
+        ("DEL", keys) => Reply::Integer(keys.iter().filter(|key| store.remove(*key).is_some()).count()),
+        ("EXISTS", keys) => Reply::Integer(keys.iter().filter(|key| store.contains_key(*key)).count()),
+        ("KEYS", [pattern]) => {
+            let mut keys: Vec<String> = store.keys().filter(|key| matches(pattern, key)).cloned().collect();
+            keys.sort();
+            Reply::strings(keys)
+        }
+        ("HSET" | "HMSET", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let Entry::Hash(hash) = store.entry(key.clone()).or_insert_with(|| Entry::Hash(BTreeMap::new())) else {
+                return wrong_type();
+            };
+            let added = pairs.chunks(2).filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none()).count();
+            if name.eq_ignore_ascii_case("HMSET") { Reply::Status("OK") } else { Reply::Integer(added) }
+        }
+        ("HGET", [key, field]) => match store.get(key) {
+            Some(Entry::Hash(hash)) => Reply::Bulk(hash.get(field).cloned()),
+            None => Reply::Bulk(None),
+            Some(_) => wrong_type(),
+        },
+        ("HGETALL", [key]) => match store.get(key) {
+            Some(Entry::Hash(hash)) => Reply::strings(hash.iter().flat_map(|(field, value)| [field.clone(), value.clone()])),
+            None => Reply::Array(Vec::new()),
+            Some(_) => wrong_type(),
+        },
+        ("HDEL", [key, fields @ ..]) => match store.get_mut(key) {
+            Some(Entry::Hash(hash)) => Reply::Integer(fields.iter().filter(|field| hash.remove(*field).is_some()).count()),
+            None => Reply::Integer(0),
+            Some(_) => wrong_type(),
+        },
+        ("ZADD", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let Entry::SortedSet(set) = store.entry(key.clone()).or_insert_with(|| Entry::SortedSet(Vec::new())) else {
+                return wrong_type();
+            };
+            let mut added = 0;
+            for pair in pairs.chunks(2) {
+                let Ok(score) = pair[0].parse::<f64>() else {
+                    return Reply::Error("ERR value is not a valid float".to_string());
+                };
+                match set.iter_mut().find(|(_, member)| *member == pair[1]) {
+                    Some(existing) => existing.0 = score,
+                    None => {
+                        set.push((score, pair[1].clone()));
+                        added += 1;
+                    }
+                }
+            }
+            set.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+            Reply::Integer(added)
+        }
+        ("ZRANGEBYSCORE", [key, min, max]) => match store.get(key) {
+            Some(Entry::SortedSet(set)) => Reply::strings(set.iter().filter(|(score, _)| in_range(*score, min, max)).map(|(_, member)| member.clone())),
+            None => Reply::Array(Vec::new()),
+            Some(_) => wrong_type(),
+        },
+        ("ZREMRANGEBYSCORE", [key, min, max]) => match store.get_mut(key) {
+            Some(Entry::SortedSet(set)) => {
+                let before = set.len();
+                set.retain(|(score, _)| !in_range(*score, min, max));
+                Reply::Integer(before - set.len())
+            }
+            None => Reply::Integer(0),
+            Some(_) => wrong_type(),
+        },
+        ("XADD", [key, rest @ ..]) => {
+            // MAXLEN [~|=] <count> is applied exactly
+            let (max_len, rest) = match rest {
+                [option, approximation, count, rest @ ..] if option.eq_ignore_ascii_case("MAXLEN") && (approximation == "~" || approximation == "=") => (count.parse::<usize>().ok(), rest),
+                [option, count, rest @ ..] if option.eq_ignore_ascii_case("MAXLEN") => (count.parse::<usize>().ok(), rest),
+                rest => (None, rest),
+            };
+            let [id, fields @ ..] = rest else {
+                return Reply::Error("ERR wrong number of arguments for 'xadd' command".to_string());
+            };
+            let Entry::Stream(entries) = store.entry(key.clone()).or_insert_with(|| Entry::Stream(Vec::new())) else {
+                return wrong_type();
+            };
+            let next = entries.last().and_then(|(id, _)| id.split('-').next()?.parse::<u64>().ok()).map_or(1, |last| last + 1);
+            let id = if id == "*" { format!("{}-0", next) } else { id.clone() };
+            entries.push((id.clone(), fields.to_vec()));
+            if let Some(max_len) = max_len {
+                let excess = entries.len().saturating_sub(max_len);
+                entries.drain(..excess);
+            }
+            Reply::Bulk(Some(id))
+        }
+        // Ranges are always "+" to "-"
+        ("XREVRANGE", [key, _, _, rest @ ..]) => {
+            let count = match rest {
+                [option, count] if option.eq_ignore_ascii_case("COUNT") => count.parse().unwrap_or(usize::MAX),
+                _ => usize::MAX,
+            };
+            match store.get(key) {
+                Some(Entry::Stream(entries)) => Reply::Array(entries.iter().rev().take(count)
+                    .map(|(id, fields)| Reply::Array(vec![Reply::Bulk(Some(id.clone())), Reply::strings(fields.clone())]))
+                    .collect()),
+                None => Reply::Array(Vec::new()),
+                Some(_) => wrong_type(),
+            }
+        }
+        ("PUBLISH", [_, _]) => Reply::Integer(0),
+        (name, _) => Reply::Error(format!("ERR unknown or malformed command '{}'", name.to_lowercase())),
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tabled")]
+use tabled::Tabled;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+// Version of the queue-update protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+// Oldest version this build still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Utilization of a worker as decided by the deployment agent. Serialized with the
+// short names stored in Redis; names of newer versions are read as Unknown.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UtilizationCategory {
+    // Low utilization (good performance)
+    #[serde(rename = "LU")]
+    Low,
+    // Medium utilization
+    #[serde(rename = "MU")]
+    Medium,
+    // High utilization (poor performance)
+    #[serde(rename = "HU")]
+    High,
+    // Started, but not measured yet
+    #[serde(rename = "INIT")]
+    Init,
+    // Marked for graceful shutdown, receives no new requests
+    #[serde(rename = "SUNDOWN")]
+    Sundown,
+    #[serde(other)]
+    Unknown,
+}
+
+impl UtilizationCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UtilizationCategory::Low => "LU",
+            UtilizationCategory::Medium => "MU",
+            UtilizationCategory::High => "HU",
+            UtilizationCategory::Init => "INIT",
+            UtilizationCategory::Sundown => "SUNDOWN",
+            UtilizationCategory::Unknown => "Unknown",
+        }
+    }
+}
+
+impl From<&str> for UtilizationCategory {
+    fn from(value: &str) -> Self {
+        match value {
+            "LU" => UtilizationCategory::Low,
+            "MU" => UtilizationCategory::Medium,
+            "HU" => UtilizationCategory::High,
+            "INIT" => UtilizationCategory::Init,
+            "SUNDOWN" => UtilizationCategory::Sundown,
+            _ => UtilizationCategory::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for UtilizationCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Backend of the queue, identified by its dns_name. The optional fields are omitted by
+// peers that do not know them; without host and port the dns_name is the address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "tabled", derive(Tabled))]
+pub struct QueueItem {
+    pub dns_name: String,
+    pub score: f64,
+    pub utilization_category: UtilizationCategory,
+    // http (default) or https
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "tabled", tabled(skip))]
+    pub scheme: Option<String>,
+    // Host name or IP under which the backend accepts requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "tabled", tabled(skip))]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "tabled", tabled(skip))]
+    pub port: Option<u16>,
+    // Zone or node the backend runs in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "tabled", tabled(skip))]
+    pub zone: Option<String>,
+    // Free-form metadata of the backend
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[cfg_attr(feature = "tabled", tabled(skip))]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl QueueItem {
+    pub fn new(dns_name: String, score: f64, utilization_category: UtilizationCategory) -> Self {
+        QueueItem {
+            dns_name,
+            score,
+            utilization_category,
+            scheme: None,
+            host: None,
+            port: None,
+            zone: None,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn scheme(&self) -> &str {
+        self.scheme.as_deref().unwrap_or("http")
+    }
+
+    // host:port of the backend. Backends without a port are served on the shared target port.
+    pub fn address(&self, target_port: &str) -> String {
+        let host = match &self.host {
+            // IPv6 addresses in brackets
+            Some(host) if host.contains(':') && !host.starts_with('[') => format!("[{}]", host),
+            Some(host) => host.clone(),
+            None if self.port.is_none() && self.dns_name.contains(':') => return self.dns_name.clone(),
+            None => self.dns_name.clone(),
+        };
+        match self.port {
+            Some(port) => format!("{}:{}", host, port),
+            None => format!("{}:{}", host, target_port),
+        }
+    }
+}
+
+// Request telemetry of a single backend as observed by a balancer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackendTelemetry {
+    pub dns_name: String,
+    pub rps: f64,
+    pub in_flight: u64,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    pub p99_latency_ms: f64,
+    pub error_rate: f64,
+}
+
+// Changes between two queues
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QueueDelta {
+    #[serde(default)]
+    pub added: Vec<QueueItem>,
+    #[serde(default)]
+    pub updated: Vec<QueueItem>,
+    // dns_names of the removed backends
+    #[serde(default)]
+    pub removed: Vec<String>,
+}
+
+impl QueueDelta {
+    pub fn diff(previous: &[QueueItem], next: &[QueueItem]) -> Self {
+        let known: HashMap<&str, &QueueItem> = previous.iter().map(|item| (item.dns_name.as_str(), item)).collect();
+        let mut delta = QueueDelta::default();
+
+        for item in next {
+            match known.get(item.dns_name.as_str()) {
+                None => delta.added.push(item.clone()),
+                Some(old) if *old != item => delta.updated.push(item.clone()),
+                Some(_) => {}
+            }
+        }
+
+        let remaining: HashSet<&str> = next.iter().map(|item| item.dns_name.as_str()).collect();
+        delta.removed = previous.iter()
+            .filter(|item| !remaining.contains(item.dns_name.as_str()))
+            .map(|item| item.dns_name.clone())
+            .collect();
+        delta
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    // Updated backends keep their position, added ones are appended
+    pub fn apply(&self, items: &mut Vec<QueueItem>) {
+        items.retain(|item| !self.removed.contains(&item.dns_name));
+        for update in &self.updated {
+            match items.iter_mut().find(|item| item.dns_name == update.dns_name) {
+                Some(item) => *item = update.clone(),
+                None => items.push(update.clone()),
+            }
+        }
+        for added in &self.added {
+            match items.iter_mut().find(|item| item.dns_name == added.dns_name) {
+                Some(item) => *item = added.clone(),
+                None => items.push(added.clone()),
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // No common protocol version, the connection is closed
+    UnsupportedVersion,
+    // The message could not be parsed
+    InvalidMessage,
+    // A message other than hello was sent before the handshake
+    HandshakeRequired,
+    #[serde(other)]
+    Unknown,
+}
+
+// Messages sent by the deployment agent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentMessage {
+    // Answer to the hello of a balancer with the version used on the connection
+    Hello { version: u32, heartbeat_interval_secs: u64 },
+    // Complete queue, replaces everything the balancer knows
+    Snapshot { seq: u64, items: Vec<QueueItem> },
+    // Changes to the queue of message seq - 1
+    Delta { seq: u64, delta: QueueDelta },
+    // Sent while the queue does not change, with the seq of the latest queue message
+    Heartbeat { seq: u64 },
+    Error { code: ErrorCode, message: String },
+}
+
+// Messages sent by a balancer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BalancerMessage {
+    // First message of a connection with the versions the balancer understands
+    Hello { min_version: u32, max_version: u32 },
+    // Requests a snapshot, e.g. after a gap in the sequence numbers
+    Resync,
+    // Requests containers for an app that was scaled to zero
+    Wake,
+    // Request telemetry per backend since the last report
+    Telemetry { backends: Vec<BackendTelemetry> },
+}
+
+// Message of a balancer published over Redis instead of a WebSocket connection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublishedEvent {
+    // Identifies the balancer, e.g. its host name
+    pub balancer: String,
+    #[serde(flatten)]
+    pub message: BalancerMessage,
+}
+
+// Highest version both sides understand, None if the ranges do not overlap
+pub fn negotiate(min_version: u32, max_version: u32) -> Option<u32> {
+    let version = max_version.min(PROTOCOL_VERSION);
+    if version >= min_version.max(MIN_PROTOCOL_VERSION) {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+pub fn encode<T: Serialize>(message: &T) -> String {
+    serde_json::to_string(message).expect("Failed to serialize protocol message")
+}
+
+pub fn decode<'a, T: Deserialize<'a>>(text: &'a str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(dns_name: &str, score: f64, category: UtilizationCategory) -> QueueItem {
+        QueueItem::new(dns_name.to_string(), score, category)
+    }
+
+    // apply keeps the positions of known backends, so only the contents are compared
+    fn round_trip(previous: Vec<QueueItem>, next: Vec<QueueItem>) {
+        let delta = QueueDelta::diff(&previous, &next);
+        let mut applied = previous;
+        delta.apply(&mut applied);
+
+        let mut expected = next;
+        applied.sort_by(|a, b| a.dns_name.cmp(&b.dns_name));
+        expected.sort_by(|a, b| a.dns_name.cmp(&b.dns_name));
+        assert_eq!(applied, expected);
+    }
+
+    fn queue() -> Vec<QueueItem> {
+        vec![
+            item("a", 90.0, UtilizationCategory::Low),
+            item("b", 50.0, UtilizationCategory::Medium),
+            item("c", 10.0, UtilizationCategory::High),
+        ]
+    }
+
+    #[test]
+    fn delta_round_trips_added_backends() {
+        let mut next = queue();
+        next.insert(1, item("d", 100.0, UtilizationCategory::Init));
+
+        let delta = QueueDelta::diff(&queue(), &next);
+        assert_eq!(delta.added, vec![item("d", 100.0, UtilizationCategory::Init)]);
+        assert!(delta.updated.is_empty() && delta.removed.is_empty());
+        round_trip(queue(), next);
+    }
+
+    #[test]
+    fn delta_round_trips_removed_backends() {
+        let mut next = queue();
+        next.remove(1);
+
+        let delta = QueueDelta::diff(&queue(), &next);
+        assert_eq!(delta.removed, vec!["b".to_string()]);
+        assert!(delta.added.is_empty() && delta.updated.is_empty());
+        round_trip(queue(), next);
+    }
+
+    #[test]
+    fn delta_round_trips_changed_backends() {
+        let mut next = queue();
+        next[0].score = 40.0;
+        next[2].utilization_category = UtilizationCategory::Sundown;
+        next[1].labels.insert("zone".to_string(), "eu-1".to_string());
+
+        let delta = QueueDelta::diff(&queue(), &next);
+        assert_eq!(delta.updated.len(), 3);
+        assert!(delta.added.is_empty() && delta.removed.is_empty());
+        round_trip(queue(), next);
+    }
+
+    #[test]
+    fn reordering_alone_is_no_change() {
+        let mut next = queue();
+        next.reverse();
+
+        assert!(QueueDelta::diff(&queue(), &next).is_empty());
+        round_trip(queue(), next);
+    }
+
+    #[test]
+    fn delta_round_trips_mixed_changes() {
+        let next = vec![
+            item("c", 95.0, UtilizationCategory::Low),
+            item("e", 100.0, UtilizationCategory::Init),
+            item("a", 90.0, UtilizationCategory::Low),
+        ];
+        round_trip(queue(), next);
+        round_trip(Vec::new(), queue());
+        round_trip(queue(), Vec::new());
+    }
+
+    #[test]
+    fn negotiate_picks_the_highest_common_version() {
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        // Newer balancers still speak our version
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 5), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(0, PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn negotiate_rejects_disjoint_ranges() {
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 3), None);
+        assert_eq!(negotiate(0, MIN_PROTOCOL_VERSION - 1), None);
+        // Inverted range
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1, PROTOCOL_VERSION), None);
+    }
+
+    #[test]
+    fn unknown_categories_decode_to_unknown() {
+        let category: UtilizationCategory = decode("\"OVERLOADED\"").unwrap();
+        assert_eq!(category, UtilizationCategory::Unknown);
+        assert_eq!(UtilizationCategory::from("OVERLOADED"), UtilizationCategory::Unknown);
+
+        let category: UtilizationCategory = decode("\"SUNDOWN\"").unwrap();
+        assert_eq!(category, UtilizationCategory::Sundown);
+    }
+
+    #[test]
+    fn v1_queue_items_still_decode() {
+        let text = r#"{"dns_name":"worker-1:8080","score":75.5,"utilization_category":"LU"}"#;
+        let decoded: QueueItem = decode(text).unwrap();
+        assert_eq!(decoded, item("worker-1:8080", 75.5, UtilizationCategory::Low));
+        assert_eq!(decoded.address("8080"), "worker-1:8080");
+
+        // Items without the optional fields are written the same way
+        assert_eq!(encode(&decoded), text);
+    }
+
+    #[test]
+    fn v1_snapshots_still_decode() {
+        let text = r#"{"type":"snapshot","seq":3,"items":[{"dns_name":"w","score":1.0,"utilization_category":"NEW"}]}"#;
+        let decoded: AgentMessage = decode(text).unwrap();
+        assert_eq!(decoded, AgentMessage::Snapshot {
+            seq: 3,
+            items: vec![item("w", 1.0, UtilizationCategory::Unknown)],
+        });
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+pub use rustls::pki_types::ServerName;
+pub use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+// Invalid or unreadable certificates of the WebSocket channel
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> Self {
+        TlsError(e.to_string())
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn open(dir: &Path, file: &str) -> Result<BufReader<File>, TlsError> {
+    let path = dir.join(file);
+    File::open(&path)
+        .map(BufReader::new)
+        .map_err(|e| TlsError(format!("failed to read {}: {}", path.display(), e)))
+}
+
+fn load_certs(dir: &Path, file: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(dir, file)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError(format!("invalid certificate in {}: {}", dir.join(file).display(), e)))?;
+    if certs.is_empty() {
+        return Err(TlsError(format!("no certificate found in {}", dir.join(file).display())));
+    }
+    Ok(certs)
+}
+
+fn load_key(dir: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(dir, "key.pem")?)
+        .map_err(|e| TlsError(format!("invalid private key in {}: {}", dir.join("key.pem").display(), e)))?
+        .ok_or_else(|| TlsError(format!("no private key found in {}", dir.join("key.pem").display())))
+}
+
+fn load_roots(dir: &Path) -> Result<Arc<RootCertStore>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(dir, "ca.pem")? {
+        roots.add(cert)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+// Agent side: presents cert.pem and only accepts balancers with a certificate signed by ca.pem
+pub fn server_config(dir: &Path) -> Result<Arc<ServerConfig>, TlsError> {
+    let verifier = WebPkiClientVerifier::builder_with_provider(load_roots(dir)?, provider())
+        .build()
+        .map_err(|e| TlsError(e.to_string()))?;
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(load_certs(dir, "cert.pem")?, load_key(dir)?)?;
+    Ok(Arc::new(config))
+}
+
+// Balancer side: presents cert.pem and only trusts an agent with a certificate signed by ca.pem
+pub fn client_config(dir: &Path) -> Result<Arc<ClientConfig>, TlsError> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(dir)?)
+        .with_client_auth_cert(load_certs(dir, "cert.pem")?, load_key(dir)?)?;
+    Ok(Arc::new(config))
+}
+
+// Name the certificate of the agent has to be issued for
+pub fn server_name(host: &str) -> Result<ServerName<'static>, TlsError> {
+    ServerName::try_from(host.to_string()).map_err(|e| TlsError(format!("invalid server name {}: {}", host, e)))
+}
+
+// Certificate authorities generated at test time, for the TLS tests of the agent and the balancer
+#[cfg(feature = "test-certs")]
+pub mod testing {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::{BigNum, MsbOption};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+    use openssl::x509::{X509Name, X509};
+    use rustls::ClientConfig;
+    use super::{load_roots, provider, TlsError};
+
+    pub struct Authority {
+        cert: X509,
+        key: PKey<Private>,
+    }
+
+    fn new_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn name(common_name: &str) -> X509Name {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name).unwrap();
+        name.build()
+    }
+
+    // Valid from a day ago for two days
+    fn builder(subject: &X509Name, key: &PKey<Private>) -> openssl::x509::X509Builder {
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let mut serial = BigNum::new().unwrap();
+        serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+        builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+        builder.set_subject_name(subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(2).unwrap()).unwrap();
+        builder
+    }
+
+    impl Authority {
+        pub fn new(common_name: &str) -> Self {
+            let key = new_key();
+            let subject = name(common_name);
+            let mut builder = builder(&subject, &key);
+            builder.set_issuer_name(&subject).unwrap();
+            builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+            builder.append_extension(KeyUsage::new().critical().key_cert_sign().crl_sign().build().unwrap()).unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+            Authority { cert: builder.build(), key }
+        }
+
+        // Writes the certificate of the authority to ca.pem in the directory
+        pub fn trust(&self, dir: &Path) {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("ca.pem"), self.cert.to_pem().unwrap()).unwrap();
+        }
+
+        // Writes a certificate for the DNS name to cert.pem and its key to key.pem in the directory
+        pub fn issue(&self, dir: &Path, dns_name: &str) {
+            let key = new_key();
+            let mut builder = builder(&name(dns_name), &key);
+            builder.set_issuer_name(self.cert.subject_name()).unwrap();
+            let context = builder.x509v3_context(Some(&self.cert), None);
+            let alt_names = SubjectAlternativeName::new().dns(dns_name).build(&context).unwrap();
+            builder.append_extension(alt_names).unwrap();
+            builder.append_extension(BasicConstraints::new().build().unwrap()).unwrap();
+            builder.append_extension(ExtendedKeyUsage::new().server_auth().client_auth().build().unwrap()).unwrap();
+            builder.sign(&self.key, MessageDigest::sha256()).unwrap();
+
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("cert.pem"), builder.build().to_pem().unwrap()).unwrap();
+            fs::write(dir.join("key.pem"), key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        }
+    }
+
+    // Client that trusts ca.pem but presents no certificate of its own
+    pub fn anonymous_client_config(dir: &Path) -> Result<Arc<ClientConfig>, TlsError> {
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(dir)?)
+            .with_no_client_auth();
+        Ok(Arc::new(config))
+    }
+}
+
+#[cfg(all(test, feature = "test-certs"))]
+mod tests {
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use super::testing::{anonymous_client_config, Authority};
+    use super::*;
+
+    fn cert_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("protocol-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // Agent and balancer directories with certificates of the same authority
+    fn cert_dirs(name: &str) -> (Authority, PathBuf, PathBuf) {
+        let authority = Authority::new("test-ca");
+        let (agent, balancer) = (cert_dir(&format!("{}-agent", name)), cert_dir(&format!("{}-balancer", name)));
+        for (dir, dns_name) in [(&agent, "localhost"), (&balancer, "balancer")] {
+            authority.trust(dir);
+            authority.issue(dir, dns_name);
+        }
+        (authority, agent, balancer)
+    }
+
+    // Runs a handshake over loopback and returns the error of the agent side first
+    async fn handshake(agent: &Path, client: Arc<ClientConfig>) -> Result<(), String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(server_config(agent).unwrap());
+        let accept = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.map_err(|e| e.to_string())?;
+            stream.write_all(b"ok").await.map_err(|e| e.to_string())?;
+            stream.flush().await.map_err(|e| e.to_string())
+        });
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        // With TLS 1.3 a rejected client certificate only shows on the first read
+        let connected = match TlsConnector::from(client).connect(server_name("localhost").unwrap(), stream).await {
+            Ok(mut stream) => stream.read_exact(&mut [0; 2]).await.map(|_| ()).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        accept.await.unwrap().and(connected)
+    }
+
+    #[tokio::test]
+    async fn accepts_balancers_signed_by_the_ca() {
+        let (_, agent, balancer) = cert_dirs("accept");
+        assert_eq!(handshake(&agent, client_config(&balancer).unwrap()).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn rejects_balancers_without_certificate() {
+        let (_, agent, balancer) = cert_dirs("anonymous");
+        let error = handshake(&agent, anonymous_client_config(&balancer).unwrap()).await.unwrap_err();
+        assert!(error.contains("no certificates"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn rejects_balancers_of_another_ca() {
+        let (_, agent, balancer) = cert_dirs("foreign");
+        // Still trusts the agent, but presents a certificate of another authority
+        Authority::new("other-ca").issue(&balancer, "balancer");
+        let error = handshake(&agent, client_config(&balancer).unwrap()).await.unwrap_err();
+        assert!(error.contains("UnknownIssuer"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn balancers_reject_agents_of_another_ca() {
+        let (_, agent, balancer) = cert_dirs("foreign-agent");
+        Authority::new("other-ca").issue(&agent, "localhost");
+        let error = handshake(&agent, client_config(&balancer).unwrap()).await.unwrap_err();
+        assert!(error.contains("UnknownCA"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_certificate_files_are_reported() {
+        let (_, dir, _) = cert_dirs("files");
+        let missing = cert_dir("missing");
+        let error = server_config(&missing).unwrap_err().to_string();
+        assert!(error.starts_with("failed to read") && error.contains("ca.pem"), "{}", error);
+
+        let key = std::fs::read(dir.join("key.pem")).unwrap();
+        std::fs::write(dir.join("key.pem"), "").unwrap();
+        let error = client_config(&dir).unwrap_err().to_string();
+        assert!(error.starts_with("no private key found"), "{}", error);
+        std::fs::write(dir.join("key.pem"), key).unwrap();
+
+        std::fs::write(dir.join("cert.pem"), "-----BEGIN CERTIFICATE-----\n@@@@\n-----END CERTIFICATE-----\n").unwrap();
+        let error = server_config(&dir).unwrap_err().to_string();
+        assert!(error.starts_with("invalid certificate"), "{}", error);
+
+        std::fs::write(dir.join("ca.pem"), "").unwrap();
+        let error = client_config(&dir).unwrap_err().to_string();
+        assert!(error.starts_with("no certificate found") && error.contains("ca.pem"), "{}", error);
+    }
+}